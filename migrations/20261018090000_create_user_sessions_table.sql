@@ -0,0 +1,15 @@
+-- sqlx migrate add create_user_sessions_table
+
+-- Add migration script here
+CREATE TABLE user_sessions (
+    session_id uuid NOT NULL ,
+    user_id uuid NOT NULL REFERENCES users(user_id) ,
+    ip_address TEXT NULL ,
+    user_agent TEXT NULL ,
+    created_at timestamptz NOT NULL ,
+    last_seen_at timestamptz NOT NULL ,
+    revoked_at timestamptz NULL ,
+    PRIMARY KEY (session_id)
+);
+
+CREATE INDEX user_sessions_active_idx ON user_sessions (user_id) WHERE revoked_at IS NULL;
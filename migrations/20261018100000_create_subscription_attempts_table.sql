@@ -0,0 +1,12 @@
+-- sqlx migrate add create_subscription_attempts_table
+
+-- Add migration script here
+-- Only hashes are kept: the table exists to rate limit, not to remember who tried to subscribe
+CREATE TABLE subscription_attempts (
+    ip_hash TEXT NOT NULL ,
+    email_hash TEXT NOT NULL ,
+    attempted_at timestamptz NOT NULL
+);
+
+CREATE INDEX subscription_attempts_ip_hash_idx ON subscription_attempts (ip_hash, attempted_at);
+CREATE INDEX subscription_attempts_email_hash_idx ON subscription_attempts (email_hash, attempted_at);
@@ -0,0 +1,16 @@
+-- sqlx migrate add create_email_outbox_table
+
+-- Add migration script here
+CREATE TABLE email_outbox (
+    message_id uuid NOT NULL ,
+    recipient TEXT NOT NULL ,
+    subject TEXT NOT NULL ,
+    html_body TEXT NOT NULL ,
+    text_body TEXT NOT NULL ,
+    n_retries SMALLINT NOT NULL DEFAULT 0 ,
+    execute_after timestamptz NOT NULL DEFAULT now() ,
+    created_at timestamptz NOT NULL DEFAULT now() ,
+    PRIMARY KEY (message_id)
+);
+
+CREATE INDEX email_outbox_execute_after_idx ON email_outbox (execute_after);
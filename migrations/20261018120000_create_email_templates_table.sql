@@ -0,0 +1,37 @@
+-- sqlx migrate add create_email_templates_table
+
+-- Add migration script here
+CREATE TABLE email_templates (
+    template_name TEXT NOT NULL ,
+    subject TEXT NOT NULL ,
+    html_body TEXT NOT NULL ,
+    text_body TEXT NOT NULL ,
+    updated_at timestamptz NOT NULL DEFAULT now() ,
+    PRIMARY KEY (template_name)
+);
+
+INSERT INTO email_templates (template_name, subject, html_body, text_body)
+VALUES (
+        'confirmation',
+        'Welcome!',
+        'Welcome to our newsletter, {{subscriber.name}}!<br />Click <a href="{{confirmation_link}}">here</a> to confirm your subscription.',
+        E'Welcome to our newsletter, {{subscriber.name}}!\nVisit {{confirmation_link}} to confirm your subscription.'
+       ),
+       (
+        'welcome',
+        'Your subscription is confirmed',
+        '<p>Hi {{subscriber.name}},</p><p>thanks for confirming your subscription, the next issue is on its way.</p><p><a href="{{unsubscribe_url}}">Unsubscribe</a></p>',
+        E'Hi {{subscriber.name}},\n\nthanks for confirming your subscription, the next issue is on its way.\n\nUnsubscribe: {{unsubscribe_url}}'
+       ),
+       (
+        'password_reset',
+        'Reset your password',
+        '<p>Hi {{user.username}},</p><p>Click <a href="{{reset_link}}">here</a> to choose a new password.</p>',
+        E'Hi {{user.username}},\n\nVisit {{reset_link}} to choose a new password.'
+       ),
+       (
+        'newsletter',
+        '{{newsletter.title}}',
+        '{{newsletter.content}}<hr /><p>You are receiving this email as {{subscriber.email}}. <a href="{{unsubscribe_url}}">Unsubscribe</a></p>',
+        E'{{newsletter.content}}\n\n--\nYou are receiving this email as {{subscriber.email}}.\nUnsubscribe: {{unsubscribe_url}}'
+       );
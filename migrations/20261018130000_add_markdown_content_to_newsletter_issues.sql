@@ -0,0 +1,5 @@
+-- sqlx migrate add add_markdown_content_to_newsletter_issues
+
+-- Add migration script here
+-- NULL when the issue was written in HTML and plain text
+ALTER TABLE newsletter_issues ADD COLUMN markdown_content TEXT NULL;
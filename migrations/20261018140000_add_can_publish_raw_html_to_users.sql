@@ -0,0 +1,5 @@
+-- sqlx migrate add add_can_publish_raw_html_to_users
+
+-- Add migration script here
+-- Opt-in: newsletter HTML of everybody else is sanitized before it is sent
+ALTER TABLE users ADD COLUMN can_publish_raw_html BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,25 @@
+-- sqlx migrate add create_issue_engagement_events_table
+
+-- Add migration script here
+-- What the author asked for, the global privacy switch of the settings still applies
+ALTER TABLE newsletter_issues ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE newsletter_issues ADD COLUMN track_clicks BOOLEAN NOT NULL DEFAULT false;
+-- Confirmed subscribers at publication time, the base of the open and click rates
+ALTER TABLE newsletter_issues ADD COLUMN recipients_count INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE issue_engagement_events (
+    event_id uuid NOT NULL ,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ,
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE ,
+    -- 'open' or 'click'
+    event_type TEXT NOT NULL ,
+    -- The followed link of a 'click'
+    url TEXT NULL ,
+    occurred_at timestamptz NOT NULL DEFAULT now() ,
+    PRIMARY KEY (event_id)
+);
+
+CREATE INDEX issue_engagement_events_issue_idx
+    ON issue_engagement_events (newsletter_issue_id, event_type);
@@ -0,0 +1,15 @@
+-- sqlx migrate add create_newsletter_issue_attachments_table
+
+-- Add migration script here
+CREATE TABLE newsletter_issue_attachments (
+    attachment_id uuid NOT NULL ,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ,
+    file_name TEXT NOT NULL ,
+    content_type TEXT NOT NULL ,
+    content BYTEA NOT NULL ,
+    -- e.g. 'cid:logo.png' when the HTML content shows the image inline
+    content_id TEXT NULL ,
+    PRIMARY KEY (attachment_id) ,
+    UNIQUE (newsletter_issue_id, file_name)
+);
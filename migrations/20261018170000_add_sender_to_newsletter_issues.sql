@@ -0,0 +1,7 @@
+-- sqlx migrate add add_sender_to_newsletter_issues
+
+-- Add migration script here
+-- NULL falls back to the sender name of the settings
+ALTER TABLE newsletter_issues ADD COLUMN from_name TEXT NULL;
+-- NULL means replies go to the sender address
+ALTER TABLE newsletter_issues ADD COLUMN reply_to TEXT NULL;
@@ -0,0 +1,16 @@
+-- sqlx migrate add create_email_deliveries_table
+
+-- Add migration script here
+-- Which email provider accepted each email
+CREATE TABLE email_deliveries (
+    delivery_id uuid NOT NULL ,
+    recipient TEXT NOT NULL ,
+    -- Set for the emails of a newsletter issue
+    newsletter_issue_id uuid NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ,
+    -- Set for the emails of the outbox, e.g. subscription confirmations
+    outbox_message_id uuid NULL ,
+    provider TEXT NOT NULL ,
+    sent_at timestamptz NOT NULL DEFAULT now() ,
+    PRIMARY KEY (delivery_id)
+);
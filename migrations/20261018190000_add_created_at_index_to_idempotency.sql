@@ -0,0 +1,5 @@
+-- sqlx migrate add add_created_at_index_to_idempotency
+
+-- Add migration script here
+-- The cleanup task looks for expired records
+CREATE INDEX idempotency_created_at_idx ON idempotency (created_at);
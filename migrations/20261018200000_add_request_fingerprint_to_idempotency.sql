@@ -0,0 +1,6 @@
+-- sqlx migrate add add_request_fingerprint_to_idempotency
+
+-- Add migration script here
+-- SHA-256 of the request, a key cannot be reused for another one
+-- NULL for the records saved before, they match any request
+ALTER TABLE idempotency ADD COLUMN request_fingerprint TEXT NULL;
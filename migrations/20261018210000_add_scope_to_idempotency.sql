@@ -0,0 +1,18 @@
+-- sqlx migrate add add_scope_to_idempotency
+
+-- Add migration script here
+-- Keys belong to a scope, e.g. 'user:<user_id>' or 'ip:<keyed hash>' for anonymous callers
+ALTER TABLE idempotency ADD COLUMN scope TEXT NULL;
+UPDATE idempotency SET scope = 'user:' || user_id;
+ALTER TABLE idempotency ALTER COLUMN scope SET NOT NULL;
+ALTER TABLE idempotency DROP CONSTRAINT idempotency_pkey;
+ALTER TABLE idempotency ADD PRIMARY KEY (scope, idempotency_key);
+-- NULL for anonymous callers
+ALTER TABLE idempotency ALTER COLUMN user_id DROP NOT NULL;
+
+-- Each scope has a retention of its own, the records saved before get the default one
+ALTER TABLE idempotency ADD COLUMN expires_at timestamptz NULL;
+UPDATE idempotency SET expires_at = created_at + interval '1 day';
+ALTER TABLE idempotency ALTER COLUMN expires_at SET NOT NULL;
+DROP INDEX idempotency_created_at_idx;
+CREATE INDEX idempotency_expires_at_idx ON idempotency (expires_at);
@@ -0,0 +1,16 @@
+-- sqlx migrate add create_api_keys_table
+
+-- Add migration script here
+-- Keys of the JSON API, only their SHA-256 hash is stored
+CREATE TABLE api_keys (
+    api_key_id uuid NOT NULL ,
+    name TEXT NOT NULL ,
+    -- The first characters of the key, to tell the keys apart
+    key_prefix TEXT NOT NULL ,
+    key_hash TEXT NOT NULL UNIQUE ,
+    created_by uuid NOT NULL REFERENCES users(user_id) ,
+    created_at timestamptz NOT NULL ,
+    last_used_at timestamptz NULL ,
+    revoked_at timestamptz NULL ,
+    PRIMARY KEY (api_key_id)
+);
@@ -0,0 +1,56 @@
+-- sqlx migrate add create_webhooks_tables
+
+-- Add migration script here
+-- Where the subscription and delivery events are posted, e.g. a CRM
+CREATE TABLE webhook_endpoints (
+    webhook_id uuid NOT NULL ,
+    url TEXT NOT NULL ,
+    -- The key of the HMAC-SHA256 signatures, kept in the clear to sign with it
+    secret TEXT NOT NULL ,
+    -- E.g. 'subscriber.confirmed'
+    event_types TEXT[] NOT NULL ,
+    created_by uuid NOT NULL REFERENCES users(user_id) ,
+    created_at timestamptz NOT NULL ,
+    -- Removed endpoints are kept for the delivery log
+    removed_at timestamptz NULL ,
+    PRIMARY KEY (webhook_id)
+);
+
+-- One row per event, whatever the number of endpoints
+CREATE TABLE webhook_events (
+    event_id uuid NOT NULL ,
+    event_type TEXT NOT NULL ,
+    -- The JSON body exactly as it is signed and posted
+    payload TEXT NOT NULL ,
+    created_at timestamptz NOT NULL DEFAULT now() ,
+    PRIMARY KEY (event_id)
+);
+
+CREATE TABLE webhook_delivery_queue (
+    event_id uuid NOT NULL REFERENCES webhook_events (event_id) ,
+    webhook_id uuid NOT NULL REFERENCES webhook_endpoints (webhook_id) ,
+    n_retries SMALLINT NOT NULL DEFAULT 0 ,
+    execute_after timestamptz NOT NULL DEFAULT now() ,
+    PRIMARY KEY (event_id, webhook_id)
+);
+
+CREATE INDEX webhook_delivery_queue_execute_after_idx ON webhook_delivery_queue (execute_after);
+
+-- Every attempt, successful or not
+CREATE TABLE webhook_deliveries (
+    delivery_id uuid NOT NULL ,
+    event_id uuid NOT NULL REFERENCES webhook_events (event_id) ,
+    webhook_id uuid NOT NULL REFERENCES webhook_endpoints (webhook_id) ,
+    -- 1 for the first attempt
+    attempt SMALLINT NOT NULL ,
+    -- NULL if no response was received, e.g. a timeout
+    status_code SMALLINT NULL ,
+    error TEXT NULL ,
+    -- 'delivered', 'retrying' or 'failed'
+    outcome TEXT NOT NULL ,
+    duration_milliseconds INTEGER NOT NULL ,
+    attempted_at timestamptz NOT NULL DEFAULT now() ,
+    PRIMARY KEY (delivery_id)
+);
+
+CREATE INDEX webhook_deliveries_attempted_at_idx ON webhook_deliveries (attempted_at);
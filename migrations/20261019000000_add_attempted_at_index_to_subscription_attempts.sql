@@ -0,0 +1,5 @@
+-- sqlx migrate add add_attempted_at_index_to_subscription_attempts
+
+-- Add migration script here
+-- The cleanup task looks for attempts older than the rate limit window
+CREATE INDEX subscription_attempts_attempted_at_idx ON subscription_attempts (attempted_at);
@@ -0,0 +1,11 @@
+-- sqlx migrate add render_email_outbox_in_worker
+
+-- Add migration script here
+-- The worker renders the email with the template as it is when the email is sent,
+-- the outbox keeps the values of its placeholders instead of the rendered bodies
+ALTER TABLE email_outbox
+    DROP COLUMN subject,
+    DROP COLUMN html_body,
+    DROP COLUMN text_body,
+    ADD COLUMN template_name TEXT NOT NULL,
+    ADD COLUMN template_context TEXT NOT NULL;
@@ -0,0 +1,244 @@
+use crate::error::BizErrorEnum;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{web, HttpMessage};
+use actix_web_lab::middleware::Next;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use unicode_segmentation::UnicodeSegmentation;
+use uuid::Uuid;
+
+/// Tells an API key apart from other secrets, e.g. in a leaked configuration file.
+const API_KEY_PREFIX: &str = "z2p_";
+const API_KEY_RANDOM_LENGTH: usize = 40;
+/// How much of the key is stored in the clear, so that its owner recognises it.
+const API_KEY_DISPLAYED_LENGTH: usize = 12;
+const API_KEY_NAME_MAX_LENGTH: usize = 100;
+
+/// The caller of the JSON API, authenticated by one of the keys of `user_id`.
+#[derive(Copy, Clone, Debug)]
+pub struct ApiClient {
+    pub api_key_id: Uuid,
+    /// Who created the key, the API acts on their behalf
+    pub user_id: Uuid,
+}
+
+/// An API key as listed to its owner, the key itself is only shown once.
+#[derive(Debug)]
+pub struct ApiKey {
+    pub api_key_id: Uuid,
+    pub name: String,
+    pub key_prefix: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// Create a key for `user_id`, returns it in the clear: only its hash is stored.
+///
+/// The keys are random, a single round of SHA-256 is as good as a password hash for them.
+#[tracing::instrument(name = "Create API key", skip(pool))]
+pub async fn create_api_key(
+    user_id: Uuid,
+    name: &str,
+    pool: &PgPool,
+) -> Result<String, BizErrorEnum> {
+    let name = parse_api_key_name(name)?;
+    let key = generate_api_key();
+    sqlx::query!(
+        r#"
+            INSERT INTO api_keys (
+                api_key_id,
+                name,
+                key_prefix,
+                key_hash,
+                created_by,
+                created_at
+            )
+            VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        Uuid::new_v4(),
+        name,
+        &key[..API_KEY_DISPLAYED_LENGTH],
+        hash_api_key(&key),
+        user_id
+    )
+    .execute(pool)
+    .await
+    .map_err(BizErrorEnum::InsertApiKeysError)?;
+
+    Ok(key)
+}
+
+#[tracing::instrument(name = "Query active API keys", skip(pool))]
+pub async fn get_active_api_keys(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<ApiKey>, BizErrorEnum> {
+    sqlx::query_as!(
+        ApiKey,
+        r#"
+            SELECT api_key_id, name, key_prefix, created_at, last_used_at
+            FROM api_keys
+            WHERE created_by = $1 AND revoked_at IS NULL
+            ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(BizErrorEnum::QueryApiKeysError)
+}
+
+/// Revoke one key of the user. Keys of other users are left untouched.
+#[tracing::instrument(name = "Revoke API key", skip(pool))]
+pub async fn revoke_api_key(
+    api_key_id: Uuid,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<(), BizErrorEnum> {
+    sqlx::query!(
+        r#"
+            UPDATE api_keys
+            SET revoked_at = now()
+            WHERE api_key_id = $1 AND created_by = $2 AND revoked_at IS NULL
+        "#,
+        api_key_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .map_err(BizErrorEnum::UpdateApiKeysError)?;
+
+    Ok(())
+}
+
+/// The caller owning `key`, `None` if the key is unknown or revoked.
+#[tracing::instrument(name = "Authenticate API key", skip(key, pool))]
+async fn authenticate_api_key(key: &str, pool: &PgPool) -> Result<Option<ApiClient>, BizErrorEnum> {
+    let record = sqlx::query!(
+        r#"
+            UPDATE api_keys
+            SET last_used_at = now()
+            WHERE key_hash = $1 AND revoked_at IS NULL
+            RETURNING api_key_id, created_by
+        "#,
+        hash_api_key(key)
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(BizErrorEnum::UpdateApiKeysError)?;
+
+    Ok(record.map(|r| ApiClient {
+        api_key_id: r.api_key_id,
+        user_id: r.created_by,
+    }))
+}
+
+/// Reject requests without a valid `Authorization: Bearer <API key>` header.
+///
+/// The [`ApiClient`] of the key is handed to the handlers.
+pub async fn reject_invalid_api_keys(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let key = match bearer_token(&req) {
+        Ok(key) => key,
+        Err(e) => return Ok(req.error_response(e).map_into_right_body()),
+    };
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .ok_or(BizErrorEnum::AppDataNotFound("PgPool"))?;
+
+    // A response rather than an `Err`, so that outer middlewares still process it
+    match authenticate_api_key(&key, pool).await {
+        Err(e) => Ok(req.error_response(e).map_into_right_body()),
+        Ok(Some(api_client)) => {
+            req.extensions_mut().insert(api_client);
+            next.call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        }
+        Ok(None) => {
+            tracing::warn!("Rejected a request with an unknown or revoked API key");
+            Ok(req
+                .error_response(BizErrorEnum::ApiKeyIsInvalid)
+                .map_into_right_body())
+        }
+    }
+}
+
+fn bearer_token(req: &ServiceRequest) -> Result<String, BizErrorEnum> {
+    let header_value = req
+        .headers()
+        .get(AUTHORIZATION)
+        .ok_or(BizErrorEnum::ApiKeyIsMissing)?
+        .to_str()
+        .map_err(|_| BizErrorEnum::ApiKeyIsInvalid)?;
+    header_value
+        .strip_prefix("Bearer ")
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty())
+        .ok_or(BizErrorEnum::ApiKeyIsMissing)
+}
+
+fn parse_api_key_name(name: &str) -> Result<&str, BizErrorEnum> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(BizErrorEnum::ApiKeyNameIsEmpty);
+    }
+    if name.graphemes(true).count() > API_KEY_NAME_MAX_LENGTH {
+        return Err(BizErrorEnum::ApiKeyNameIsTooLong {
+            max: API_KEY_NAME_MAX_LENGTH,
+        });
+    }
+    Ok(name)
+}
+
+fn generate_api_key() -> String {
+    let mut rng = rand::thread_rng();
+    let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(API_KEY_RANDOM_LENGTH)
+        .collect();
+    format!("{}{}", API_KEY_PREFIX, random)
+}
+
+/// Hex-encoded SHA-256 of the key, what `api_keys.key_hash` holds.
+fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_api_key, hash_api_key, parse_api_key_name, API_KEY_PREFIX};
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn generated_keys_are_prefixed_and_unique() {
+        let (a, b) = (generate_api_key(), generate_api_key());
+        assert!(a.starts_with(API_KEY_PREFIX));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn the_hash_depends_on_the_whole_key() {
+        let key = generate_api_key();
+        assert_eq!(hash_api_key(&key), hash_api_key(&key));
+        assert_ne!(hash_api_key(&key), hash_api_key(&key[..key.len() - 1]));
+    }
+
+    #[test]
+    fn a_blank_name_is_rejected() {
+        assert_err!(parse_api_key_name("   "));
+    }
+
+    #[test]
+    fn a_name_longer_than_the_maximum_is_rejected() {
+        assert_err!(parse_api_key_name(&"a".repeat(101)));
+        assert_ok!(parse_api_key_name(&"a".repeat(100)));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::error::BizErrorEnum;
+use crate::request::CsrfData;
+use crate::session_state::TypedSession;
+use crate::utils;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::{web, FromRequest, HttpRequest};
+use actix_web_lab::middleware::Next;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::future::Ready;
+use std::ops::Deref;
+
+const CSRF_TOKEN_LENGTH: usize = 32;
+
+/// The synchronizer token of the current session, to be embedded in every form.
+///
+/// It is created the first time a form is rendered and lives as long as the session,
+/// login rotates it together with the session key.
+#[derive(Debug)]
+pub struct CsrfToken(String);
+
+impl Deref for CsrfToken {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = BizErrorEnum;
+    type Future = Ready<Result<CsrfToken, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        std::future::ready(get_or_insert_csrf_token(req, payload).map(CsrfToken))
+    }
+}
+
+fn get_or_insert_csrf_token(
+    req: &HttpRequest,
+    payload: &mut Payload,
+) -> Result<String, BizErrorEnum> {
+    let session = TypedSession::from_request(req, payload)
+        .into_inner()
+        .map_err(|_| BizErrorEnum::SessionExtractError)?;
+    if let Some(csrf_token) = session.get_csrf_token()? {
+        return Ok(csrf_token);
+    }
+    let csrf_token = generate_csrf_token();
+    session.insert_csrf_token(&csrf_token)?;
+    Ok(csrf_token)
+}
+
+/// Replace the token of the session, forms rendered before are no longer accepted.
+///
+/// A token is issued right away rather than on the next form, so that concurrent
+/// requests of the same session don't race to create it.
+pub fn rotate_csrf_token(session: &TypedSession) -> Result<(), BizErrorEnum> {
+    session.insert_csrf_token(&generate_csrf_token())
+}
+
+fn generate_csrf_token() -> String {
+    let mut rng = rand::thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(CSRF_TOKEN_LENGTH)
+        .collect()
+}
+
+/// Reject state-changing requests whose `csrf_token` form field does not match
+/// the token stored in the session.
+///
+/// The body has to be read to find the field, it is handed back to the handler untouched.
+pub async fn reject_forged_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if req.method().is_safe() {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let body = req.extract::<web::Bytes>().await?;
+    let csrf_token = if utils::is_multipart(req.headers()) {
+        utils::multipart_text_field(req.headers(), body.clone(), "csrf_token").await
+    } else {
+        serde_urlencoded::from_bytes::<CsrfData>(&body)
+            .unwrap_or_default()
+            .csrf_token
+    };
+
+    match (session.get_csrf_token()?, csrf_token) {
+        (Some(expected), Some(actual)) if constant_time_eq(&expected, &actual) => {}
+        _ => {
+            tracing::warn!("Rejected a request with a missing or invalid CSRF token");
+            return Ok(req
+                .error_response(BizErrorEnum::InvalidCsrfToken)
+                .map_into_right_body());
+        }
+    }
+
+    req.set_payload(utils::payload_of(body));
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Compare without leaking, through timing, how many leading characters matched.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
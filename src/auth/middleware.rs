@@ -1,11 +1,16 @@
+use crate::configuration::SessionSettings;
+use crate::error::BizErrorEnum;
 use crate::session_state::TypedSession;
-use crate::utils;
+use crate::{auth, utils};
 use actix_web::body::MessageBody;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::error::InternalError;
-use actix_web::{FromRequest, HttpMessage};
+use actix_web::{web, FromRequest, HttpMessage, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
 use actix_web_lab::middleware::Next;
 use anyhow::anyhow;
+use chrono::Utc;
+use sqlx::PgPool;
 use std::fmt::{Debug, Display, Formatter};
 use std::ops::Deref;
 use uuid::Uuid;
@@ -34,22 +39,55 @@ use uuid::Uuid;
 ///
 pub async fn reject_anonymous_users(
     mut req: ServiceRequest,
-    next: Next<impl MessageBody>,
+    next: Next<impl MessageBody + 'static>,
 ) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
     let session = {
         let (http_request, payload) = req.parts_mut();
         TypedSession::from_request(http_request, payload).await
     }?;
 
-    match session.get_user_id()? {
-        Some(user_id) => {
+    match (
+        session.get_user_id()?,
+        session.get_session_id()?,
+        session.get_logged_in_at()?,
+    ) {
+        (Some(user_id), Some(session_id), Some(logged_in_at)) => {
+            let pool = req
+                .app_data::<web::Data<PgPool>>()
+                .ok_or(BizErrorEnum::AppDataNotFound("PgPool"))?;
+            let session_settings = req
+                .app_data::<web::Data<SessionSettings>>()
+                .ok_or(BizErrorEnum::AppDataNotFound("SessionSettings"))?;
+            // The idle timeout is enforced by Redis, the absolute one is up to us
+            let session_age = (Utc::now() - logged_in_at).to_std().unwrap_or_default();
+            if session_age >= session_settings.absolute_timeout() {
+                auth::revoke_user_session(session_id, user_id, pool).await?;
+                session.log_out();
+                let response =
+                    redirect_to_login_with_error("Your session has expired, please log in again.");
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+            // The session may have been revoked from another device
+            if !auth::touch_user_session(session_id, user_id, pool).await? {
+                session.log_out();
+                let response = redirect_to_login_with_error(
+                    "Your session has been revoked, please log in again.",
+                );
+                return Ok(req.into_response(response).map_into_right_body());
+            }
             req.extensions_mut().insert(UserId(user_id));
-            next.call(req).await
+            req.extensions_mut().insert(UserSessionId(session_id));
+            next.call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
         }
-        None => {
+        _ => {
             let response = utils::redirect_to("/login");
             let error = anyhow!("The user has not logged in");
-            Err(InternalError::from_response(error, response).into())
+            // A response rather than an `Err`, so that outer middlewares still process it
+            Ok(req
+                .error_response(InternalError::from_response(error, response))
+                .map_into_right_body())
         }
     }
     /* match session.get_user_id()? {
@@ -63,6 +101,12 @@ pub async fn reject_anonymous_users(
     }*/
 }
 
+/// Not an error: the flash message is only sent along a successful response.
+fn redirect_to_login_with_error(message: &str) -> HttpResponse {
+    FlashMessage::error(message).send();
+    utils::redirect_to("/login")
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct UserId(Uuid);
 
@@ -79,3 +123,21 @@ impl Deref for UserId {
         &self.0
     }
 }
+
+/// The id of the `user_sessions` record of the current request.
+#[derive(Copy, Clone, Debug)]
+pub struct UserSessionId(Uuid);
+
+impl Display for UserSessionId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl Deref for UserSessionId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
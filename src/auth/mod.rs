@@ -1,7 +1,13 @@
+mod api_key;
 mod credentials;
+mod csrf;
 mod middleware;
 mod password;
+mod user_session;
 
+pub use api_key::*;
 pub use credentials::*;
+pub use csrf::*;
 pub use middleware::*;
 pub use password::*;
+pub use user_session::*;
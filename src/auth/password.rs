@@ -1,8 +1,9 @@
 use crate::auth::credentials::Credentials;
+use crate::constant::FALLBACK_PASSWORD_SALT_AND_HASH;
 use crate::error::BizErrorEnum;
 use crate::telemetry;
 use argon2::password_hash::SaltString;
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
 use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
 use uuid::Uuid;
@@ -14,28 +15,54 @@ use uuid::Uuid;
 /// $argon2id$v=19$m=19456,t=2,p=1$OzLfJ+WIZzODQlNBT20mbw$8DU86CFOWvlJu5D+75BV6DidbTJLM92egH4+ZJxXZU4
 ///
 ///
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+#[tracing::instrument(name = "Validate credentials", skip(credentials, params, pool))]
 pub async fn validate_credentials(
     credentials: Credentials,
+    params: &Params,
     pool: &PgPool,
 ) -> Result<Uuid, BizErrorEnum> {
+    // Fall back to a dummy hash when the username is unknown, so that both paths
+    // pay for a full argon2 verification and take roughly the same time.
+    let mut user_id = None;
+    let mut password_hash_from_db = fallback_password_hash(params);
+
     // query user_id, password_hash from table
-    let (user_id, password_hash_from_db) = get_stored_credentials(&credentials.username, pool)
-        .await?
-        .ok_or(BizErrorEnum::InvalidUsername)?;
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        password_hash_from_db = stored_password_hash;
+    }
 
     // PHC string format takes care of salt for us, implicitly
     // Offload CPU-intensive task to a separate thread-pool using tokio::task::spawn_blocking.
-    telemetry::spawn_blocking_with_tracing(move || {
+    let verified = telemetry::spawn_blocking_with_tracing(move || {
         verify_password_hash(password_hash_from_db, credentials.password)
     })
     .await
     // spawn_blocking is fallible - we have a nested Result here!
-    .map_err(|e| BizErrorEnum::SpawnBlockingTaskError(e))??;
+    .map_err(BizErrorEnum::SpawnBlockingTaskError)?;
+
+    // Only report the verification outcome once we know the user exists,
+    // the dummy hash can never match anyway.
+    let user_id = user_id.ok_or(BizErrorEnum::InvalidUsername)?;
+    verified?;
 
     Ok(user_id)
 }
 
+/// A PHC string nobody knows the password of, carrying the configured parameters:
+/// verifying against it costs as much as verifying against a freshly computed hash.
+fn fallback_password_hash(params: &Params) -> Secret<String> {
+    Secret::new(format!(
+        "$argon2id$v=19$m={},t={},p={}${}",
+        params.m_cost(),
+        params.t_cost(),
+        params.p_cost(),
+        FALLBACK_PASSWORD_SALT_AND_HASH
+    ))
+}
+
 #[tracing::instrument(name = "Get stored credentials", skip(pool))]
 async fn get_stored_credentials(
     username: &str,
@@ -69,27 +96,29 @@ fn verify_password_hash(
     password_from_user: Secret<String>,
 ) -> Result<(), BizErrorEnum> {
     // Parse a password hash from a string in the PHC string format.
-    let expected_password_hash = PasswordHash::new(&password_hash_from_db.expose_secret())
-        .map_err(|e| BizErrorEnum::Argon2HashParseError(e))?;
+    let expected_password_hash = PasswordHash::new(password_hash_from_db.expose_secret())
+        .map_err(BizErrorEnum::Argon2HashParseError)?;
     Argon2::default()
         .verify_password(
             password_from_user.expose_secret().as_bytes(),
             &expected_password_hash,
         )
-        .map_err(|e| BizErrorEnum::InvalidPassword(e))?;
+        .map_err(BizErrorEnum::InvalidPassword)?;
     Ok(())
 }
 
-#[tracing::instrument(name = "Update new password", skip(new_password, pool))]
+#[tracing::instrument(name = "Update new password", skip(new_password, params, pool))]
 pub async fn update_new_password(
     user_id: Uuid,
     new_password: Secret<String>,
+    params: &Params,
     pool: &PgPool,
 ) -> Result<(), BizErrorEnum> {
+    let params = params.clone();
     let password_hash =
-        telemetry::spawn_blocking_with_tracing(move || compute_password_hash(new_password))
+        telemetry::spawn_blocking_with_tracing(move || compute_password_hash(new_password, params))
             .await
-            .map_err(|e| BizErrorEnum::SpawnBlockingTaskError(e))??;
+            .map_err(BizErrorEnum::SpawnBlockingTaskError)??;
 
     sqlx::query!(
         r#"UPDATE users SET password_hash = $1 WHERE user_id = $2"#,
@@ -98,19 +127,133 @@ pub async fn update_new_password(
     )
     .execute(pool)
     .await
-    .map_err(|e| BizErrorEnum::UpdateUsersError(e))?;
+    .map_err(BizErrorEnum::UpdateUsersError)?;
 
     Ok(())
 }
 
-#[tracing::instrument(name = "Compute password hash", skip(password))]
-fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, BizErrorEnum> {
+/// Re-hash the password with the configured parameters if the stored hash was
+/// computed with weaker ones (e.g. the seeded user, or before the settings were raised).
+///
+/// The caller must have validated `password` against the stored hash beforehand.
+/// Returns `true` if the stored hash has been replaced.
+#[tracing::instrument(name = "Upgrade password hash", skip(password, params, pool))]
+pub async fn upgrade_password_hash(
+    user_id: Uuid,
+    password: Secret<String>,
+    params: &Params,
+    pool: &PgPool,
+) -> Result<bool, BizErrorEnum> {
+    let record = sqlx::query!(
+        r#"SELECT password_hash FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to query users table: {:?}", e);
+        BizErrorEnum::QueryUsersError(e)
+    })?;
+    let stored_password_hash = Secret::new(record.password_hash);
+
+    if !needs_rehash(&stored_password_hash, params)? {
+        return Ok(false);
+    }
+
+    let new_params = params.clone();
+    let new_password_hash =
+        telemetry::spawn_blocking_with_tracing(move || compute_password_hash(password, new_params))
+            .await
+            .map_err(BizErrorEnum::SpawnBlockingTaskError)??;
+
+    // Only replace the hash we have checked, a concurrent password change wins.
+    let rows_affected = sqlx::query!(
+        r#"UPDATE users SET password_hash = $1 WHERE user_id = $2 AND password_hash = $3"#,
+        new_password_hash.expose_secret(),
+        user_id,
+        stored_password_hash.expose_secret()
+    )
+    .execute(pool)
+    .await
+    .map_err(BizErrorEnum::UpdateUsersError)?
+    .rows_affected();
+
+    Ok(rows_affected > 0)
+}
+
+/// A stored hash must be re-computed if it does not use argon2id v19,
+/// or if any of its cost parameters is lower than the configured one.
+fn needs_rehash(password_hash: &Secret<String>, params: &Params) -> Result<bool, BizErrorEnum> {
+    let password_hash = PasswordHash::new(password_hash.expose_secret())
+        .map_err(BizErrorEnum::Argon2HashParseError)?;
+    if password_hash.algorithm != Algorithm::Argon2id.ident()
+        || password_hash.version != Some(Version::V0x13.into())
+    {
+        return Ok(true);
+    }
+    let stored_params =
+        Params::try_from(&password_hash).map_err(BizErrorEnum::Argon2HashParseError)?;
+
+    Ok(stored_params.m_cost() < params.m_cost()
+        || stored_params.t_cost() < params.t_cost()
+        || stored_params.p_cost() < params.p_cost())
+}
+
+#[tracing::instrument(name = "Compute password hash", skip(password, params))]
+fn compute_password_hash(
+    password: Secret<String>,
+    params: Params,
+) -> Result<Secret<String>, BizErrorEnum> {
     let salt = SaltString::generate(&mut rand::thread_rng());
 
-    let password_hash = Argon2::default()
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
         .hash_password(password.expose_secret().as_bytes(), &salt)
-        .map_err(|e| BizErrorEnum::Argon2HashPasswordError(e))?
+        .map_err(BizErrorEnum::Argon2HashPasswordError)?
         .to_string();
 
     Ok(Secret::new(password_hash))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{compute_password_hash, fallback_password_hash, needs_rehash};
+    use argon2::Params;
+    use claims::assert_ok_eq;
+    use secrecy::Secret;
+
+    fn params(m_cost: u32, t_cost: u32, p_cost: u32) -> Params {
+        Params::new(m_cost, t_cost, p_cost, None).unwrap()
+    }
+
+    #[test]
+    fn a_hash_with_the_configured_parameters_is_kept() {
+        let params = params(8192, 2, 1);
+        let hash = compute_password_hash(Secret::new("password".into()), params.clone()).unwrap();
+        assert_ok_eq!(needs_rehash(&hash, &params), false);
+    }
+
+    #[test]
+    fn a_hash_with_stronger_parameters_is_kept() {
+        let hash =
+            compute_password_hash(Secret::new("password".into()), params(8192, 3, 2)).unwrap();
+        assert_ok_eq!(needs_rehash(&hash, &params(8192, 2, 1)), false);
+    }
+
+    #[test]
+    fn a_hash_with_weaker_parameters_is_rehashed() {
+        let hash =
+            compute_password_hash(Secret::new("password".into()), params(4096, 1, 1)).unwrap();
+        assert_ok_eq!(needs_rehash(&hash, &params(8192, 1, 1)), true);
+        assert_ok_eq!(needs_rehash(&hash, &params(4096, 2, 1)), true);
+        assert_ok_eq!(needs_rehash(&hash, &params(4096, 1, 2)), true);
+    }
+
+    #[test]
+    fn the_fallback_hash_carries_the_configured_parameters() {
+        let params = params(8192, 3, 1);
+        assert_ok_eq!(
+            needs_rehash(&fallback_password_hash(&params), &params),
+            false
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use crate::error::BizErrorEnum;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Metadata of a logged-in session, the session state itself lives in Redis.
+#[derive(Debug)]
+pub struct UserSession {
+    pub session_id: Uuid,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Create user session", skip(pool))]
+pub async fn create_user_session(
+    user_id: Uuid,
+    ip_address: Option<&str>,
+    user_agent: Option<&str>,
+    pool: &PgPool,
+) -> Result<Uuid, BizErrorEnum> {
+    let session_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+            INSERT INTO user_sessions (
+                session_id,
+                user_id,
+                ip_address,
+                user_agent,
+                created_at,
+                last_seen_at
+            )
+            VALUES ($1, $2, $3, $4, now(), now())
+        "#,
+        session_id,
+        user_id,
+        ip_address,
+        user_agent
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to insert into user_sessions: {:?}", e);
+        BizErrorEnum::InsertUserSessionsError(e)
+    })?;
+
+    Ok(session_id)
+}
+
+/// Record activity on a session.
+///
+/// Returns `false` if the session has been revoked (or never existed),
+/// in which case the caller must not let the request through.
+#[tracing::instrument(name = "Touch user session", skip(pool))]
+pub async fn touch_user_session(
+    session_id: Uuid,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, BizErrorEnum> {
+    let rows_affected = sqlx::query!(
+        r#"
+            UPDATE user_sessions
+            SET last_seen_at = now()
+            WHERE session_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        session_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to update user_sessions: {:?}", e);
+        BizErrorEnum::UpdateUserSessionsError(e)
+    })?
+    .rows_affected();
+
+    Ok(rows_affected > 0)
+}
+
+#[tracing::instrument(name = "Query active user sessions", skip(pool))]
+pub async fn get_active_user_sessions(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<UserSession>, BizErrorEnum> {
+    sqlx::query_as!(
+        UserSession,
+        r#"
+            SELECT session_id, ip_address, user_agent, created_at, last_seen_at
+            FROM user_sessions
+            WHERE user_id = $1 AND revoked_at IS NULL
+            ORDER BY last_seen_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to query user_sessions: {:?}", e);
+        BizErrorEnum::QueryUserSessionsError(e)
+    })
+}
+
+/// Revoke one session of the user. Sessions of other users are left untouched.
+#[tracing::instrument(name = "Revoke user session", skip(pool))]
+pub async fn revoke_user_session(
+    session_id: Uuid,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<(), BizErrorEnum> {
+    sqlx::query!(
+        r#"
+            UPDATE user_sessions
+            SET revoked_at = now()
+            WHERE session_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        session_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to revoke user session: {:?}", e);
+        BizErrorEnum::UpdateUserSessionsError(e)
+    })?;
+
+    Ok(())
+}
+
+/// Revoke every session of the user except `current_session_id`.
+#[tracing::instrument(name = "Revoke other user sessions", skip(pool))]
+pub async fn revoke_other_user_sessions(
+    current_session_id: Uuid,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<u64, BizErrorEnum> {
+    let rows_affected = sqlx::query!(
+        r#"
+            UPDATE user_sessions
+            SET revoked_at = now()
+            WHERE user_id = $1 AND session_id <> $2 AND revoked_at IS NULL
+        "#,
+        user_id,
+        current_session_id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to revoke other user sessions: {:?}", e);
+        BizErrorEnum::UpdateUserSessionsError(e)
+    })?
+    .rows_affected();
+
+    Ok(rows_affected)
+}
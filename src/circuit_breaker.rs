@@ -0,0 +1,270 @@
+use crate::configuration::CircuitBreakerSettings;
+use crate::error::BizErrorEnum;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Stops calling a failing dependency for a while,
+/// so that callers fail fast instead of waiting for a timeout every time.
+///
+/// - Closed: calls go through, `failure_threshold` failures in a row open the circuit
+/// - Open: calls are rejected until `open_milliseconds` have elapsed, then it is half-open
+/// - Half-open: one call at a time goes through as a trial, the others are rejected;
+///   `success_threshold` successes in a row close the circuit, a single failure opens it again
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    name: String,
+    settings: CircuitBreakerSettings,
+    state: Mutex<State>,
+}
+
+/// What `/health_check` reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed {
+        consecutive_failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    HalfOpen {
+        consecutive_successes: u32,
+        /// Set while a trial is in flight, a trial never reported is given up on by then
+        trial_until: Option<Instant>,
+    },
+}
+
+impl CircuitBreaker {
+    pub fn new(name: String, settings: CircuitBreakerSettings) -> Self {
+        Self {
+            name,
+            settings,
+            state: Mutex::new(State::Closed {
+                consecutive_failures: 0,
+            }),
+        }
+    }
+
+    /// Ask before every call, fails while the circuit is open or a trial is in flight.
+    pub fn try_acquire(&self) -> Result<(), BizErrorEnum> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            State::Open { until }
+            | State::HalfOpen {
+                trial_until: Some(until),
+                ..
+            } if now < until => Err(BizErrorEnum::CircuitBreakerIsOpen {
+                name: self.name.clone(),
+                retry_after: until - now,
+            }),
+            State::Open { .. } => {
+                tracing::info!(circuit = %self.name, "Circuit breaker is half-open.");
+                *state = State::HalfOpen {
+                    consecutive_successes: 0,
+                    trial_until: Some(now + self.settings.open_duration()),
+                };
+                Ok(())
+            }
+            State::HalfOpen {
+                consecutive_successes,
+                ..
+            } => {
+                *state = State::HalfOpen {
+                    consecutive_successes,
+                    trial_until: Some(now + self.settings.open_duration()),
+                };
+                Ok(())
+            }
+            State::Closed { .. } => Ok(()),
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => {
+                *state = State::Closed {
+                    consecutive_failures: 0,
+                }
+            }
+            State::HalfOpen {
+                consecutive_successes,
+                ..
+            } if consecutive_successes + 1 >= self.settings.success_threshold => {
+                tracing::info!(circuit = %self.name, "Circuit breaker is closed.");
+                *state = State::Closed {
+                    consecutive_failures: 0,
+                };
+            }
+            // The next caller gets to make the next trial
+            State::HalfOpen {
+                consecutive_successes,
+                ..
+            } => {
+                *state = State::HalfOpen {
+                    consecutive_successes: consecutive_successes + 1,
+                    trial_until: None,
+                }
+            }
+            // A call which started before the circuit opened
+            State::Open { .. } => {}
+        }
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed {
+                consecutive_failures,
+            } if consecutive_failures + 1 < self.settings.failure_threshold => {
+                *state = State::Closed {
+                    consecutive_failures: consecutive_failures + 1,
+                }
+            }
+            State::Closed { .. } | State::HalfOpen { .. } => {
+                tracing::warn!(
+                    circuit = %self.name,
+                    open_milliseconds = self.settings.open_milliseconds,
+                    "Circuit breaker is open."
+                );
+                *state = State::Open {
+                    until: Instant::now() + self.settings.open_duration(),
+                };
+            }
+            State::Open { .. } => {}
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { until } if Instant::now() < until => CircuitState::Open,
+            // The next call will be a trial
+            State::Open { .. } | State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// How long calls are still rejected, `None` unless the circuit is open.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match *self.state.lock().unwrap() {
+            State::Open { until } => until.checked_duration_since(Instant::now()),
+            State::Closed { .. } | State::HalfOpen { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit_breaker::{CircuitBreaker, CircuitState};
+    use crate::configuration::CircuitBreakerSettings;
+    use claims::{assert_err, assert_none, assert_ok, assert_some};
+    use std::time::Duration;
+
+    fn circuit_breaker(open_milliseconds: u64) -> CircuitBreaker {
+        CircuitBreaker::new(
+            "test".into(),
+            CircuitBreakerSettings {
+                failure_threshold: 3,
+                success_threshold: 2,
+                open_milliseconds,
+            },
+        )
+    }
+
+    #[test]
+    fn the_circuit_opens_after_the_failure_threshold_is_reached() {
+        let breaker = circuit_breaker(60_000);
+
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_ok!(breaker.try_acquire());
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_err!(breaker.try_acquire());
+        assert_some!(breaker.retry_after());
+    }
+
+    #[test]
+    fn a_success_resets_the_failure_count() {
+        let breaker = circuit_breaker(60_000);
+
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn an_open_circuit_becomes_half_open_then_closes_after_enough_successes() {
+        let breaker = circuit_breaker(10);
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_ok!(breaker.try_acquire());
+        assert_none!(breaker.retry_after());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn a_failed_trial_opens_the_circuit_again() {
+        let breaker = circuit_breaker(10);
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        assert_ok!(breaker.try_acquire());
+
+        breaker.record_failure();
+
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[test]
+    fn only_one_trial_at_a_time_goes_through_a_half_open_circuit() {
+        let breaker = circuit_breaker(10);
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_ok!(breaker.try_acquire());
+        assert_err!(breaker.try_acquire());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_ok!(breaker.try_acquire());
+        assert_err!(breaker.try_acquire());
+    }
+
+    #[test]
+    fn a_trial_never_reported_is_given_up_on() {
+        let breaker = circuit_breaker(10);
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        assert_ok!(breaker.try_acquire());
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_ok!(breaker.try_acquire());
+    }
+}
@@ -2,12 +2,18 @@ use crate::constant::{LOCAL_ENVIRONMENT, PRODUCTION_ENVIRONMENT};
 use crate::domain::SubscriberEmail;
 use crate::email_client::EmailClient;
 use crate::error::BizErrorEnum;
+use actix_session::config::{BrowserSession, PersistentSession, TtlExtensionPolicy};
+use actix_session::storage::RedisSessionStore;
+use actix_session::SessionMiddleware;
+use actix_web::cookie::{Key, SameSite};
+use argon2::Params;
 use config::{Config, File};
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
+use std::net::IpAddr;
 use std::time::Duration;
 use tracing::log::LevelFilter;
 
@@ -17,6 +23,17 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: Secret<String>,
+    pub argon2: Argon2Settings,
+    pub password_policy: PasswordPolicySettings,
+    pub session: SessionSettings,
+    pub security_headers: SecurityHeadersSettings,
+    pub subscriptions: SubscriptionSettings,
+    pub email_outbox: EmailOutboxSettings,
+    pub tracking: TrackingSettings,
+    pub attachments: AttachmentSettings,
+    pub idempotency: IdempotencySettings,
+    pub openapi: OpenApiSettings,
+    pub webhooks: WebhookSettings,
 }
 
 #[derive(Deserialize, Clone)]
@@ -26,6 +43,9 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// Reverse proxies allowed to tell the client IP address through `X-Forwarded-For`
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 #[derive(Deserialize, Clone)]
@@ -63,22 +83,34 @@ impl DatabaseSettings {
 
 #[derive(Deserialize, Clone)]
 pub struct EmailClientSettings {
-    pub base_url: String,
+    pub primary: EmailProviderSettings,
+    /// Takes over while the primary times out or answers with 5xx
+    pub secondary: Option<EmailProviderSettings>,
     pub sender_email: String,
-    pub authorization_token: Secret<String>,
+    /// Display name of `sender_email`, newsletter issues can choose their own
+    pub sender_name: Option<String>,
     pub timeout_milliseconds: u64,
+    /// Postmark message stream of one-to-one emails, e.g. subscription confirmations
+    pub transactional_message_stream: String,
+    /// Postmark message stream of newsletter issues
+    pub broadcast_message_stream: String,
+    /// Every provider has its own circuit breaker with these settings
+    pub circuit_breaker: CircuitBreakerSettings,
 }
 
 impl EmailClientSettings {
     pub fn client(self) -> EmailClient {
         let sender_email = self.sender().expect("Invalid sender email address.");
         let timeout = self.timeout();
-        EmailClient::new(
-            self.base_url,
-            sender_email,
-            self.authorization_token,
-            timeout,
-        )
+        let providers = std::iter::once(self.primary)
+            .chain(self.secondary)
+            .collect();
+        EmailClient::new(providers, sender_email, timeout, self.circuit_breaker)
+            .with_sender_name(self.sender_name)
+            .with_message_streams(
+                self.transactional_message_stream,
+                self.broadcast_message_stream,
+            )
     }
     pub fn sender(&self) -> Result<SubscriberEmail, BizErrorEnum> {
         SubscriberEmail::parse(self.sender_email.clone())
@@ -88,6 +120,320 @@ impl EmailClientSettings {
     }
 }
 
+/// An email API speaking Postmark's protocol.
+#[derive(Deserialize, Clone, Debug)]
+pub struct EmailProviderSettings {
+    /// Recorded with every email the provider sends
+    pub name: String,
+    pub base_url: String,
+    pub authorization_token: Secret<String>,
+}
+
+/// When to stop calling a failing dependency, see `circuit_breaker::CircuitBreaker`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct CircuitBreakerSettings {
+    /// Failures in a row which open the circuit
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failure_threshold: u32,
+    /// Successful trials in a row which close the circuit again
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub success_threshold: u32,
+    /// How long calls are rejected before a trial is allowed
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub open_milliseconds: u64,
+}
+
+impl CircuitBreakerSettings {
+    pub fn open_duration(&self) -> Duration {
+        Duration::from_millis(self.open_milliseconds)
+    }
+}
+
+/// Cost parameters used when hashing passwords.
+///
+/// Hashes stored with weaker parameters are upgraded the next time their owner logs in.
+#[derive(Deserialize, Clone)]
+pub struct Argon2Settings {
+    /// Memory size, in KiB
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_cost: u32,
+    /// Number of iterations
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub time_cost: u32,
+    /// Degree of parallelism
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+}
+
+impl Argon2Settings {
+    pub fn params(&self) -> Result<Params, BizErrorEnum> {
+        Params::new(self.memory_cost, self.time_cost, self.parallelism, None).map_err(|e| {
+            tracing::error!("Invalid argon2 parameters: {:?}", e);
+            BizErrorEnum::Argon2ParamsError(e)
+        })
+    }
+}
+
+/// Rules a new password must satisfy, see `domain::NewPassword`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct PasswordPolicySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_length: usize,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_length: usize,
+    /// zxcvbn-style score, from 0 (too guessable) to 4 (very unguessable)
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_strength_score: u8,
+    pub reject_current_password: bool,
+    pub reject_username: bool,
+    /// Check against the small built-in deny-list of common passwords
+    pub reject_breached: bool,
+}
+
+/// Lifetime of logged-in sessions and attributes of the cookie carrying them.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SessionSettings {
+    /// A session ends this long after login, whatever the activity, in seconds
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub absolute_timeout_seconds: u64,
+    /// A session without any request for this long ends, in seconds
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idle_timeout_seconds: u64,
+    /// Persistent cookies (`Max-Age`) survive a browser restart, browser-session cookies do not
+    pub persistent_cookie: bool,
+    pub cookie_name: String,
+    pub cookie_secure: bool,
+    pub cookie_http_only: bool,
+    pub cookie_same_site: SameSitePolicy,
+}
+
+impl SessionSettings {
+    pub fn absolute_timeout(&self) -> Duration {
+        Duration::from_secs(self.absolute_timeout_seconds)
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_seconds)
+    }
+
+    /// Build the session middleware, the session state expires in Redis
+    /// once it has not been touched for `idle_timeout_seconds`.
+    pub fn middleware(
+        &self,
+        store: RedisSessionStore,
+        key: Key,
+    ) -> SessionMiddleware<RedisSessionStore> {
+        let idle_timeout =
+            actix_web::cookie::time::Duration::seconds(self.idle_timeout_seconds as i64);
+        let builder = SessionMiddleware::builder(store, key)
+            .cookie_name(self.cookie_name.clone())
+            .cookie_secure(self.cookie_secure)
+            .cookie_http_only(self.cookie_http_only)
+            .cookie_same_site(self.cookie_same_site.into());
+        let builder = if self.persistent_cookie {
+            builder.session_lifecycle(
+                PersistentSession::default()
+                    .session_ttl(idle_timeout)
+                    .session_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest),
+            )
+        } else {
+            builder.session_lifecycle(
+                BrowserSession::default()
+                    .state_ttl(idle_timeout)
+                    .state_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest),
+            )
+        };
+        builder.build()
+    }
+}
+
+/// The `SameSite` attribute of the session cookie.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSitePolicy {
+    Strict,
+    Lax,
+    None,
+}
+
+impl From<SameSitePolicy> for SameSite {
+    fn from(value: SameSitePolicy) -> Self {
+        match value {
+            SameSitePolicy::Strict => SameSite::Strict,
+            SameSitePolicy::Lax => SameSite::Lax,
+            SameSitePolicy::None => SameSite::None,
+        }
+    }
+}
+
+/// Headers added to every response by `security_headers::add_security_headers`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SecurityHeadersSettings {
+    /// `{{nonce}}` is replaced by a fresh nonce on every request
+    pub content_security_policy: String,
+    pub frame_options: String,
+    pub referrer_policy: String,
+    /// `Strict-Transport-Security` max-age, `0` leaves the header out
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub hsts_max_age_seconds: u64,
+}
+
+/// Protection of the public subscription form against abuse.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SubscriptionSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_ip: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts_per_email: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub rate_limit_window_seconds: u64,
+    /// How often the cleanup task deletes the attempts past the window
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cleanup_interval_seconds: u64,
+    /// Humans need a few seconds to fill the form, bots do not
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_form_fill_seconds: u64,
+    /// Forms rendered longer ago than this are not accepted anymore
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_form_age_seconds: u64,
+    /// Check against the bundled list of throwaway mailbox providers
+    pub reject_disposable_emails: bool,
+}
+
+impl SubscriptionSettings {
+    pub fn min_form_fill_time(&self) -> Duration {
+        Duration::from_secs(self.min_form_fill_seconds)
+    }
+
+    pub fn max_form_age(&self) -> Duration {
+        Duration::from_secs(self.max_form_age_seconds)
+    }
+
+    pub fn cleanup_interval(&self) -> Duration {
+        Duration::from_secs(self.cleanup_interval_seconds)
+    }
+}
+
+/// Retry policy of `email_outbox_worker`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct EmailOutboxSettings {
+    /// A message is dropped after this many failed deliveries
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_retries: i16,
+    /// Delay before the first retry, doubled after every failure
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub retry_backoff_seconds: u64,
+}
+
+impl EmailOutboxSettings {
+    /// How long to wait after the `n_retries`-th failure.
+    pub fn retry_backoff(&self, n_retries: i16) -> Duration {
+        let factor = 2u32.saturating_pow(n_retries.max(0) as u32);
+        Duration::from_secs(self.retry_backoff_seconds).saturating_mul(factor)
+    }
+}
+
+/// Deliveries of the events posted to the webhook endpoints.
+#[derive(Deserialize, Clone, Debug)]
+pub struct WebhookSettings {
+    /// A delivery is given up after this many failed attempts
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_retries: i16,
+    /// Delay before the first retry, doubled after every failure
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub retry_backoff_seconds: u64,
+    /// How long the endpoint has to answer
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+    /// Let the endpoints be on loopback, private or link-local addresses.
+    /// Off in production: whoever adds an endpoint could otherwise reach the internal services
+    pub allow_private_networks: bool,
+}
+
+impl WebhookSettings {
+    /// How long to wait after the `n_retries`-th failure.
+    pub fn retry_backoff(&self, n_retries: i16) -> Duration {
+        let factor = 2u32.saturating_pow(n_retries.max(0) as u32);
+        Duration::from_secs(self.retry_backoff_seconds).saturating_mul(factor)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+/// Open and click tracking of newsletter issues.
+#[derive(Deserialize, Clone, Debug)]
+pub struct TrackingSettings {
+    /// Privacy switch: when off no pixel or rewritten link is sent and no event is recorded,
+    /// whatever the authors asked for when publishing
+    pub enabled: bool,
+}
+
+/// The documentation of the routes, `/openapi.json` is always served.
+#[derive(Deserialize, Clone, Debug)]
+pub struct OpenApiSettings {
+    /// Also serve `/openapi`, a page which shows the document to humans
+    pub viewer_enabled: bool,
+}
+
+/// Files sent along with newsletter issues.
+#[derive(Deserialize, Clone, Debug)]
+pub struct AttachmentSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_file_size_bytes: usize,
+    /// All the files of an issue together, the email API has a limit too
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_total_size_bytes: usize,
+    /// Media types such as "application/pdf"
+    pub allowed_content_types: Vec<String>,
+}
+
+impl AttachmentSettings {
+    /// Room for the files and for the other fields of the publish form.
+    pub fn max_request_size(&self) -> usize {
+        self.max_total_size_bytes.saturating_add(1024 * 1024)
+    }
+}
+
+/// How long the saved responses of `idempotency` are kept.
+#[derive(Deserialize, Clone, Debug)]
+pub struct IdempotencySettings {
+    /// A key older than this is processed again, as if it had never been seen
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub retention_seconds: u64,
+    /// Same for callers who are not logged in, e.g. of the subscription form
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub anonymous_retention_seconds: u64,
+    /// How often the cleanup task deletes expired records
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cleanup_interval_seconds: u64,
+    /// Records deleted per statement, so that the table is never locked for long
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cleanup_batch_size: i64,
+    /// How long a duplicate request waits for the one in flight, before a `409 Conflict`
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub in_flight_timeout_milliseconds: u64,
+}
+
+impl IdempotencySettings {
+    pub fn retention(&self) -> Duration {
+        Duration::from_secs(self.retention_seconds)
+    }
+
+    pub fn anonymous_retention(&self) -> Duration {
+        Duration::from_secs(self.anonymous_retention_seconds)
+    }
+
+    pub fn cleanup_interval(&self) -> Duration {
+        Duration::from_secs(self.cleanup_interval_seconds)
+    }
+
+    pub fn in_flight_timeout(&self) -> Duration {
+        Duration::from_millis(self.in_flight_timeout_milliseconds)
+    }
+}
+
 pub fn get_configuration() -> Result<Settings, BizErrorEnum> {
     let base_path = std::env::current_dir().map_err(|e| {
         tracing::error!("Failed to get current dir.");
@@ -11,5 +11,13 @@ pub const HEADER_KEY: &str = "X-Postmark-Server-Token";
 /// login error msg
 pub const LOGIN_ERROR_MSG: &str = "login_error_msg";
 
+/// Salt and hash of a password nobody knows, verified against when the username is unknown.
+/// The argon2 parameters are prepended at runtime, see `auth::validate_credentials`.
+pub const FALLBACK_PASSWORD_SALT_AND_HASH: &str =
+    "+DBtMBZLFTpqm2hHmGIYDw$VkYrE83XJl3foWs+iSRY4h1udMN/YCtjxkrmKz6hDwo";
+
 /// session
 pub const SESSION_USER_ID: &str = "user_id";
+pub const SESSION_ID: &str = "session_id";
+pub const SESSION_LOGGED_IN_AT: &str = "logged_in_at";
+pub const SESSION_CSRF_TOKEN: &str = "csrf_token";
@@ -1,7 +1,15 @@
+mod new_password;
 mod new_subscriber;
+mod newsletter_attachment;
+mod newsletter_content;
+mod sender_name;
 mod subscriber_email;
 mod subscriber_name;
 
+pub use new_password::*;
 pub use new_subscriber::*;
+pub use newsletter_attachment::NewsletterAttachment;
+pub use newsletter_content::NewsletterContent;
+pub use sender_name::SenderName;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
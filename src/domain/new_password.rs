@@ -0,0 +1,230 @@
+use crate::configuration::PasswordPolicySettings;
+use crate::error::BizErrorEnum;
+use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
+use sha1::{Digest, Sha1};
+use std::collections::{HashMap, HashSet};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A small built-in deny-list of about 170 of the most common passwords, not a breach corpus:
+/// anything missing from it is accepted, however often it has leaked elsewhere.
+///
+/// SHA-1 hashes, one `HASH:COUNT` per line, upper-case hex, the same layout as the files
+/// distributed by https://haveibeenpwned.com/Passwords so that a real corpus can be dropped in.
+/// The counts are ignored.
+const BREACHED_PASSWORDS: &str = include_str!("breached_passwords.txt");
+
+/// Length of the hash prefix used to bucket the deny-list, as in the
+/// k-anonymity range API: only the prefix is used to pick the candidates.
+const HASH_PREFIX_LENGTH: usize = 5;
+
+/// Breached hashes indexed by prefix, suffixes are kept in their own set.
+static BREACHED_PASSWORD_RANGES: Lazy<HashMap<&'static str, HashSet<&'static str>>> =
+    Lazy::new(|| {
+        let mut ranges: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for line in BREACHED_PASSWORDS.lines() {
+            let hash = line.split(':').next().unwrap_or_default().trim();
+            if hash.len() <= HASH_PREFIX_LENGTH {
+                continue;
+            }
+            let (prefix, suffix) = hash.split_at(HASH_PREFIX_LENGTH);
+            ranges.entry(prefix).or_default().insert(suffix);
+        }
+        ranges
+    });
+
+/// A password that satisfies the configured password policy.
+#[derive(Debug)]
+pub struct NewPassword(Secret<String>);
+
+impl NewPassword {
+    /// Returns an instance of `NewPassword` if `password` satisfies every rule of `policy`.
+    ///
+    /// `username` and `current_password` belong to the user changing their password,
+    /// they are used to reject passwords too close to what an attacker already knows.
+    pub fn parse(
+        password: Secret<String>,
+        username: &str,
+        current_password: &Secret<String>,
+        policy: &PasswordPolicySettings,
+    ) -> Result<Self, BizErrorEnum> {
+        let candidate = password.expose_secret();
+
+        let length = candidate.graphemes(true).count();
+        if length < policy.min_length || length > policy.max_length {
+            return Err(BizErrorEnum::NewPasswordLengthIsInvalid {
+                min: policy.min_length,
+                max: policy.max_length,
+            });
+        }
+
+        if policy.reject_current_password && candidate == current_password.expose_secret() {
+            return Err(BizErrorEnum::NewPasswordEqualsCurrentPassword);
+        }
+
+        if policy.reject_username
+            && !username.trim().is_empty()
+            && candidate
+                .to_lowercase()
+                .contains(&username.trim().to_lowercase())
+        {
+            return Err(BizErrorEnum::NewPasswordContainsUsername);
+        }
+
+        if policy.reject_breached && is_breached(candidate) {
+            return Err(BizErrorEnum::NewPasswordIsBreached);
+        }
+
+        if strength_score(candidate) < policy.min_strength_score {
+            return Err(BizErrorEnum::NewPasswordIsTooWeak);
+        }
+
+        Ok(Self(password))
+    }
+}
+
+impl AsRef<Secret<String>> for NewPassword {
+    fn as_ref(&self) -> &Secret<String> {
+        &self.0
+    }
+}
+
+impl From<NewPassword> for Secret<String> {
+    fn from(value: NewPassword) -> Self {
+        value.0
+    }
+}
+
+/// Look the password up in the built-in deny-list, without ever needing the network.
+fn is_breached(password: &str) -> bool {
+    let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = hash.split_at(HASH_PREFIX_LENGTH);
+    BREACHED_PASSWORD_RANGES
+        .get(prefix)
+        .map(|suffixes| suffixes.contains(suffix))
+        .unwrap_or(false)
+}
+
+/// Estimate the strength of a password on the same 0-4 scale as zxcvbn.
+///
+/// We approximate the number of guesses an attacker needs with a brute-force model:
+/// every character costs the size of its character class, except characters that
+/// repeat the previous one or continue a sequence (`aaa`, `abc`, `321`), which are
+/// almost free to guess. The score buckets use zxcvbn's thresholds.
+pub fn strength_score(password: &str) -> u8 {
+    let chars = password.chars().collect::<Vec<_>>();
+    let cardinality = cardinality(&chars);
+
+    let mut guesses_log10 = 0f64;
+    for (i, c) in chars.iter().enumerate() {
+        let is_pattern = i > 0 && {
+            let delta = *c as i64 - chars[i - 1] as i64;
+            delta.abs() <= 1
+        };
+        guesses_log10 += if is_pattern {
+            2f64.log10()
+        } else {
+            cardinality.log10()
+        };
+    }
+
+    match guesses_log10 {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    }
+}
+
+/// The size of the smallest alphabet containing every character of the password.
+fn cardinality(chars: &[char]) -> f64 {
+    let mut cardinality = 0;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        cardinality += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        cardinality += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        cardinality += 10;
+    }
+    if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') {
+        cardinality += 33;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        cardinality += 100;
+    }
+    cardinality.max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_breached, strength_score, NewPassword};
+    use crate::configuration::PasswordPolicySettings;
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    fn policy() -> PasswordPolicySettings {
+        PasswordPolicySettings {
+            min_length: 6,
+            max_length: 128,
+            min_strength_score: 3,
+            reject_current_password: true,
+            reject_username: true,
+            reject_breached: true,
+        }
+    }
+
+    fn parse(password: &str) -> Result<NewPassword, crate::error::BizErrorEnum> {
+        NewPassword::parse(
+            Secret::new(password.into()),
+            "ursula",
+            &Secret::new("current-Passw0rd!".into()),
+            &policy(),
+        )
+    }
+
+    #[test]
+    fn a_strong_password_is_accepted() {
+        assert_ok!(parse("correct horse battery staple"));
+    }
+
+    #[test]
+    fn a_password_shorter_than_the_minimum_is_rejected() {
+        assert_err!(parse("aB3$x"));
+    }
+
+    #[test]
+    fn a_password_longer_than_the_maximum_is_rejected() {
+        assert_err!(parse(&"aB3$x".repeat(30)));
+    }
+
+    #[test]
+    fn the_current_password_is_rejected() {
+        assert_err!(parse("current-Passw0rd!"));
+    }
+
+    #[test]
+    fn a_password_containing_the_username_is_rejected() {
+        assert_err!(parse("my name is URSULA, 1929"));
+    }
+
+    #[test]
+    fn a_breached_password_is_rejected() {
+        assert!(is_breached("password123"));
+        assert_err!(parse("password123"));
+    }
+
+    #[test]
+    fn repeated_and_sequential_characters_are_weak() {
+        assert_eq!(strength_score("aaaaaa"), 0);
+        assert_eq!(strength_score("abcdefghijkl"), 1);
+        assert_err!(parse("123456789012"));
+    }
+
+    #[test]
+    fn random_passwords_are_strong() {
+        assert_eq!(strength_score(&uuid::Uuid::new_v4().to_string()), 4);
+    }
+}
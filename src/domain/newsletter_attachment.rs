@@ -0,0 +1,251 @@
+use crate::configuration::AttachmentSettings;
+use crate::error::BizErrorEnum;
+use crate::utils;
+use std::collections::HashSet;
+
+/// A file sent along with a newsletter issue.
+#[derive(Debug)]
+pub struct NewsletterAttachment {
+    file_name: String,
+    content_type: String,
+    content: Vec<u8>,
+}
+
+impl NewsletterAttachment {
+    /// `file_name` and `content_type` come from the browser of the author, neither is trusted.
+    pub fn parse(
+        file_name: &str,
+        content_type: &str,
+        content: Vec<u8>,
+        settings: &AttachmentSettings,
+    ) -> Result<Self, BizErrorEnum> {
+        // Some browsers send the full path of the file
+        let file_name = file_name
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        if utils::is_blank(&file_name) || file_name.chars().any(char::is_control) {
+            return Err(BizErrorEnum::AttachmentFileNameIsEmpty);
+        }
+        // Parameters such as "; charset=utf-8" do not matter
+        let content_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        if !settings
+            .allowed_content_types
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&content_type))
+        {
+            return Err(BizErrorEnum::AttachmentTypeIsNotAllowed {
+                file_name,
+                content_type,
+                allowed: settings.allowed_content_types.join(", "),
+            });
+        }
+        if content.len() > settings.max_file_size_bytes {
+            return Err(BizErrorEnum::AttachmentIsTooLarge {
+                file_name,
+                max_kib: settings.max_file_size_bytes / 1024,
+            });
+        }
+        if !content_matches_type(&content, &content_type) {
+            return Err(BizErrorEnum::AttachmentContentDoesNotMatchType {
+                file_name,
+                content_type,
+            });
+        }
+        Ok(Self {
+            file_name,
+            content_type,
+            content,
+        })
+    }
+
+    /// The limits which apply to the files of an issue taken together.
+    pub fn validate_all(
+        attachments: &[NewsletterAttachment],
+        settings: &AttachmentSettings,
+    ) -> Result<(), BizErrorEnum> {
+        let mut file_names = HashSet::new();
+        if let Some(duplicate) = attachments
+            .iter()
+            .find(|attachment| !file_names.insert(attachment.file_name.as_str()))
+        {
+            return Err(BizErrorEnum::AttachmentFileNameIsDuplicated(
+                duplicate.file_name.clone(),
+            ));
+        }
+        let total_size: usize = attachments.iter().map(|a| a.content.len()).sum();
+        if total_size > settings.max_total_size_bytes {
+            return Err(BizErrorEnum::AttachmentsAreTooLarge {
+                max_kib: settings.max_total_size_bytes / 1024,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// `Some("cid:logo.png")` if the HTML content shows the image with `<img src="cid:logo.png">`.
+    pub fn content_id(&self, html: &str) -> Option<String> {
+        let content_id = format!("cid:{}", self.file_name);
+        let is_inline = self.content_type.starts_with("image/")
+            && [format!("\"{}\"", content_id), format!("'{}'", content_id)]
+                .iter()
+                .any(|quoted| html.contains(quoted.as_str()));
+        is_inline.then_some(content_id)
+    }
+}
+
+/// The declared type must be what the bytes really are, e.g. no executable named `report.pdf`.
+fn content_matches_type(content: &[u8], content_type: &str) -> bool {
+    match content_type {
+        "application/pdf" => content.starts_with(b"%PDF-"),
+        "image/png" => content.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "image/jpeg" => content.starts_with(b"\xff\xd8\xff"),
+        "image/gif" => content.starts_with(b"GIF87a") || content.starts_with(b"GIF89a"),
+        "image/webp" => {
+            content.len() >= 12 && &content[..4] == b"RIFF" && &content[8..12] == b"WEBP"
+        }
+        content_type if content_type.starts_with("text/") => std::str::from_utf8(content).is_ok(),
+        // Allowed by the settings, but we do not know how to recognize it
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NewsletterAttachment;
+    use crate::configuration::AttachmentSettings;
+    use claims::{assert_err, assert_ok};
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    fn settings() -> AttachmentSettings {
+        AttachmentSettings {
+            max_file_size_bytes: 32,
+            max_total_size_bytes: 48,
+            allowed_content_types: vec!["application/pdf".into(), "image/png".into()],
+        }
+    }
+
+    #[test]
+    fn allowed_files_are_accepted() {
+        let attachment = NewsletterAttachment::parse(
+            r"C:\Users\ursula\report.pdf",
+            "Application/PDF; name=report.pdf",
+            b"%PDF-1.7".to_vec(),
+            &settings(),
+        )
+        .unwrap();
+
+        assert_eq!(attachment.file_name(), "report.pdf");
+        assert_eq!(attachment.content_type(), "application/pdf");
+    }
+
+    #[test]
+    fn files_of_other_types_are_rejected() {
+        assert_err!(NewsletterAttachment::parse(
+            "setup.exe",
+            "application/x-msdownload",
+            b"MZ".to_vec(),
+            &settings()
+        ));
+    }
+
+    #[test]
+    fn files_which_are_not_what_they_claim_are_rejected() {
+        assert_err!(NewsletterAttachment::parse(
+            "report.pdf",
+            "application/pdf",
+            b"MZ".to_vec(),
+            &settings()
+        ));
+        assert_ok!(NewsletterAttachment::parse(
+            "logo.png",
+            "image/png",
+            PNG.to_vec(),
+            &settings()
+        ));
+    }
+
+    #[test]
+    fn files_without_a_name_are_rejected() {
+        assert_err!(NewsletterAttachment::parse(
+            " ",
+            "image/png",
+            PNG.to_vec(),
+            &settings()
+        ));
+        assert_err!(NewsletterAttachment::parse(
+            "photos/",
+            "image/png",
+            PNG.to_vec(),
+            &settings()
+        ));
+    }
+
+    #[test]
+    fn size_limits_are_enforced() {
+        let settings = settings();
+        assert_err!(NewsletterAttachment::parse(
+            "large.pdf",
+            "application/pdf",
+            [b"%PDF-".as_slice(), &[b' '; 28]].concat(),
+            &settings
+        ));
+
+        let attachments: Vec<_> = ["a.png", "b.png", "c.png", "d.png"]
+            .iter()
+            .map(|name| {
+                NewsletterAttachment::parse(name, "image/png", PNG.to_vec(), &settings).unwrap()
+            })
+            .collect();
+        assert_ok!(NewsletterAttachment::validate_all(
+            &attachments[..2],
+            &settings
+        ));
+        assert_err!(NewsletterAttachment::validate_all(&attachments, &settings));
+    }
+
+    #[test]
+    fn file_names_must_be_unique() {
+        let settings = settings();
+        let attachments: Vec<_> = ["a.png", "a.png"]
+            .iter()
+            .map(|name| {
+                NewsletterAttachment::parse(name, "image/png", PNG.to_vec(), &settings).unwrap()
+            })
+            .collect();
+
+        assert_err!(NewsletterAttachment::validate_all(&attachments, &settings));
+    }
+
+    #[test]
+    fn images_referenced_by_the_html_content_are_inline() {
+        let logo = NewsletterAttachment::parse("logo.png", "image/png", PNG.to_vec(), &settings())
+            .unwrap();
+
+        assert_eq!(
+            logo.content_id(r#"<img src="cid:logo.png" alt="Logo">"#),
+            Some("cid:logo.png".to_string())
+        );
+        assert_eq!(logo.content_id(r#"<img src="cid:logo.png.old">"#), None);
+        assert_eq!(logo.content_id("<p>No image</p>"), None);
+    }
+}
@@ -0,0 +1,399 @@
+use crate::error::BizErrorEnum;
+use crate::utils;
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag};
+use std::collections::BTreeMap;
+
+/// The bodies of a newsletter issue, as they are stored and sent.
+#[derive(Debug)]
+pub struct NewsletterContent {
+    html: String,
+    text: String,
+    /// What `html` and `text` were rendered from, if the issue was written in Markdown
+    markdown: Option<String>,
+    /// What the sanitizer stripped from the HTML written by hand
+    warnings: Vec<String>,
+}
+
+impl NewsletterContent {
+    /// Both bodies written by hand, the HTML is reduced to an allowlist of elements and attributes.
+    pub fn parse(html: String, text: String) -> Result<Self, BizErrorEnum> {
+        let mut content = Self::parse_trusted(html, text)?;
+        // `cid:` shows an image attached to the email
+        let sanitized = ammonia::Builder::default()
+            .add_url_schemes(&["cid"])
+            .clean(&content.html)
+            .to_string();
+        content.warnings = stripped_markup(&content.html, &sanitized);
+        content.html = sanitized;
+        Ok(content)
+    }
+
+    /// Both bodies written by hand, the HTML is sent exactly as it was typed.
+    pub fn parse_trusted(html: String, text: String) -> Result<Self, BizErrorEnum> {
+        if utils::is_blank(&html) || utils::is_blank(&text) {
+            return Err(BizErrorEnum::NewsletterContentIsEmpty);
+        }
+        Ok(Self {
+            html,
+            text,
+            markdown: None,
+            warnings: vec![],
+        })
+    }
+
+    /// Both bodies rendered from Markdown, raw HTML is escaped rather than interpreted.
+    pub fn from_markdown(markdown: String) -> Result<Self, BizErrorEnum> {
+        if utils::is_blank(&markdown) {
+            return Err(BizErrorEnum::NewsletterContentIsEmpty);
+        }
+        Ok(Self {
+            html: markdown_to_html(&markdown),
+            text: markdown_to_text(&markdown),
+            markdown: Some(markdown),
+            warnings: vec![],
+        })
+    }
+
+    pub fn html(&self) -> &str {
+        &self.html
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn markdown(&self) -> Option<&str> {
+        self.markdown.as_deref()
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Markup {
+    Comment,
+    Element(String),
+    Attribute { element: String, name: String },
+}
+
+/// Describes what is in `original` but no longer in `sanitized`, e.g. "Removed 1 <script> element.".
+fn stripped_markup(original: &str, sanitized: &str) -> Vec<String> {
+    let kept = count_markup(sanitized);
+    count_markup(original)
+        .into_iter()
+        .filter_map(|(markup, count)| {
+            let removed = count.saturating_sub(kept.get(&markup).copied().unwrap_or(0));
+            if removed == 0 {
+                return None;
+            }
+            let plural = if removed == 1 { "" } else { "s" };
+            Some(match markup {
+                Markup::Comment => format!("Removed {} HTML comment{}.", removed, plural),
+                Markup::Element(element) => {
+                    format!("Removed {} <{}> element{}.", removed, element, plural)
+                }
+                Markup::Attribute { element, name } => format!(
+                    "Removed the {} attribute from {} <{}> element{}.",
+                    name, removed, element, plural
+                ),
+            })
+        })
+        .collect()
+}
+
+/// A rough tokenizer, good enough to tell the author what the sanitizer took away.
+fn count_markup(html: &str) -> BTreeMap<Markup, usize> {
+    let mut counts = BTreeMap::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            *counts.entry(Markup::Comment).or_insert(0) += 1;
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        if !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            continue;
+        }
+        let name_len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+            .unwrap_or(rest.len());
+        let element = rest[..name_len].to_ascii_lowercase();
+        rest = &rest[name_len..];
+        loop {
+            rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+            if rest.is_empty() || rest.starts_with('>') {
+                break;
+            }
+            let name_len = rest
+                .find(|c: char| c.is_whitespace() || "/>=".contains(c))
+                .unwrap_or(rest.len());
+            let name = rest[..name_len].to_ascii_lowercase();
+            rest = rest[name_len..].trim_start();
+            if let Some(value) = rest.strip_prefix('=') {
+                let value = value.trim_start();
+                rest = match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        value[1..].find(quote).map_or("", |end| &value[end + 2..])
+                    }
+                    _ => {
+                        let len = value
+                            .find(|c: char| c.is_whitespace() || c == '>')
+                            .unwrap_or(value.len());
+                        &value[len..]
+                    }
+                };
+            }
+            if !name.is_empty() {
+                let attribute = Markup::Attribute {
+                    element: element.clone(),
+                    name,
+                };
+                *counts.entry(attribute).or_insert(0) += 1;
+            }
+        }
+        *counts.entry(Markup::Element(element)).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn markdown_options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH
+}
+
+fn markdown_to_html(markdown: &str) -> String {
+    let events = merge_text_events(Parser::new_ext(markdown, markdown_options()))
+        .into_iter()
+        .flat_map(|event| match event {
+            // Shown as it was typed, never interpreted
+            Event::Html(html) => vec![Event::Text(html)],
+            Event::Text(text) => keep_merge_fields(text),
+            Event::Start(Tag::Link(link_type, url, title)) if !is_safe_url(&url) => {
+                vec![Event::Start(Tag::Link(link_type, "#".into(), title))]
+            }
+            Event::Start(Tag::Image(link_type, url, title)) if !is_safe_url(&url) => {
+                vec![Event::Start(Tag::Image(link_type, "#".into(), title))]
+            }
+            event => vec![event],
+        });
+    let mut html = String::with_capacity(markdown.len() * 3 / 2);
+    pulldown_cmark::html::push_html(&mut html, events);
+    html
+}
+
+/// A readable alternative for mail clients which do not display HTML.
+fn markdown_to_text(markdown: &str) -> String {
+    let mut text = String::with_capacity(markdown.len());
+    // The next number of every open list, `None` if it is a bullet list
+    let mut lists: Vec<Option<u64>> = vec![];
+    let mut urls: Vec<CowStr> = vec![];
+    for event in Parser::new_ext(markdown, markdown_options()) {
+        match event {
+            Event::Start(Tag::List(first_number)) => {
+                start_line(&mut text);
+                lists.push(first_number);
+            }
+            Event::End(Tag::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::Start(Tag::Item) => {
+                start_line(&mut text);
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        text.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::End(Tag::Item) => start_line(&mut text),
+            Event::End(Tag::Paragraph)
+            | Event::End(Tag::Heading(..))
+            | Event::End(Tag::CodeBlock(_))
+            | Event::End(Tag::TableRow)
+            | Event::End(Tag::TableHead) => {
+                text.push('\n');
+                if lists.is_empty() && !matches!(event, Event::End(Tag::TableRow)) {
+                    text.push('\n');
+                }
+            }
+            Event::End(Tag::TableCell) => text.push('\t'),
+            Event::Start(Tag::Link(_, url, _)) | Event::Start(Tag::Image(_, url, _)) => {
+                urls.push(url)
+            }
+            Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => {
+                if let Some(url) = urls.pop() {
+                    if !text.ends_with(url.as_ref()) {
+                        text.push_str(&format!(" ({})", url));
+                    }
+                }
+            }
+            Event::Text(value) | Event::Code(value) | Event::Html(value) => text.push_str(&value),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::Rule => text.push_str("---\n\n"),
+            _ => {}
+        }
+    }
+    text.trim_end().to_string()
+}
+
+fn start_line(text: &mut String) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+/// The parser may split a text on characters such as `_`, merge fields need it in one piece.
+fn merge_text_events<'a>(events: impl Iterator<Item = Event<'a>>) -> Vec<Event<'a>> {
+    let mut merged: Vec<Event> = vec![];
+    for event in events {
+        match (merged.last_mut(), event) {
+            (Some(Event::Text(previous)), Event::Text(text)) => {
+                *previous = format!("{}{}", previous, text).into();
+            }
+            (_, event) => merged.push(event),
+        }
+    }
+    merged
+}
+
+/// Merge fields must reach the worker as they were typed, e.g. the quotes of a fallback.
+/// They are always filled in, with escaped values, before the issue is sent.
+fn keep_merge_fields(text: CowStr) -> Vec<Event> {
+    let mut events = vec![];
+    let mut rest = text.as_ref();
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end + 2,
+            None => break,
+        };
+        if start > 0 {
+            events.push(Event::Text(rest[..start].to_string().into()));
+        }
+        events.push(Event::Html(rest[start..end].to_string().into()));
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() {
+        events.push(Event::Text(rest.to_string().into()));
+    }
+    events
+}
+
+fn is_safe_url(url: &str) -> bool {
+    match url.split_once(':') {
+        // Relative, or a colon further down the path
+        None => true,
+        Some((scheme, _)) if scheme.contains(['/', '?', '#']) => true,
+        Some((scheme, _)) => ["http", "https", "mailto", "cid"]
+            .iter()
+            .any(|allowed| scheme.eq_ignore_ascii_case(allowed)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NewsletterContent;
+    use claims::assert_err;
+
+    #[test]
+    fn blank_content_is_rejected() {
+        assert_err!(NewsletterContent::parse("<p>Hi</p>".into(), " ".into()));
+        assert_err!(NewsletterContent::parse("".into(), "Hi".into()));
+        assert_err!(NewsletterContent::from_markdown("\n ".into()));
+    }
+
+    #[test]
+    fn html_written_by_hand_is_sanitized() {
+        let html = r#"<p onclick="steal()">Hi <b>there</b></p><script>alert(1)</script>
+            <!-- note --><a href="javascript:alert(1)">a</a><a href="https://example.com/?id={{subscriber.email}}">b</a>"#;
+        let content = NewsletterContent::parse(html.into(), "Hi there".into()).unwrap();
+
+        assert!(content.html().starts_with("<p>Hi <b>there</b></p>"));
+        assert!(!content.html().contains("script"));
+        assert!(!content.html().contains("javascript"));
+        assert!(content
+            .html()
+            .contains(r#"href="https://example.com/?id={{subscriber.email}}""#));
+        assert_eq!(
+            content.warnings(),
+            [
+                "Removed 1 HTML comment.",
+                "Removed 1 <script> element.",
+                "Removed the href attribute from 1 <a> element.",
+                "Removed the onclick attribute from 1 <p> element.",
+            ]
+        );
+    }
+
+    #[test]
+    fn safe_html_is_kept_without_warnings() {
+        let html = r#"<h1>News</h1><p>Dear {{ subscriber.name | "reader" }}, <img src="https://example.com/a.png" alt="A"><img src="cid:logo.png" alt="Logo"></p>"#;
+        let content = NewsletterContent::parse(html.into(), "News".into()).unwrap();
+
+        assert_eq!(content.html(), html);
+        assert!(content.warnings().is_empty());
+    }
+
+    #[test]
+    fn trusted_html_is_kept_as_it_was_typed() {
+        let html = "<script>track()</script><p>Hi</p>";
+        let content = NewsletterContent::parse_trusted(html.into(), "Hi".into()).unwrap();
+
+        assert_eq!(content.html(), html);
+        assert!(content.warnings().is_empty());
+    }
+
+    #[test]
+    fn markdown_is_rendered_to_html_and_plain_text() {
+        let markdown = "# News\n\nHello *there*, read [the post](https://example.com/post).\n\n\
+                        - one\n- two\n\n1. first\n2. second\n\n---\n\nBye";
+        let content = NewsletterContent::from_markdown(markdown.into()).unwrap();
+
+        assert!(content
+            .html()
+            .starts_with("<h1>News</h1>\n<p>Hello <em>there</em>"));
+        assert!(content
+            .html()
+            .contains(r#"<a href="https://example.com/post">the post</a>"#));
+        assert!(content.html().contains("<li>one</li>"));
+        assert_eq!(
+            content.text(),
+            "News\n\nHello there, read the post (https://example.com/post).\n\n\
+             - one\n- two\n\n1. first\n2. second\n\n---\n\nBye"
+        );
+        assert_eq!(content.markdown(), Some(markdown));
+    }
+
+    #[test]
+    fn raw_html_and_dangerous_links_are_not_interpreted() {
+        let markdown = "<script>alert(1)</script>\n\nA <b>bold</b> [link](javascript:alert(1))";
+        let content = NewsletterContent::from_markdown(markdown.into()).unwrap();
+
+        assert!(!content.html().contains("<script>"));
+        assert!(!content.html().contains("<b>"));
+        assert!(content.html().contains("&lt;b&gt;bold&lt;/b&gt;"));
+        assert!(content.html().contains(r##"<a href="#">link</a>"##));
+    }
+
+    #[test]
+    fn merge_fields_are_kept_as_they_were_typed() {
+        let markdown =
+            r#"Dear **{{ subscriber.name | "reader" }}**, since {{subscriber.subscribed_at}}"#;
+        let content = NewsletterContent::from_markdown(markdown.into()).unwrap();
+
+        assert_eq!(
+            content.html(),
+            "<p>Dear <strong>{{ subscriber.name | \"reader\" }}</strong>, since {{subscriber.subscribed_at}}</p>\n"
+        );
+        assert_eq!(
+            content.text(),
+            r#"Dear {{ subscriber.name | "reader" }}, since {{subscriber.subscribed_at}}"#
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use crate::constant::FORBIDDEN_CHARACTERS;
+use crate::error::BizErrorEnum;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Display name in the `From` header of an email, e.g. "Our Newsletter".
+#[derive(Debug)]
+pub struct SenderName(String);
+impl SenderName {
+    pub const MAX_LENGTH: usize = 100;
+
+    /// Returns `None` for a blank name, the sender name of the settings is used instead.
+    pub fn parse(name: String) -> Result<Option<Self>, BizErrorEnum> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Ok(None);
+        }
+
+        if name.graphemes(true).count() > Self::MAX_LENGTH {
+            return Err(BizErrorEnum::SenderNameIsTooLong {
+                max: Self::MAX_LENGTH,
+            });
+        }
+
+        // A line break would end the header
+        if name
+            .chars()
+            .any(|c| c.is_control() || FORBIDDEN_CHARACTERS.contains(&c))
+        {
+            return Err(BizErrorEnum::SenderNameContainsIllegalCharacter);
+        }
+
+        Ok(Some(Self(name.to_string())))
+    }
+}
+
+impl AsRef<str> for SenderName {
+    fn as_ref(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SenderName;
+    use claims::{assert_err, assert_none, assert_ok};
+
+    #[test]
+    fn a_blank_name_means_no_name() {
+        assert_none!(SenderName::parse("  ".into()).unwrap());
+    }
+
+    #[test]
+    fn a_name_longer_than_the_maximum_is_rejected() {
+        let name = "a".repeat(SenderName::MAX_LENGTH + 1);
+        assert_err!(SenderName::parse(name));
+    }
+
+    #[test]
+    fn a_name_with_a_line_break_is_rejected() {
+        assert_err!(SenderName::parse("Our\r\nBcc: x@y.z".into()));
+    }
+
+    #[test]
+    fn a_name_with_angle_brackets_is_rejected() {
+        assert_err!(SenderName::parse("Our <Newsletter>".into()));
+    }
+
+    #[test]
+    fn a_valid_name_is_trimmed() {
+        let name = assert_ok!(SenderName::parse(" Our Newsletter ".into())).unwrap();
+        assert_eq!(name.as_ref(), "Our Newsletter");
+    }
+}
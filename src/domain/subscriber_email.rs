@@ -1,6 +1,19 @@
 use crate::error::BizErrorEnum;
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
 
-#[derive(Debug)]
+/// Domains of throwaway mailbox providers, one per line, lower-case.
+const DISPOSABLE_EMAIL_DOMAINS: &str = include_str!("disposable_email_domains.txt");
+
+static DISPOSABLE_EMAIL_DOMAIN_SET: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    DISPOSABLE_EMAIL_DOMAINS
+        .lines()
+        .map(str::trim)
+        .filter(|domain| !domain.is_empty())
+        .collect()
+});
+
+#[derive(Debug, Clone)]
 pub struct SubscriberEmail(String);
 impl SubscriberEmail {
     pub fn parse(email: String) -> Result<Self, BizErrorEnum> {
@@ -30,6 +43,23 @@ impl SubscriberEmail {
     }
 }
 
+impl SubscriberEmail {
+    /// Whether the address belongs to a throwaway mailbox provider, subdomains included.
+    pub fn is_disposable(&self) -> bool {
+        let domain = self.0.rsplit('@').next().unwrap_or_default().to_lowercase();
+        let mut candidate = domain.as_str();
+        loop {
+            if DISPOSABLE_EMAIL_DOMAIN_SET.contains(candidate) {
+                return true;
+            }
+            match candidate.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => candidate = parent,
+                _ => return false,
+            }
+        }
+    }
+}
+
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
         self.0.as_str()
@@ -79,6 +109,16 @@ mod tests {
         assert_ok!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn disposable_domains_are_detected() {
+        let email = SubscriberEmail::parse("ursula@Mailinator.com".to_string()).unwrap();
+        assert!(email.is_disposable());
+        let email = SubscriberEmail::parse("ursula@inbox.yopmail.com".to_string()).unwrap();
+        assert!(email.is_disposable());
+        let email = SubscriberEmail::parse("ursula@gmail.com".to_string()).unwrap();
+        assert!(!email.is_disposable());
+    }
+
     #[quickcheck_macros::quickcheck]
     fn valid_emails_are_parsed_successfully_2(valid_email: ValidEmailFixture) -> bool {
         SubscriberEmail::parse(valid_email.0).is_ok()
@@ -1,52 +1,201 @@
+use crate::circuit_breaker::{CircuitBreaker, CircuitState};
+use crate::configuration::{CircuitBreakerSettings, EmailProviderSettings};
 use crate::constant::HEADER_KEY;
 use crate::domain::SubscriberEmail;
 use crate::error::BizErrorEnum;
+use base64::Engine;
 use reqwest::{Client, Url};
 use secrecy::{ExposeSecret, Secret};
 use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 use std::time::Duration;
 
-#[derive(Debug)]
+/// Clones share the HTTP connections and the circuit breakers.
+#[derive(Debug, Clone)]
 pub struct EmailClient {
     http_client: Client,
     sender: SubscriberEmail,
+    /// Display name of `sender`, e.g. "Our Newsletter" in `Our Newsletter <news@example.com>`
+    sender_name: Option<String>,
+    /// Tried in order, a provider is only used when the ones before it are failing
+    providers: Arc<Vec<EmailProvider>>,
+    transactional_stream: String,
+    broadcast_stream: String,
+}
+
+/// An email API speaking Postmark's protocol.
+#[derive(Debug)]
+struct EmailProvider {
+    name: String,
     base_url: String,
     authorization_token: Secret<String>,
+    circuit_breaker: CircuitBreaker,
+}
+
+/// How a provider handled an email.
+enum Attempt {
+    Sent,
+    /// The provider is up but refused the email, e.g. a 4xx: another one would refuse it too
+    Rejected(BizErrorEnum),
+    /// A timeout or a 5xx, the next provider may do better
+    Failed(BizErrorEnum),
 }
 
 impl EmailClient {
+    /// `providers` in order of preference, e.g. primary then secondary.
     pub fn new(
-        base_url: String,
+        providers: Vec<EmailProviderSettings>,
         sender: SubscriberEmail,
-        authorization_token: Secret<String>,
         timeout: Duration,
+        circuit_breaker: CircuitBreakerSettings,
     ) -> Self {
         let http_client = Client::builder()
             // timeout is a MUST option for client
             .timeout(timeout)
             .build()
             .unwrap();
+        let providers = providers
+            .into_iter()
+            .map(|provider| EmailProvider {
+                circuit_breaker: CircuitBreaker::new(
+                    format!("email_provider:{}", provider.name),
+                    circuit_breaker.clone(),
+                ),
+                name: provider.name,
+                base_url: provider.base_url,
+                authorization_token: provider.authorization_token,
+            })
+            .collect();
         EmailClient {
             http_client,
             sender,
-            base_url,
-            authorization_token,
+            sender_name: None,
+            providers: Arc::new(providers),
+            transactional_stream: DEFAULT_TRANSACTIONAL_STREAM.into(),
+            broadcast_stream: DEFAULT_BROADCAST_STREAM.into(),
         }
     }
 
+    /// Display name used when a message does not choose its own.
+    pub fn with_sender_name(mut self, sender_name: Option<String>) -> Self {
+        self.sender_name = sender_name;
+        self
+    }
+
+    /// Postmark ids of the message streams, see `MessageStream`.
+    pub fn with_message_streams(mut self, transactional: String, broadcast: String) -> Self {
+        self.transactional_stream = transactional;
+        self.broadcast_stream = broadcast;
+        self
+    }
+
+    /// The circuit breaker of every provider, by name.
+    pub fn circuit_states(&self) -> Vec<(&str, CircuitState, Option<Duration>)> {
+        self.providers
+            .iter()
+            .map(|provider| {
+                (
+                    provider.name.as_str(),
+                    provider.circuit_breaker.state(),
+                    provider.circuit_breaker.retry_after(),
+                )
+            })
+            .collect()
+    }
+
+    /// How long emails are still refused, `None` unless every provider is considered down.
+    pub fn unavailable_for(&self) -> Option<Duration> {
+        self.providers
+            .iter()
+            .map(|provider| provider.circuit_breaker.retry_after())
+            .collect::<Option<Vec<_>>>()
+            .and_then(|retry_afters| retry_afters.into_iter().min())
+    }
+
+    /// Returns the name of the provider which accepted the email.
     pub async fn send_email(
         &self,
         recipient: &SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), BizErrorEnum> {
+    ) -> Result<&str, BizErrorEnum> {
+        self.send(EmailMessage::new(
+            recipient,
+            subject,
+            html_content,
+            text_content,
+        ))
+        .await
+    }
+
+    /// Returns the name of the provider which accepted the email.
+    ///
+    /// The next provider is tried when one times out or answers with a 5xx.
+    pub async fn send(&self, message: EmailMessage<'_>) -> Result<&str, BizErrorEnum> {
+        if let Some(header) = message.headers.iter().find(|h| !h.is_valid()) {
+            return Err(BizErrorEnum::EmailHeaderIsInvalid(header.name.clone()));
+        }
+        let from_name = message.from_name.or(self.sender_name.as_deref());
+        let request_body = SendEmailRequest {
+            from: mailbox(from_name, &self.sender),
+            to: message.recipient.as_ref(),
+            cc: address_list(&message.cc),
+            bcc: address_list(&message.bcc),
+            reply_to: message.reply_to.map(|reply_to| reply_to.as_ref()),
+            subject: message.subject,
+            html_body: message.html_body,
+            text_body: message.text_body,
+            headers: &message.headers,
+            tag: message.tag,
+            metadata: &message.metadata,
+            message_stream: match message.message_stream {
+                MessageStream::Transactional => &self.transactional_stream,
+                MessageStream::Broadcast => &self.broadcast_stream,
+            },
+            attachments: message.attachments,
+        };
+
+        let mut last_error = None;
+        for provider in self.providers.iter() {
+            // Fail fast rather than waiting for the timeout of a provider which is down
+            if provider.circuit_breaker.try_acquire().is_err() {
+                continue;
+            }
+            match self.send_with(provider, &request_body).await? {
+                Attempt::Sent => return Ok(&provider.name),
+                Attempt::Rejected(e) => return Err(e),
+                Attempt::Failed(e) => {
+                    tracing::warn!(
+                        provider = %provider.name,
+                        error.message = %e,
+                        "Failed to send an email, trying the next provider."
+                    );
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(
+            last_error.unwrap_or_else(|| BizErrorEnum::CircuitBreakerIsOpen {
+                name: "email_providers".into(),
+                retry_after: self.unavailable_for().unwrap_or_default(),
+            }),
+        )
+    }
+
+    async fn send_with(
+        &self,
+        provider: &EmailProvider,
+        request_body: &SendEmailRequest<'_>,
+    ) -> Result<Attempt, BizErrorEnum> {
         // You can do better using `reqwest::Url::join` if you change
         // `base_url`'s type from `String` to `reqwest::Url`.
         // I'll leave it as an exercise for the reader!
-        let url = Url::parse(&self.base_url)
+        let url = Url::parse(&provider.base_url)
             .map_err(|e| {
-                tracing::error!("Failed to parse url: url={}, e={:?}", &self.base_url, e);
+                tracing::error!("Failed to parse url: url={}, e={:?}", &provider.base_url, e);
                 BizErrorEnum::ParseUrlError
             })?
             .join("/email")
@@ -55,42 +204,274 @@ impl EmailClient {
                 BizErrorEnum::JoinUrlError
             })?;
 
-        let request_body = SendEmailRequest {
-            from: self.sender.as_ref(),
-            to: recipient.as_ref(),
-            subject,
-            html_body: html_content,
-            text_body: text_content,
-        };
-        self.http_client
+        let outcome = self
+            .http_client
             .post(url)
-            .header(HEADER_KEY, self.authorization_token.expose_secret())
-            .json(&request_body)
+            .header(HEADER_KEY, provider.authorization_token.expose_secret())
+            .json(request_body)
             .send()
-            .await
-            .map_err(|e| {
+            .await;
+        let response = match outcome {
+            Ok(response) => response,
+            Err(e) => {
                 tracing::error!("Failed to send email: {:?}", e);
-                BizErrorEnum::SendEmailError(e)
-            })?
-            .error_for_status()?;
-        Ok(())
+                provider.circuit_breaker.record_failure();
+                return Ok(Attempt::Failed(BizErrorEnum::SendEmailError(e)));
+            }
+        };
+        // A 4xx is about our request, the provider itself is up
+        if response.status().is_server_error() {
+            provider.circuit_breaker.record_failure();
+        } else {
+            provider.circuit_breaker.record_success();
+        }
+        Ok(match response.error_for_status() {
+            Ok(_) => Attempt::Sent,
+            Err(e) if e.status().is_some_and(|s| s.is_server_error()) => Attempt::Failed(e.into()),
+            Err(e) => Attempt::Rejected(e.into()),
+        })
+    }
+}
+
+/// Postmark's default stream ids.
+const DEFAULT_TRANSACTIONAL_STREAM: &str = "outbound";
+const DEFAULT_BROADCAST_STREAM: &str = "broadcast";
+
+/// Postmark keeps one-to-one messages and bulk messages apart,
+/// so that a newsletter cannot hurt the deliverability of confirmation emails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MessageStream {
+    /// Triggered by what a single user did, e.g. subscribing
+    #[default]
+    Transactional,
+    /// Sent to many users at once, e.g. a newsletter issue
+    Broadcast,
+}
+
+/// An email to send with `EmailClient::send`.
+///
+/// Only the recipient, the subject and the bodies are required, the rest is set step by step:
+/// `EmailMessage::new(..).reply_to(&author).tag("newsletter")`.
+#[derive(Debug)]
+pub struct EmailMessage<'a> {
+    recipient: &'a SubscriberEmail,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    from_name: Option<&'a str>,
+    reply_to: Option<&'a SubscriberEmail>,
+    cc: Vec<&'a SubscriberEmail>,
+    bcc: Vec<&'a SubscriberEmail>,
+    headers: Vec<EmailHeader>,
+    tag: Option<&'a str>,
+    metadata: BTreeMap<String, String>,
+    message_stream: MessageStream,
+    attachments: &'a [EmailAttachment<'a>],
+}
+
+impl<'a> EmailMessage<'a> {
+    pub fn new(
+        recipient: &'a SubscriberEmail,
+        subject: &'a str,
+        html_body: &'a str,
+        text_body: &'a str,
+    ) -> Self {
+        Self {
+            recipient,
+            subject,
+            html_body,
+            text_body,
+            from_name: None,
+            reply_to: None,
+            cc: vec![],
+            bcc: vec![],
+            headers: vec![],
+            tag: None,
+            metadata: BTreeMap::new(),
+            message_stream: MessageStream::default(),
+            attachments: &[],
+        }
+    }
+
+    /// Display name of the sender, overrides the one of `EmailClient`.
+    pub fn from_name(mut self, from_name: &'a str) -> Self {
+        self.from_name = Some(from_name);
+        self
+    }
+
+    pub fn reply_to(mut self, reply_to: &'a SubscriberEmail) -> Self {
+        self.reply_to = Some(reply_to);
+        self
+    }
+
+    pub fn cc(mut self, cc: &'a SubscriberEmail) -> Self {
+        self.cc.push(cc);
+        self
+    }
+
+    pub fn bcc(mut self, bcc: &'a SubscriberEmail) -> Self {
+        self.bcc.push(bcc);
+        self
+    }
+
+    /// Any header but the ones set from the fields above, e.g. `List-Unsubscribe`.
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push(EmailHeader {
+            name: name.into(),
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Groups messages in the statistics of the email provider.
+    pub fn tag(mut self, tag: &'a str) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    /// Sent back with the events of the email provider, e.g. bounces.
+    pub fn metadata(mut self, key: &str, value: impl ToString) -> Self {
+        self.metadata.insert(key.into(), value.to_string());
+        self
+    }
+
+    pub fn message_stream(mut self, message_stream: MessageStream) -> Self {
+        self.message_stream = message_stream;
+        self
+    }
+
+    pub fn attachments(mut self, attachments: &'a [EmailAttachment<'a>]) -> Self {
+        self.attachments = attachments;
+        self
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader {
+    name: String,
+    value: String,
+}
+
+impl EmailHeader {
+    /// A line break would let the value inject headers of its own.
+    fn is_valid(&self) -> bool {
+        !self.name.is_empty()
+            && self.name.chars().all(|c| c.is_ascii_graphic() && c != ':')
+            && !self.value.chars().any(|c| c == '\r' || c == '\n')
+    }
+}
+
+/// `Name <address>`, the name is quoted when it contains characters with a meaning in addresses.
+fn mailbox(name: Option<&str>, address: &SubscriberEmail) -> String {
+    let name = name
+        .map(|name| name.chars().filter(|c| !c.is_control()).collect::<String>())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    match name {
+        None => address.as_ref().to_string(),
+        Some(name) if name.contains(|c| "()<>[]:;@\\,.\"".contains(c)) => format!(
+            "\"{}\" <{}>",
+            name.replace('\\', "\\\\").replace('"', "\\\""),
+            address.as_ref()
+        ),
+        Some(name) => format!("{} <{}>", name, address.as_ref()),
+    }
+}
+
+fn address_list(addresses: &[&SubscriberEmail]) -> Option<String> {
+    if addresses.is_empty() {
+        return None;
     }
+    Some(
+        addresses
+            .iter()
+            .map(|address| address.as_ref())
+            .collect::<Vec<_>>()
+            .join(","),
+    )
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "PascalCase")]
 struct SendEmailRequest<'a> {
-    from: &'a str,
+    from: String,
     to: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bcc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<&'a str>,
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<&'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: &'a BTreeMap<String, String>,
+    message_stream: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    attachments: &'a [EmailAttachment<'a>],
+}
+
+/// A file sent along with an email, shown inline when the HTML body refers to its `content_id`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailAttachment<'a> {
+    name: &'a str,
+    /// Base64 encoded
+    content: Cow<'a, str>,
+    content_type: &'a str,
+    #[serde(rename = "ContentID", skip_serializing_if = "Option::is_none")]
+    content_id: Option<String>,
+}
+
+impl<'a> EmailAttachment<'a> {
+    pub fn new(
+        name: &'a str,
+        content: &[u8],
+        content_type: &'a str,
+        content_id: Option<String>,
+    ) -> Self {
+        Self {
+            name,
+            content: Cow::Owned(Self::encode(content)),
+            content_type,
+            content_id,
+        }
+    }
+
+    /// Same as `new` with a content encoded by `encode`, for a file sent to many recipients.
+    pub fn from_base64(
+        name: &'a str,
+        content: &'a str,
+        content_type: &'a str,
+        content_id: Option<String>,
+    ) -> Self {
+        Self {
+            name,
+            content: Cow::Borrowed(content),
+            content_type,
+            content_id,
+        }
+    }
+
+    pub fn encode(content: &[u8]) -> String {
+        base64::engine::general_purpose::STANDARD.encode(content)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::circuit_breaker::CircuitState;
+    use crate::configuration::{CircuitBreakerSettings, EmailProviderSettings};
     use crate::domain::SubscriberEmail;
-    use crate::email_client::{EmailClient, HEADER_KEY};
+    use crate::email_client::{
+        BizErrorEnum, EmailAttachment, EmailClient, EmailMessage, MessageStream, HEADER_KEY,
+    };
     use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
@@ -136,13 +517,47 @@ mod tests {
         SubscriberEmail::parse(SafeEmail().fake()).unwrap()
     }
 
+    /// Opens after 3 failures in a row, for a minute.
+    fn circuit_breaker() -> CircuitBreakerSettings {
+        CircuitBreakerSettings {
+            failure_threshold: 3,
+            success_threshold: 1,
+            open_milliseconds: 60_000,
+        }
+    }
+
+    fn provider(name: &str, base_url: String) -> EmailProviderSettings {
+        EmailProviderSettings {
+            name: name.into(),
+            base_url,
+            authorization_token: Secret::new(Faker.fake()),
+        }
+    }
+
     /// Get a test instance of `EmailClient`.
     fn email_client(base_url: String) -> EmailClient {
         EmailClient::new(
-            base_url,
+            vec![provider("primary", base_url)],
             email(),
-            Secret::new(Faker.fake()),
             Duration::from_secs(5),
+            circuit_breaker(),
+        )
+    }
+
+    /// Get a test instance of `EmailClient` which fails over from `primary_url` to `secondary_url`.
+    fn email_client_with_failover(
+        primary_url: String,
+        secondary_url: String,
+        circuit_breaker: CircuitBreakerSettings,
+    ) -> EmailClient {
+        EmailClient::new(
+            vec![
+                provider("primary", primary_url),
+                provider("secondary", secondary_url),
+            ],
+            email(),
+            Duration::from_secs(2),
+            circuit_breaker,
         )
     }
 
@@ -172,6 +587,186 @@ mod tests {
         // Mock expectations are checked on drop
     }
 
+    #[tokio::test]
+    async fn attachments_are_sent_base64_encoded() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let attachments = [
+            EmailAttachment::new("report.pdf", b"%PDF-1.7", "application/pdf", None),
+            EmailAttachment::new(
+                "logo.png",
+                b"\x89PNG",
+                "image/png",
+                Some("cid:logo.png".into()),
+            ),
+        ];
+
+        // Act
+        let (recipient, subject, content) = (email(), subject(), content());
+        let outcome = email_client
+            .send(
+                EmailMessage::new(&recipient, &subject, &content, &content)
+                    .attachments(&attachments),
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(
+            body["Attachments"],
+            serde_json::json!([
+                {"Name": "report.pdf", "Content": "JVBERi0xLjc=", "ContentType": "application/pdf"},
+                {"Name": "logo.png", "Content": "iVBORw==", "ContentType": "image/png", "ContentID": "cid:logo.png"}
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn optional_fields_of_the_message_are_sent() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let sender = SubscriberEmail::parse("news@example.com".into()).unwrap();
+        let email_client = EmailClient::new(
+            vec![provider("primary", mock_server.uri())],
+            sender,
+            Duration::from_secs(5),
+            circuit_breaker(),
+        )
+        .with_message_streams("outbound".into(), "newsletter".into());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let (recipient, cc, bcc, reply_to) = (email(), email(), email(), email());
+
+        // Act
+        let outcome = email_client
+            .send(
+                EmailMessage::new(&recipient, "Subject", "<p>Hi</p>", "Hi")
+                    .from_name("Our Newsletter")
+                    .reply_to(&reply_to)
+                    .cc(&cc)
+                    .bcc(&bcc)
+                    .header("List-Unsubscribe", "<https://example.com/unsubscribe>")
+                    .tag("newsletter")
+                    .metadata("newsletter_issue_id", 42)
+                    .message_stream(MessageStream::Broadcast),
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["From"], "Our Newsletter <news@example.com>");
+        assert_eq!(body["ReplyTo"], reply_to.as_ref());
+        assert_eq!(body["Cc"], cc.as_ref());
+        assert_eq!(body["Bcc"], bcc.as_ref());
+        assert_eq!(
+            body["Headers"],
+            serde_json::json!([{"Name": "List-Unsubscribe", "Value": "<https://example.com/unsubscribe>"}])
+        );
+        assert_eq!(body["Tag"], "newsletter");
+        assert_eq!(
+            body["Metadata"],
+            serde_json::json!({"newsletter_issue_id": "42"})
+        );
+        assert_eq!(body["MessageStream"], "newsletter");
+    }
+
+    #[tokio::test]
+    async fn a_plain_message_goes_to_the_transactional_stream_without_optional_fields() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["MessageStream"], "outbound");
+        for field in ["Cc", "Bcc", "ReplyTo", "Headers", "Tag", "Metadata"] {
+            assert!(body.get(field).is_none(), "{} should be left out", field);
+        }
+    }
+
+    #[test]
+    fn display_names_with_special_characters_are_quoted() {
+        let address = SubscriberEmail::parse("news@example.com".into()).unwrap();
+        let cases = [
+            (None, "news@example.com"),
+            (Some("  "), "news@example.com"),
+            (Some("Our Newsletter"), "Our Newsletter <news@example.com>"),
+            (
+                Some("Le Guin, Ursula"),
+                r#""Le Guin, Ursula" <news@example.com>"#,
+            ),
+            (
+                Some(r#"The "Best" News"#),
+                r#""The \"Best\" News" <news@example.com>"#,
+            ),
+            (
+                Some("Evil\r\nBcc: x@y.z"),
+                r#""EvilBcc: x@y.z" <news@example.com>"#,
+            ),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(super::mailbox(name, &address), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn headers_which_could_inject_other_headers_are_rejected() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        let (recipient, subject, content) = (email(), subject(), content());
+
+        for (name, value) in [
+            ("X-Campaign", "spring\r\nBcc: someone@example.com"),
+            ("X Campaign", "spring"),
+            ("", "spring"),
+        ] {
+            // Act
+            let outcome = email_client
+                .send(
+                    EmailMessage::new(&recipient, &subject, &content, &content).header(name, value),
+                )
+                .await;
+
+            // Assert
+            assert_err!(outcome);
+        }
+    }
+
     #[tokio::test]
     async fn send_email_success_if_the_server_returns_200() {
         // Arrange
@@ -240,4 +835,236 @@ mod tests {
         // Assert
         assert_err!(outcome);
     }
+
+    #[tokio::test]
+    async fn the_email_provider_is_not_called_once_the_circuit_is_open() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            // The 4th email is refused without calling the provider
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..3 {
+            assert_err!(
+                email_client
+                    .send_email(&email(), &subject(), &content(), &content())
+                    .await
+            );
+        }
+        assert_eq!(email_client.circuit_states()[0].1, CircuitState::Open);
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert!(matches!(
+            outcome,
+            Err(BizErrorEnum::CircuitBreakerIsOpen { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn client_errors_do_not_open_the_circuit() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(4)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        for _ in 0..4 {
+            assert_err!(
+                email_client
+                    .send_email(&email(), &subject(), &content(), &content())
+                    .await
+            );
+        }
+
+        // Assert
+        assert_eq!(email_client.circuit_states()[0].1, CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn the_secondary_provider_sends_the_email_when_the_primary_returns_500() {
+        // Arrange
+        let primary = MockServer::start().await;
+        let secondary = MockServer::start().await;
+        let email_client =
+            email_client_with_failover(primary.uri(), secondary.uri(), circuit_breaker());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&secondary)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_eq!(assert_ok!(outcome), "secondary");
+    }
+
+    #[tokio::test]
+    async fn the_secondary_provider_sends_the_email_when_the_primary_times_out() {
+        // Arrange
+        let primary = MockServer::start().await;
+        let secondary = MockServer::start().await;
+        let email_client =
+            email_client_with_failover(primary.uri(), secondary.uri(), circuit_breaker());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(180)))
+            .expect(1)
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&secondary)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_eq!(assert_ok!(outcome), "secondary");
+    }
+
+    #[tokio::test]
+    async fn the_secondary_provider_is_not_tried_when_the_primary_rejects_the_email() {
+        // Arrange
+        let primary = MockServer::start().await;
+        let secondary = MockServer::start().await;
+        let email_client =
+            email_client_with_failover(primary.uri(), secondary.uri(), circuit_breaker());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&secondary)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn emails_go_back_to_the_primary_provider_once_it_recovers() {
+        // Arrange
+        let primary = MockServer::start().await;
+        let secondary = MockServer::start().await;
+        let email_client = email_client_with_failover(
+            primary.uri(),
+            secondary.uri(),
+            CircuitBreakerSettings {
+                failure_threshold: 1,
+                success_threshold: 1,
+                open_milliseconds: 200,
+            },
+        );
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&secondary)
+            .await;
+        let outage = Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount_as_scoped(&primary)
+            .await;
+
+        // Act - Part 1 - The primary fails, its circuit opens
+        for _ in 0..2 {
+            let outcome = email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await;
+            assert_eq!(assert_ok!(outcome), "secondary");
+        }
+        drop(outage);
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&primary)
+            .await;
+
+        // Act - Part 2 - A trial is sent to the primary after the open period
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_eq!(assert_ok!(outcome), "primary");
+        assert_eq!(email_client.circuit_states()[0].1, CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn emails_are_refused_while_every_circuit_is_open() {
+        // Arrange
+        let primary = MockServer::start().await;
+        let secondary = MockServer::start().await;
+        let email_client = email_client_with_failover(
+            primary.uri(),
+            secondary.uri(),
+            CircuitBreakerSettings {
+                failure_threshold: 1,
+                success_threshold: 1,
+                open_milliseconds: 60_000,
+            },
+        );
+        for server in [&primary, &secondary] {
+            Mock::given(any())
+                .respond_with(ResponseTemplate::new(500))
+                .expect(1)
+                .mount(server)
+                .await;
+        }
+        assert_err!(
+            email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await
+        );
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert!(matches!(
+            outcome,
+            Err(BizErrorEnum::CircuitBreakerIsOpen { .. })
+        ));
+        assert!(email_client.unavailable_for().is_some());
+    }
 }
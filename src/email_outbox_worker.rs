@@ -0,0 +1,258 @@
+use crate::configuration::{EmailOutboxSettings, Settings};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::email_template::{EmailTemplateName, TemplateContext};
+use crate::error::BizErrorEnum;
+use crate::issue_delivery_worker::{EmailDelivery, ExecutionOutcome};
+use crate::{email_template, issue_delivery_worker, startup, telemetry};
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+use uuid::Uuid;
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+/// An email written to `email_outbox` in the same transaction as the change it reports.
+///
+/// It is rendered by the worker, with the template as it is when the email is sent.
+pub struct OutboxEmail<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub template_name: EmailTemplateName,
+    pub context: TemplateContext,
+}
+
+#[tracing::instrument(
+    name = "Store email in outbox",
+    skip_all,
+    fields(template_name = email.template_name.as_str())
+)]
+pub async fn enqueue_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: OutboxEmail<'_>,
+) -> Result<Uuid, BizErrorEnum> {
+    let message_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+            INSERT INTO email_outbox (message_id, recipient, template_name, template_context)
+            VALUES ($1, $2, $3, $4)
+        "#,
+        message_id,
+        email.recipient.as_ref(),
+        email.template_name.as_str(),
+        email.context.to_json()?
+    )
+    .execute(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to insert into email_outbox: {:?}", e);
+        BizErrorEnum::InsertEmailOutboxError(e)
+    })?;
+
+    Ok(message_id)
+}
+
+#[tracing::instrument(name = "Run email outbox worker", skip_all)]
+pub async fn run_worker_until_stopped(
+    config: Settings,
+    email_client: EmailClient,
+) -> Result<(), BizErrorEnum> {
+    let connection_pool = startup::get_connection_pool(&config.database);
+
+    worker_loop(connection_pool, email_client, config.email_outbox).await
+}
+
+#[tracing::instrument(name = "Email outbox worker loop", skip_all)]
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    settings: EmailOutboxSettings,
+) -> Result<(), BizErrorEnum> {
+    loop {
+        match try_execute_task(&pool, &email_client, &settings).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmailProviderUnavailable) => {
+                issue_delivery_worker::pause_while_unavailable(&email_client).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+struct OutboxTask {
+    message_id: Uuid,
+    recipient: String,
+    template_name: String,
+    template_context: String,
+    n_retries: i16,
+}
+
+/// Send the oldest due email of the outbox.
+///
+/// A failed delivery is retried later with an exponential backoff,
+/// until `max_retries` is reached.
+#[tracing::instrument(
+    name = "Execute task in email outbox",
+    skip_all,
+    fields(message_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    settings: &EmailOutboxSettings,
+) -> Result<ExecutionOutcome, BizErrorEnum> {
+    // Nothing is sent while the circuit is open, so no retry is used up
+    if email_client.unavailable_for().is_some() {
+        return Ok(ExecutionOutcome::EmailProviderUnavailable);
+    }
+    let (mut transaction, task) = match dequeue_task(pool).await? {
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+        Some(task) => task,
+    };
+    telemetry::record_field("message_id", task.message_id);
+
+    let recipient = match SubscriberEmail::parse(task.recipient.clone()) {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Dropping an outbox email. Its recipient is invalid."
+            );
+            delete_task(transaction, task.message_id).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    let (template_name, context) = match (
+        EmailTemplateName::parse(&task.template_name),
+        TemplateContext::from_json(&task.template_context),
+    ) {
+        (Ok(template_name), Ok(context)) => (template_name, context),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Dropping an outbox email. Its template cannot be rendered."
+            );
+            delete_task(transaction, task.message_id).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    let email = email_template::get_email_template(&mut transaction, template_name)
+        .await?
+        .render(&context);
+
+    match email_client
+        .send_email(
+            &recipient,
+            &email.subject,
+            &email.html_body,
+            &email.text_body,
+        )
+        .await
+    {
+        Ok(provider) => {
+            let delivery = EmailDelivery {
+                recipient: recipient.as_ref(),
+                newsletter_issue_id: None,
+                outbox_message_id: Some(task.message_id),
+                provider,
+            };
+            issue_delivery_worker::record_delivery(&mut transaction, delivery).await;
+            delete_task(transaction, task.message_id).await?
+        }
+        // Another sender opened the circuit meanwhile, the task is rolled back for later
+        Err(BizErrorEnum::CircuitBreakerIsOpen { .. }) => {
+            return Ok(ExecutionOutcome::EmailProviderUnavailable)
+        }
+        Err(e) if task.n_retries + 1 >= settings.max_retries => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                n_retries = task.n_retries,
+                "Failed to deliver an outbox email. Giving up."
+            );
+            delete_task(transaction, task.message_id).await?;
+        }
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                n_retries = task.n_retries,
+                "Failed to deliver an outbox email. Retrying later."
+            );
+            let backoff = settings.retry_backoff(task.n_retries);
+            postpone_task(transaction, task.message_id, backoff).await?;
+        }
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[tracing::instrument(name = "Dequeue email outbox task", skip_all)]
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, OutboxTask)>, BizErrorEnum> {
+    let mut transaction = pool.begin().await.map_err(BizErrorEnum::PgPoolError)?;
+
+    let task = sqlx::query_as!(
+        OutboxTask,
+        r#"
+            SELECT message_id, recipient, template_name, template_context, n_retries
+            FROM email_outbox
+            WHERE execute_after <= now()
+            ORDER BY execute_after
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .map_err(BizErrorEnum::QueryEmailOutboxError)?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(name = "Postpone email outbox task", skip(transaction))]
+async fn postpone_task(
+    mut transaction: PgTransaction,
+    message_id: Uuid,
+    backoff: Duration,
+) -> Result<(), BizErrorEnum> {
+    let execute_after =
+        Utc::now() + chrono::Duration::from_std(backoff).unwrap_or(chrono::Duration::max_value());
+    sqlx::query!(
+        r#"
+            UPDATE email_outbox
+            SET n_retries = n_retries + 1, execute_after = $2
+            WHERE message_id = $1
+        "#,
+        message_id,
+        execute_after
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(BizErrorEnum::UpdateEmailOutboxError)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(BizErrorEnum::TransactionCommitError)
+}
+
+#[tracing::instrument(name = "Delete email outbox task", skip(transaction))]
+async fn delete_task(mut transaction: PgTransaction, message_id: Uuid) -> Result<(), BizErrorEnum> {
+    sqlx::query!("DELETE FROM email_outbox WHERE message_id = $1", message_id)
+        .execute(&mut transaction)
+        .await
+        .map_err(BizErrorEnum::DeleteEmailOutboxError)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(BizErrorEnum::TransactionCommitError)
+}
@@ -0,0 +1,447 @@
+use crate::error::BizErrorEnum;
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool};
+use std::collections::HashMap;
+
+/// The emails whose wording can be edited from the admin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTemplateName {
+    Confirmation,
+    Welcome,
+    PasswordReset,
+    Newsletter,
+}
+
+impl EmailTemplateName {
+    pub const ALL: [EmailTemplateName; 4] = [
+        EmailTemplateName::Confirmation,
+        EmailTemplateName::Welcome,
+        EmailTemplateName::PasswordReset,
+        EmailTemplateName::Newsletter,
+    ];
+
+    pub fn parse(name: &str) -> Result<Self, BizErrorEnum> {
+        Self::ALL
+            .into_iter()
+            .find(|template_name| template_name.as_str() == name)
+            .ok_or_else(|| BizErrorEnum::EmailTemplateNameIsUnknown(name.to_string()))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailTemplateName::Confirmation => "confirmation",
+            EmailTemplateName::Welcome => "welcome",
+            EmailTemplateName::PasswordReset => "password_reset",
+            EmailTemplateName::Newsletter => "newsletter",
+        }
+    }
+
+    /// The placeholders the template can use, e.g. `{{subscriber.name}}`.
+    pub fn placeholders(&self) -> &'static [&'static str] {
+        match self {
+            EmailTemplateName::Confirmation => {
+                &["subscriber.name", "subscriber.email", "confirmation_link"]
+            }
+            EmailTemplateName::Welcome => {
+                &["subscriber.name", "subscriber.email", "unsubscribe_url"]
+            }
+            EmailTemplateName::PasswordReset => &["user.username", "reset_link"],
+            EmailTemplateName::Newsletter => &[
+                "subscriber.name",
+                "subscriber.email",
+                "subscriber.subscribed_at",
+                "unsubscribe_url",
+                "newsletter.title",
+                "newsletter.content",
+            ],
+        }
+    }
+}
+
+/// The merge fields an author can use in the title and the content of a newsletter issue.
+pub const NEWSLETTER_ISSUE_MERGE_FIELDS: [&str; 3] = [
+    "subscriber.name",
+    "subscriber.email",
+    "subscriber.subscribed_at",
+];
+
+#[derive(Debug)]
+pub struct EmailTemplate {
+    pub name: EmailTemplateName,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+/// An email ready to be sent to one recipient.
+#[derive(Debug)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum TemplateValue {
+    /// Escaped when rendered into the HTML body, attribute values must be quoted.
+    Text(String),
+    /// A block of content which comes in both formats, already safe to embed as HTML.
+    Content { html: String, text: String },
+}
+
+/// The values of the placeholders, for one recipient.
+///
+/// Stored as JSON with the emails of the outbox, which are rendered when they are sent.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TemplateContext {
+    values: HashMap<String, TemplateValue>,
+}
+
+impl TemplateContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(mut self, placeholder: &'static str, value: impl Into<String>) -> Self {
+        self.values
+            .insert(placeholder.to_string(), TemplateValue::Text(value.into()));
+        self
+    }
+
+    pub fn insert_content(
+        mut self,
+        placeholder: &'static str,
+        html: impl Into<String>,
+        text: impl Into<String>,
+    ) -> Self {
+        self.values.insert(
+            placeholder.to_string(),
+            TemplateValue::Content {
+                html: html.into(),
+                text: text.into(),
+            },
+        );
+        self
+    }
+
+    pub fn to_json(&self) -> Result<String, BizErrorEnum> {
+        serde_json::to_string(self).map_err(BizErrorEnum::SerializeTemplateContextError)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, BizErrorEnum> {
+        serde_json::from_str(json).map_err(BizErrorEnum::DeserializeTemplateContextError)
+    }
+
+    /// Fill in the placeholders of an HTML source, values are escaped.
+    pub fn render_html(&self, source: &str) -> String {
+        substitute(
+            source,
+            |placeholder| {
+                self.values.get(placeholder).map(|value| match value {
+                    TemplateValue::Text(text) => htmlescape::encode_minimal(text),
+                    TemplateValue::Content { html, .. } => html.clone(),
+                })
+            },
+            htmlescape::encode_minimal,
+        )
+    }
+
+    /// Fill in the placeholders of a plain text source, e.g. a subject.
+    pub fn render_text(&self, source: &str) -> String {
+        substitute(
+            source,
+            |placeholder| {
+                self.values.get(placeholder).map(|value| match value {
+                    TemplateValue::Text(text) => text.clone(),
+                    TemplateValue::Content { text, .. } => text.clone(),
+                })
+            },
+            str::to_string,
+        )
+    }
+}
+
+impl EmailTemplate {
+    pub fn render(&self, context: &TemplateContext) -> RenderedEmail {
+        RenderedEmail {
+            subject: context.render_text(&self.subject),
+            html_body: context.render_html(&self.html_body),
+            text_body: context.render_text(&self.text_body),
+        }
+    }
+
+    /// Reject blank templates and placeholders which would never be filled in.
+    pub fn validate(&self) -> Result<(), BizErrorEnum> {
+        if utils::is_blank(&self.subject) {
+            return Err(BizErrorEnum::EmailTemplateSubjectIsEmpty);
+        }
+        if utils::is_blank(&self.html_body) || utils::is_blank(&self.text_body) {
+            return Err(BizErrorEnum::EmailTemplateBodyIsEmpty);
+        }
+        let known = self.name.placeholders();
+        for source in [&self.subject, &self.html_body, &self.text_body] {
+            if let Some(unknown) = find_unknown_placeholder(source, known) {
+                return Err(BizErrorEnum::EmailTemplatePlaceholderIsUnknown(
+                    unknown.to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A `{{name}}` or `{{name | fallback}}` placeholder, the fallback is used when the value is blank.
+struct Placeholder<'a> {
+    name: &'a str,
+    fallback: Option<&'a str>,
+}
+
+/// Every `{{placeholder}}` of `source` by name, in order of appearance.
+pub fn placeholders_in(source: &str) -> Vec<&str> {
+    let mut placeholders = vec![];
+    let mut rest = source;
+    while let Some(((_, placeholder, _), after)) = next_placeholder(rest) {
+        placeholders.push(placeholder.name);
+        rest = after;
+    }
+    placeholders
+}
+
+/// The first placeholder of `source` which is not one of `known`.
+pub fn find_unknown_placeholder<'a>(source: &'a str, known: &[&str]) -> Option<&'a str> {
+    placeholders_in(source)
+        .into_iter()
+        .find(|placeholder| !known.contains(placeholder))
+}
+
+/// Replace every `{{placeholder}}` of `source` by its value, unknown ones are left as they are.
+fn substitute(
+    source: &str,
+    value_of: impl Fn(&str) -> Option<String>,
+    escape_fallback: impl Fn(&str) -> String,
+) -> String {
+    let mut rendered = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(((start, placeholder, end), after)) = next_placeholder(rest) {
+        rendered.push_str(&rest[..start]);
+        match (value_of(placeholder.name), placeholder.fallback) {
+            (Some(value), Some(fallback)) if utils::is_blank(&value) => {
+                rendered.push_str(&escape_fallback(fallback))
+            }
+            (Some(value), _) => rendered.push_str(&value),
+            (None, Some(fallback)) => rendered.push_str(&escape_fallback(fallback)),
+            (None, None) => rendered.push_str(&rest[start..end]),
+        }
+        rest = after;
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// The next placeholder with its `(start, end)` bounds, and what follows it.
+fn next_placeholder(source: &str) -> Option<((usize, Placeholder<'_>, usize), &str)> {
+    let start = source.find("{{")?;
+    let end = start + 2 + source[start + 2..].find("}}")? + 2;
+    let inner = &source[start + 2..end - 2];
+    let placeholder = match inner.split_once('|') {
+        None => Placeholder {
+            name: inner.trim(),
+            fallback: None,
+        },
+        Some((name, fallback)) => {
+            let fallback = fallback.trim();
+            Placeholder {
+                name: name.trim(),
+                fallback: Some(
+                    fallback
+                        .strip_prefix('"')
+                        .and_then(|f| f.strip_suffix('"'))
+                        .unwrap_or(fallback),
+                ),
+            }
+        }
+    };
+    Some(((start, placeholder, end), &source[end..]))
+}
+
+#[tracing::instrument(name = "Query email template", skip(executor))]
+pub async fn get_email_template(
+    executor: impl PgExecutor<'_>,
+    name: EmailTemplateName,
+) -> Result<EmailTemplate, BizErrorEnum> {
+    let record = sqlx::query!(
+        r#"
+        SELECT subject, html_body, text_body
+        FROM email_templates
+        WHERE template_name = $1
+    "#,
+        name.as_str()
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to query email_templates: {:?}", e);
+        BizErrorEnum::QueryEmailTemplatesError(e)
+    })?;
+
+    Ok(EmailTemplate {
+        name,
+        subject: record.subject,
+        html_body: record.html_body,
+        text_body: record.text_body,
+    })
+}
+
+#[tracing::instrument(name = "Query all email templates", skip(pool))]
+pub async fn get_email_templates(pool: &PgPool) -> Result<Vec<EmailTemplate>, BizErrorEnum> {
+    let mut templates = Vec::with_capacity(EmailTemplateName::ALL.len());
+    for name in EmailTemplateName::ALL {
+        templates.push(get_email_template(pool, name).await?);
+    }
+    Ok(templates)
+}
+
+#[tracing::instrument(name = "Update email template", skip(template, pool), fields(template_name = template.name.as_str()))]
+pub async fn update_email_template(
+    template: &EmailTemplate,
+    pool: &PgPool,
+) -> Result<(), BizErrorEnum> {
+    sqlx::query!(
+        r#"
+        UPDATE email_templates
+        SET subject = $2, html_body = $3, text_body = $4, updated_at = now()
+        WHERE template_name = $1
+    "#,
+        template.name.as_str(),
+        template.subject,
+        template.html_body,
+        template.text_body
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to update email_templates: {:?}", e);
+        BizErrorEnum::UpdateEmailTemplatesError(e)
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{placeholders_in, EmailTemplate, EmailTemplateName, TemplateContext};
+    use claims::{assert_err, assert_ok};
+
+    fn template(subject: &str, html_body: &str, text_body: &str) -> EmailTemplate {
+        EmailTemplate {
+            name: EmailTemplateName::Newsletter,
+            subject: subject.into(),
+            html_body: html_body.into(),
+            text_body: text_body.into(),
+        }
+    }
+
+    #[test]
+    fn placeholders_are_replaced_by_their_values() {
+        let template = template(
+            "{{newsletter.title}}",
+            "<p>Hi {{ subscriber.name }}</p>{{newsletter.content}}",
+            "Hi {{subscriber.name}}\n{{newsletter.content}}",
+        );
+        let context = TemplateContext::new()
+            .insert("newsletter.title", "Issue #1")
+            .insert("subscriber.name", "Ursula")
+            .insert_content("newsletter.content", "<b>News</b>", "News");
+
+        let email = template.render(&context);
+
+        assert_eq!(email.subject, "Issue #1");
+        assert_eq!(email.html_body, "<p>Hi Ursula</p><b>News</b>");
+        assert_eq!(email.text_body, "Hi Ursula\nNews");
+    }
+
+    #[test]
+    fn values_are_escaped_in_the_html_body_only() {
+        let template = template(
+            "{{subscriber.name}}",
+            "{{subscriber.name}}",
+            "{{subscriber.name}}",
+        );
+        let context = TemplateContext::new().insert("subscriber.name", "<script>\"x\"</script>");
+
+        let email = template.render(&context);
+
+        assert_eq!(email.subject, "<script>\"x\"</script>");
+        assert!(!email.html_body.contains('<'));
+        assert!(!email.html_body.contains('"'));
+        assert_eq!(email.text_body, "<script>\"x\"</script>");
+    }
+
+    #[test]
+    fn a_context_renders_the_same_once_stored() {
+        let template = template(
+            "{{subscriber.name}}",
+            "{{newsletter.content}}",
+            "{{newsletter.content}}",
+        );
+        let context = TemplateContext::new()
+            .insert("subscriber.name", "Ursula")
+            .insert_content("newsletter.content", "<b>News</b>", "News");
+
+        let stored = TemplateContext::from_json(&context.to_json().unwrap()).unwrap();
+
+        let email = template.render(&stored);
+        assert_eq!(email.subject, "Ursula");
+        assert_eq!(email.html_body, "<b>News</b>");
+        assert_eq!(email.text_body, "News");
+    }
+
+    #[test]
+    fn placeholders_without_a_value_are_left_as_they_are() {
+        let template = template("{{newsletter.title}}", "{{ unknown }} {{", "}} {{x");
+
+        let email = template.render(&TemplateContext::new());
+
+        assert_eq!(email.subject, "{{newsletter.title}}");
+        assert_eq!(email.html_body, "{{ unknown }} {{");
+        assert_eq!(email.text_body, "}} {{x");
+    }
+
+    #[test]
+    fn fallbacks_are_used_for_blank_or_missing_values() {
+        let context = TemplateContext::new().insert("subscriber.name", " ");
+
+        assert_eq!(
+            context.render_text(r#"Hi {{ subscriber.name | "reader" }}"#),
+            "Hi reader"
+        );
+        assert_eq!(
+            context.render_html("Hi {{subscriber.name|you & me}}"),
+            "Hi you &amp; me"
+        );
+        assert_eq!(context.render_text("Hi {{subscriber.name}}!"), "Hi  !");
+        assert_eq!(
+            context.render_text("since {{subscriber.subscribed_at | ever}}"),
+            "since ever"
+        );
+    }
+
+    #[test]
+    fn placeholders_are_listed_in_order() {
+        assert_eq!(
+            placeholders_in("{{a}} and {{ b.c }}, {{a | \"x\"}}"),
+            vec!["a", "b.c", "a"]
+        );
+        assert!(placeholders_in("no placeholder {{ here").is_empty());
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        assert_ok!(template("{{newsletter.title}}", "{{unsubscribe_url}}", "text").validate());
+        assert_err!(template("{{newsletter.title}}", "{{reset_link}}", "text").validate());
+        assert_err!(template(" ", "html", "text").validate());
+        assert_err!(template("subject", "html", "").validate());
+    }
+}
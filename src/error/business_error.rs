@@ -41,6 +41,96 @@ pub enum BizErrorEnum {
     #[error("Newsletter's content is empty.")]
     NewsletterContentIsEmpty,
 
+    #[error("The newsletter issue uses an unknown merge field: {{{{{field}}}}}. Available merge fields: {available}.")]
+    NewsletterMergeFieldIsUnknown { field: String, available: String },
+
+    #[error("The sender name is too long, it must not exceed {max} characters.")]
+    SenderNameIsTooLong { max: usize },
+
+    #[error("The sender name contains an illegal character.")]
+    SenderNameContainsIllegalCharacter,
+
+    #[error("The reply-to address {0} is not a valid email address.")]
+    NewsletterReplyToIsInvalid(String),
+
+    #[error("You are not allowed to publish raw HTML, leave \"Trusted raw HTML\" unchecked to have it sanitized.")]
+    RawHtmlIsNotAllowed,
+
+    // VALIDATE NEWSLETTER'S ATTACHMENTS
+    #[error("An attachment has no file name.")]
+    AttachmentFileNameIsEmpty,
+
+    #[error("Two attachments are named {0}, file names must be unique.")]
+    AttachmentFileNameIsDuplicated(String),
+
+    #[error("{file_name} has the type {content_type}, allowed types: {allowed}.")]
+    AttachmentTypeIsNotAllowed {
+        file_name: String,
+        content_type: String,
+        allowed: String,
+    },
+
+    #[error("{file_name} is not a valid {content_type} file.")]
+    AttachmentContentDoesNotMatchType {
+        file_name: String,
+        content_type: String,
+    },
+
+    #[error("{file_name} is too large, attachments must not exceed {max_kib} KiB.")]
+    AttachmentIsTooLarge { file_name: String, max_kib: usize },
+
+    #[error("The attachments are too large, together they must not exceed {max_kib} KiB.")]
+    AttachmentsAreTooLarge { max_kib: usize },
+
+    #[error("The publish form could not be read.")]
+    NewsletterFormIsInvalid(#[source] anyhow::Error),
+
+    // VALIDATE NEW PASSWORD
+    #[error("The length of new password must >= {min} && <= {max} characters.")]
+    NewPasswordLengthIsInvalid { min: usize, max: usize },
+
+    #[error("The new password must be different from the current password.")]
+    NewPasswordEqualsCurrentPassword,
+
+    #[error("The new password must not contain your username.")]
+    NewPasswordContainsUsername,
+
+    #[error("The new password has appeared in a data breach, please choose another one.")]
+    NewPasswordIsBreached,
+
+    #[error("The new password is too easy to guess, please choose a stronger one.")]
+    NewPasswordIsTooWeak,
+
+    // VALIDATE EMAIL TEMPLATE
+    #[error("There is no email template named '{0}'.")]
+    EmailTemplateNameIsUnknown(String),
+
+    #[error("The subject of the email template is empty.")]
+    EmailTemplateSubjectIsEmpty,
+
+    #[error("Both the HTML and the plain text bodies of the email template are required.")]
+    EmailTemplateBodyIsEmpty,
+
+    #[error("The email template uses an unknown placeholder: {{{{{0}}}}}.")]
+    EmailTemplatePlaceholderIsUnknown(String),
+
+    // VALIDATE API KEY
+    #[error("The name of the API key is empty.")]
+    ApiKeyNameIsEmpty,
+
+    #[error("The name of the API key is too long, it must not exceed {max} characters.")]
+    ApiKeyNameIsTooLong { max: usize },
+
+    // VALIDATE WEBHOOK
+    #[error("The URL of the webhook must be an absolute http or https URL.")]
+    WebhookUrlIsInvalid,
+
+    #[error("The URL of the webhook must not point to a private network.")]
+    WebhookUrlIsPrivate,
+
+    #[error("Choose at least one event for the webhook.")]
+    WebhookEventTypesAreEmpty,
+
     // VALIDATE URL
     #[error("Url is incorrect.")]
     ParseUrlError,
@@ -58,6 +148,14 @@ pub enum BizErrorEnum {
     #[error("The idempotency key is too long, length should >= 10 && <= 50.")]
     IdempotencyKeyIsTooLong,
 
+    #[error(
+        "A request with the same idempotency key is still in flight, retry in {retry_after:?}."
+    )]
+    IdempotencyKeyIsInFlight { retry_after: std::time::Duration },
+
+    #[error("The idempotency key was already used with a different request.")]
+    IdempotencyKeyIsReused,
+
     // VALIDATE AUTH
     #[error("The 'Authorization' header was missing.")]
     AuthorizationHeaderIsMissing,
@@ -86,19 +184,67 @@ pub enum BizErrorEnum {
     #[error("Invalid password.")]
     InvalidPassword(#[source] password_hash::Error),
 
+    #[error("Invalid username or password.")]
+    InvalidCredentials,
+
+    #[error("An API key must be provided in the 'Authorization: Bearer' header.")]
+    ApiKeyIsMissing,
+
+    #[error("The API key is invalid or has been revoked.")]
+    ApiKeyIsInvalid,
+
     #[error("The user has not logged in")]
     UserNotLoggedIn,
 
+    #[error("The form has expired or was not issued by us, please reload the page and try again.")]
+    InvalidCsrfToken,
+
     #[error("Failed to extract session from HttpRequest")]
     SessionExtractError,
 
     #[error("Failed to call next service")]
     ServiceCallError,
 
+    #[error("Failed to find {0} in the application data")]
+    AppDataNotFound(&'static str),
+
+    #[error("Failed to build the {0} header value")]
+    InvalidHeaderValue(
+        String,
+        #[source] actix_web::http::header::InvalidHeaderValue,
+    ),
+
     // VALIDATE DATABASE ACCESS
     #[error("Subscription_token is invalid.")]
     SubscriptionTokenInvalidError,
 
+    #[error("The subscription form token is malformed.")]
+    SubscribeFormTokenIsMalformed,
+
+    #[error("The unsubscribe link is invalid.")]
+    UnsubscribeLinkIsInvalid,
+
+    #[error("The tracking link is invalid.")]
+    TrackingLinkIsInvalid,
+
+    #[error("The newsletter issue does not exist.")]
+    NewsletterIssueNotFound,
+
+    #[error("The subscriber does not exist.")]
+    SubscriberNotFound,
+
+    #[error("A subscriber with this email address already exists.")]
+    SubscriberAlreadyExists,
+
+    #[error("There is no such API endpoint.")]
+    ApiEndpointNotFound,
+
+    #[error("The API only responds with application/json.")]
+    ApiResponseIsNotAcceptable,
+
+    #[error("The OpenAPI viewer is disabled.")]
+    OpenApiViewerIsDisabled,
+
     #[error("Failed to acquire a Postgres connection from the pool.")]
     PgPoolError(#[source] sqlx::Error),
 
@@ -114,26 +260,89 @@ pub enum BizErrorEnum {
     #[error("Failed to query subscriptions.")]
     QuerySubscriptionsError(#[source] sqlx::Error),
 
+    #[error("Failed to delete subscriptions.")]
+    DeleteSubscriptionsError(#[source] sqlx::Error),
+
     #[error("Failed to insert subscription_tokens.")]
     InsertSubscriptionTokensError(#[source] sqlx::Error),
 
     #[error("Failed to query subscription_tokens")]
     QuerySubscriptionTokensError(#[source] sqlx::Error),
 
+    #[error("Failed to delete subscription_tokens")]
+    DeleteSubscriptionTokensError(#[source] sqlx::Error),
+
+    #[error("Failed to insert subscription_attempts.")]
+    InsertSubscriptionAttemptsError(#[source] sqlx::Error),
+
+    #[error("Failed to query subscription_attempts")]
+    QuerySubscriptionAttemptsError(#[source] sqlx::Error),
+
+    #[error("Failed to delete subscription_attempts")]
+    DeleteSubscriptionAttemptsError(#[source] sqlx::Error),
+
     #[error("Failed to query users.")]
     QueryUsersError(#[source] sqlx::Error),
 
     #[error("Failed to update users.")]
     UpdateUsersError(#[source] sqlx::Error),
 
+    #[error("Failed to insert user_sessions.")]
+    InsertUserSessionsError(#[source] sqlx::Error),
+
+    #[error("Failed to query user_sessions.")]
+    QueryUserSessionsError(#[source] sqlx::Error),
+
+    #[error("Failed to update user_sessions.")]
+    UpdateUserSessionsError(#[source] sqlx::Error),
+
+    #[error("Failed to insert api_keys.")]
+    InsertApiKeysError(#[source] sqlx::Error),
+
+    #[error("Failed to query api_keys.")]
+    QueryApiKeysError(#[source] sqlx::Error),
+
+    #[error("Failed to update api_keys.")]
+    UpdateApiKeysError(#[source] sqlx::Error),
+
+    #[error("Failed to insert webhook_endpoints.")]
+    InsertWebhookEndpointsError(#[source] sqlx::Error),
+
+    #[error("Failed to query webhook_endpoints.")]
+    QueryWebhookEndpointsError(#[source] sqlx::Error),
+
+    #[error("Failed to update webhook_endpoints.")]
+    UpdateWebhookEndpointsError(#[source] sqlx::Error),
+
+    #[error("Failed to insert webhook_events.")]
+    InsertWebhookEventsError(#[source] sqlx::Error),
+
+    #[error("Failed to insert webhook_delivery_queue.")]
+    InsertWebhookDeliveryQueueError(#[source] sqlx::Error),
+
+    #[error("Failed to query webhook_delivery_queue.")]
+    QueryWebhookDeliveryQueueError(#[source] sqlx::Error),
+
+    #[error("Failed to update webhook_delivery_queue.")]
+    UpdateWebhookDeliveryQueueError(#[source] sqlx::Error),
+
+    #[error("Failed to delete record from webhook_delivery_queue.")]
+    DeleteWebhookDeliveryQueueError(#[source] sqlx::Error),
+
+    #[error("Failed to insert webhook_deliveries.")]
+    InsertWebhookDeliveriesError(#[source] sqlx::Error),
+
+    #[error("Failed to query webhook_deliveries.")]
+    QueryWebhookDeliveriesError(#[source] sqlx::Error),
+
     #[error("Failed to query idempotency.")]
     QueryIdempotencyError(#[source] sqlx::Error),
 
     #[error("Failed to insert idempotency.")]
     InsertIdempotencyError(#[source] sqlx::Error),
 
-    #[error("We expected a saved record, we didn't find it")]
-    FindExpectedSavedRecordError,
+    #[error("Failed to delete expired idempotency.")]
+    DeleteIdempotencyError(#[source] sqlx::Error),
 
     #[error("Failed to insert newsletter_issues.")]
     InsertNewsletterIssuesError(#[source] sqlx::Error),
@@ -141,6 +350,21 @@ pub enum BizErrorEnum {
     #[error("Failed to query newsletter_issues.")]
     QueryNewsletterIssuesError(#[source] sqlx::Error),
 
+    #[error("Failed to insert newsletter_issue_attachments.")]
+    InsertNewsletterIssueAttachmentsError(#[source] sqlx::Error),
+
+    #[error("Failed to query newsletter_issue_attachments.")]
+    QueryNewsletterIssueAttachmentsError(#[source] sqlx::Error),
+
+    #[error("Failed to update newsletter_issues.")]
+    UpdateNewsletterIssuesError(#[source] sqlx::Error),
+
+    #[error("Failed to insert issue_engagement_events.")]
+    InsertIssueEngagementEventsError(#[source] sqlx::Error),
+
+    #[error("Failed to query issue_engagement_events.")]
+    QueryIssueEngagementEventsError(#[source] sqlx::Error),
+
     #[error("Failed to insert issue_delivery_queue.")]
     InsertIssueDeliveryQueueError(#[source] sqlx::Error),
 
@@ -150,10 +374,52 @@ pub enum BizErrorEnum {
     #[error("Failed to delete record from issue_delivery_queue.")]
     DeleteIssueDeliveryQueueError(#[source] sqlx::Error),
 
+    #[error("Failed to insert email_outbox.")]
+    InsertEmailOutboxError(#[source] sqlx::Error),
+
+    #[error("Failed to query email_outbox.")]
+    QueryEmailOutboxError(#[source] sqlx::Error),
+
+    #[error("Failed to update email_outbox.")]
+    UpdateEmailOutboxError(#[source] sqlx::Error),
+
+    #[error("Failed to delete record from email_outbox.")]
+    DeleteEmailOutboxError(#[source] sqlx::Error),
+
+    #[error("Failed to insert email_deliveries.")]
+    InsertEmailDeliveriesError(#[source] sqlx::Error),
+
+    #[error("Failed to query email_deliveries.")]
+    QueryEmailDeliveriesError(#[source] sqlx::Error),
+
+    #[error("Failed to query email_templates.")]
+    QueryEmailTemplatesError(#[source] sqlx::Error),
+
+    #[error("Failed to update email_templates.")]
+    UpdateEmailTemplatesError(#[source] sqlx::Error),
+
     // OTHER
     #[error("Failed to send a confirmation email.")]
     SendEmailError(#[from] reqwest::Error),
 
+    #[error("The {name} circuit breaker is open, retry in {retry_after:?}.")]
+    CircuitBreakerIsOpen {
+        name: String,
+        retry_after: std::time::Duration,
+    },
+
+    #[error("Failed to serialize the payload of a webhook event.")]
+    SerializeWebhookPayloadError(#[source] serde_json::Error),
+
+    #[error("Failed to serialize the placeholders of an email template.")]
+    SerializeTemplateContextError(#[source] serde_json::Error),
+
+    #[error("Failed to deserialize the placeholders of an email template.")]
+    DeserializeTemplateContextError(#[source] serde_json::Error),
+
+    #[error("The email header '{0}' is invalid.")]
+    EmailHeaderIsInvalid(String),
+
     #[error("Failed to bind TcpListener.")]
     BindTcpListenerError(#[source] std::io::Error),
 
@@ -217,6 +483,9 @@ pub enum BizErrorEnum {
 
     #[error("Failed to hash password")]
     Argon2HashPasswordError(#[source] password_hash::Error),
+
+    #[error("Invalid argon2 parameters.")]
+    Argon2ParamsError(#[source] argon2::Error),
 }
 
 impl Debug for BizErrorEnum {
@@ -269,9 +538,24 @@ impl ResponseError for BizErrorEnum {
             | BizErrorEnum::SubscriberEmailFormatIsIncorrect
             | BizErrorEnum::NewsletterTitleIsEmpty
             | BizErrorEnum::NewsletterContentIsEmpty
+            | BizErrorEnum::NewsletterMergeFieldIsUnknown { .. }
+            | BizErrorEnum::SenderNameIsTooLong { .. }
+            | BizErrorEnum::SenderNameContainsIllegalCharacter
+            | BizErrorEnum::NewsletterReplyToIsInvalid(_)
+            | BizErrorEnum::ApiKeyNameIsEmpty
+            | BizErrorEnum::ApiKeyNameIsTooLong { .. }
+            | BizErrorEnum::WebhookUrlIsInvalid
+            | BizErrorEnum::WebhookUrlIsPrivate
+            | BizErrorEnum::WebhookEventTypesAreEmpty
             | BizErrorEnum::IdempotencyKeyIsBlank
             | BizErrorEnum::IdempotencyKeyIsTooShort
-            | BizErrorEnum::IdempotencyKeyIsTooLong => HttpResponse::new(StatusCode::BAD_REQUEST),
+            | BizErrorEnum::IdempotencyKeyIsTooLong
+            | BizErrorEnum::EmailTemplateNameIsUnknown(_)
+            | BizErrorEnum::UnsubscribeLinkIsInvalid
+            | BizErrorEnum::TrackingLinkIsInvalid
+            | BizErrorEnum::NewsletterFormIsInvalid(_) => {
+                HttpResponse::new(StatusCode::BAD_REQUEST)
+            }
 
             BizErrorEnum::AuthorizationHeaderIsMissing
             | BizErrorEnum::AuthorizationHeaderIsInvalidUtf8String(_)
@@ -282,6 +566,7 @@ impl ResponseError for BizErrorEnum {
             | BizErrorEnum::CredentialMissingPassword
             | BizErrorEnum::InvalidUsername
             | BizErrorEnum::InvalidPassword(_)
+            | BizErrorEnum::InvalidCredentials
             | BizErrorEnum::ActixSessionInsertError(_) => {
                 let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
                 let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
@@ -295,6 +580,39 @@ impl ResponseError for BizErrorEnum {
                 response
             }
 
+            BizErrorEnum::ApiKeyIsMissing | BizErrorEnum::ApiKeyIsInvalid => {
+                HttpResponse::Unauthorized()
+                    .insert_header((actix_web::http::header::WWW_AUTHENTICATE, "Bearer"))
+                    .finish()
+            }
+
+            BizErrorEnum::InvalidCsrfToken | BizErrorEnum::RawHtmlIsNotAllowed => {
+                HttpResponse::new(StatusCode::FORBIDDEN)
+            }
+
+            BizErrorEnum::ApiResponseIsNotAcceptable => {
+                HttpResponse::new(StatusCode::NOT_ACCEPTABLE)
+            }
+
+            BizErrorEnum::SubscriberAlreadyExists => HttpResponse::new(StatusCode::CONFLICT),
+
+            BizErrorEnum::IdempotencyKeyIsReused => {
+                HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY)
+            }
+
+            BizErrorEnum::IdempotencyKeyIsInFlight { retry_after } => {
+                // Whole seconds, rounded up so that the retry does not come too early
+                let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                HttpResponse::Conflict()
+                    .insert_header((actix_web::http::header::RETRY_AFTER, seconds.max(1)))
+                    .finish()
+            }
+
+            BizErrorEnum::NewsletterIssueNotFound
+            | BizErrorEnum::SubscriberNotFound
+            | BizErrorEnum::ApiEndpointNotFound
+            | BizErrorEnum::OpenApiViewerIsDisabled => HttpResponse::new(StatusCode::NOT_FOUND),
+
             _ => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
         }
     }
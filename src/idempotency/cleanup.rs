@@ -0,0 +1,62 @@
+use crate::configuration::{IdempotencySettings, Settings};
+use crate::error::BizErrorEnum;
+use crate::startup;
+use sqlx::PgPool;
+
+#[tracing::instrument(name = "Run idempotency cleanup", skip_all)]
+pub async fn run_cleanup_until_stopped(config: Settings) -> Result<(), BizErrorEnum> {
+    let connection_pool = startup::get_connection_pool(&config.database);
+
+    cleanup_loop(connection_pool, config.idempotency).await
+}
+
+#[tracing::instrument(name = "Idempotency cleanup loop", skip_all)]
+async fn cleanup_loop(pool: PgPool, settings: IdempotencySettings) -> Result<(), BizErrorEnum> {
+    loop {
+        // A failed run is retried at the next tick, expired records do no harm meanwhile
+        let _ = delete_expired_records(&pool, &settings).await;
+        tokio::time::sleep(settings.cleanup_interval()).await;
+    }
+}
+
+/// Delete the records past their retention window, one batch at a time.
+///
+/// Returns how many records were deleted.
+#[tracing::instrument(name = "Delete expired idempotency records", skip_all)]
+pub async fn delete_expired_records(
+    pool: &PgPool,
+    settings: &IdempotencySettings,
+) -> Result<u64, BizErrorEnum> {
+    let mut n_deleted = 0;
+    loop {
+        // A record locked by a request in flight is left for the next run
+        let n_batch = sqlx::query!(
+            r#"
+                DELETE FROM idempotency
+                WHERE (scope, idempotency_key) IN (
+                    SELECT scope, idempotency_key
+                    FROM idempotency
+                    WHERE expires_at < now()
+                    LIMIT $1
+                    FOR UPDATE
+                    SKIP LOCKED
+                )
+            "#,
+            settings.cleanup_batch_size
+        )
+        .execute(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to delete expired idempotency records: {:?}", e);
+            BizErrorEnum::DeleteIdempotencyError(e)
+        })?
+        .rows_affected();
+
+        n_deleted += n_batch;
+        if n_batch < settings.cleanup_batch_size.max(1) as u64 {
+            break;
+        }
+    }
+    tracing::info!(n_deleted, "Deleted expired idempotency records.");
+    Ok(n_deleted)
+}
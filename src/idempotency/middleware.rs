@@ -0,0 +1,297 @@
+use crate::auth::{ApiClient, UserId};
+use crate::configuration::IdempotencySettings;
+use crate::error::BizErrorEnum;
+use crate::idempotency::{
+    try_processing, update_response, IdempotencyKey, IdempotencyScope, NextAction,
+};
+use crate::request::IdempotencyData;
+use crate::startup::HmacSecret;
+use crate::utils;
+use actix_multipart::Multipart;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderMap;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest};
+use actix_web_lab::middleware::Next;
+use futures_util::TryStreamExt;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::cell::{Cell, RefCell};
+use std::future::Ready;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+/// Sent by API clients, forms have an `idempotency_key` field instead.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+/// How a route uses [`idempotent`].
+#[derive(Debug, Clone, Copy)]
+pub struct IdempotencyPolicy {
+    key_required: bool,
+    on_replay: Option<fn()>,
+}
+
+impl IdempotencyPolicy {
+    /// A request without a key is rejected with a `400`, e.g. the forms which always have one.
+    pub fn required() -> Self {
+        Self {
+            key_required: true,
+            on_replay: None,
+        }
+    }
+
+    /// A request without a key is processed as usual, only retries with a key are replayed.
+    pub fn optional() -> Self {
+        Self {
+            key_required: false,
+            on_replay: None,
+        }
+    }
+
+    /// Runs before a saved response is replayed, e.g. to send the flash message
+    /// the handler sent the first time.
+    pub fn on_replay(mut self, on_replay: fn()) -> Self {
+        self.on_replay = Some(on_replay);
+        self
+    }
+}
+
+/// Process a request once per idempotency key, replay the saved response for its retries.
+///
+/// The key is read from the `Idempotency-Key` header, or else from the `idempotency_key`
+/// field of a form. Reusing a key for a request with another body is rejected with a `422`.
+/// Keys are scoped by user or API key, or by IP address for callers who are not logged in.
+///
+/// The response is only saved if the handler did its work in the transaction of
+/// [`IdempotentRequest::transaction`], and did not fail with a `5xx`: both are committed
+/// together. Otherwise the key is released, a retry runs the handler again.
+pub async fn idempotent(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+    policy: IdempotencyPolicy,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .ok_or(BizErrorEnum::AppDataNotFound("PgPool"))?
+        .clone();
+    let settings = req
+        .app_data::<web::Data<IdempotencySettings>>()
+        .ok_or(BizErrorEnum::AppDataNotFound("IdempotencySettings"))?
+        .clone();
+
+    let body = req.extract::<web::Bytes>().await?;
+    let idempotency_key = match read_idempotency_key(req.headers(), body.clone()).await {
+        Ok(Some(idempotency_key)) => Some(idempotency_key),
+        Ok(None) if !policy.key_required => None,
+        Ok(None) => return Ok(req.error_response(BizErrorEnum::IdempotencyKeyIsBlank)),
+        Err(e) => return Ok(req.error_response(e)),
+    };
+    let scope = match request_scope(&req) {
+        Ok(scope) => scope,
+        Err(e) => return Ok(req.error_response(e)),
+    };
+
+    // Without a key, the handler still gets a transaction, only nothing is saved with it
+    let transaction = match &idempotency_key {
+        Some(idempotency_key) => {
+            let request_fingerprint = fingerprint(&req, body.clone()).await;
+            let next_action = try_processing(
+                &pool,
+                idempotency_key,
+                &scope,
+                &request_fingerprint,
+                &settings,
+            )
+            .await;
+            match next_action {
+                Ok(NextAction::StartProcessing(transaction)) => transaction,
+                Ok(NextAction::ReturnSavedResponse(saved_response)) => {
+                    if let Some(on_replay) = policy.on_replay {
+                        on_replay();
+                    }
+                    return Ok(req.into_response(saved_response));
+                }
+                Err(e) => return Ok(req.error_response(e)),
+            }
+        }
+        None => pool.begin().await.map_err(BizErrorEnum::PgPoolError)?,
+    };
+
+    let slot = TransactionSlot::new(transaction);
+    req.extensions_mut().insert(slot.clone());
+    req.set_payload(utils::payload_of(body));
+    // On `Err` the transaction is dropped with the slot, i.e. rolled back
+    let response = next.call(req).await?.map_into_boxed_body();
+
+    let transaction = slot.transaction.borrow_mut().take();
+    match (transaction, idempotency_key) {
+        (Some(transaction), idempotency_key)
+            if slot.used.get() && !response.status().is_server_error() =>
+        {
+            let (req, response) = response.into_parts();
+            let response = match idempotency_key {
+                Some(idempotency_key) => {
+                    update_response(transaction, &scope, &idempotency_key, response).await?
+                }
+                None => {
+                    transaction
+                        .commit()
+                        .await
+                        .map_err(BizErrorEnum::TransactionCommitError)?;
+                    response
+                }
+            };
+            Ok(ServiceResponse::new(req, response))
+        }
+        // Dropping the transaction releases the key
+        _ => Ok(response),
+    }
+}
+
+async fn read_idempotency_key(
+    headers: &HeaderMap,
+    body: web::Bytes,
+) -> Result<Option<IdempotencyKey>, BizErrorEnum> {
+    let idempotency_key = if let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) {
+        Some(value.to_str().unwrap_or_default().to_string())
+    } else if utils::is_multipart(headers) {
+        utils::multipart_text_field(headers, body, "idempotency_key").await
+    } else {
+        serde_urlencoded::from_bytes::<IdempotencyData>(&body)
+            .unwrap_or_default()
+            .idempotency_key
+    };
+    idempotency_key.map(IdempotencyKey::try_from).transpose()
+}
+
+/// The logged-in user or the API key, or else the IP address of the caller.
+fn request_scope(req: &ServiceRequest) -> Result<IdempotencyScope, BizErrorEnum> {
+    if let Some(user_id) = req.extensions().get::<UserId>() {
+        return Ok(IdempotencyScope::User(**user_id));
+    }
+    if let Some(api_client) = req.extensions().get::<ApiClient>() {
+        return Ok(IdempotencyScope::ApiKey(api_client.api_key_id));
+    }
+    let hmac_secret = req
+        .app_data::<web::Data<HmacSecret>>()
+        .ok_or(BizErrorEnum::AppDataNotFound("HmacSecret"))?;
+    let ip_address = utils::client_ip(req.request())
+        .map(|ip| ip.to_string())
+        .unwrap_or_default();
+    IdempotencyScope::anonymous(hmac_secret, &ip_address)
+}
+
+/// SHA-256 of the method, the path and the body of the request, hex-encoded.
+///
+/// The parts of a multipart body are hashed rather than the body itself,
+/// browsers pick another boundary every time a form is sent.
+async fn fingerprint(req: &ServiceRequest, body: web::Bytes) -> String {
+    let mut hasher = Sha256::new();
+    // Length-prefixed, so that moving bytes from one part to the next changes the hash
+    let mut update = |bytes: &[u8]| {
+        hasher.update((bytes.len() as u64).to_be_bytes());
+        hasher.update(bytes);
+    };
+    update(req.method().as_str().as_bytes());
+    update(req.path().as_bytes());
+    if utils::is_multipart(req.headers()) {
+        let mut multipart = Multipart::new(req.headers(), utils::payload_of(body));
+        while let Ok(Some(mut field)) = multipart.try_next().await {
+            let file_name = field
+                .content_disposition()
+                .and_then(|disposition| disposition.get_filename())
+                .map(str::to_string);
+            let content_type = field.content_type().map(|mime| mime.to_string());
+            update(field.name().unwrap_or_default().as_bytes());
+            update(file_name.unwrap_or_default().as_bytes());
+            update(content_type.unwrap_or_default().as_bytes());
+            let mut content = vec![];
+            while let Ok(Some(chunk)) = field.try_next().await {
+                content.extend_from_slice(&chunk);
+            }
+            update(&content);
+        }
+    } else {
+        update(&body);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Holds the transaction of the key while the handler doesn't use it.
+#[derive(Clone)]
+struct TransactionSlot {
+    transaction: Rc<RefCell<Option<PgTransaction>>>,
+    used: Rc<Cell<bool>>,
+}
+
+impl TransactionSlot {
+    fn new(transaction: PgTransaction) -> Self {
+        Self {
+            transaction: Rc::new(RefCell::new(Some(transaction))),
+            used: Rc::new(Cell::new(false)),
+        }
+    }
+}
+
+/// A request going through [`idempotent`].
+pub struct IdempotentRequest(TransactionSlot);
+
+impl FromRequest for IdempotentRequest {
+    type Error = BizErrorEnum;
+    type Future = Ready<Result<IdempotentRequest, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let slot = req.extensions().get::<TransactionSlot>().cloned();
+        std::future::ready(
+            slot.map(IdempotentRequest)
+                .ok_or(BizErrorEnum::AppDataNotFound("IdempotentRequest")),
+        )
+    }
+}
+
+impl IdempotentRequest {
+    /// The transaction holding the idempotency key, to do the work of the handler in.
+    ///
+    /// Ask for it once validation is over: from then on the response is saved.
+    pub fn transaction(&self) -> Result<IdempotentTransaction, BizErrorEnum> {
+        let transaction = self
+            .0
+            .transaction
+            .borrow_mut()
+            .take()
+            .ok_or(BizErrorEnum::AppDataNotFound("IdempotentTransaction"))?;
+        self.0.used.set(true);
+        Ok(IdempotentTransaction {
+            transaction: Some(transaction),
+            slot: self.0.clone(),
+        })
+    }
+}
+
+/// Goes back to [`idempotent`] when dropped, which commits it with the response.
+pub struct IdempotentTransaction {
+    transaction: Option<PgTransaction>,
+    slot: TransactionSlot,
+}
+
+impl Deref for IdempotentTransaction {
+    type Target = PgTransaction;
+
+    fn deref(&self) -> &Self::Target {
+        self.transaction.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for IdempotentTransaction {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.transaction.as_mut().unwrap()
+    }
+}
+
+impl Drop for IdempotentTransaction {
+    fn drop(&mut self) {
+        *self.slot.transaction.borrow_mut() = self.transaction.take();
+    }
+}
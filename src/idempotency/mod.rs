@@ -1,5 +1,11 @@
+mod cleanup;
 mod key;
+mod middleware;
 mod persistence;
+mod scope;
 
+pub use cleanup::*;
 pub use key::*;
+pub use middleware::*;
 pub use persistence::*;
+pub use scope::*;
@@ -1,10 +1,10 @@
+use crate::configuration::IdempotencySettings;
 use crate::error::BizErrorEnum;
-use crate::idempotency::IdempotencyKey;
+use crate::idempotency::{IdempotencyKey, IdempotencyScope};
 use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
 use sqlx::{PgPool, Postgres, Transaction};
-use uuid::Uuid;
 
 #[derive(Debug, sqlx::Type)]
 #[sqlx(type_name = "header_pair")]
@@ -21,38 +21,52 @@ impl PgHasArrayType for HeaderPairRecord {
 
 #[tracing::instrument(
     name = "Query response from idempotency",
-    skip(pool, idempotency_key, user_id)
+    skip(pool, idempotency_key, request_fingerprint),
+    fields(scope = %scope)
 )]
 pub async fn get_saved_response(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope: &IdempotencyScope,
+    request_fingerprint: &str,
 ) -> Result<Option<HttpResponse>, BizErrorEnum> {
     // ask sqlx to forcefully assume that the columns will not be null - if we are wrong, it will
     // cause an error at runtime (!)
+    // The columns are only NULL while the request is in flight, such a record is skipped
     let response = sqlx::query!(
         r#"
             SELECT 
                 response_status_code as "response_status_code!", 
                 response_headers as "response_headers!: Vec<HeaderPairRecord>", 
-                response_body as "response_body!"
+                response_body as "response_body!",
+                request_fingerprint
             FROM idempotency 
-            WHERE user_id = $1 AND idempotency_key = $2
+            WHERE 
+                scope = $1 AND 
+                idempotency_key = $2 AND 
+                response_status_code IS NOT NULL
     "#,
-        user_id,
+        scope.to_string(),
         idempotency_key.as_ref()
     )
     .fetch_optional(pool)
     .await
-    .map_err(|e| BizErrorEnum::QueryIdempotencyError(e))?;
+    .map_err(BizErrorEnum::QueryIdempotencyError)?;
 
     match response {
         None => Ok(None),
+        Some(r)
+            if r.request_fingerprint
+                .as_deref()
+                .is_some_and(|f| f != request_fingerprint) =>
+        {
+            Err(BizErrorEnum::IdempotencyKeyIsReused)
+        }
         Some(r) => {
             let status_code = StatusCode::from_u16(
                 r.response_status_code
                     .try_into()
-                    .map_err(|e| BizErrorEnum::ResponseStatusCodeTryIntoError(e))?,
+                    .map_err(BizErrorEnum::ResponseStatusCodeTryIntoError)?,
             )
             .map_err(|_e| BizErrorEnum::StatusCodeConvertError)?;
 
@@ -68,12 +82,13 @@ pub async fn get_saved_response(
 
 #[tracing::instrument(
     name = "Update response in idempotency",
-    skip(transaction, user_id, idempotency_key, http_response)
+    skip(transaction, idempotency_key, http_response),
+    fields(scope = %scope)
 )]
 pub async fn update_response(
     // No longer a `Pool`!
     mut transaction: Transaction<'static, Postgres>,
-    user_id: Uuid,
+    scope: &IdempotencyScope,
     idempotency_key: &IdempotencyKey,
     http_response: HttpResponse,
 ) -> Result<HttpResponse, BizErrorEnum> {
@@ -104,23 +119,23 @@ pub async fn update_response(
                 response_status_code = $1, 
                 response_headers = $2, 
                 response_body = $3
-            WHERE user_id = $4 AND idempotency_key = $5 
+            WHERE scope = $4 AND idempotency_key = $5 
         "#,
         status_code,
         headers,
         body.as_ref(),
-        user_id,
+        scope.to_string(),
         idempotency_key.as_ref()
     )
     .execute(&mut transaction)
     .await
-    .map_err(|e| BizErrorEnum::InsertIdempotencyError(e))?;
+    .map_err(BizErrorEnum::InsertIdempotencyError)?;
 
     // Commit transaction
     transaction
         .commit()
         .await
-        .map_err(|e| BizErrorEnum::TransactionCommitError(e))?;
+        .map_err(BizErrorEnum::TransactionCommitError)?;
 
     let response = response_head.set_body(body).map_into_boxed_body();
     Ok(response)
@@ -134,43 +149,93 @@ pub enum NextAction {
 
 #[tracing::instrument(
     name = "Try processing insert operation",
-    skip(pool, idempotency_key, user_id)
+    skip(pool, idempotency_key, request_fingerprint, settings),
+    fields(scope = %scope)
 )]
 pub async fn try_processing(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope: &IdempotencyScope,
+    request_fingerprint: &str,
+    settings: &IdempotencySettings,
 ) -> Result<NextAction, BizErrorEnum> {
-    let mut transaction = pool
-        .begin()
-        .await
-        .map_err(|e| BizErrorEnum::PgPoolError(e))?;
+    let mut transaction = pool.begin().await.map_err(BizErrorEnum::PgPoolError)?;
+
+    // The insert waits for the transaction of a request in flight with the same key,
+    // then either replays its response or, if it was rolled back, takes over the key
+    let in_flight_timeout = settings.in_flight_timeout();
+    sqlx::query!(
+        "SELECT set_config('lock_timeout', $1, true)",
+        format!("{}ms", in_flight_timeout.as_millis().max(1))
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .map_err(BizErrorEnum::InsertIdempotencyError)?;
 
     let rows_affected = sqlx::query!(
         r#"
             INSERT INTO idempotency(
+                scope, 
                 user_id, 
                 idempotency_key, 
-                created_at
+                request_fingerprint, 
+                created_at, 
+                expires_at
             ) 
-            VALUES ($1, $2, now()) 
-            ON CONFLICT DO NOTHING
+            VALUES ($1, $2, $3, $4, now(), now() + make_interval(secs => $5)) 
+            ON CONFLICT (scope, idempotency_key) DO UPDATE
+            SET 
+                request_fingerprint = EXCLUDED.request_fingerprint, 
+                created_at = EXCLUDED.created_at, 
+                expires_at = EXCLUDED.expires_at, 
+                response_status_code = NULL, 
+                response_headers = NULL, 
+                response_body = NULL
+            -- An expired key is taken over, as if it had never been seen
+            WHERE idempotency.expires_at < now()
         "#,
-        user_id,
-        idempotency_key.as_ref()
+        scope.to_string(),
+        scope.user_id(),
+        idempotency_key.as_ref(),
+        request_fingerprint,
+        scope.retention(settings).as_secs_f64()
     )
     .execute(&mut transaction)
     .await
-    .map_err(|e| BizErrorEnum::InsertIdempotencyError(e))?
+    .map_err(|e| {
+        if is_lock_timeout(&e) {
+            tracing::warn!("Gave up waiting for a request in flight with the same key.");
+            BizErrorEnum::IdempotencyKeyIsInFlight {
+                retry_after: in_flight_timeout,
+            }
+        } else {
+            BizErrorEnum::InsertIdempotencyError(e)
+        }
+    })?
     .rows_affected();
 
     if rows_affected > 0 {
+        // The rest of the transaction belongs to the handler, no more waiting limit
+        sqlx::query!("SET LOCAL lock_timeout TO DEFAULT")
+            .execute(&mut transaction)
+            .await
+            .map_err(BizErrorEnum::InsertIdempotencyError)?;
         // Return transaction for later usage
         Ok(NextAction::StartProcessing(transaction))
     } else {
-        let saved_response = get_saved_response(pool, idempotency_key, user_id)
+        // No saved response means the record was taken over by a newer request in the meantime
+        let saved_response = get_saved_response(pool, idempotency_key, scope, request_fingerprint)
             .await?
-            .ok_or(BizErrorEnum::FindExpectedSavedRecordError)?;
+            .ok_or(BizErrorEnum::IdempotencyKeyIsInFlight {
+                retry_after: in_flight_timeout,
+            })?;
         Ok(NextAction::ReturnSavedResponse(saved_response))
     }
 }
+
+/// `lock_not_available`, raised once `lock_timeout` is over.
+fn is_lock_timeout(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| code == "55P03")
+}
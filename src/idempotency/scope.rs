@@ -0,0 +1,49 @@
+use crate::configuration::IdempotencySettings;
+use crate::error::BizErrorEnum;
+use crate::startup::HmacSecret;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Whose idempotency keys a record belongs to: the same key in two scopes is two records.
+#[derive(Debug, Clone)]
+pub enum IdempotencyScope {
+    /// A logged-in user
+    User(Uuid),
+    /// A caller of the JSON API, by the id of their key
+    ApiKey(Uuid),
+    /// Callers who are not logged in, known by a keyed hash of their IP address only
+    Anonymous { ip_hash: String },
+}
+
+impl IdempotencyScope {
+    pub fn anonymous(hmac_secret: &HmacSecret, ip_address: &str) -> Result<Self, BizErrorEnum> {
+        Ok(Self::Anonymous {
+            ip_hash: hmac_secret.keyed_hash("idempotency_ip", ip_address)?,
+        })
+    }
+
+    /// Stored in `idempotency.user_id`, so that the records of a user can be found.
+    pub fn user_id(&self) -> Option<Uuid> {
+        match self {
+            IdempotencyScope::User(user_id) => Some(*user_id),
+            IdempotencyScope::ApiKey(_) | IdempotencyScope::Anonymous { .. } => None,
+        }
+    }
+
+    pub fn retention(&self, settings: &IdempotencySettings) -> Duration {
+        match self {
+            IdempotencyScope::User(_) | IdempotencyScope::ApiKey(_) => settings.retention(),
+            IdempotencyScope::Anonymous { .. } => settings.anonymous_retention(),
+        }
+    }
+}
+
+impl std::fmt::Display for IdempotencyScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdempotencyScope::User(user_id) => write!(f, "user:{}", user_id),
+            IdempotencyScope::ApiKey(api_key_id) => write!(f, "api_key:{}", api_key_id),
+            IdempotencyScope::Anonymous { ip_hash } => write!(f, "ip:{}", ip_hash),
+        }
+    }
+}
@@ -1,32 +1,72 @@
-use crate::configuration::Settings;
+use crate::configuration::{Settings, TrackingSettings};
 use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::email_client::{EmailAttachment, EmailClient, EmailMessage, MessageStream};
+use crate::email_template::{EmailTemplateName, TemplateContext};
 use crate::error::BizErrorEnum;
 use crate::error::BizErrorEnum::QueryNewsletterIssuesError;
-use crate::{startup, telemetry};
-use sqlx::{PgPool, Postgres, Transaction};
+use crate::request::UnsubscribeData;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::webhooks::{WebhookEvent, WebhookIssue};
+use crate::{email_template, startup, telemetry, tracking, webhook_delivery_worker};
+use chrono::{DateTime, Utc};
+use sqlx::{Connection, PgPool, Postgres, Transaction};
+use std::collections::HashMap;
 use std::time::Duration;
 use uuid::Uuid;
 
 type PgTransaction = Transaction<'static, Postgres>;
 
+/// How `{{subscriber.subscribed_at}}` reads, e.g. "October 18, 2026".
+const SUBSCRIBED_AT_FORMAT: &str = "%B %-d, %Y";
+
 #[tracing::instrument(name = "Run work", skip_all)]
-pub async fn run_work_until_stopped(config: Settings) -> Result<(), BizErrorEnum> {
+pub async fn run_work_until_stopped(
+    config: Settings,
+    email_client: EmailClient,
+) -> Result<(), BizErrorEnum> {
     let connection_pool = startup::get_connection_pool(&config.database);
 
-    let email_client = config.email_client.client();
+    // Use at building the unsubscribe link of every recipient
+    let app_base_url = ApplicationBaseUrl(config.application.base_url);
+    let hmac_secret = HmacSecret(config.application.hmac_secret);
 
-    worker_loop(connection_pool, email_client).await
+    worker_loop(
+        connection_pool,
+        email_client,
+        app_base_url,
+        hmac_secret,
+        config.tracking,
+    )
+    .await
 }
 
 #[tracing::instrument(name = "Worker loop", skip_all)]
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), BizErrorEnum> {
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    app_base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+    tracking: TrackingSettings,
+) -> Result<(), BizErrorEnum> {
+    let mut attachment_cache = AttachmentCache::default();
     loop {
-        match try_execute_task(&pool, &email_client).await {
+        let outcome = try_execute_task(
+            &pool,
+            &email_client,
+            &app_base_url,
+            &hmac_secret,
+            &tracking,
+            &mut attachment_cache,
+        )
+        .await;
+        match outcome {
             Ok(ExecutionOutcome::EmptyQueue) => {
                 tokio::time::sleep(Duration::from_secs(10)).await;
             }
             Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmailProviderUnavailable) => {
+                pause_while_unavailable(&email_client).await;
+            }
             Err(_) => {
                 tokio::time::sleep(Duration::from_secs(1)).await;
             }
@@ -37,6 +77,20 @@ async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), BizE
 pub enum ExecutionOutcome {
     TaskCompleted,
     EmptyQueue,
+    /// The circuit breaker of the email client is open, the queue was left untouched
+    EmailProviderUnavailable,
+}
+
+/// Sleep until the circuit breaker lets a trial email through.
+pub(crate) async fn pause_while_unavailable(email_client: &EmailClient) {
+    let pause = email_client
+        .unavailable_for()
+        .unwrap_or(Duration::from_secs(1));
+    tracing::info!(
+        pause_milliseconds = pause.as_millis() as u64,
+        "The email provider is unavailable. Pausing deliveries."
+    );
+    tokio::time::sleep(pause).await;
 }
 
 #[tracing::instrument(
@@ -51,37 +105,133 @@ pub enum ExecutionOutcome {
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    app_base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    tracking: &TrackingSettings,
+    attachment_cache: &mut AttachmentCache,
 ) -> Result<ExecutionOutcome, BizErrorEnum> {
+    // Leave the queue alone rather than failing every delivery
+    if email_client.unavailable_for().is_some() {
+        return Ok(ExecutionOutcome::EmailProviderUnavailable);
+    }
     // Query table: issue_delivery_queue
     let task = dequeue_task(pool).await?;
     if task.is_none() {
         return Ok(ExecutionOutcome::EmptyQueue);
     }
 
-    let (transaction, issue_id, email) = task.unwrap();
+    let (mut transaction, task) = task.unwrap();
+    let issue_id = task.newsletter_issue_id;
+    let email = task.subscriber_email;
     telemetry::record_field("newsletter_issue_id", issue_id);
     telemetry::record_field("subscriber_email", &email);
     // Send email
-    match SubscriberEmail::parse(email.clone()) {
-        Ok(email) => {
+    match (SubscriberEmail::parse(email.clone()), task.recipient) {
+        // They unsubscribed after the issue was published
+        (Ok(_), None) => tracing::info!("Skipping a subscriber who is no longer confirmed."),
+        (Ok(email), Some(recipient)) => {
             let issue = get_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
-                    &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
+            let template =
+                email_template::get_email_template(pool, EmailTemplateName::Newsletter).await?;
+            // Rendered for each recipient: the merge fields and the unsubscribe link are theirs
+            let unsubscribe_url = UnsubscribeData::link(recipient.id, app_base_url, hmac_secret)?;
+            let context = TemplateContext::new()
+                .insert("subscriber.name", recipient.name)
+                .insert("subscriber.email", email.as_ref())
+                .insert(
+                    "subscriber.subscribed_at",
+                    recipient
+                        .subscribed_at
+                        .format(SUBSCRIBED_AT_FORMAT)
+                        .to_string(),
                 )
-                .await
-            {
-                tracing::error!(
-                    error.cause_chain = ?e,
-                    error.message = %e,
-                    "Failed to deliver issue to a confirmed subscriber. Skipping."
-                );
+                .insert("unsubscribe_url", unsubscribe_url.clone());
+            let title = context.render_text(&issue.title);
+            let mut html_content = context.render_html(&issue.html_content);
+            // Only the content of the issue, never the links of the template such as unsubscribe
+            if tracking.enabled && issue.track_clicks {
+                html_content = tracking::track_clicks(
+                    &html_content,
+                    issue_id,
+                    recipient.id,
+                    app_base_url,
+                    hmac_secret,
+                )?;
+            }
+            if tracking.enabled && issue.track_opens {
+                html_content.push_str(&tracking::open_pixel(
+                    issue_id,
+                    recipient.id,
+                    app_base_url,
+                    hmac_secret,
+                )?);
+            }
+            let text_content = context.render_text(&issue.text_content);
+            let context = context.insert("newsletter.title", title).insert_content(
+                "newsletter.content",
+                html_content,
+                text_content,
+            );
+            let rendered = template.render(&context);
+            let attachments: Vec<EmailAttachment> = attachment_cache
+                .get(pool, issue_id)
+                .await?
+                .iter()
+                .map(|a| {
+                    EmailAttachment::from_base64(
+                        &a.file_name,
+                        &a.content,
+                        &a.content_type,
+                        a.content_id.clone(),
+                    )
+                })
+                .collect();
+            // An invalid address would have been rejected when the issue was published
+            let reply_to = issue
+                .reply_to
+                .and_then(|reply_to| SubscriberEmail::parse(reply_to).ok());
+            let mut message = EmailMessage::new(
+                &email,
+                &rendered.subject,
+                &rendered.html_body,
+                &rendered.text_body,
+            )
+            .attachments(&attachments)
+            .message_stream(MessageStream::Broadcast)
+            // Mail clients show their own unsubscribe button for it
+            .header("List-Unsubscribe", format!("<{}>", unsubscribe_url))
+            .tag("newsletter")
+            .metadata("newsletter_issue_id", issue_id);
+            if let Some(from_name) = issue.from_name.as_deref() {
+                message = message.from_name(from_name);
+            }
+            if let Some(reply_to) = reply_to.as_ref() {
+                message = message.reply_to(reply_to);
+            }
+            match email_client.send(message).await {
+                Ok(provider) => {
+                    let delivery = EmailDelivery {
+                        recipient: email.as_ref(),
+                        newsletter_issue_id: Some(issue_id),
+                        outbox_message_id: None,
+                        provider,
+                    };
+                    record_delivery(&mut transaction, delivery).await;
+                }
+                // Another sender opened the circuit meanwhile, the task is rolled back for later
+                Err(BizErrorEnum::CircuitBreakerIsOpen { .. }) => {
+                    return Ok(ExecutionOutcome::EmailProviderUnavailable)
+                }
+                Err(e) => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to deliver issue to a confirmed subscriber. Skipping."
+                    );
+                }
             }
         }
-        Err(e) => {
+        (Err(e), _) => {
             tracing::error!(
                 error.cause_chain = ?e,
                 error.message = %e,
@@ -95,35 +245,128 @@ pub async fn try_execute_task(
     Ok(ExecutionOutcome::TaskCompleted)
 }
 
+/// An email accepted by one of the email providers.
+pub(crate) struct EmailDelivery<'a> {
+    pub recipient: &'a str,
+    pub newsletter_issue_id: Option<Uuid>,
+    pub outbox_message_id: Option<Uuid>,
+    pub provider: &'a str,
+}
+
+/// Keep track of which provider sent the email, the secondary one takes over during outages.
+///
+/// The email is gone already: a failure is only logged, rolling the task back
+/// would send the email again.
+#[tracing::instrument(name = "Record email delivery", skip_all, fields(provider = delivery.provider))]
+pub(crate) async fn record_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    delivery: EmailDelivery<'_>,
+) {
+    if let Err(e) = insert_delivery(transaction, delivery).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to record an email delivery. Carrying on."
+        );
+    }
+}
+
+async fn insert_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    delivery: EmailDelivery<'_>,
+) -> Result<(), BizErrorEnum> {
+    // A failed statement aborts the whole transaction, unless it runs in a savepoint
+    let mut savepoint = transaction
+        .begin()
+        .await
+        .map_err(BizErrorEnum::PgPoolError)?;
+    sqlx::query!(
+        r#"
+            INSERT INTO email_deliveries (
+                delivery_id,
+                recipient,
+                newsletter_issue_id,
+                outbox_message_id,
+                provider
+            )
+            VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        delivery.recipient,
+        delivery.newsletter_issue_id,
+        delivery.outbox_message_id,
+        delivery.provider
+    )
+    .execute(&mut savepoint)
+    .await
+    .map_err(BizErrorEnum::InsertEmailDeliveriesError)?;
+
+    savepoint
+        .commit()
+        .await
+        .map_err(BizErrorEnum::TransactionCommitError)
+}
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    /// `None` if they are no longer a confirmed subscriber
+    recipient: Option<Recipient>,
+}
+
+struct Recipient {
+    id: Uuid,
+    name: String,
+    subscribed_at: DateTime<Utc>,
+}
+
 #[tracing::instrument(name = "Dequeue task", skip_all)]
 async fn dequeue_task(
     pool: &PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, BizErrorEnum> {
-    let mut transaction = pool
-        .begin()
-        .await
-        .map_err(|e| BizErrorEnum::PgPoolError(e))?;
+) -> Result<Option<(PgTransaction, DeliveryTask)>, BizErrorEnum> {
+    let mut transaction = pool.begin().await.map_err(BizErrorEnum::PgPoolError)?;
 
+    // The merge fields are resolved now, not when the issue was published
     let record = sqlx::query!(
         r#"
-            SELECT newsletter_issue_id, subscriber_email 
-            FROM issue_delivery_queue
-            FOR UPDATE
+            SELECT
+                q.newsletter_issue_id,
+                q.subscriber_email,
+                s.id AS "subscriber_id?",
+                s.name AS "subscriber_name?",
+                s.subscribed_at AS "subscribed_at?"
+            FROM issue_delivery_queue q
+            LEFT JOIN subscriptions s
+                ON s.email = q.subscriber_email AND s.status = 'confirmed'
+            FOR UPDATE OF q
             SKIP LOCKED
             LIMIT 1
         "#
     )
     .fetch_optional(&mut transaction)
     .await
-    .map_err(|e| BizErrorEnum::QueryIssueDeliveryQueueError(e))?;
+    .map_err(BizErrorEnum::QueryIssueDeliveryQueueError)?;
 
     match record {
         None => Ok(None),
-        Some(r) => Ok(Some((
-            transaction,
-            r.newsletter_issue_id,
-            r.subscriber_email,
-        ))),
+        Some(r) => {
+            let recipient = match (r.subscriber_id, r.subscriber_name, r.subscribed_at) {
+                (Some(id), Some(name), Some(subscribed_at)) => Some(Recipient {
+                    id,
+                    name,
+                    subscribed_at,
+                }),
+                _ => None,
+            };
+            Ok(Some((
+                transaction,
+                DeliveryTask {
+                    newsletter_issue_id: r.newsletter_issue_id,
+                    subscriber_email: r.subscriber_email,
+                    recipient,
+                },
+            )))
+        }
     }
 }
 
@@ -145,19 +388,97 @@ async fn delete_task(
     )
     .execute(&mut transaction)
     .await
-    .map_err(|e| BizErrorEnum::DeleteIssueDeliveryQueueError(e))?;
+    .map_err(BizErrorEnum::DeleteIssueDeliveryQueueError)?;
+    report_if_issue_delivered(&mut transaction, issue_id).await?;
 
     transaction
         .commit()
         .await
-        .map_err(|e| BizErrorEnum::TransactionCommitError(e))?;
+        .map_err(BizErrorEnum::TransactionCommitError)?;
 
     Ok(())
 }
+
+/// Post `issue.delivered` to the webhooks once the queue of the issue is empty.
+#[tracing::instrument(name = "Report delivered issue", skip(transaction))]
+pub(crate) async fn report_if_issue_delivered(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<(), BizErrorEnum> {
+    // A task nobody works on will be reported by whoever executes it, no need to lock anything.
+    // The tasks in flight are locked by their workers and skipped
+    let waiting = sqlx::query!(
+        r#"
+            SELECT newsletter_issue_id FROM issue_delivery_queue
+            WHERE newsletter_issue_id = $1
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT 1
+        "#,
+        issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(BizErrorEnum::QueryIssueDeliveryQueueError)?;
+    if waiting.is_some() {
+        return Ok(());
+    }
+
+    // Workers finishing the same issue wait for each other here,
+    // so that the last one to commit sees the queue empty
+    let issue = sqlx::query!(
+        r#"
+            SELECT title, recipients_count
+            FROM newsletter_issues
+            WHERE newsletter_issue_id = $1
+            FOR UPDATE
+        "#,
+        issue_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(QueryNewsletterIssuesError)?;
+    let pending = sqlx::query!(
+        r#"
+            SELECT EXISTS (
+                SELECT 1 FROM issue_delivery_queue WHERE newsletter_issue_id = $1
+            ) AS "pending!"
+        "#,
+        issue_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(BizErrorEnum::QueryIssueDeliveryQueueError)?
+    .pending;
+    if pending {
+        return Ok(());
+    }
+
+    let delivered = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM email_deliveries WHERE newsletter_issue_id = $1"#,
+        issue_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(BizErrorEnum::QueryEmailDeliveriesError)?
+    .count;
+    let event = WebhookEvent::IssueDelivered(WebhookIssue {
+        id: issue_id,
+        title: issue.title,
+        recipients: issue.recipients_count,
+        delivered,
+    });
+    webhook_delivery_worker::enqueue_event(transaction, event).await
+}
+
 struct NewsletterIssue {
     title: String,
     text_content: String,
     html_content: String,
+    track_opens: bool,
+    track_clicks: bool,
+    from_name: Option<String>,
+    reply_to: Option<String>,
 }
 
 #[tracing::instrument(name = "Query newsletter issue", skip(pool))]
@@ -165,7 +486,8 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, Biz
     let record = sqlx::query_as!(
         NewsletterIssue,
         r#"
-            SELECT title, text_content, html_content 
+            SELECT
+                title, text_content, html_content, track_opens, track_clicks, from_name, reply_to
             FROM newsletter_issues 
             WHERE newsletter_issue_id = $1
         "#,
@@ -173,7 +495,85 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, Biz
     )
     .fetch_one(pool)
     .await
-    .map_err(|e| QueryNewsletterIssuesError(e))?;
+    .map_err(QueryNewsletterIssuesError)?;
 
     Ok(record)
 }
+
+struct IssueAttachment {
+    file_name: String,
+    content_type: String,
+    content: Vec<u8>,
+    content_id: Option<String>,
+}
+
+/// The same file with its content base64 encoded, ready for `EmailAttachment::from_base64`.
+struct EncodedAttachment {
+    file_name: String,
+    content_type: String,
+    content: String,
+    content_id: Option<String>,
+}
+
+impl From<IssueAttachment> for EncodedAttachment {
+    fn from(attachment: IssueAttachment) -> Self {
+        Self {
+            file_name: attachment.file_name,
+            content_type: attachment.content_type,
+            content: EmailAttachment::encode(&attachment.content),
+            content_id: attachment.content_id,
+        }
+    }
+}
+
+/// How many issues `AttachmentCache` holds the attachments of.
+const ATTACHMENT_CACHE_ISSUES: usize = 4;
+
+/// The attachments of the issues being delivered, loaded and encoded once for all their
+/// recipients rather than for each of them. They never change once the issue is published.
+#[derive(Default)]
+pub struct AttachmentCache(HashMap<Uuid, Vec<EncodedAttachment>>);
+
+impl AttachmentCache {
+    async fn get(
+        &mut self,
+        pool: &PgPool,
+        issue_id: Uuid,
+    ) -> Result<&[EncodedAttachment], BizErrorEnum> {
+        if !self.0.contains_key(&issue_id) {
+            // Few issues are delivered at the same time, starting over is good enough
+            if self.0.len() >= ATTACHMENT_CACHE_ISSUES {
+                self.0.clear();
+            }
+            let attachments = get_issue_attachments(pool, issue_id).await?;
+            self.0.insert(
+                issue_id,
+                attachments
+                    .into_iter()
+                    .map(EncodedAttachment::from)
+                    .collect(),
+            );
+        }
+        Ok(&self.0[&issue_id])
+    }
+}
+
+#[tracing::instrument(name = "Query newsletter issue attachments", skip(pool))]
+async fn get_issue_attachments(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<IssueAttachment>, BizErrorEnum> {
+    sqlx::query_as!(
+        IssueAttachment,
+        r#"
+            SELECT file_name, content_type, content, content_id
+            FROM newsletter_issue_attachments
+            WHERE newsletter_issue_id = $1
+            ORDER BY file_name
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(BizErrorEnum::QueryNewsletterIssueAttachmentsError)
+}
@@ -1,14 +1,22 @@
 pub mod auth;
+pub mod circuit_breaker;
 pub mod configuration;
 pub mod constant;
 pub mod domain;
 pub mod email_client;
+pub mod email_outbox_worker;
+pub mod email_template;
 pub mod error;
 pub mod idempotency;
 pub mod issue_delivery_worker;
 pub mod request;
 pub mod routes;
+pub mod security_headers;
 pub mod session_state;
 pub mod startup;
+pub mod subscription_attempts_cleanup;
 pub mod telemetry;
+pub mod tracking;
 pub mod utils;
+pub mod webhook_delivery_worker;
+pub mod webhooks;
@@ -1,10 +1,14 @@
 use std::fmt::{Debug, Display};
 use tokio::task::JoinError;
 use zero_2_prod::configuration;
+use zero_2_prod::email_outbox_worker;
 use zero_2_prod::error::BizErrorEnum;
+use zero_2_prod::idempotency;
 use zero_2_prod::issue_delivery_worker;
 use zero_2_prod::startup::Application;
+use zero_2_prod::subscription_attempts_cleanup;
 use zero_2_prod::telemetry;
+use zero_2_prod::webhook_delivery_worker;
 
 #[tokio::main]
 async fn main() -> Result<(), BizErrorEnum> {
@@ -22,15 +26,35 @@ async fn main() -> Result<(), BizErrorEnum> {
      * If parallelism is required,
      *  spawn each async expression using tokio::spawn and pass the join handle to select!.
      */
-    let application = Application::build(config.clone()).await?;
+    // One circuit breaker for the email provider, whoever sends the email
+    let email_client = config.email_client.clone().client();
+
+    let application = Application::build(config.clone(), email_client.clone()).await?;
     let application_task = tokio::spawn(application.run_until_stopped());
 
-    let worker = issue_delivery_worker::run_work_until_stopped(config);
+    let worker =
+        issue_delivery_worker::run_work_until_stopped(config.clone(), email_client.clone());
     let worker_task = tokio::spawn(worker);
 
+    let outbox_worker = email_outbox_worker::run_worker_until_stopped(config.clone(), email_client);
+    let outbox_worker_task = tokio::spawn(outbox_worker);
+
+    let webhook_worker = webhook_delivery_worker::run_worker_until_stopped(config.clone());
+    let webhook_worker_task = tokio::spawn(webhook_worker);
+
+    let idempotency_cleanup = idempotency::run_cleanup_until_stopped(config.clone());
+    let idempotency_cleanup_task = tokio::spawn(idempotency_cleanup);
+
+    let attempts_cleanup = subscription_attempts_cleanup::run_cleanup_until_stopped(config);
+    let attempts_cleanup_task = tokio::spawn(attempts_cleanup);
+
     tokio::select! {
         o = application_task => report_exit("API", o),
         o = worker_task => report_exit("Background worker", o),
+        o = outbox_worker_task => report_exit("Email outbox worker", o),
+        o = webhook_worker_task => report_exit("Webhook delivery worker", o),
+        o = idempotency_cleanup_task => report_exit("Idempotency cleanup", o),
+        o = attempts_cleanup_task => report_exit("Subscription attempts cleanup", o),
     }
 
     Ok(())
@@ -0,0 +1,67 @@
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::error::BizErrorEnum;
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+/// Body of `POST /api/v1/subscribers`.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct CreateSubscriberData {
+    pub email: String,
+    pub name: String,
+}
+
+/// Body of `PATCH /api/v1/subscribers/{subscriber_id}`, absent fields are left untouched.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct UpdateSubscriberData {
+    pub name: Option<String>,
+}
+
+/// Query of `GET /api/v1/subscribers`.
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListSubscribersQuery {
+    pub status: Option<SubscriberStatus>,
+    /// Page size, larger values are lowered to the maximum
+    #[serde(default = "ListSubscribersQuery::default_limit")]
+    #[param(default = 50, maximum = 100)]
+    pub limit: i64,
+    #[serde(default)]
+    pub offset: i64,
+}
+
+impl ListSubscribersQuery {
+    pub const MAX_LIMIT: i64 = 100;
+
+    fn default_limit() -> i64 {
+        50
+    }
+}
+
+/// `subscriptions.status`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriberStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl SubscriberStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriberStatus::PendingConfirmation => "pending_confirmation",
+            SubscriberStatus::Confirmed => "confirmed",
+            SubscriberStatus::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+impl TryFrom<CreateSubscriberData> for NewSubscriber {
+    type Error = BizErrorEnum;
+
+    fn try_from(data: CreateSubscriberData) -> Result<Self, Self::Error> {
+        let name = SubscriberName::parse(data.name)?;
+        let email = SubscriberEmail::parse(data.email)?;
+        Ok(NewSubscriber::new(email, name))
+    }
+}
@@ -1,9 +1,13 @@
 use secrecy::Secret;
 use serde::Deserialize;
+use utoipa::ToSchema;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ChangePasswordData {
+    #[schema(value_type = String, format = Password)]
     pub current_password: Secret<String>,
+    #[schema(value_type = String, format = Password)]
     pub new_password: Secret<String>,
+    #[schema(value_type = String, format = Password)]
     pub new_password_check: Secret<String>,
 }
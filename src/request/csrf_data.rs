@@ -0,0 +1,8 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+/// The anti-forgery field of a form, every other field is ignored.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct CsrfData {
+    pub csrf_token: Option<String>,
+}
@@ -0,0 +1,10 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct EmailTemplateData {
+    pub template_name: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
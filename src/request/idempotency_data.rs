@@ -0,0 +1,7 @@
+use serde::Deserialize;
+
+/// The idempotency key of a form, every other field is ignored.
+#[derive(Debug, Default, Deserialize)]
+pub struct IdempotencyData {
+    pub idempotency_key: Option<String>,
+}
@@ -1,8 +1,10 @@
 use secrecy::Secret;
 use serde::Deserialize;
+use utoipa::ToSchema;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct LoginData {
     pub username: String,
+    #[schema(value_type = String, format = Password)]
     pub password: Secret<String>,
 }
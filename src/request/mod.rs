@@ -1,13 +1,31 @@
+mod api_key_data;
+mod api_subscriber_data;
 mod change_password_data;
 mod confirm_data;
+mod csrf_data;
+mod email_template_data;
 mod error_data;
+mod idempotency_data;
 mod login_data;
 mod newsletter_data;
+mod revoke_session_data;
 mod subscribe_data;
+mod tracking_data;
+mod unsubscribe_data;
+mod webhook_data;
 
+pub use api_key_data::*;
+pub use api_subscriber_data::*;
 pub use change_password_data::*;
 pub use confirm_data::ConfirmData;
+pub use csrf_data::CsrfData;
+pub use email_template_data::*;
 pub use error_data::*;
+pub use idempotency_data::IdempotencyData;
 pub use login_data::LoginData;
 pub use newsletter_data::*;
+pub use revoke_session_data::*;
 pub use subscribe_data::SubscribeData;
+pub use tracking_data::*;
+pub use unsubscribe_data::UnsubscribeData;
+pub use webhook_data::*;
@@ -1,15 +1,111 @@
+use crate::error::BizErrorEnum;
 use crate::utils;
+use actix_multipart::Multipart;
+use futures_util::TryStreamExt;
 use serde::Deserialize;
+use utoipa::ToSchema;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct NewsletterData {
     pub title: String,
+    #[serde(default)]
+    pub content_format: ContentFormat,
+    #[serde(default)]
     pub text_content: String,
+    #[serde(default)]
     pub html_content: String,
-    pub idempotency_key: String,
+    #[serde(default)]
+    pub markdown_content: String,
+    /// Skip the sanitizer, only honoured for users who may publish raw HTML
+    #[serde(default)]
+    pub trusted_raw_html: bool,
+    /// Display name of the sender, blank for the one of the settings
+    #[serde(default)]
+    pub from_name: String,
+    /// Where replies go, blank for the sender address
+    #[serde(default)]
+    pub reply_to: String,
+    /// Embed a pixel which reports when the issue is opened
+    #[serde(default)]
+    pub track_opens: bool,
+    /// Send the links of the HTML content through a redirect which reports clicks
+    #[serde(default)]
+    pub track_clicks: bool,
+}
+
+/// How the author wrote the content of the issue.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentFormat {
+    /// Both `html_content` and `text_content`, by hand
+    #[default]
+    Html,
+    /// `markdown_content` only, both bodies are rendered from it
+    Markdown,
+}
+
+/// A file of the publish form, as the browser described it.
+#[derive(Debug)]
+pub struct UploadedFile {
+    pub file_name: String,
+    pub content_type: String,
+    pub content: Vec<u8>,
 }
 
 impl NewsletterData {
+    /// The publish form sent as `multipart/form-data`, i.e. with files attached.
+    ///
+    /// Reading stops as soon as a file is larger than `max_file_size` bytes.
+    pub async fn from_multipart(
+        mut multipart: Multipart,
+        max_file_size: usize,
+    ) -> Result<(Self, Vec<UploadedFile>), BizErrorEnum> {
+        let mut fields: Vec<(String, String)> = vec![];
+        let mut files = vec![];
+        while let Some(mut field) = multipart.try_next().await.map_err(invalid_form)? {
+            let name = field.name().unwrap_or_default().to_string();
+            let file_name = field
+                .content_disposition()
+                .and_then(|disposition| disposition.get_filename())
+                .map(str::to_string);
+            let content_type = field
+                .content_type()
+                .map_or("application/octet-stream".to_string(), |mime| {
+                    mime.essence_str().to_string()
+                });
+            let mut content = vec![];
+            while let Some(chunk) = field.try_next().await.map_err(invalid_form)? {
+                content.extend_from_slice(&chunk);
+                if file_name.is_some() && content.len() > max_file_size {
+                    return Err(BizErrorEnum::AttachmentIsTooLarge {
+                        file_name: file_name.unwrap_or_default(),
+                        max_kib: max_file_size / 1024,
+                    });
+                }
+            }
+            match file_name {
+                // The browser sends an empty part when no file was chosen
+                Some(file_name) if file_name.is_empty() && content.is_empty() => {}
+                Some(file_name) => files.push(UploadedFile {
+                    file_name,
+                    content_type,
+                    content,
+                }),
+                None => fields.push((
+                    name,
+                    String::from_utf8(content)
+                        .map_err(|e| BizErrorEnum::NewsletterFormIsInvalid(e.into()))?,
+                )),
+            }
+        }
+        // Same rules as for the url-encoded form
+        let data = serde_urlencoded::to_string(&fields)
+            .map_err(anyhow::Error::from)
+            .and_then(|encoded| serde_urlencoded::from_str(&encoded).map_err(anyhow::Error::from))
+            .map_err(BizErrorEnum::NewsletterFormIsInvalid)?;
+        Ok((data, files))
+    }
+
     pub fn is_title_blank(&self) -> bool {
         utils::is_blank(&self.title)
     }
@@ -21,4 +117,12 @@ impl NewsletterData {
     pub fn is_text_blank(&self) -> bool {
         utils::is_blank(&self.text_content)
     }
+
+    pub fn is_markdown_blank(&self) -> bool {
+        utils::is_blank(&self.markdown_content)
+    }
+}
+
+fn invalid_form(e: actix_multipart::MultipartError) -> BizErrorEnum {
+    BizErrorEnum::NewsletterFormIsInvalid(anyhow::anyhow!("{}", e))
 }
@@ -0,0 +1,8 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RevokeSessionData {
+    pub session_id: Uuid,
+}
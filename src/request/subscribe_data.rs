@@ -1,12 +1,65 @@
 use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
 use crate::error::BizErrorEnum;
+use crate::startup::HmacSecret;
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
 use serde::Deserialize;
+use utoipa::ToSchema;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 pub struct SubscribeData {
     pub email: String,
     pub name: String,
+    /// Honeypot: hidden from humans, bots fill in every field they find
+    #[serde(default)]
+    pub website: String,
+    /// When the form was rendered, signed, see `SubscribeData::sign_form_token`
+    #[serde(default)]
+    pub form_token: String,
 }
+
+impl SubscribeData {
+    /// Sign the time at which the subscription form is rendered: `{timestamp}.{hmac tag}`.
+    pub fn sign_form_token(
+        rendered_at: DateTime<Utc>,
+        secret: &HmacSecret,
+    ) -> Result<String, BizErrorEnum> {
+        let timestamp = rendered_at.timestamp().to_string();
+        let tag = form_token_hmac(&timestamp, secret)?.finalize().into_bytes();
+        Ok(format!("{}.{}", timestamp, hex::encode(tag)))
+    }
+
+    /// The time at which the submitted form was rendered, if the token was issued by us.
+    pub fn form_rendered_at(&self, secret: &HmacSecret) -> Result<DateTime<Utc>, BizErrorEnum> {
+        let (timestamp, tag) = self
+            .form_token
+            .split_once('.')
+            .ok_or(BizErrorEnum::SubscribeFormTokenIsMalformed)?;
+        let tag = hex::decode(tag).map_err(BizErrorEnum::HexStringDecodedError)?;
+        form_token_hmac(timestamp, secret)?
+            .verify_slice(&tag)
+            .map_err(BizErrorEnum::HmacVerifySliceError)?;
+
+        timestamp
+            .parse::<i64>()
+            .ok()
+            .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+            .ok_or(BizErrorEnum::SubscribeFormTokenIsMalformed)
+    }
+}
+
+fn form_token_hmac(
+    timestamp: &str,
+    secret: &HmacSecret,
+) -> Result<Hmac<sha2::Sha256>, BizErrorEnum> {
+    let mut hmac = Hmac::<sha2::Sha256>::new_from_slice(secret.0.expose_secret().as_bytes())
+        .map_err(BizErrorEnum::HmacGenerateError)?;
+    hmac.update(b"subscribe_form=");
+    hmac.update(timestamp.as_bytes());
+    Ok(hmac)
+}
+
 impl TryFrom<SubscribeData> for NewSubscriber {
     type Error = BizErrorEnum;
 
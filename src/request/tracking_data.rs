@@ -0,0 +1,102 @@
+use crate::error::BizErrorEnum;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+/// The query parameters of the pixel which tells us that an issue was opened.
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OpenTrackingData {
+    pub issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub tag: String,
+}
+
+impl OpenTrackingData {
+    /// The source of the 1x1 image embedded in the issue sent to `subscriber_id`.
+    pub fn link(
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+        app_base_url: &ApplicationBaseUrl,
+        secret: &HmacSecret,
+    ) -> Result<String, BizErrorEnum> {
+        let tag = tracking_hmac(b"open=", issue_id, subscriber_id, secret)?
+            .finalize()
+            .into_bytes();
+        Ok(format!(
+            "{}/issues/open?issue_id={}&subscriber_id={}&tag={}",
+            app_base_url.0,
+            issue_id,
+            subscriber_id,
+            hex::encode(tag)
+        ))
+    }
+
+    /// The issue and the subscriber who opened it, if the pixel was issued by us.
+    pub fn verify(self, secret: &HmacSecret) -> Result<(Uuid, Uuid), BizErrorEnum> {
+        let tag = hex::decode(&self.tag).map_err(|_| BizErrorEnum::TrackingLinkIsInvalid)?;
+        tracking_hmac(b"open=", self.issue_id, self.subscriber_id, secret)?
+            .verify_slice(&tag)
+            .map_err(|_| BizErrorEnum::TrackingLinkIsInvalid)?;
+        Ok((self.issue_id, self.subscriber_id))
+    }
+}
+
+/// The query parameters of a link of an issue, rewritten to pass through us.
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ClickTrackingData {
+    pub issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub url: String,
+    pub tag: String,
+}
+
+impl ClickTrackingData {
+    /// Redirects to `url`, the tag makes sure that we never become an open redirect.
+    pub fn link(
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+        url: &str,
+        app_base_url: &ApplicationBaseUrl,
+        secret: &HmacSecret,
+    ) -> Result<String, BizErrorEnum> {
+        let mut hmac = tracking_hmac(b"click=", issue_id, subscriber_id, secret)?;
+        hmac.update(url.as_bytes());
+        Ok(format!(
+            "{}/issues/click?issue_id={}&subscriber_id={}&url={}&tag={}",
+            app_base_url.0,
+            issue_id,
+            subscriber_id,
+            urlencoding::encode(url),
+            hex::encode(hmac.finalize().into_bytes())
+        ))
+    }
+
+    /// The issue, the subscriber and the link they followed, if the link was issued by us.
+    pub fn verify(self, secret: &HmacSecret) -> Result<(Uuid, Uuid, String), BizErrorEnum> {
+        let tag = hex::decode(&self.tag).map_err(|_| BizErrorEnum::TrackingLinkIsInvalid)?;
+        let mut hmac = tracking_hmac(b"click=", self.issue_id, self.subscriber_id, secret)?;
+        hmac.update(self.url.as_bytes());
+        hmac.verify_slice(&tag)
+            .map_err(|_| BizErrorEnum::TrackingLinkIsInvalid)?;
+        Ok((self.issue_id, self.subscriber_id, self.url))
+    }
+}
+
+fn tracking_hmac(
+    event: &[u8],
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    secret: &HmacSecret,
+) -> Result<Hmac<sha2::Sha256>, BizErrorEnum> {
+    let mut hmac = Hmac::<sha2::Sha256>::new_from_slice(secret.0.expose_secret().as_bytes())
+        .map_err(BizErrorEnum::HmacGenerateError)?;
+    hmac.update(event);
+    hmac.update(issue_id.as_bytes());
+    hmac.update(subscriber_id.as_bytes());
+    Ok(hmac)
+}
@@ -0,0 +1,54 @@
+use crate::error::BizErrorEnum;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+/// The query parameters of an unsubscribe link, the tag proves that we issued it.
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UnsubscribeData {
+    pub subscriber_id: Uuid,
+    pub tag: String,
+}
+
+impl UnsubscribeData {
+    /// The link a subscriber follows to stop receiving our emails, it never expires.
+    pub fn link(
+        subscriber_id: Uuid,
+        app_base_url: &ApplicationBaseUrl,
+        secret: &HmacSecret,
+    ) -> Result<String, BizErrorEnum> {
+        let tag = unsubscribe_hmac(subscriber_id, secret)?
+            .finalize()
+            .into_bytes();
+        Ok(format!(
+            "{}/subscriptions/unsubscribe?subscriber_id={}&tag={}",
+            app_base_url.0,
+            subscriber_id,
+            hex::encode(tag)
+        ))
+    }
+
+    /// The subscriber who wants to unsubscribe, if the link was issued by us.
+    pub fn verify(self, secret: &HmacSecret) -> Result<Uuid, BizErrorEnum> {
+        let tag = hex::decode(&self.tag).map_err(|_| BizErrorEnum::UnsubscribeLinkIsInvalid)?;
+        unsubscribe_hmac(self.subscriber_id, secret)?
+            .verify_slice(&tag)
+            .map_err(|_| BizErrorEnum::UnsubscribeLinkIsInvalid)?;
+        Ok(self.subscriber_id)
+    }
+}
+
+fn unsubscribe_hmac(
+    subscriber_id: Uuid,
+    secret: &HmacSecret,
+) -> Result<Hmac<sha2::Sha256>, BizErrorEnum> {
+    let mut hmac = Hmac::<sha2::Sha256>::new_from_slice(secret.0.expose_secret().as_bytes())
+        .map_err(BizErrorEnum::HmacGenerateError)?;
+    hmac.update(b"unsubscribe=");
+    hmac.update(subscriber_id.as_bytes());
+    Ok(hmac)
+}
@@ -0,0 +1,41 @@
+use crate::webhooks::WebhookEventType;
+use serde::Deserialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// One checkbox per event type, an unchecked box is not sent at all.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateWebhookData {
+    /// Where the events are posted, http or https
+    pub url: String,
+    #[serde(default)]
+    pub subscriber_subscribed: bool,
+    #[serde(default)]
+    pub subscriber_confirmed: bool,
+    #[serde(default)]
+    pub subscriber_unsubscribed: bool,
+    #[serde(default)]
+    pub subscriber_deleted: bool,
+    #[serde(default)]
+    pub issue_delivered: bool,
+}
+
+impl CreateWebhookData {
+    pub fn event_types(&self) -> Vec<WebhookEventType> {
+        WebhookEventType::ALL
+            .into_iter()
+            .filter(|event_type| match event_type {
+                WebhookEventType::SubscriberSubscribed => self.subscriber_subscribed,
+                WebhookEventType::SubscriberConfirmed => self.subscriber_confirmed,
+                WebhookEventType::SubscriberUnsubscribed => self.subscriber_unsubscribed,
+                WebhookEventType::SubscriberDeleted => self.subscriber_deleted,
+                WebhookEventType::IssueDelivered => self.issue_delivered,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RemoveWebhookData {
+    pub webhook_id: Uuid,
+}
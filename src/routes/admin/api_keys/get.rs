@@ -0,0 +1,77 @@
+use crate::auth;
+use crate::auth::{CsrfToken, UserId};
+use crate::error::BizErrorEnum;
+use crate::utils;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S UTC";
+
+#[utoipa::path(
+    get,
+    path = "/admin/api_keys",
+    tag = "admin",
+    summary = "API keys",
+    responses((status = 200, description = "The active API keys of the user", content_type = "text/html"))
+)]
+#[tracing::instrument(
+    name = "/admin/api_keys: Get API keys",
+    skip(flash_msgs, user_id, pool, csrf_token)
+)]
+pub async fn api_keys(
+    flash_msgs: IncomingFlashMessages,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, BizErrorEnum> {
+    let mut msg_html = String::new();
+    for msg in flash_msgs.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", msg.content()).unwrap();
+    }
+
+    api_keys_page(*user_id.into_inner(), &pool, &csrf_token, &msg_html).await
+}
+
+/// Also the response to the creation of a key, which shows the key this once.
+pub(crate) async fn api_keys_page(
+    user_id: Uuid,
+    pool: &PgPool,
+    csrf_token: &str,
+    msg_html: &str,
+) -> Result<HttpResponse, BizErrorEnum> {
+    // The names are typed in by the users, escape them!
+    let mut rows_html = String::new();
+    for api_key in auth::get_active_api_keys(user_id, pool).await? {
+        let last_used_at = api_key
+            .last_used_at
+            .map_or("Never".to_string(), |last_used_at| {
+                last_used_at.format(DATETIME_FORMAT).to_string()
+            });
+        writeln!(
+            rows_html,
+            r#"<tr><td>{}</td><td>{}…</td><td>{}</td><td>{}</td><td>
+                <form action="/admin/api_keys/revoke" method="post">
+                    <input hidden="hidden" type="text" name="api_key_id" value="{}">
+                    <input hidden="hidden" type="text" name="csrf_token" value="{}">
+                    <button type="submit">Revoke</button>
+                </form>
+            </td></tr>"#,
+            htmlescape::encode_minimal(&api_key.name),
+            api_key.key_prefix,
+            api_key.created_at.format(DATETIME_FORMAT),
+            last_used_at,
+            api_key.api_key_id,
+            csrf_token
+        )
+        .unwrap();
+    }
+
+    let body = include_str!("api_keys.html")
+        .replace("{{csrf_token}}", csrf_token)
+        .replace("{}", msg_html)
+        .replace("<>", &rows_html);
+    Ok(utils::ok_to(body))
+}
@@ -0,0 +1,82 @@
+use crate::auth;
+use crate::auth::{CsrfToken, UserId};
+use crate::error::BizErrorEnum;
+use crate::request::{CreateApiKeyData, RevokeApiKeyData};
+use crate::routes::admin::api_keys::api_keys_page;
+use crate::utils;
+use actix_web::http::header::{HeaderValue, CACHE_CONTROL};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+/// Answered with the page rather than a redirect: the key is shown once, and never stored.
+#[utoipa::path(
+    post,
+    path = "/admin/api_keys",
+    tag = "admin",
+    summary = "Create an API key",
+    request_body(content = CreateApiKeyData, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The API keys page, with the new key shown this once", content_type = "text/html"),
+        (status = 303, description = "Back to `/admin/api_keys` with a message, the name is invalid"),
+    )
+)]
+#[tracing::instrument(
+    name = "/admin/api_keys: Create an API key",
+    skip(form, user_id, pool, csrf_token)
+)]
+pub async fn create_api_key(
+    form: web::Form<CreateApiKeyData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, BizErrorEnum> {
+    let user_id = *user_id.into_inner();
+
+    let key = match auth::create_api_key(user_id, &form.name, &pool).await {
+        Ok(key) => key,
+        Err(
+            error @ (BizErrorEnum::ApiKeyNameIsEmpty | BizErrorEnum::ApiKeyNameIsTooLong { .. }),
+        ) => {
+            FlashMessage::error(error.to_string()).send();
+            return Ok(utils::redirect_to("/admin/api_keys"));
+        }
+        Err(error) => return Err(error),
+    };
+
+    let msg_html = format!(
+        "<p><i>The API key has been created, copy it now: it will not be shown again.</i></p>\
+        <p><code>{}</code></p>",
+        key
+    );
+    let mut response = api_keys_page(user_id, &pool, &csrf_token, &msg_html).await?;
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok(response)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/api_keys/revoke",
+    tag = "admin",
+    summary = "Revoke an API key",
+    request_body(content = RevokeApiKeyData, content_type = "application/x-www-form-urlencoded"),
+    responses((status = 303, description = "Back to `/admin/api_keys` with a message"))
+)]
+#[tracing::instrument(
+    name = "/admin/api_keys/revoke: Revoke an API key",
+    skip(form, user_id, pool)
+)]
+pub async fn revoke_api_key(
+    form: web::Form<RevokeApiKeyData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, BizErrorEnum> {
+    let user_id = *user_id.into_inner();
+
+    auth::revoke_api_key(form.into_inner().api_key_id, user_id, &pool).await?;
+
+    FlashMessage::info("The API key has been revoked.").send();
+    Ok(utils::redirect_to("/admin/api_keys"))
+}
@@ -1,14 +1,25 @@
-use crate::auth::UserId;
+use crate::auth::{CsrfToken, UserId};
 use crate::error::BizErrorEnum;
 use crate::utils;
 use actix_web::{web, HttpResponse};
 use sqlx::PgPool;
 use uuid::Uuid;
 
-#[tracing::instrument(name = "/admin/dashboard: Get admin dashboard", skip(pool, user_id))]
+#[utoipa::path(
+    get,
+    path = "/admin/dashboard",
+    tag = "admin",
+    summary = "Dashboard",
+    responses((status = 200, description = "The dashboard", content_type = "text/html"))
+)]
+#[tracing::instrument(
+    name = "/admin/dashboard: Get admin dashboard",
+    skip(pool, user_id, csrf_token)
+)]
 pub async fn admin_dashboard(
     user_id: web::ReqData<UserId>,
     pool: web::Data<PgPool>,
+    csrf_token: CsrfToken,
 ) -> Result<HttpResponse, BizErrorEnum> {
     // Verify if the user is logged in
     let user_id = *user_id.into_inner();
@@ -16,7 +27,9 @@ pub async fn admin_dashboard(
     // Get username
     let username = query_username(user_id, &pool).await?;
 
-    let body = include_str!("dashboard.html").replace("{}", &username);
+    let body = include_str!("dashboard.html")
+        .replace("{{csrf_token}}", &csrf_token)
+        .replace("{}", &username);
     Ok(utils::ok_to(body))
 }
 
@@ -30,7 +43,7 @@ pub async fn query_username(user_id: Uuid, pool: &PgPool) -> Result<String, BizE
     )
     .fetch_one(pool)
     .await
-    .map_err(|e| BizErrorEnum::QueryUsersError(e))?;
+    .map_err(BizErrorEnum::QueryUsersError)?;
 
     Ok(record.username)
 }
@@ -0,0 +1,86 @@
+use crate::auth::CsrfToken;
+use crate::email_template;
+use crate::error::BizErrorEnum;
+use crate::utils;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+#[utoipa::path(
+    get,
+    path = "/admin/email_templates",
+    tag = "admin",
+    summary = "Email templates form",
+    responses((status = 200, description = "The email templates form", content_type = "text/html"))
+)]
+#[tracing::instrument(
+    name = "/admin/email_templates: Get email templates",
+    skip(flash_msgs, pool, csrf_token)
+)]
+pub async fn email_templates_form(
+    flash_msgs: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, BizErrorEnum> {
+    // Verify if the user is logged in
+
+    // Error messages may quote what was typed in, escape them!
+    let mut msg_html = String::new();
+    for msg in flash_msgs.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(msg.content())
+        )
+        .unwrap();
+    }
+
+    let mut forms_html = String::new();
+    for template in email_template::get_email_templates(&pool).await? {
+        let placeholders = template
+            .name
+            .placeholders()
+            .iter()
+            .map(|placeholder| format!("{{{{{}}}}}", placeholder))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(
+            forms_html,
+            r#"<h3>{name}</h3>
+    <p>Placeholders: {placeholders}</p>
+    <form action="/admin/email_templates" method="post">
+        <label>
+            Subject
+            <input type="text" name="subject" value="{subject}">
+        </label>
+        <br>
+        <label>
+            HTML body
+            <textarea name="html_body" rows="10" cols="50">{html_body}</textarea>
+        </label>
+        <br>
+        <label>
+            Plain text body
+            <textarea name="text_body" rows="10" cols="50">{text_body}</textarea>
+        </label>
+        <br>
+        <input hidden="hidden" type="text" name="template_name" value="{name}">
+        <input hidden="hidden" type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Save</button>
+    </form>"#,
+            name = template.name.as_str(),
+            placeholders = placeholders,
+            subject = htmlescape::encode_attribute(&template.subject),
+            html_body = htmlescape::encode_minimal(&template.html_body),
+            text_body = htmlescape::encode_minimal(&template.text_body),
+            csrf_token = &*csrf_token,
+        )
+        .unwrap();
+    }
+
+    let body = include_str!("email_templates.html")
+        .replace("{}", &msg_html)
+        .replace("<>", &forms_html);
+    Ok(utils::ok_to(body))
+}
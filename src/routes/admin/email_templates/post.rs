@@ -0,0 +1,55 @@
+use crate::email_template;
+use crate::email_template::{EmailTemplate, EmailTemplateName};
+use crate::error::BizErrorEnum;
+use crate::request::EmailTemplateData;
+use crate::utils;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+#[utoipa::path(
+    post,
+    path = "/admin/email_templates",
+    tag = "admin",
+    summary = "Update an email template",
+    request_body(content = EmailTemplateData, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 303, description = "Back to `/admin/email_templates` with a message"),
+        (status = 400, description = "No template has this name"),
+    )
+)]
+#[tracing::instrument(
+    name = "/admin/email_templates: Update an email template",
+    skip(form, pool),
+    fields(template_name = %form.template_name)
+)]
+pub async fn update_email_template(
+    form: web::Form<EmailTemplateData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, BizErrorEnum> {
+    let EmailTemplateData {
+        template_name,
+        subject,
+        html_body,
+        text_body,
+    } = form.into_inner();
+    let template = EmailTemplate {
+        name: EmailTemplateName::parse(&template_name)?,
+        subject,
+        html_body,
+        text_body,
+    };
+    if let Err(error) = template.validate() {
+        FlashMessage::error(error.to_string()).send();
+        return Ok(utils::redirect_to("/admin/email_templates"));
+    }
+
+    email_template::update_email_template(&template, &pool).await?;
+
+    FlashMessage::info(format!(
+        "The {} email template has been saved.",
+        template.name.as_str()
+    ))
+    .send();
+    Ok(utils::redirect_to("/admin/email_templates"))
+}
@@ -0,0 +1,141 @@
+use crate::configuration::TrackingSettings;
+use crate::error::BizErrorEnum;
+use crate::{tracking, utils};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S UTC";
+
+#[utoipa::path(
+    get,
+    path = "/admin/issues",
+    tag = "admin",
+    summary = "Published newsletter issues",
+    responses((status = 200, description = "The issues", content_type = "text/html"))
+)]
+#[tracing::instrument(name = "/admin/issues: Get newsletter issues", skip(pool))]
+pub async fn newsletter_issues(pool: web::Data<PgPool>) -> Result<HttpResponse, BizErrorEnum> {
+    // Titles are typed in by the authors, escape them!
+    let mut rows_html = String::new();
+    for issue in get_issues(&pool).await? {
+        writeln!(
+            rows_html,
+            r#"<tr><td><a href="/admin/issues/{}">{}</a></td><td>{}</td></tr>"#,
+            issue.newsletter_issue_id,
+            htmlescape::encode_minimal(&issue.title),
+            issue.published_at.format(DATETIME_FORMAT)
+        )
+        .unwrap();
+    }
+
+    let body = include_str!("issues.html").replace("{}", &rows_html);
+    Ok(utils::ok_to(body))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/issues/{issue_id}",
+    tag = "admin",
+    summary = "Engagement of a newsletter issue",
+    params(("issue_id" = Uuid, Path)),
+    responses(
+        (status = 200, description = "Opens and clicks of the issue", content_type = "text/html"),
+        (status = 404, description = "No such issue"),
+    )
+)]
+#[tracing::instrument(
+    name = "/admin/issues/{issue_id}: Get engagement of a newsletter issue",
+    skip(pool, tracking_settings)
+)]
+pub async fn newsletter_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    tracking_settings: web::Data<TrackingSettings>,
+) -> Result<HttpResponse, BizErrorEnum> {
+    let engagement = tracking::get_issue_engagement(&pool, issue_id.into_inner())
+        .await?
+        .ok_or(BizErrorEnum::NewsletterIssueNotFound)?;
+
+    let tracking_note = if tracking_settings.enabled {
+        ""
+    } else {
+        "<p><i>Tracking is disabled in the settings, no new event is recorded.</i></p>"
+    };
+    let mut stats_html = String::new();
+    writeln!(
+        stats_html,
+        "<tr><td>Recipients</td><td>{}</td><td></td></tr>",
+        engagement.recipients_count
+    )
+    .unwrap();
+    writeln!(
+        stats_html,
+        "<tr><td>Opens</td><td>{}</td><td>{}</td></tr>",
+        engagement.unique_opens,
+        tracked_rate(engagement.track_opens, engagement.open_rate())
+    )
+    .unwrap();
+    writeln!(
+        stats_html,
+        "<tr><td>Clicks</td><td>{}</td><td>{}</td></tr>",
+        engagement.unique_clicks,
+        tracked_rate(engagement.track_clicks, engagement.click_rate())
+    )
+    .unwrap();
+
+    // The links come from the content of the issue, escape them!
+    let mut links_html = String::new();
+    for (url, clicks) in &engagement.links {
+        writeln!(
+            links_html,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(url),
+            clicks
+        )
+        .unwrap();
+    }
+
+    let body = include_str!("issue.html")
+        .replace("{{title}}", &htmlescape::encode_minimal(&engagement.title))
+        .replace(
+            "{{published_at}}",
+            &engagement.published_at.format(DATETIME_FORMAT).to_string(),
+        )
+        .replace("{{tracking_note}}", tracking_note)
+        .replace("{}", &stats_html)
+        .replace("<>", &links_html);
+    Ok(utils::ok_to(body))
+}
+
+/// E.g. "42.9%", or why there is no rate.
+fn tracked_rate(tracked: bool, rate: f64) -> String {
+    if tracked {
+        format!("{:.1}%", rate)
+    } else {
+        "Not tracked".to_string()
+    }
+}
+
+struct IssueRow {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Query newsletter issues", skip(pool))]
+async fn get_issues(pool: &PgPool) -> Result<Vec<IssueRow>, BizErrorEnum> {
+    sqlx::query_as!(
+        IssueRow,
+        r#"
+            SELECT newsletter_issue_id, title, published_at
+            FROM newsletter_issues
+            ORDER BY published_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(BizErrorEnum::QueryNewsletterIssuesError)
+}
@@ -1,13 +1,36 @@
+use crate::auth;
+use crate::auth::{UserId, UserSessionId};
 use crate::error::BizErrorEnum;
+use crate::request::CsrfData;
 use crate::session_state::TypedSession;
 use crate::utils;
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
 
-#[tracing::instrument(name = "/admin/logout: Logout", skip(session))]
-pub async fn log_out(session: TypedSession) -> Result<HttpResponse, BizErrorEnum> {
+#[utoipa::path(
+    post,
+    path = "/admin/logout",
+    tag = "admin",
+    summary = "Log out",
+    request_body(content = CsrfData, content_type = "application/x-www-form-urlencoded"),
+    responses((status = 303, description = "To `/login`"))
+)]
+#[tracing::instrument(
+    name = "/admin/logout: Logout",
+    skip(session, user_id, session_id, pool)
+)]
+pub async fn log_out(
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    session_id: web::ReqData<UserSessionId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, BizErrorEnum> {
     // Verify if the user is logged in
+    let user_id = *user_id.into_inner();
+    let session_id = *session_id.into_inner();
 
+    auth::revoke_user_session(session_id, user_id, &pool).await?;
     session.log_out();
 
     FlashMessage::info("You have successfully logged out.").send();
@@ -1,9 +1,19 @@
+mod api_keys;
 mod dashboard;
+mod email_templates;
+mod issues;
 mod logout;
 mod newsletter;
 mod password;
+mod sessions;
+mod webhooks;
 
+pub use api_keys::*;
 pub use dashboard::*;
+pub use email_templates::*;
+pub use issues::*;
 pub use logout::*;
 pub use newsletter::*;
 pub use password::*;
+pub use sessions::*;
+pub use webhooks::*;
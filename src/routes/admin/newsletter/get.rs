@@ -1,3 +1,4 @@
+use crate::auth::CsrfToken;
 use crate::error::BizErrorEnum;
 use crate::utils;
 use actix_web::HttpResponse;
@@ -5,18 +6,36 @@ use actix_web_flash_messages::IncomingFlashMessages;
 use std::fmt::Write;
 use uuid::Uuid;
 
-#[tracing::instrument(name = "/admin/newsletter: Get newsletter form", skip(flash_msgs))]
+#[utoipa::path(
+    get,
+    path = "/admin/newsletter",
+    tag = "admin",
+    summary = "Publish form",
+    responses((status = 200, description = "The publish form", content_type = "text/html"))
+)]
+#[tracing::instrument(
+    name = "/admin/newsletter: Get newsletter form",
+    skip(flash_msgs, csrf_token)
+)]
 pub async fn publish_newsletter_form(
     flash_msgs: IncomingFlashMessages,
+    csrf_token: CsrfToken,
 ) -> Result<HttpResponse, BizErrorEnum> {
     // Verify if the user is logged in
 
+    // Error messages may quote what was typed in, escape them!
     let mut msg_html = String::new();
     for msg in flash_msgs.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", msg.content()).unwrap();
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(msg.content())
+        )
+        .unwrap();
     }
     let idempotency_key = Uuid::new_v4().to_string();
     let body = include_str!("newsletter.html")
+        .replace("{{csrf_token}}", &csrf_token)
         .replace("{}", &msg_html)
         .replace("<>", &idempotency_key);
     Ok(utils::ok_to(body))
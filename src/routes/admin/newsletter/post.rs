@@ -1,70 +1,219 @@
 use crate::auth::Credentials;
 use crate::auth::UserId;
-use crate::domain::SubscriberEmail;
+use crate::configuration::AttachmentSettings;
+use crate::domain::{NewsletterAttachment, NewsletterContent, SenderName, SubscriberEmail};
+use crate::email_template;
+use crate::email_template::NEWSLETTER_ISSUE_MERGE_FIELDS;
 use crate::error::BizErrorEnum;
-use crate::idempotency::{IdempotencyKey, NextAction};
-use crate::request::NewsletterData;
-use crate::{idempotency, telemetry, utils};
+use crate::idempotency::IdempotentRequest;
+use crate::request::{ContentFormat, NewsletterData, UploadedFile};
+use crate::{issue_delivery_worker, telemetry, utils};
+use actix_multipart::Multipart;
 use actix_web::http::header::HeaderMap;
-use actix_web::{web, HttpResponse};
+use actix_web::{web, Either, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use base64::Engine;
 use secrecy::Secret;
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+#[utoipa::path(
+    post,
+    path = "/admin/newsletter",
+    tag = "admin",
+    summary = "Publish a newsletter issue",
+    description = "The form also has an `idempotency_key` field, which is required. \
+        Sent as `multipart/form-data`, it may have files in its `attachments` field.",
+    request_body(content(
+        (NewsletterData = "application/x-www-form-urlencoded"),
+        (NewsletterData = "multipart/form-data"),
+    )),
+    responses(
+        (status = 303, description = "Back to `/admin/newsletter` with a message"),
+        (status = 400, description = "The idempotency key is missing or invalid"),
+        (status = 409, description = "A retry with the same key is in flight"),
+        (status = 422, description = "The idempotency key was used for another request"),
+    )
+)]
 #[tracing::instrument(
     name = "/admin/newsletter: Publish a newsletter issue",
     skip_all,
     fields(user_id=tracing::field::Empty)
 )]
 pub async fn publish_newsletter(
-    body: web::Form<NewsletterData>,
+    body: Either<web::Form<NewsletterData>, Multipart>,
     pool: web::Data<PgPool>,
     user_id: web::ReqData<UserId>,
+    attachment_settings: web::Data<AttachmentSettings>,
+    idempotency: IdempotentRequest,
 ) -> Result<HttpResponse, BizErrorEnum> {
     // Get user_id
     let user_id = *user_id.into_inner();
-    telemetry::record_field("user_id", &user_id);
+    telemetry::record_field("user_id", user_id);
+    // Files can only be sent as multipart/form-data
+    let (body, uploaded_files) = match body {
+        Either::Left(form) => (form.into_inner(), vec![]),
+        Either::Right(multipart) => {
+            match NewsletterData::from_multipart(multipart, attachment_settings.max_file_size_bytes)
+                .await
+            {
+                Ok(form) => form,
+                Err(error @ BizErrorEnum::AttachmentIsTooLarge { .. }) => {
+                    return Ok(back_to_form_with(error))
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    };
     let NewsletterData {
         title,
+        content_format,
         text_content,
         html_content,
-        idempotency_key,
-    } = body.into_inner();
-    let idempotency_key: IdempotencyKey = idempotency_key.try_into()?;
-
-    // Return early if we have a saved response in the database
-    let mut transaction =
-        match idempotency::try_processing(&pool, &idempotency_key, user_id).await? {
-            NextAction::StartProcessing(t) => t,
-            NextAction::ReturnSavedResponse(saved_response) => {
-                FlashMessage::info(
-                    "The newsletter issue has been accepted - emails will go out shortly.",
-                )
-                .send();
-                return Ok(saved_response);
-            }
-        };
+        markdown_content,
+        trusted_raw_html,
+        from_name,
+        reply_to,
+        track_opens,
+        track_clicks,
+    } = body;
+    if trusted_raw_html && !can_publish_raw_html(user_id, &pool).await? {
+        return Ok(back_to_form_with(BizErrorEnum::RawHtmlIsNotAllowed));
+    }
+    let content = match content_format {
+        ContentFormat::Html if trusted_raw_html => {
+            NewsletterContent::parse_trusted(html_content, text_content)?
+        }
+        ContentFormat::Html => NewsletterContent::parse(html_content, text_content)?,
+        ContentFormat::Markdown => NewsletterContent::from_markdown(markdown_content)?,
+    };
 
-    // Save title and content
-    let issue_id =
-        insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content).await?;
+    // Catch a mistyped merge field before anything is sent
+    if let Err(error) = validate_merge_fields(&[&title, content.html(), content.text()]) {
+        return Ok(back_to_form_with(error));
+    }
+    let sender = match IssueSender::parse(from_name, reply_to) {
+        Ok(sender) => sender,
+        Err(error) => return Ok(back_to_form_with(error)),
+    };
+    let attachments = match parse_attachments(uploaded_files, &attachment_settings) {
+        Ok(attachments) => attachments,
+        Err(error) => return Ok(back_to_form_with(error)),
+    };
 
-    // Gen delivery task
-    enqueue_delivery_tasks(&mut transaction, issue_id).await?;
+    // From here on the response is saved, see `idempotency::idempotent`
+    let mut transaction = idempotency.transaction()?;
 
-    // Make response
-    let response = utils::redirect_to("/admin/newsletter");
-    let http_response =
-        idempotency::update_response(transaction, user_id, &idempotency_key, response).await?;
+    let tracking = IssueTracking {
+        opens: track_opens,
+        clicks: track_clicks,
+    };
+    publish_issue(
+        &mut transaction,
+        &title,
+        &content,
+        &sender,
+        tracking,
+        &attachments,
+    )
+    .await?;
+
+    flash_newsletter_accepted();
+    // Let the author know what did not make it into the emails
+    for warning in content.warnings() {
+        FlashMessage::warning(format!("The HTML content was sanitized: {}", warning)).send();
+    }
+    Ok(utils::redirect_to("/admin/newsletter"))
+}
+
+/// Also sent when a retry of the form is answered with the saved response.
+pub fn flash_newsletter_accepted() {
     FlashMessage::info("The newsletter issue has been accepted - emails will go out shortly.")
         .send();
-    Ok(http_response)
+}
+
+/// Save the issue and queue an email for every confirmed subscriber, returns the id of the issue.
+///
+/// Shared with `POST /api/v1/issues`, which has no attachments.
+pub(crate) async fn publish_issue(
+    transaction: &mut Transaction<'static, Postgres>,
+    title: &str,
+    content: &NewsletterContent,
+    sender: &IssueSender,
+    tracking: IssueTracking,
+    attachments: &[NewsletterAttachment],
+) -> Result<Uuid, BizErrorEnum> {
+    // Save title and content
+    let issue_id = insert_newsletter_issue(transaction, title, content, sender, tracking).await?;
+    insert_attachments(transaction, issue_id, attachments, content.html()).await?;
+
+    // Gen delivery task
+    let recipients_count = enqueue_delivery_tasks(transaction, issue_id).await?;
+    update_recipients_count(transaction, issue_id, recipients_count).await?;
+    // Without confirmed subscribers, the issue is delivered already
+    if recipients_count == 0 {
+        issue_delivery_worker::report_if_issue_delivered(transaction, issue_id).await?;
+    }
+
+    Ok(issue_id)
+}
+
+#[tracing::instrument(name = "Query raw HTML permission", skip(pool))]
+pub(crate) async fn can_publish_raw_html(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, BizErrorEnum> {
+    let record = sqlx::query!(
+        r#"SELECT can_publish_raw_html FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(BizErrorEnum::QueryUsersError)?;
+
+    Ok(record.can_publish_raw_html)
+}
+
+/// Mistakes the author can fix, nothing has been saved.
+fn back_to_form_with(error: BizErrorEnum) -> HttpResponse {
+    FlashMessage::error(error.to_string()).send();
+    utils::redirect_to("/admin/newsletter")
+}
+
+fn parse_attachments(
+    uploaded_files: Vec<UploadedFile>,
+    settings: &AttachmentSettings,
+) -> Result<Vec<NewsletterAttachment>, BizErrorEnum> {
+    let attachments = uploaded_files
+        .into_iter()
+        .map(|file| {
+            NewsletterAttachment::parse(&file.file_name, &file.content_type, file.content, settings)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    NewsletterAttachment::validate_all(&attachments, settings)?;
+    Ok(attachments)
+}
+
+/// Merge fields are resolved for each recipient when the issue is sent.
+pub(crate) fn validate_merge_fields(sources: &[&str]) -> Result<(), BizErrorEnum> {
+    let unknown = sources.iter().find_map(|source| {
+        email_template::find_unknown_placeholder(source, &NEWSLETTER_ISSUE_MERGE_FIELDS)
+    });
+    match unknown {
+        None => Ok(()),
+        Some(field) => Err(BizErrorEnum::NewsletterMergeFieldIsUnknown {
+            field: field.to_string(),
+            available: NEWSLETTER_ISSUE_MERGE_FIELDS
+                .iter()
+                .map(|field| format!("{{{{{}}}}}", field))
+                .collect::<Vec<_>>()
+                .join(", "),
+        }),
+    }
 }
 
 #[tracing::instrument(name = "Query confirmed subscribers", skip(pool))]
-#[deprecated(since = "1.0", note = "refactoring")]
+#[deprecated(since = "1.0.0", note = "refactoring")]
 async fn get_confirmed_subscribers(
     pool: &PgPool,
     // We are returning a `Vec` of `Result`s in the happy case.
@@ -113,23 +262,23 @@ async fn get_confirmed_subscribers(
 ///
 /// Authorization: Basic {username}:{password}
 #[tracing::instrument(name = "Get credentials from headers", skip(headers))]
-#[deprecated(since = "1.0", note = "refactoring")]
+#[deprecated(since = "1.0.0", note = "refactoring")]
 fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, BizErrorEnum> {
     let header_value = headers
         .get("Authorization")
         .ok_or(BizErrorEnum::AuthorizationHeaderIsMissing)?
         .to_str()
-        .map_err(|e| BizErrorEnum::AuthorizationHeaderIsInvalidUtf8String(e))?;
+        .map_err(BizErrorEnum::AuthorizationHeaderIsInvalidUtf8String)?;
     let base64_encoded_segment = header_value
         .strip_prefix("Basic ")
         .ok_or(BizErrorEnum::AuthorizationSchemeNotBasic)?;
 
     let decoded_bytes = base64::engine::general_purpose::STANDARD
         .decode(base64_encoded_segment)
-        .map_err(|e| BizErrorEnum::Base64DecodeError(e))?;
+        .map_err(BizErrorEnum::Base64DecodeError)?;
 
     let decoded_credentials = String::from_utf8(decoded_bytes)
-        .map_err(|e| BizErrorEnum::CredentialStringIsInvalidUtf8String(e))?;
+        .map_err(BizErrorEnum::CredentialStringIsInvalidUtf8String)?;
 
     // Split into two segments, using ':' as delimiter
     let mut credentials = decoded_credentials.splitn(2, ':');
@@ -149,12 +298,44 @@ fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, BizErrorEnum
     })
 }
 
+/// Who the issue comes from, as the subscribers see it.
+#[derive(Debug)]
+pub(crate) struct IssueSender {
+    from_name: Option<SenderName>,
+    reply_to: Option<SubscriberEmail>,
+}
+
+impl IssueSender {
+    pub(crate) fn parse(from_name: String, reply_to: String) -> Result<Self, BizErrorEnum> {
+        let from_name = SenderName::parse(from_name)?;
+        let reply_to = match reply_to.trim() {
+            "" => None,
+            reply_to => Some(
+                SubscriberEmail::parse(reply_to.to_string())
+                    .map_err(|_| BizErrorEnum::NewsletterReplyToIsInvalid(reply_to.to_string()))?,
+            ),
+        };
+        Ok(Self {
+            from_name,
+            reply_to,
+        })
+    }
+}
+
+/// What the author chose to track, see `tracking`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IssueTracking {
+    pub opens: bool,
+    pub clicks: bool,
+}
+
 #[tracing::instrument(name = "Insert newsletter issue", skip_all)]
 async fn insert_newsletter_issue(
     transaction: &mut Transaction<'static, Postgres>,
     title: &str,
-    text_content: &str,
-    html_content: &str,
+    content: &NewsletterContent,
+    sender: &IssueSender,
+    tracking: IssueTracking,
 ) -> Result<Uuid, BizErrorEnum> {
     let newsletter_issue_id = Uuid::new_v4();
     sqlx::query!(
@@ -164,28 +345,74 @@ async fn insert_newsletter_issue(
                 title,
                 text_content,
                 html_content,
+                markdown_content,
+                track_opens,
+                track_clicks,
+                from_name,
+                reply_to,
                 published_at
             ) 
-            VALUES ($1, $2, $3, $4, now())
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, now())
         "#,
         newsletter_issue_id,
         title,
-        text_content,
-        html_content
+        content.text(),
+        content.html(),
+        content.markdown(),
+        tracking.opens,
+        tracking.clicks,
+        sender.from_name.as_ref().map(AsRef::as_ref),
+        sender.reply_to.as_ref().map(AsRef::as_ref)
     )
     .execute(transaction)
     .await
-    .map_err(|e| BizErrorEnum::InsertNewsletterIssuesError(e))?;
+    .map_err(BizErrorEnum::InsertNewsletterIssuesError)?;
 
     Ok(newsletter_issue_id)
 }
 
+/// Images referenced by the HTML content with `cid:` are shown inline rather than attached.
+#[tracing::instrument(name = "Insert newsletter issue attachments", skip_all)]
+async fn insert_attachments(
+    transaction: &mut Transaction<'static, Postgres>,
+    newsletter_issue_id: Uuid,
+    attachments: &[NewsletterAttachment],
+    html_content: &str,
+) -> Result<(), BizErrorEnum> {
+    for attachment in attachments {
+        sqlx::query!(
+            r#"
+                INSERT INTO newsletter_issue_attachments (
+                    attachment_id,
+                    newsletter_issue_id,
+                    file_name,
+                    content_type,
+                    content,
+                    content_id
+                )
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            Uuid::new_v4(),
+            newsletter_issue_id,
+            attachment.file_name(),
+            attachment.content_type(),
+            attachment.content(),
+            attachment.content_id(html_content)
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(BizErrorEnum::InsertNewsletterIssueAttachmentsError)?;
+    }
+    Ok(())
+}
+
+/// Returns the number of recipients.
 #[tracing::instrument(name = "Insert issue delivery queue", skip_all)]
 async fn enqueue_delivery_tasks(
     transaction: &mut Transaction<'static, Postgres>,
     newsletter_issue_id: Uuid,
-) -> Result<(), BizErrorEnum> {
-    sqlx::query!(
+) -> Result<u64, BizErrorEnum> {
+    let result = sqlx::query!(
         r#"
             INSERT INTO issue_delivery_queue (
                 newsletter_issue_id, 
@@ -199,7 +426,29 @@ async fn enqueue_delivery_tasks(
     )
     .execute(transaction)
     .await
-    .map_err(|e| BizErrorEnum::InsertIssueDeliveryQueueError(e))?;
+    .map_err(BizErrorEnum::InsertIssueDeliveryQueueError)?;
+
+    Ok(result.rows_affected())
+}
+
+/// The base of the open and click rates.
+#[tracing::instrument(
+    name = "Update recipients count of newsletter issue",
+    skip(transaction)
+)]
+async fn update_recipients_count(
+    transaction: &mut Transaction<'static, Postgres>,
+    newsletter_issue_id: Uuid,
+    recipients_count: u64,
+) -> Result<(), BizErrorEnum> {
+    sqlx::query!(
+        r#"UPDATE newsletter_issues SET recipients_count = $2 WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id,
+        i32::try_from(recipients_count).unwrap_or(i32::MAX)
+    )
+    .execute(transaction)
+    .await
+    .map_err(BizErrorEnum::UpdateNewsletterIssuesError)?;
 
     Ok(())
 }
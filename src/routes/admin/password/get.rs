@@ -1,12 +1,24 @@
+use crate::auth::CsrfToken;
 use crate::error::BizErrorEnum;
 use crate::utils;
 use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
 use std::fmt::Write;
 
-#[tracing::instrument(name = "/admin/password: Change password page", skip(flash_msgs))]
+#[utoipa::path(
+    get,
+    path = "/admin/password",
+    tag = "admin",
+    summary = "Change password form",
+    responses((status = 200, description = "The change password form", content_type = "text/html"))
+)]
+#[tracing::instrument(
+    name = "/admin/password: Change password page",
+    skip(flash_msgs, csrf_token)
+)]
 pub async fn change_password_form(
     flash_msgs: IncomingFlashMessages,
+    csrf_token: CsrfToken,
 ) -> Result<HttpResponse, BizErrorEnum> {
     // Verify if the user is logged in
 
@@ -14,6 +26,8 @@ pub async fn change_password_form(
     for msg in flash_msgs.iter() {
         writeln!(msg_html, "<p><i>{}</i></p>", msg.content()).unwrap();
     }
-    let body = include_str!("change_password.html").replace("{}", &msg_html);
+    let body = include_str!("change_password.html")
+        .replace("{{csrf_token}}", &csrf_token)
+        .replace("{}", &msg_html);
     Ok(utils::ok_to(body))
 }
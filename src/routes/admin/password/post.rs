@@ -1,26 +1,41 @@
-use crate::auth::{Credentials, UserId};
+use crate::auth::{Credentials, UserId, UserSessionId};
+use crate::configuration::PasswordPolicySettings;
+use crate::domain::NewPassword;
 use crate::error::BizErrorEnum;
 use crate::request::ChangePasswordData;
 use crate::routes;
 use crate::{auth, utils};
 use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use argon2::Params;
 use secrecy::ExposeSecret;
 use sqlx::PgPool;
-use validator::HasLen;
 
+#[utoipa::path(
+    post,
+    path = "/admin/password",
+    tag = "admin",
+    summary = "Change password",
+    description = "Every other session of the user is revoked.",
+    request_body(content = ChangePasswordData, content_type = "application/x-www-form-urlencoded"),
+    responses((status = 303, description = "Back to `/admin/password` with a message"))
+)]
 #[tracing::instrument(
     name = "/admin/password: Handle change password",
-    skip(form, pool, user_id)
+    skip(form, pool, argon2_params, password_policy, user_id, session_id)
 )]
 pub async fn change_password(
     form: web::Form<ChangePasswordData>,
     pool: web::Data<PgPool>,
+    argon2_params: web::Data<Params>,
+    password_policy: web::Data<PasswordPolicySettings>,
     // No longer injecting TypedSession!
     user_id: web::ReqData<UserId>,
+    session_id: web::ReqData<UserSessionId>,
 ) -> Result<HttpResponse, BizErrorEnum> {
     // Verify if the user is logged in
     let user_id = *user_id.into_inner();
+    let session_id = *session_id.into_inner();
 
     // Get username
     let username = routes::query_username(user_id, &pool).await?;
@@ -28,10 +43,10 @@ pub async fn change_password(
     // Validate current password is valid
     let password_data = form.into_inner();
     let credentials = Credentials {
-        username,
+        username: username.clone(),
         password: password_data.current_password.clone(),
     };
-    if let Err(error) = auth::validate_credentials(credentials, &pool).await {
+    if let Err(error) = auth::validate_credentials(credentials, &argon2_params, &pool).await {
         return match error {
             BizErrorEnum::InvalidUsername => {
                 FlashMessage::error("The current username is incorrect.").send();
@@ -54,14 +69,23 @@ pub async fn change_password(
         .send();
         return Ok(utils::redirect_to("/admin/password"));
     }
-    // Validate the length of new password
-    let length = password_data.new_password.expose_secret().length();
-    if length < 6 || length > 128 {
-        FlashMessage::error("The length of new password must >= 6 && <= 128 characters.").send();
-        return Ok(utils::redirect_to("/admin/password"));
-    }
+    // Validate new password against the password policy
+    let new_password = match NewPassword::parse(
+        password_data.new_password,
+        &username,
+        &password_data.current_password,
+        &password_policy,
+    ) {
+        Ok(new_password) => new_password,
+        Err(error) => {
+            FlashMessage::error(error.to_string()).send();
+            return Ok(utils::redirect_to("/admin/password"));
+        }
+    };
     // Update new password
-    auth::update_new_password(user_id, password_data.new_password, &pool).await?;
+    auth::update_new_password(user_id, new_password.into(), &argon2_params, &pool).await?;
+    // Whoever knew the old password must not stay logged in
+    auth::revoke_other_user_sessions(session_id, user_id, &pool).await?;
 
     FlashMessage::info("Your password has been changed.").send();
     Ok(utils::redirect_to("/admin/password"))
@@ -0,0 +1,71 @@
+use crate::auth;
+use crate::auth::{CsrfToken, UserId, UserSessionId};
+use crate::error::BizErrorEnum;
+use crate::utils;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S UTC";
+
+#[utoipa::path(
+    get,
+    path = "/admin/sessions",
+    tag = "admin",
+    summary = "Active sessions",
+    responses((status = 200, description = "The sessions of the user", content_type = "text/html"))
+)]
+#[tracing::instrument(
+    name = "/admin/sessions: Get active sessions",
+    skip(flash_msgs, user_id, session_id, pool, csrf_token)
+)]
+pub async fn active_sessions(
+    flash_msgs: IncomingFlashMessages,
+    user_id: web::ReqData<UserId>,
+    session_id: web::ReqData<UserSessionId>,
+    pool: web::Data<PgPool>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, BizErrorEnum> {
+    // Verify if the user is logged in
+    let user_id = *user_id.into_inner();
+    let current_session_id = *session_id.into_inner();
+
+    let mut msg_html = String::new();
+    for msg in flash_msgs.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", msg.content()).unwrap();
+    }
+
+    // The ip address and the user agent come from the client, escape them!
+    let mut rows_html = String::new();
+    for user_session in auth::get_active_user_sessions(user_id, &pool).await? {
+        let action = if user_session.session_id == current_session_id {
+            "This session".to_string()
+        } else {
+            format!(
+                r#"<form action="/admin/sessions/revoke" method="post">
+                <input hidden="hidden" type="text" name="session_id" value="{}">
+                <input hidden="hidden" type="text" name="csrf_token" value="{}">
+                <button type="submit">Log out</button>
+            </form>"#,
+                user_session.session_id, &*csrf_token
+            )
+        };
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            user_session.created_at.format(DATETIME_FORMAT),
+            user_session.last_seen_at.format(DATETIME_FORMAT),
+            htmlescape::encode_minimal(user_session.ip_address.as_deref().unwrap_or("Unknown")),
+            htmlescape::encode_minimal(user_session.user_agent.as_deref().unwrap_or("Unknown")),
+            action
+        )
+        .unwrap();
+    }
+
+    let body = include_str!("sessions.html")
+        .replace("{{csrf_token}}", &csrf_token)
+        .replace("{}", &msg_html)
+        .replace("<>", &rows_html);
+    Ok(utils::ok_to(body))
+}
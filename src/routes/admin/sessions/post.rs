@@ -0,0 +1,73 @@
+use crate::auth;
+use crate::auth::{UserId, UserSessionId};
+use crate::error::BizErrorEnum;
+use crate::request::{CsrfData, RevokeSessionData};
+use crate::session_state::TypedSession;
+use crate::utils;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+#[utoipa::path(
+    post,
+    path = "/admin/sessions/revoke",
+    tag = "admin",
+    summary = "Revoke a session",
+    request_body(content = RevokeSessionData, content_type = "application/x-www-form-urlencoded"),
+    responses((status = 303, description = "Back to `/admin/sessions`, or to `/login` for the current session"))
+)]
+#[tracing::instrument(
+    name = "/admin/sessions/revoke: Revoke a session",
+    skip(form, session, user_id, session_id, pool)
+)]
+pub async fn revoke_session(
+    form: web::Form<RevokeSessionData>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    session_id: web::ReqData<UserSessionId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, BizErrorEnum> {
+    // Verify if the user is logged in
+    let user_id = *user_id.into_inner();
+    let current_session_id = *session_id.into_inner();
+    let session_id = form.into_inner().session_id;
+
+    auth::revoke_user_session(session_id, user_id, &pool).await?;
+
+    // Revoking the current session is just a log out
+    if session_id == current_session_id {
+        session.log_out();
+        FlashMessage::info("You have successfully logged out.").send();
+        return Ok(utils::redirect_to("/login"));
+    }
+
+    FlashMessage::info("The session has been logged out.").send();
+    Ok(utils::redirect_to("/admin/sessions"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/sessions/revoke_others",
+    tag = "admin",
+    summary = "Revoke all the other sessions",
+    request_body(content = CsrfData, content_type = "application/x-www-form-urlencoded"),
+    responses((status = 303, description = "Back to `/admin/sessions`"))
+)]
+#[tracing::instrument(
+    name = "/admin/sessions/revoke_others: Revoke all other sessions",
+    skip(user_id, session_id, pool)
+)]
+pub async fn revoke_other_sessions(
+    user_id: web::ReqData<UserId>,
+    session_id: web::ReqData<UserSessionId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, BizErrorEnum> {
+    // Verify if the user is logged in
+    let user_id = *user_id.into_inner();
+    let session_id = *session_id.into_inner();
+
+    auth::revoke_other_user_sessions(session_id, user_id, &pool).await?;
+
+    FlashMessage::info("All other sessions have been logged out.").send();
+    Ok(utils::redirect_to("/admin/sessions"))
+}
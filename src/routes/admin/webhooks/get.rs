@@ -0,0 +1,105 @@
+use crate::auth::CsrfToken;
+use crate::error::BizErrorEnum;
+use crate::{utils, webhooks};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S UTC";
+
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks",
+    tag = "admin",
+    summary = "Webhooks",
+    responses((status = 200, description = "The endpoints the events are posted to", content_type = "text/html"))
+)]
+#[tracing::instrument(
+    name = "/admin/webhooks: Get webhooks",
+    skip(flash_msgs, pool, csrf_token)
+)]
+pub async fn webhook_endpoints(
+    flash_msgs: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, BizErrorEnum> {
+    let mut msg_html = String::new();
+    for msg in flash_msgs.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", msg.content()).unwrap();
+    }
+
+    webhooks_page(&pool, &csrf_token, &msg_html).await
+}
+
+/// Also the response to the creation of an endpoint, which shows its secret this once.
+pub(crate) async fn webhooks_page(
+    pool: &PgPool,
+    csrf_token: &str,
+    msg_html: &str,
+) -> Result<HttpResponse, BizErrorEnum> {
+    // The URLs are typed in by the users, escape them!
+    let mut rows_html = String::new();
+    for webhook in webhooks::get_webhooks(pool).await? {
+        writeln!(
+            rows_html,
+            r#"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>
+                <form action="/admin/webhooks/remove" method="post">
+                    <input hidden="hidden" type="text" name="webhook_id" value="{}">
+                    <input hidden="hidden" type="text" name="csrf_token" value="{}">
+                    <button type="submit">Remove</button>
+                </form>
+            </td></tr>"#,
+            htmlescape::encode_minimal(&webhook.url),
+            htmlescape::encode_minimal(&webhook.event_types.join(", ")),
+            webhook.created_at.format(DATETIME_FORMAT),
+            webhook.pending,
+            webhook.webhook_id,
+            csrf_token
+        )
+        .unwrap();
+    }
+
+    let body = include_str!("webhooks.html")
+        .replace("{{csrf_token}}", csrf_token)
+        .replace("{}", msg_html)
+        .replace("<>", &rows_html);
+    Ok(utils::ok_to(body))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks/deliveries",
+    tag = "admin",
+    summary = "Webhook delivery log",
+    responses((status = 200, description = "The latest attempts to post an event", content_type = "text/html"))
+)]
+#[tracing::instrument(
+    name = "/admin/webhooks/deliveries: Get webhook deliveries",
+    skip(pool)
+)]
+pub async fn webhook_deliveries(pool: web::Data<PgPool>) -> Result<HttpResponse, BizErrorEnum> {
+    // The URLs are typed in by the users and the errors quote them, escape them!
+    let mut rows_html = String::new();
+    for delivery in webhooks::get_webhook_deliveries(&pool).await? {
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}<br><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{} ms</td><td>{}</td></tr>",
+            delivery.attempted_at.format(DATETIME_FORMAT),
+            htmlescape::encode_minimal(&delivery.url),
+            htmlescape::encode_minimal(&delivery.event_type),
+            delivery.event_id,
+            delivery.attempt,
+            delivery.outcome,
+            delivery
+                .status_code
+                .map_or("None".to_string(), |status_code| status_code.to_string()),
+            delivery.duration_milliseconds,
+            htmlescape::encode_minimal(delivery.error.as_deref().unwrap_or_default())
+        )
+        .unwrap();
+    }
+
+    let body = include_str!("deliveries.html").replace("{}", &rows_html);
+    Ok(utils::ok_to(body))
+}
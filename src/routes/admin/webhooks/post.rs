@@ -0,0 +1,90 @@
+use crate::auth::{CsrfToken, UserId};
+use crate::configuration::WebhookSettings;
+use crate::error::BizErrorEnum;
+use crate::request::{CreateWebhookData, RemoveWebhookData};
+use crate::routes::admin::webhooks::webhooks_page;
+use crate::{utils, webhooks};
+use actix_web::http::header::{HeaderValue, CACHE_CONTROL};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+/// Answered with the page rather than a redirect: the secret is shown once.
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks",
+    tag = "admin",
+    summary = "Add a webhook endpoint",
+    request_body(content = CreateWebhookData, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The webhooks page, with the secret of the new endpoint shown this once", content_type = "text/html"),
+        (status = 303, description = "Back to `/admin/webhooks` with a message, the URL or the events are invalid"),
+    )
+)]
+#[tracing::instrument(
+    name = "/admin/webhooks: Add a webhook endpoint",
+    skip(form, user_id, pool, settings, csrf_token),
+    fields(url = %form.url)
+)]
+pub async fn create_webhook(
+    form: web::Form<CreateWebhookData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<WebhookSettings>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, BizErrorEnum> {
+    let user_id = *user_id.into_inner();
+
+    let secret = match webhooks::create_webhook(
+        user_id,
+        &form.url,
+        &form.event_types(),
+        settings.allow_private_networks,
+        &pool,
+    )
+    .await
+    {
+        Ok(secret) => secret,
+        Err(
+            error @ (BizErrorEnum::WebhookUrlIsInvalid
+            | BizErrorEnum::WebhookUrlIsPrivate
+            | BizErrorEnum::WebhookEventTypesAreEmpty),
+        ) => {
+            FlashMessage::error(error.to_string()).send();
+            return Ok(utils::redirect_to("/admin/webhooks"));
+        }
+        Err(error) => return Err(error),
+    };
+
+    let msg_html = format!(
+        "<p><i>The endpoint has been added, copy the secret of its signatures now: \
+        it will not be shown again.</i></p>\
+        <p><code>{}</code></p>",
+        secret
+    );
+    let mut response = webhooks_page(&pool, &csrf_token, &msg_html).await?;
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok(response)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks/remove",
+    tag = "admin",
+    summary = "Remove a webhook endpoint",
+    description = "Its pending deliveries are dropped, its delivery log is kept.",
+    request_body(content = RemoveWebhookData, content_type = "application/x-www-form-urlencoded"),
+    responses((status = 303, description = "Back to `/admin/webhooks` with a message"))
+)]
+#[tracing::instrument(name = "/admin/webhooks/remove: Remove a webhook endpoint", skip(pool))]
+pub async fn remove_webhook(
+    form: web::Form<RemoveWebhookData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, BizErrorEnum> {
+    webhooks::remove_webhook(form.into_inner().webhook_id, &pool).await?;
+
+    FlashMessage::info("The endpoint has been removed.").send();
+    Ok(utils::redirect_to("/admin/webhooks"))
+}
@@ -0,0 +1,175 @@
+use crate::error::BizErrorEnum;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE};
+use actix_web::{HttpResponse, ResponseError};
+use actix_web_lab::middleware::Next;
+use serde::Serialize;
+use std::fmt::{Debug, Display, Formatter};
+use utoipa::ToSchema;
+
+/// A [`BizErrorEnum`] as the API reports it: the status and headers of the HTML routes,
+/// with a JSON body, see [`json_error`].
+///
+/// Handlers return it rather than the [`BizErrorEnum`], so that the JSON body is
+/// what `idempotency::idempotent` saves and replays.
+pub struct ApiError(BizErrorEnum);
+
+impl From<BizErrorEnum> for ApiError {
+    fn from(e: BizErrorEnum) -> Self {
+        Self(e)
+    }
+}
+
+impl Debug for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.0, f)
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        json_error(self.0.error_response(), &self.0)
+    }
+}
+
+/// The body of every error response of the API,
+/// e.g. `{"error": {"status": 404, "code": "not_found", "message": "..."}}`.
+#[derive(Serialize, ToSchema)]
+pub struct ApiErrorBody {
+    pub error: ApiErrorDetail,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ApiErrorDetail {
+    /// Same as the status of the response
+    pub status: u16,
+    /// The reason phrase of the status in snake case, e.g. `unprocessable_entity`
+    pub code: String,
+    /// What went wrong, for humans
+    pub message: String,
+}
+
+/// Replace the body of an error response with an [`ApiErrorBody`].
+///
+/// The message of a `5xx` is not the one of the error, it could leak internals.
+fn json_error(response: HttpResponse, error: &dyn Display) -> HttpResponse {
+    let status = response.status();
+    let message = if status.is_server_error() {
+        "Something went wrong on our side, please retry later.".to_string()
+    } else {
+        error.to_string()
+    };
+    let code = status
+        .canonical_reason()
+        .unwrap_or("Error")
+        .to_lowercase()
+        .replace(' ', "_");
+    let body = ApiErrorBody {
+        error: ApiErrorDetail {
+            status: status.as_u16(),
+            code,
+            message,
+        },
+    };
+
+    let body = serde_json::to_string(&body).unwrap_or_default();
+    let mut response = response.set_body(BoxBody::new(body));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+/// Every response of the API is JSON, whatever went wrong and wherever: in a handler,
+/// in an extractor (e.g. a malformed body), in a middleware (e.g. an invalid API key).
+///
+/// Requests which do not accept JSON are rejected with a `406`.
+pub async fn render_json_errors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    if !accepts_json(req.headers()) {
+        let response = ApiError(BizErrorEnum::ApiResponseIsNotAcceptable).error_response();
+        return Ok(req.into_response(response));
+    }
+
+    let response = next.call(req).await?.map_into_boxed_body();
+
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == "application/json");
+    if !response.status().is_success() && !response.status().is_redirection() && !is_json {
+        let (http_request, response) = response.into_parts();
+        // Not every error response comes with its error, e.g. a bare status
+        let message = match response.error() {
+            Some(error) => error.to_string(),
+            None => response
+                .status()
+                .canonical_reason()
+                .unwrap_or_default()
+                .to_string(),
+        };
+        return Ok(ServiceResponse::new(
+            http_request,
+            json_error(response, &message),
+        ));
+    }
+    Ok(response)
+}
+
+/// A missing `Accept` header accepts anything.
+fn accepts_json(headers: &HeaderMap) -> bool {
+    let accept = match headers.get(ACCEPT).and_then(|value| value.to_str().ok()) {
+        Some(accept) => accept,
+        None => return true,
+    };
+    accept.split(',').any(|media_range| {
+        let media_type = media_range.split(';').next().unwrap_or_default().trim();
+        matches!(media_type, "application/json" | "application/*" | "*/*")
+    })
+}
+
+/// The default service of the API scope: unknown endpoints get a JSON `404` too.
+pub async fn api_endpoint_not_found() -> Result<HttpResponse, ApiError> {
+    Err(BizErrorEnum::ApiEndpointNotFound.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::accepts_json;
+    use actix_web::http::header::{HeaderMap, HeaderValue, ACCEPT};
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn json_and_wildcards_are_accepted() {
+        assert!(accepts_json(&HeaderMap::new()));
+        assert!(accepts_json(&accept("application/json")));
+        assert!(accepts_json(&accept("text/html, */*;q=0.8")));
+        assert!(accepts_json(&accept("application/*")));
+    }
+
+    #[test]
+    fn html_only_is_not_accepted() {
+        assert!(!accepts_json(&accept("text/html")));
+        assert!(!accepts_json(&accept("text/html, application/xhtml+xml")));
+    }
+}
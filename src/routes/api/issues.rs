@@ -0,0 +1,207 @@
+use crate::auth::ApiClient;
+use crate::domain::NewsletterContent;
+use crate::error::BizErrorEnum;
+use crate::idempotency::IdempotentRequest;
+use crate::request::{ContentFormat, NewsletterData};
+use crate::routes::api::{ApiError, ApiErrorBody};
+use crate::routes::{
+    can_publish_raw_html, publish_issue, validate_merge_fields, IssueSender, IssueTracking,
+};
+use crate::telemetry;
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// The fields of the publish form, without attachments. Publishing is immediate:
+/// the emails go out as soon as the issue is accepted.
+#[utoipa::path(
+    post,
+    path = "/api/v1/issues",
+    tag = "api",
+    summary = "Publish a newsletter issue",
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key get the first response"),
+    ),
+    request_body = NewsletterData,
+    responses(
+        (status = 201, description = "The issue, its emails are queued", body = PublishedIssue,
+            headers(("Location" = String, description = "The URL of the issue"))),
+        (status = 400, description = "The title, the content or the sender is invalid", body = ApiErrorBody),
+        (status = 403, description = "The owner of the key may not publish raw HTML", body = ApiErrorBody),
+        (status = 409, description = "A retry with the same key is in flight", body = ApiErrorBody),
+        (status = 422, description = "The idempotency key was used for another request", body = ApiErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "/api/v1/issues: Publish a newsletter issue",
+    skip_all,
+    fields(api_key_id = %api_client.api_key_id)
+)]
+pub async fn api_publish_issue(
+    body: web::Json<NewsletterData>,
+    pool: web::Data<PgPool>,
+    api_client: web::ReqData<ApiClient>,
+    idempotency: IdempotentRequest,
+) -> Result<HttpResponse, ApiError> {
+    let api_client = api_client.into_inner();
+    telemetry::record_field("user_id", api_client.user_id);
+    if body.is_title_blank() {
+        return Err(BizErrorEnum::NewsletterTitleIsEmpty.into());
+    }
+    let NewsletterData {
+        title,
+        content_format,
+        text_content,
+        html_content,
+        markdown_content,
+        trusted_raw_html,
+        from_name,
+        reply_to,
+        track_opens,
+        track_clicks,
+    } = body.into_inner();
+    // The key may do what its owner may do
+    if trusted_raw_html && !can_publish_raw_html(api_client.user_id, &pool).await? {
+        return Err(BizErrorEnum::RawHtmlIsNotAllowed.into());
+    }
+    let content = match content_format {
+        ContentFormat::Html if trusted_raw_html => {
+            NewsletterContent::parse_trusted(html_content, text_content)?
+        }
+        ContentFormat::Html => NewsletterContent::parse(html_content, text_content)?,
+        ContentFormat::Markdown => NewsletterContent::from_markdown(markdown_content)?,
+    };
+    validate_merge_fields(&[&title, content.html(), content.text()])?;
+    let sender = IssueSender::parse(from_name, reply_to)?;
+
+    let mut transaction = idempotency.transaction()?;
+    let tracking = IssueTracking {
+        opens: track_opens,
+        clicks: track_clicks,
+    };
+    let issue_id =
+        publish_issue(&mut transaction, &title, &content, &sender, tracking, &[]).await?;
+    let issue = get_issue(&mut transaction, issue_id)
+        .await?
+        .ok_or(BizErrorEnum::NewsletterIssueNotFound)?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, format!("/api/v1/issues/{}", issue_id)))
+        .json(PublishedIssue {
+            issue,
+            warnings: content.warnings().to_vec(),
+        }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/issues/{issue_id}",
+    tag = "api",
+    summary = "Get the delivery status of a newsletter issue",
+    params(("issue_id" = Uuid, Path)),
+    responses(
+        (status = 200, description = "The issue and where its emails are", body = IssueResource),
+        (status = 404, description = "No such issue", body = ApiErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "/api/v1/issues/{issue_id}: Get the delivery status",
+    skip(pool)
+)]
+pub async fn api_get_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let issue_id = issue_id.into_inner();
+    let mut connection = pool.acquire().await.map_err(BizErrorEnum::PgPoolError)?;
+    let issue = get_issue(&mut connection, issue_id)
+        .await?
+        .ok_or(BizErrorEnum::NewsletterIssueNotFound)?;
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+/// A newsletter issue as the API shows it.
+#[derive(Serialize, ToSchema)]
+pub struct IssueResource {
+    pub id: Uuid,
+    pub title: String,
+    /// RFC 3339
+    pub published_at: String,
+    pub delivery: DeliveryStatus,
+}
+
+/// A newsletter issue just published.
+#[derive(Serialize, ToSchema)]
+pub struct PublishedIssue {
+    #[serde(flatten)]
+    pub issue: IssueResource,
+    /// What the sanitizer removed from the HTML content
+    pub warnings: Vec<String>,
+}
+
+/// Where the emails of an issue are.
+#[derive(Serialize, ToSchema)]
+pub struct DeliveryStatus {
+    /// Confirmed subscribers when the issue was published
+    pub recipients: i32,
+    /// Accepted by an email provider
+    pub delivered: i64,
+    /// Still in the delivery queue
+    pub pending: i64,
+    /// Left the queue without being delivered, e.g. they unsubscribed meanwhile
+    pub not_delivered: i64,
+}
+
+#[tracing::instrument(name = "Query delivery status of newsletter issue", skip(connection))]
+async fn get_issue(
+    connection: &mut PgConnection,
+    issue_id: Uuid,
+) -> Result<Option<IssueResource>, BizErrorEnum> {
+    let issue = sqlx::query!(
+        r#"
+            SELECT title, published_at, recipients_count
+            FROM newsletter_issues
+            WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(&mut *connection)
+    .await
+    .map_err(BizErrorEnum::QueryNewsletterIssuesError)?;
+    let issue = match issue {
+        Some(issue) => issue,
+        None => return Ok(None),
+    };
+
+    let pending = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue WHERE newsletter_issue_id = $1"#,
+        issue_id
+    )
+    .fetch_one(&mut *connection)
+    .await
+    .map_err(BizErrorEnum::QueryIssueDeliveryQueueError)?
+    .count;
+    let delivered = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM email_deliveries WHERE newsletter_issue_id = $1"#,
+        issue_id
+    )
+    .fetch_one(&mut *connection)
+    .await
+    .map_err(BizErrorEnum::QueryEmailDeliveriesError)?
+    .count;
+
+    Ok(Some(IssueResource {
+        id: issue_id,
+        title: issue.title,
+        published_at: issue.published_at.to_rfc3339(),
+        delivery: DeliveryStatus {
+            recipients: issue.recipients_count,
+            delivered,
+            pending,
+            not_delivered: (i64::from(issue.recipients_count) - delivered - pending).max(0),
+        },
+    }))
+}
@@ -0,0 +1,7 @@
+mod error;
+mod issues;
+mod subscribers;
+
+pub use error::*;
+pub use issues::*;
+pub use subscribers::*;
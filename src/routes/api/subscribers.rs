@@ -0,0 +1,299 @@
+use crate::domain::{NewSubscriber, SubscriberName};
+use crate::error::BizErrorEnum;
+use crate::idempotency::IdempotentRequest;
+use crate::request::{
+    CreateSubscriberData, ListSubscribersQuery, SubscriberStatus, UpdateSubscriberData,
+};
+use crate::routes::api::{ApiError, ApiErrorBody};
+use crate::routes::{
+    delete_subscription_tokens, insert_subscriber, query_subscriber, send_confirmation_email,
+};
+use crate::startup::ApplicationBaseUrl;
+use crate::webhook_delivery_worker;
+use crate::webhooks::{WebhookEvent, WebhookSubscriber};
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgExecutor, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A subscriber as the API shows it.
+#[derive(Serialize, ToSchema)]
+pub struct SubscriberResource {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    #[schema(value_type = SubscriberStatus)]
+    pub status: String,
+    /// RFC 3339
+    pub subscribed_at: String,
+}
+
+/// A page of subscribers, in the order they subscribed.
+#[derive(Serialize, ToSchema)]
+pub struct SubscriberList {
+    pub subscribers: Vec<SubscriberResource>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+struct SubscriberRow {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+impl From<SubscriberRow> for SubscriberResource {
+    fn from(row: SubscriberRow) -> Self {
+        Self {
+            id: row.id,
+            email: row.email,
+            name: row.name,
+            status: row.status,
+            subscribed_at: row.subscribed_at.to_rfc3339(),
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/subscribers",
+    tag = "api",
+    summary = "List subscribers",
+    params(ListSubscribersQuery),
+    responses(
+        (status = 200, description = "A page of subscribers", body = SubscriberList),
+        (status = 400, description = "The query is invalid", body = ApiErrorBody),
+    )
+)]
+#[tracing::instrument(name = "/api/v1/subscribers: List subscribers", skip(pool))]
+pub async fn api_list_subscribers(
+    query: web::Query<ListSubscribersQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let query = query.into_inner();
+    let limit = query.limit.clamp(1, ListSubscribersQuery::MAX_LIMIT);
+    let offset = query.offset.max(0);
+    let subscribers: Vec<SubscriberResource> = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+            SELECT id, email, name, status, subscribed_at
+            FROM subscriptions
+            WHERE $1::text IS NULL OR status = $1
+            ORDER BY subscribed_at, id
+            LIMIT $2 OFFSET $3
+        "#,
+        query.status.map(|status| status.as_str()),
+        limit,
+        offset
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .map_err(BizErrorEnum::QuerySubscriptionsError)?
+    .into_iter()
+    .map(SubscriberResource::from)
+    .collect();
+
+    Ok(HttpResponse::Ok().json(SubscriberList {
+        subscribers,
+        limit,
+        offset,
+    }))
+}
+
+/// Like the subscription form, the subscriber has to confirm with the link of the email.
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscribers",
+    tag = "api",
+    summary = "Create a subscriber",
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key get the first response"),
+    ),
+    request_body = CreateSubscriberData,
+    responses(
+        (status = 201, description = "The subscriber, pending confirmation", body = SubscriberResource,
+            headers(("Location" = String, description = "The URL of the subscriber"))),
+        (status = 400, description = "The name or the email is invalid", body = ApiErrorBody),
+        (status = 409, description = "The email is already subscribed, or a retry with the same key is in flight", body = ApiErrorBody),
+        (status = 422, description = "The idempotency key was used for another request", body = ApiErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "/api/v1/subscribers: Create a subscriber",
+    skip(body, app_base_url, idempotency),
+    fields(subscriber_email = %body.email)
+)]
+pub async fn api_create_subscriber(
+    body: web::Json<CreateSubscriberData>,
+    app_base_url: web::Data<ApplicationBaseUrl>,
+    idempotency: IdempotentRequest,
+) -> Result<HttpResponse, ApiError> {
+    let subscriber: NewSubscriber = body.into_inner().try_into()?;
+
+    let mut transaction = idempotency.transaction()?;
+    if query_subscriber(&mut transaction, &subscriber)
+        .await?
+        .is_some()
+    {
+        return Err(BizErrorEnum::SubscriberAlreadyExists.into());
+    }
+    let subscriber_id = insert_subscriber(&mut transaction, &subscriber).await?;
+    send_confirmation_email(&mut transaction, subscriber_id, &subscriber, &app_base_url).await?;
+    let event = WebhookEvent::SubscriberSubscribed(WebhookSubscriber {
+        id: subscriber_id,
+        email: subscriber.email(),
+        name: subscriber.name(),
+    });
+    webhook_delivery_worker::enqueue_event(&mut transaction, event).await?;
+    let subscriber = get_subscriber(&mut *transaction, subscriber_id)
+        .await?
+        .ok_or(BizErrorEnum::SubscriberNotFound)?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, format!("/api/v1/subscribers/{}", subscriber_id)))
+        .json(subscriber))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/subscribers/{subscriber_id}",
+    tag = "api",
+    summary = "Get a subscriber",
+    params(("subscriber_id" = Uuid, Path)),
+    responses(
+        (status = 200, description = "The subscriber", body = SubscriberResource),
+        (status = 404, description = "No such subscriber", body = ApiErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "/api/v1/subscribers/{subscriber_id}: Get a subscriber",
+    skip(pool)
+)]
+pub async fn api_get_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let subscriber = get_subscriber(pool.get_ref(), subscriber_id.into_inner())
+        .await?
+        .ok_or(BizErrorEnum::SubscriberNotFound)?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/subscribers/{subscriber_id}",
+    tag = "api",
+    summary = "Update a subscriber",
+    params(("subscriber_id" = Uuid, Path)),
+    request_body = UpdateSubscriberData,
+    responses(
+        (status = 200, description = "The updated subscriber", body = SubscriberResource),
+        (status = 400, description = "The name is invalid", body = ApiErrorBody),
+        (status = 404, description = "No such subscriber", body = ApiErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "/api/v1/subscribers/{subscriber_id}: Update a subscriber",
+    skip(body, pool)
+)]
+pub async fn api_update_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    body: web::Json<UpdateSubscriberData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let name = body
+        .into_inner()
+        .name
+        .map(SubscriberName::parse)
+        .transpose()?;
+    let subscriber = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+            UPDATE subscriptions
+            SET name = COALESCE($2, name)
+            WHERE id = $1
+            RETURNING id, email, name, status, subscribed_at
+        "#,
+        subscriber_id.into_inner(),
+        name.as_ref().map(AsRef::as_ref)
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .map_err(BizErrorEnum::UpdateSubscriptionsError)?
+    .ok_or(BizErrorEnum::SubscriberNotFound)?;
+
+    Ok(HttpResponse::Ok().json(SubscriberResource::from(subscriber)))
+}
+
+/// Forget the subscriber, their engagement events go with them.
+///
+/// `subscriber.deleted` is posted to the webhooks, with the last email and name of the subscriber.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/subscribers/{subscriber_id}",
+    tag = "api",
+    summary = "Delete a subscriber",
+    params(("subscriber_id" = Uuid, Path)),
+    responses(
+        (status = 204, description = "The subscriber is deleted"),
+        (status = 404, description = "No such subscriber", body = ApiErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "/api/v1/subscribers/{subscriber_id}: Delete a subscriber",
+    skip(pool)
+)]
+pub async fn api_delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = pool.begin().await.map_err(BizErrorEnum::PgPoolError)?;
+    delete_subscription_tokens(&mut transaction, subscriber_id).await?;
+    let deleted = sqlx::query!(
+        "DELETE FROM subscriptions WHERE id = $1 RETURNING email, name",
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .map_err(BizErrorEnum::DeleteSubscriptionsError)?
+    .ok_or(BizErrorEnum::SubscriberNotFound)?;
+    let event = WebhookEvent::SubscriberDeleted(WebhookSubscriber {
+        id: subscriber_id,
+        email: &deleted.email,
+        name: &deleted.name,
+    });
+    webhook_delivery_worker::enqueue_event(&mut transaction, event).await?;
+    transaction
+        .commit()
+        .await
+        .map_err(BizErrorEnum::TransactionCommitError)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(name = "Query subscriber by id", skip(executor))]
+async fn get_subscriber(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberResource>, BizErrorEnum> {
+    let subscriber = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+            SELECT id, email, name, status, subscribed_at
+            FROM subscriptions
+            WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(BizErrorEnum::QuerySubscriptionsError)?;
+
+    Ok(subscriber.map(SubscriberResource::from))
+}
@@ -1,17 +1,62 @@
-use actix_web::HttpResponse;
+use crate::email_client::EmailClient;
+use actix_web::{web, HttpResponse};
 
-#[tracing::instrument(name = "/health_check: Health check")]
-pub async fn health_check() -> HttpResponse {
-    HttpResponse::Ok().finish()
+/// Always `200 OK` while the application runs, down email providers are reported but do not
+/// make the application unhealthy: subscriptions and publications are queued meanwhile.
+#[utoipa::path(
+    get,
+    path = "/health_check",
+    tag = "public",
+    summary = "Health check",
+    responses(
+        (status = 200, description = "The application runs, with the circuit breaker of each email provider",
+            content_type = "application/json"),
+    )
+)]
+#[tracing::instrument(name = "/health_check: Health check", skip(email_client))]
+pub async fn health_check(email_client: web::Data<EmailClient>) -> HttpResponse {
+    let email_providers: Vec<_> = email_client
+        .circuit_states()
+        .into_iter()
+        .map(|(name, state, retry_after)| {
+            serde_json::json!({
+                "name": name,
+                "circuit_breaker": state,
+                "retry_after_seconds": retry_after.map(|d| d.as_secs()),
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({ "email_providers": email_providers }))
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::configuration::{CircuitBreakerSettings, EmailProviderSettings};
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::EmailClient;
     use crate::routes::health_check;
+    use actix_web::web;
+    use secrecy::Secret;
+    use std::time::Duration;
 
     #[tokio::test]
     async fn health_check_succeeds() {
-        let response = health_check().await;
+        let provider = EmailProviderSettings {
+            name: "postmark".into(),
+            base_url: "http://127.0.0.1".into(),
+            authorization_token: Secret::new("token".into()),
+        };
+        let email_client = EmailClient::new(
+            vec![provider],
+            SubscriberEmail::parse("news@example.com".into()).unwrap(),
+            Duration::from_secs(1),
+            CircuitBreakerSettings {
+                failure_threshold: 1,
+                success_threshold: 1,
+                open_milliseconds: 1000,
+            },
+        );
+        let response = health_check(web::Data::new(email_client)).await;
         // This requires changing the return type of `health_check`
         // from `impl Responder` to `HttpResponse` to compile
         // You also need to import it with `use actix_web::HttpResponse`!
@@ -1,8 +1,27 @@
+use crate::error::BizErrorEnum;
+use crate::request::SubscribeData;
+use crate::security_headers::CspNonce;
+use crate::startup::HmacSecret;
 use crate::utils;
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
 
-#[tracing::instrument(name = "/: Homepage")]
-pub async fn home() -> HttpResponse {
-    let body = include_str!("home.html");
-    utils::ok_to(body.into())
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "public",
+    summary = "Homepage with the subscription form",
+    responses((status = 200, description = "The homepage", content_type = "text/html"))
+)]
+#[tracing::instrument(name = "/: Homepage", skip(hmac_secret, csp_nonce))]
+pub async fn home(
+    hmac_secret: web::Data<HmacSecret>,
+    csp_nonce: web::ReqData<CspNonce>,
+) -> Result<HttpResponse, BizErrorEnum> {
+    // Lets `subscribe` tell how long it took to fill in the form
+    let form_token = SubscribeData::sign_form_token(Utc::now(), &hmac_secret)?;
+    let body = include_str!("home.html")
+        .replace("{{csp_nonce}}", &csp_nonce)
+        .replace("{{form_token}}", &form_token);
+    Ok(utils::ok_to(body))
 }
@@ -0,0 +1,94 @@
+use crate::configuration::TrackingSettings;
+use crate::error::BizErrorEnum;
+use crate::request::{ClickTrackingData, OpenTrackingData};
+use crate::startup::HmacSecret;
+use crate::tracking;
+use crate::tracking::EngagementEvent;
+use actix_web::http::header::{CacheControl, CacheDirective, LOCATION};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[utoipa::path(
+    get,
+    path = "/issues/open",
+    tag = "public",
+    summary = "Record that an issue was opened",
+    description = "The tracking pixel embedded in the issues.",
+    params(OpenTrackingData),
+    responses(
+        (status = 200, description = "A transparent 1x1 image", content_type = "image/gif"),
+        (status = 400, description = "The link was not issued by us"),
+    )
+)]
+#[tracing::instrument(
+    name = "/issues/open: Record that an issue was opened",
+    skip(query, pool, hmac_secret, tracking_settings)
+)]
+pub async fn track_open(
+    query: web::Query<OpenTrackingData>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    tracking_settings: web::Data<TrackingSettings>,
+) -> Result<HttpResponse, BizErrorEnum> {
+    let (issue_id, subscriber_id) = query.into_inner().verify(&hmac_secret).inspect_err(|_| {
+        tracing::warn!("Rejected an open tracking pixel which was not issued by us")
+    })?;
+
+    if tracking_settings.enabled {
+        record_event(&pool, issue_id, subscriber_id, EngagementEvent::Open).await;
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("image/gif")
+        // Every time the email is opened, not only the first one
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(tracking::PIXEL_GIF))
+}
+
+#[utoipa::path(
+    get,
+    path = "/issues/click",
+    tag = "public",
+    summary = "Record that a link of an issue was followed",
+    description = "The links of the issues go through it.",
+    params(ClickTrackingData),
+    responses(
+        (status = 302, description = "To the original link"),
+        (status = 400, description = "The link was not issued by us"),
+    )
+)]
+#[tracing::instrument(
+    name = "/issues/click: Record that a link of an issue was followed",
+    skip(query, pool, hmac_secret, tracking_settings)
+)]
+pub async fn track_click(
+    query: web::Query<ClickTrackingData>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    tracking_settings: web::Data<TrackingSettings>,
+) -> Result<HttpResponse, BizErrorEnum> {
+    // Never redirect to a link which we did not put in an issue ourselves
+    let (issue_id, subscriber_id, url) =
+        query.into_inner().verify(&hmac_secret).inspect_err(|_| {
+            tracing::warn!("Rejected a click tracking link which was not issued by us")
+        })?;
+
+    if tracking_settings.enabled {
+        let event = EngagementEvent::Click(url.clone());
+        record_event(&pool, issue_id, subscriber_id, event).await;
+    }
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, url))
+        .finish())
+}
+
+/// The subscriber gets their image or their page whether the event could be saved or not.
+async fn record_event(pool: &PgPool, issue_id: Uuid, subscriber_id: Uuid, event: EngagementEvent) {
+    if let Err(e) = tracking::record_event(pool, issue_id, subscriber_id, event).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to save an engagement event."
+        );
+    }
+}
@@ -5,33 +5,76 @@ use crate::request::LoginData;
 use crate::session_state::TypedSession;
 use crate::telemetry;
 use crate::utils;
-use actix_web::{web, HttpResponse};
+use actix_web::http::header::USER_AGENT;
+use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use argon2::Params;
+use chrono::Utc;
 use sqlx::PgPool;
 
 /// HMAC: hash-based message authentication code
 /// role: verify that the query parameters have been set by our API and that they have not
 ///       been altered by a third party
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "public",
+    summary = "Log in",
+    request_body(content = LoginData, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 303, description = "To `/admin/dashboard`, or back to `/login` with an error message"),
+        (status = 403, description = "The `csrf_token` field does not match the session"),
+    )
+)]
 #[tracing::instrument(
     name = "/login: Handle login",
-    skip(form, pool, session),
+    skip(form, pool, argon2_params, session, request),
     fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
 )]
 pub async fn login(
     form: web::Form<LoginData>,
     pool: web::Data<PgPool>,
+    argon2_params: web::Data<Params>,
     session: TypedSession,
+    request: HttpRequest,
 ) -> Result<HttpResponse, BizErrorEnum> {
     let credentials: Credentials = form.into_inner().into();
     telemetry::record_field("username", &credentials.username);
+    let password = credentials.password.clone();
 
-    match auth::validate_credentials(credentials, &pool).await {
+    match auth::validate_credentials(credentials, &argon2_params, &pool).await {
         Ok(user_id) => {
-            telemetry::record_field("user_id", &user_id);
+            telemetry::record_field("user_id", user_id);
+            // Transparently move hashes stored with outdated parameters to the configured ones.
+            // The user has proven their identity already, a failure here must not block them.
+            if let Err(e) =
+                auth::upgrade_password_hash(user_id, password, &argon2_params, &pool).await
+            {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to upgrade the password hash"
+                );
+            }
+            // Keep track of the session, so that it can be listed and revoked later on
+            let ip_address = utils::client_ip(&request).map(|ip| ip.to_string());
+            let user_agent = request
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok());
+            let session_id =
+                auth::create_user_session(user_id, ip_address.as_deref(), user_agent, &pool)
+                    .await?;
             // Avoid session fixation attack
             session.renew();
             // If failed, redirect to login page
-            if let Err(error) = session.insert_user_id(user_id) {
+            if let Err(error) = session
+                .insert_user_id(user_id)
+                .and_then(|_| session.insert_session_id(session_id))
+                .and_then(|_| session.insert_logged_in_at(Utc::now()))
+                // Forms rendered before login must not be replayable afterwards
+                .and_then(|_| auth::rotate_csrf_token(&session))
+            {
                 return Ok(redirect_to_login_when_error(error));
             };
             // if login is successfully, redirect to the dashboard
@@ -61,9 +104,14 @@ pub async fn login(
                 // To set a persistent cookie you must specify an expiration policy using a cookie attribute - either Max-Age or Expires
                 //
                 // Response Headers:
-                // set-cookie: login_error_msg=Invalid username.
-
-                Ok(redirect_to_login_when_error(error))
+                // set-cookie: login_error_msg=Invalid username or password.
+                //
+                // Never tell the user which half of the credentials was wrong,
+                // otherwise the login page can be used to enumerate usernames.
+                tracing::warn!(error.cause_chain = ?error, "Failed to validate credentials");
+                Ok(redirect_to_login_when_error(
+                    BizErrorEnum::InvalidCredentials,
+                ))
             }
             _ => Err(error),
         },
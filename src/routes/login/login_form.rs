@@ -1,10 +1,18 @@
+use crate::auth::CsrfToken;
 use crate::utils;
 use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
 use std::fmt::Write;
 
-#[tracing::instrument(name = "/login: Get login page", skip(flash_msgs))]
-pub async fn login_form(flash_msgs: IncomingFlashMessages) -> HttpResponse {
+#[utoipa::path(
+    get,
+    path = "/login",
+    tag = "public",
+    summary = "Login form",
+    responses((status = 200, description = "The login form", content_type = "text/html"))
+)]
+#[tracing::instrument(name = "/login: Get login page", skip(flash_msgs, csrf_token))]
+pub async fn login_form(flash_msgs: IncomingFlashMessages, csrf_token: CsrfToken) -> HttpResponse {
     // HMAC to verify integrity and provenance for our query parameters
     /*let error_msg = match query {
         None => "".into(),
@@ -34,6 +42,8 @@ pub async fn login_form(flash_msgs: IncomingFlashMessages) -> HttpResponse {
         writeln!(error_msg, "<p><i>{}</i></p>", msg.content()).unwrap();
     }
 
-    let login_page = include_str!("login.html").replace("{}", &error_msg);
+    let login_page = include_str!("login.html")
+        .replace("{{csrf_token}}", &csrf_token)
+        .replace("{}", &error_msg);
     utils::ok_to(login_page)
 }
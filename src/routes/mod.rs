@@ -1,14 +1,22 @@
 mod admin;
+mod api;
 mod health_check;
 mod home;
+mod issues_tracking;
 mod login;
+mod openapi;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
 
 // re-export
 pub use admin::*;
+pub use api::*;
 pub use health_check::*;
 pub use home::*;
+pub use issues_tracking::*;
 pub use login::*;
+pub use openapi::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
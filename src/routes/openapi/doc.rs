@@ -0,0 +1,166 @@
+use crate::routes;
+use crate::routes::{ApiErrorBody, IssueResource, PublishedIssue, SubscriberList};
+use utoipa::openapi::path::{Operation, PathItem};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{Ref, ResponseBuilder, SecurityRequirement};
+use utoipa::{Modify, OpenApi};
+
+/// The documentation of every route of `startup::run`.
+///
+/// A route registered there must be listed in `paths`, `tests/api/openapi.rs` checks it.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Zero To Production newsletter",
+        description = "The public pages, the admin pages (logged in with a session cookie) \
+            and the JSON API (authenticated with an API key)."
+    ),
+    paths(
+        routes::home,
+        routes::health_check,
+        routes::login_form,
+        routes::login,
+        routes::subscribe,
+        routes::confirm,
+        routes::unsubscribe,
+        routes::track_open,
+        routes::track_click,
+        routes::openapi_json,
+        routes::openapi_viewer,
+        routes::admin_dashboard,
+        routes::change_password_form,
+        routes::change_password,
+        routes::publish_newsletter_form,
+        routes::publish_newsletter,
+        routes::email_templates_form,
+        routes::update_email_template,
+        routes::newsletter_issues,
+        routes::newsletter_issue,
+        routes::active_sessions,
+        routes::revoke_session,
+        routes::revoke_other_sessions,
+        routes::api_keys,
+        routes::create_api_key,
+        routes::revoke_api_key,
+        routes::webhook_endpoints,
+        routes::create_webhook,
+        routes::remove_webhook,
+        routes::webhook_deliveries,
+        routes::log_out,
+        routes::api_list_subscribers,
+        routes::api_create_subscriber,
+        routes::api_get_subscriber,
+        routes::api_update_subscriber,
+        routes::api_delete_subscriber,
+        routes::api_publish_issue,
+        routes::api_get_issue,
+    ),
+    components(schemas(ApiErrorBody, SubscriberList, PublishedIssue, IssueResource)),
+    modifiers(&ScopeMiddlewares),
+    tags(
+        (name = "public", description = "Pages and links anybody may follow"),
+        (name = "admin", description = "Pages of the logged in users. \
+            Every form also has a `csrf_token` field, the token of the session."),
+        (name = "api", description = "JSON, versioned. Every error has an `ApiErrorBody`."),
+    )
+)]
+pub struct ApiDoc;
+
+/// What the middlewares of the `/admin` and `/api/v1` scopes add to each of their routes,
+/// so that the handlers only document their own responses.
+struct ScopeMiddlewares;
+
+impl Modify for ScopeMiddlewares {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::with_description(
+                "id",
+                "The session cookie set by `POST /login`, its name is configurable",
+            ))),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("An API key created at `/admin/api_keys`"))
+                    .build(),
+            ),
+        );
+
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if path.starts_with("/admin/") {
+                for (method, operation) in operations_mut(item) {
+                    operation.security = Some(vec![SecurityRequirement::new(
+                        "session",
+                        Vec::<String>::new(),
+                    )]);
+                    add_response(
+                        operation,
+                        "303",
+                        ResponseBuilder::new()
+                            .description("To `/login` if the session is missing or expired")
+                            .build(),
+                    );
+                    if method == "post" {
+                        add_response(
+                            operation,
+                            "403",
+                            ResponseBuilder::new()
+                                .description("The `csrf_token` field does not match the session")
+                                .build(),
+                        );
+                    }
+                }
+            } else if path.starts_with("/api/v1/") {
+                for (_, operation) in operations_mut(item) {
+                    operation.security = Some(vec![SecurityRequirement::new(
+                        "api_key",
+                        Vec::<String>::new(),
+                    )]);
+                    for (status, description) in [
+                        ("401", "The API key is missing, unknown or revoked"),
+                        ("406", "The `Accept` header excludes `application/json`"),
+                    ] {
+                        add_response(
+                            operation,
+                            status,
+                            ResponseBuilder::new()
+                                .description(description)
+                                .content(
+                                    "application/json",
+                                    utoipa::openapi::Content::new(Some(Ref::from_schema_name(
+                                        "ApiErrorBody",
+                                    ))),
+                                )
+                                .build(),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn operations_mut(item: &mut PathItem) -> impl Iterator<Item = (&'static str, &mut Operation)> {
+    [
+        ("get", item.get.as_mut()),
+        ("post", item.post.as_mut()),
+        ("put", item.put.as_mut()),
+        ("patch", item.patch.as_mut()),
+        ("delete", item.delete.as_mut()),
+    ]
+    .into_iter()
+    .filter_map(|(method, operation)| operation.map(|operation| (method, operation)))
+}
+
+/// Leave the response of the handler if it documented the same status, e.g. its own `303`.
+fn add_response(operation: &mut Operation, status: &str, response: utoipa::openapi::Response) {
+    operation
+        .responses
+        .responses
+        .entry(status.to_string())
+        .or_insert(response.into());
+}
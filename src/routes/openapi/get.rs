@@ -0,0 +1,221 @@
+use crate::configuration::OpenApiSettings;
+use crate::error::BizErrorEnum;
+use crate::routes::openapi::ApiDoc;
+use crate::utils;
+use actix_web::{web, HttpResponse};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::fmt::Write;
+use utoipa::OpenApi;
+
+/// The document never changes while the application runs.
+static OPENAPI_JSON: Lazy<Value> = Lazy::new(|| {
+    serde_json::to_value(ApiDoc::openapi()).expect("Failed to serialize the OpenAPI document")
+});
+
+const METHODS: [&str; 5] = ["get", "post", "put", "patch", "delete"];
+
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "public",
+    summary = "This document",
+    responses((status = 200, description = "The OpenAPI 3 document", content_type = "application/json"))
+)]
+#[tracing::instrument(name = "/openapi.json: Get the OpenAPI document")]
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(&*OPENAPI_JSON)
+}
+
+/// Without JavaScript, the Content-Security-Policy would not let a third-party viewer in.
+#[utoipa::path(
+    get,
+    path = "/openapi",
+    tag = "public",
+    summary = "This document, for humans",
+    responses(
+        (status = 200, description = "The routes and the schemas", content_type = "text/html"),
+        (status = 404, description = "The viewer is disabled"),
+    )
+)]
+#[tracing::instrument(name = "/openapi: Get the OpenAPI viewer", skip(settings))]
+pub async fn openapi_viewer(
+    settings: web::Data<OpenApiSettings>,
+) -> Result<HttpResponse, BizErrorEnum> {
+    if !settings.viewer_enabled {
+        return Err(BizErrorEnum::OpenApiViewerIsDisabled);
+    }
+
+    let body = include_str!("openapi.html")
+        .replace("{{title}}", &escape(&OPENAPI_JSON["info"]["title"]))
+        .replace(
+            "{{description}}",
+            &escape(&OPENAPI_JSON["info"]["description"]),
+        )
+        .replace("{{operations}}", &operations_html(&OPENAPI_JSON))
+        .replace("{{schemas}}", &schemas_html(&OPENAPI_JSON));
+    Ok(utils::ok_to(body))
+}
+
+/// The operations grouped by tag, in the order of the tags of the document.
+fn operations_html(doc: &Value) -> String {
+    let mut html = String::new();
+    for tag in doc["tags"].as_array().into_iter().flatten() {
+        writeln!(
+            html,
+            "<h2>{}</h2>\n<p>{}</p>",
+            escape(&tag["name"]),
+            escape(&tag["description"])
+        )
+        .unwrap();
+        for (path, item) in doc["paths"].as_object().into_iter().flatten() {
+            for method in METHODS {
+                let operation = &item[method];
+                let has_tag = operation["tags"]
+                    .as_array()
+                    .is_some_and(|tags| tags.contains(&tag["name"]));
+                if has_tag {
+                    html.push_str(&operation_html(method, path, operation));
+                }
+            }
+        }
+    }
+    html
+}
+
+fn operation_html(method: &str, path: &str, operation: &Value) -> String {
+    let mut html = String::new();
+    writeln!(
+        html,
+        "<h3><code>{} {}</code> {}</h3>",
+        method.to_uppercase(),
+        htmlescape::encode_minimal(path),
+        escape(&operation["summary"])
+    )
+    .unwrap();
+    if operation["description"].is_string() {
+        writeln!(html, "<p>{}</p>", escape(&operation["description"])).unwrap();
+    }
+    for requirement in operation["security"].as_array().into_iter().flatten() {
+        for scheme in requirement.as_object().into_iter().flat_map(|r| r.keys()) {
+            writeln!(
+                html,
+                "<p>Authentication: <code>{}</code></p>",
+                htmlescape::encode_minimal(scheme)
+            )
+            .unwrap();
+        }
+    }
+
+    if let Some(parameters) = operation["parameters"].as_array() {
+        html.push_str(
+            "<table>\n<tr><th>Parameter</th><th>In</th><th>Schema</th><th>Description</th></tr>\n",
+        );
+        for parameter in parameters {
+            let required = if parameter["required"] == true {
+                ""
+            } else {
+                " (optional)"
+            };
+            writeln!(
+                html,
+                "<tr><td><code>{}</code>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&parameter["name"]),
+                required,
+                escape(&parameter["in"]),
+                schema_html(&parameter["schema"]),
+                escape(&parameter["description"])
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n");
+    }
+
+    for (content_type, content) in operation["requestBody"]["content"]
+        .as_object()
+        .into_iter()
+        .flatten()
+    {
+        writeln!(
+            html,
+            "<p>Body: <code>{}</code> {}</p>",
+            htmlescape::encode_minimal(content_type),
+            schema_html(&content["schema"])
+        )
+        .unwrap();
+    }
+
+    html.push_str("<ul>\n");
+    for (status, response) in operation["responses"].as_object().into_iter().flatten() {
+        let contents: Vec<String> = response["content"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(content_type, content)| {
+                format!(
+                    "<code>{}</code> {}",
+                    htmlescape::encode_minimal(content_type),
+                    schema_html(&content["schema"])
+                )
+            })
+            .collect();
+        writeln!(
+            html,
+            "<li><b>{}</b> {} {}</li>",
+            htmlescape::encode_minimal(status),
+            escape(&response["description"]),
+            contents.join(", ")
+        )
+        .unwrap();
+    }
+    html.push_str("</ul>\n");
+    html
+}
+
+/// A link to a schema of the components, or else its type.
+fn schema_html(schema: &Value) -> String {
+    if let Some(name) = schema["$ref"]
+        .as_str()
+        .and_then(|reference| reference.strip_prefix("#/components/schemas/"))
+    {
+        let name = htmlescape::encode_minimal(name);
+        return format!(r##"<a href="#schema-{}">{}</a>"##, name, name);
+    }
+    // OpenAPI 3.1 writes the type of an optional value as `["string", "null"]`
+    let schema_type = match &schema["type"] {
+        Value::Array(types) => types.iter().map(escape).collect::<Vec<_>>().join(" | "),
+        schema_type => escape(schema_type),
+    };
+    match &schema["format"] {
+        Value::Null => schema_type,
+        format => format!("{} ({})", schema_type, escape(format)),
+    }
+}
+
+fn schemas_html(doc: &Value) -> String {
+    let mut html = String::new();
+    for (name, schema) in doc["components"]["schemas"]
+        .as_object()
+        .into_iter()
+        .flatten()
+    {
+        let name = htmlescape::encode_minimal(name);
+        writeln!(
+            html,
+            "<h3 id=\"schema-{}\">{}</h3>\n<pre>{}</pre>",
+            name,
+            name,
+            htmlescape::encode_minimal(&serde_json::to_string_pretty(schema).unwrap_or_default())
+        )
+        .unwrap();
+    }
+    html
+}
+
+/// The text of a string value, escaped. Anything else, e.g. a missing value, is left out.
+fn escape(value: &Value) -> String {
+    value
+        .as_str()
+        .map(htmlescape::encode_minimal)
+        .unwrap_or_default()
+}
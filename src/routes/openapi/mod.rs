@@ -0,0 +1,5 @@
+mod doc;
+mod get;
+
+pub use doc::*;
+pub use get::*;
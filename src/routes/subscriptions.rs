@@ -1,18 +1,43 @@
+use crate::configuration::SubscriptionSettings;
 use crate::domain::NewSubscriber;
-use crate::email_client::EmailClient;
+use crate::email_outbox_worker;
+use crate::email_outbox_worker::OutboxEmail;
+use crate::email_template::{EmailTemplateName, TemplateContext};
 use crate::error::BizErrorEnum;
+use crate::idempotency::IdempotentRequest;
 use crate::request::SubscribeData;
-use crate::startup::ApplicationBaseUrl;
-use actix_web::{web, HttpResponse};
-use chrono::Local;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::utils;
+use crate::webhook_delivery_worker;
+use crate::webhooks::{WebhookEvent, WebhookSubscriber};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{Local, Utc};
 use rand::distributions::Alphanumeric;
 use rand::Rng;
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+#[utoipa::path(
+    post,
+    path = "/subscriptions",
+    tag = "public",
+    summary = "Subscribe to the newsletter",
+    description = "A confirmation email is sent. The response is the same whether a subscriber \
+        was added, was already confirmed, or the attempt was ignored as abusive.",
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key get the first response"),
+    ),
+    request_body(content = SubscribeData, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Check your inbox"),
+        (status = 400, description = "The name or the email is invalid"),
+        (status = 409, description = "A retry with the same key is in flight"),
+        (status = 422, description = "The idempotency key was used for another request"),
+    )
+)]
 #[tracing::instrument(
     name = "/subscriptions: Adding a new subscriber",
-    skip(form, pool, email_client),
+    skip(form, pool, app_base_url, hmac_secret, settings, request, idempotency),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -21,49 +46,201 @@ use uuid::Uuid;
 pub async fn subscribe(
     form: web::Form<SubscribeData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
     app_base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    settings: web::Data<SubscriptionSettings>,
+    request: HttpRequest,
+    idempotency: IdempotentRequest,
 ) -> Result<HttpResponse, BizErrorEnum> {
+    // Whatever happens below, the client gets the very same response:
+    // neither bots nor someone probing addresses must learn anything from it.
+    let accepted = HttpResponse::Ok().finish();
+
     // `web::Form` is a wrapper around `FormData`
     // `form.0` gives us access to the underlying `FormData`
-    let subscriber = form.into_inner().try_into()?;
+    let form = form.into_inner();
+    if let Some(reason) = detect_bot(&form, &hmac_secret, &settings) {
+        tracing::warn!(reason, "Ignored a subscription attempt");
+        return Ok(accepted);
+    }
+    let subscriber: NewSubscriber = form.try_into()?;
+    if settings.reject_disposable_emails && subscriber.get_email().is_disposable() {
+        tracing::warn!("Ignored a subscription attempt with a disposable email address");
+        return Ok(accepted);
+    }
 
-    // get a transaction object
-    let mut transaction = pool.begin().await.map_err(|e| {
-        tracing::error!("Failed to get a transaction: {:?}", e);
-        BizErrorEnum::PgPoolError(e)
-    })?;
+    let ip_address = utils::client_ip(&request)
+        .map(|ip| ip.to_string())
+        .unwrap_or_default();
+    if is_rate_limited(
+        &pool,
+        &hmac_secret,
+        &settings,
+        &ip_address,
+        subscriber.email(),
+    )
+    .await?
+    {
+        tracing::warn!("Ignored a rate limited subscription attempt");
+        return Ok(accepted);
+    }
+
+    // Committed once the response is ready, together with it when the form came with a key
+    let mut transaction = idempotency.transaction()?;
 
-    // insert subscriptions table
-    let subscriber_id = insert_subscriber(&mut transaction, &subscriber).await?;
+    let (subscriber_id, subscribed_now) = match query_subscriber(&mut transaction, &subscriber)
+        .await?
+    {
+        // insert subscriptions table
+        None => (
+            insert_subscriber(&mut transaction, &subscriber).await?,
+            true,
+        ),
+        // A lost confirmation email: send a new one
+        Some((subscriber_id, status)) if status == "pending_confirmation" => (subscriber_id, false),
+        // Someone who unsubscribed coming back: pending again until they confirm
+        Some((subscriber_id, status)) if status == "unsubscribed" => {
+            resubscribe_subscriber(&mut transaction, subscriber_id).await?;
+            (subscriber_id, true)
+        }
+        // Already confirmed, nothing to do
+        Some(_) => return Ok(accepted),
+    };
+    send_confirmation_email(&mut transaction, subscriber_id, &subscriber, &app_base_url).await?;
+    if subscribed_now {
+        let event = WebhookEvent::SubscriberSubscribed(WebhookSubscriber {
+            id: subscriber_id,
+            email: subscriber.email(),
+            name: subscriber.name(),
+        });
+        webhook_delivery_worker::enqueue_event(&mut transaction, event).await?;
+    }
+
+    Ok(accepted)
+}
+
+/// Issue a new subscription token and queue the email with its confirmation link.
+///
+/// The email is sent by `email_outbox_worker`, once the transaction is committed:
+/// either both the subscriber and their email are stored, or neither is.
+pub(crate) async fn send_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscriber: &NewSubscriber,
+    app_base_url: &ApplicationBaseUrl,
+) -> Result<(), BizErrorEnum> {
     let subscription_token = generate_subscription_token();
 
     // insert subscription_tokens table
-    store_token(&mut transaction, subscriber_id, &subscription_token).await?;
+    store_token(transaction, subscriber_id, &subscription_token).await?;
+
+    store_confirmation_email(transaction, subscriber, app_base_url, &subscription_token).await
+}
+
+/// Returns why the form looks like it was submitted by a bot, if it does.
+fn detect_bot(
+    form: &SubscribeData,
+    hmac_secret: &HmacSecret,
+    settings: &SubscriptionSettings,
+) -> Option<&'static str> {
+    if !form.website.is_empty() {
+        return Some("the honeypot field was filled in");
+    }
+    let rendered_at = match form.form_rendered_at(hmac_secret) {
+        Ok(rendered_at) => rendered_at,
+        Err(_) => return Some("the form token is missing or forged"),
+    };
+    let fill_time = (Utc::now() - rendered_at).to_std().unwrap_or_default();
+    if fill_time < settings.min_form_fill_time() {
+        return Some("the form was filled in too fast");
+    }
+    if fill_time > settings.max_form_age() {
+        return Some("the form has expired");
+    }
+    None
+}
 
-    // explicitly commit
-    transaction.commit().await.map_err(|e| {
-        tracing::error!("Failed to commit a transaction: {:?}", e);
-        BizErrorEnum::TransactionCommitError(e)
+/// Record the attempt, then check it against the per-IP and per-email limits.
+///
+/// Only keyed hashes of the IP address and of the email are stored,
+/// `subscription_attempts_cleanup` deletes them once out of the window.
+#[tracing::instrument(
+    name = "Check subscription rate limits",
+    skip(pool, hmac_secret, settings, ip_address, email)
+)]
+async fn is_rate_limited(
+    pool: &PgPool,
+    hmac_secret: &HmacSecret,
+    settings: &SubscriptionSettings,
+    ip_address: &str,
+    email: &str,
+) -> Result<bool, BizErrorEnum> {
+    let ip_hash = hmac_secret.keyed_hash("ip", ip_address)?;
+    let email_hash = hmac_secret.keyed_hash("email", &email.to_lowercase())?;
+    let window_start =
+        Utc::now() - chrono::Duration::seconds(settings.rate_limit_window_seconds as i64);
+
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_attempts (ip_hash, email_hash, attempted_at)
+        VALUES ($1, $2, now())
+    "#,
+        ip_hash,
+        email_hash
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to insert into subscription_attempts: {:?}", e);
+        BizErrorEnum::InsertSubscriptionAttemptsError(e)
     })?;
 
-    // send confirmation email
-    send_confirmation_email(
-        &email_client,
-        &subscriber,
-        &app_base_url,
-        &subscription_token,
+    let attempts = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE ip_hash = $1) AS "per_ip!",
+            COUNT(*) FILTER (WHERE email_hash = $2) AS "per_email!"
+        FROM subscription_attempts
+        WHERE attempted_at >= $3 AND (ip_hash = $1 OR email_hash = $2)
+    "#,
+        ip_hash,
+        email_hash,
+        window_start
     )
-    .await?;
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to query subscription_attempts: {:?}", e);
+        BizErrorEnum::QuerySubscriptionAttemptsError(e)
+    })?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(attempts.per_ip > settings.max_attempts_per_ip
+        || attempts.per_email > settings.max_attempts_per_email)
+}
+
+#[tracing::instrument(name = "Query subscriber by email", skip(transaction, subscriber))]
+pub(crate) async fn query_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber: &NewSubscriber,
+) -> Result<Option<(Uuid, String)>, BizErrorEnum> {
+    let record = sqlx::query!(
+        "SELECT id, status FROM subscriptions WHERE email = $1",
+        subscriber.email()
+    )
+    .fetch_optional(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to query subscriptions: {:?}", e);
+        BizErrorEnum::QuerySubscriptionsError(e)
+    })?;
+    Ok(record.map(|r| (r.id, r.status)))
 }
 
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(subscriber, pool)
 )]
-async fn insert_subscriber(
+pub(crate) async fn insert_subscriber(
     pool: &mut Transaction<'_, Postgres>,
     subscriber: &NewSubscriber,
 ) -> Result<Uuid, BizErrorEnum> {
@@ -88,6 +265,46 @@ async fn insert_subscriber(
     Ok(subscriber_id)
 }
 
+#[tracing::instrument(name = "Update status of subscriptions to pending", skip(transaction))]
+async fn resubscribe_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+) -> Result<(), BizErrorEnum> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'pending_confirmation'
+        WHERE id = $1 AND status = 'unsubscribed'
+    "#,
+        id
+    )
+    .execute(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to update subscriptions: {:?}", e);
+        BizErrorEnum::UpdateSubscriptionsError(e)
+    })?;
+    Ok(())
+}
+
+/// Used up tokens must not confirm the subscriber again.
+#[tracing::instrument(name = "Delete subscription tokens of a subscriber", skip(transaction))]
+pub(crate) async fn delete_subscription_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), BizErrorEnum> {
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to delete subscription_tokens: {:?}", e);
+        BizErrorEnum::DeleteSubscriptionTokensError(e)
+    })?;
+    Ok(())
+}
+
 #[tracing::instrument(name = "Store subscriber id and token in the database", skip(pool))]
 async fn store_token(
     pool: &mut Transaction<'_, Postgres>,
@@ -112,11 +329,11 @@ async fn store_token(
 }
 
 #[tracing::instrument(
-    name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber)
+    name = "Store a confirmation email to a new subscriber",
+    skip(transaction, new_subscriber)
 )]
-async fn send_confirmation_email(
-    email_client: &EmailClient,
+async fn store_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
     app_base_url: &ApplicationBaseUrl,
     subscription_token: &str,
@@ -126,23 +343,19 @@ async fn send_confirmation_email(
         app_base_url.0.as_str(),
         subscription_token
     );
-    let html_body = format!(
-        "Welcome to our newsletter!<br />\
-                Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
-    );
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
-    );
-    email_client
-        .send_email(
-            new_subscriber.get_email(),
-            "Welcome!",
-            &html_body,
-            &plain_body,
-        )
-        .await
+    email_outbox_worker::enqueue_email(
+        transaction,
+        OutboxEmail {
+            recipient: new_subscriber.get_email(),
+            template_name: EmailTemplateName::Confirmation,
+            context: TemplateContext::new()
+                .insert("subscriber.name", new_subscriber.name())
+                .insert("subscriber.email", new_subscriber.email())
+                .insert("confirmation_link", confirmation_link),
+        },
+    )
+    .await?;
+    Ok(())
 }
 
 /// Generate a random 25-characters-long case-sensitive subscription token.
@@ -1,16 +1,38 @@
+use crate::domain::SubscriberEmail;
+use crate::email_outbox_worker::OutboxEmail;
+use crate::email_template::{EmailTemplateName, TemplateContext};
 use crate::error::BizErrorEnum;
-use crate::request::ConfirmData;
+use crate::request::{ConfirmData, UnsubscribeData};
+use crate::routes::delete_subscription_tokens;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::webhooks::{WebhookEvent, WebhookSubscriber};
+use crate::{email_outbox_worker, webhook_delivery_worker};
 use actix_web::{web, HttpResponse};
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+#[utoipa::path(
+    get,
+    path = "/subscriptions/confirm",
+    tag = "public",
+    summary = "Confirm a subscription",
+    description = "The link of the confirmation email.",
+    params(ConfirmData),
+    responses(
+        (status = 200, description = "The subscription is confirmed"),
+        (status = 400, description = "The token is missing"),
+        (status = 500, description = "The token is unknown"),
+    )
+)]
 #[tracing::instrument(
     name = "/subscriptions/confirm: Confirm a pending subscriber",
-    skip(confirm, pool)
+    skip(confirm, pool, app_base_url, hmac_secret)
 )]
 pub async fn confirm(
     confirm: web::Query<ConfirmData>,
     pool: web::Data<PgPool>,
+    app_base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
 ) -> Result<HttpResponse, BizErrorEnum> {
     let token = confirm.into_inner().subscription_token;
 
@@ -27,7 +49,35 @@ pub async fn confirm(
             Err(BizErrorEnum::SubscriptionTokenInvalidError)
         }
         Some(subscriber_id) => {
-            confirm_subscriber(&pool, subscriber_id).await?;
+            let mut transaction = pool.begin().await.map_err(|e| {
+                tracing::error!("Failed to get a transaction: {:?}", e);
+                BizErrorEnum::PgPoolError(e)
+            })?;
+            // The token is used up, whatever the status: an old link must not subscribe again
+            // someone who unsubscribed since
+            delete_subscription_tokens(&mut transaction, subscriber_id).await?;
+            if let Some((email, name)) = confirm_subscriber(&mut transaction, subscriber_id).await?
+            {
+                store_welcome_email(
+                    &mut transaction,
+                    subscriber_id,
+                    &email,
+                    &name,
+                    &app_base_url,
+                    &hmac_secret,
+                )
+                .await?;
+                let event = WebhookEvent::SubscriberConfirmed(WebhookSubscriber {
+                    id: subscriber_id,
+                    email: &email,
+                    name: &name,
+                });
+                webhook_delivery_worker::enqueue_event(&mut transaction, event).await?;
+            }
+            transaction.commit().await.map_err(|e| {
+                tracing::error!("Failed to commit a transaction: {:?}", e);
+                BizErrorEnum::TransactionCommitError(e)
+            })?;
             Ok(HttpResponse::Ok().finish())
         }
     }
@@ -54,17 +104,57 @@ async fn get_subscriber_id_from_token(
     Ok(result.map(|r| r.subscriber_id))
 }
 
-#[tracing::instrument(name = "Update status of subscriptions by subscriber_id", skip(pool))]
-async fn confirm_subscriber(pool: &PgPool, id: Uuid) -> Result<(), BizErrorEnum> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+/// Returns the email and the name of the subscriber, unless they are not pending any more.
+#[tracing::instrument(
+    name = "Update status of subscriptions by subscriber_id",
+    skip(transaction)
+)]
+async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+) -> Result<Option<(String, String)>, BizErrorEnum> {
+    let record = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status = 'pending_confirmation'
+        RETURNING email, name
+    "#,
         id
     )
-    .execute(pool)
+    .fetch_optional(transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to update subscriptions: {:?}", e);
         BizErrorEnum::UpdateSubscriptionsError(e)
     })?;
+    Ok(record.map(|r| (r.email, r.name)))
+}
+
+#[tracing::instrument(
+    name = "Store a welcome email to a confirmed subscriber",
+    skip(transaction, email, name, app_base_url, hmac_secret)
+)]
+async fn store_welcome_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    email: &str,
+    name: &str,
+    app_base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+) -> Result<(), BizErrorEnum> {
+    let recipient = SubscriberEmail::parse(email.to_string())?;
+    let unsubscribe_url = UnsubscribeData::link(subscriber_id, app_base_url, hmac_secret)?;
+    email_outbox_worker::enqueue_email(
+        transaction,
+        OutboxEmail {
+            recipient: &recipient,
+            template_name: EmailTemplateName::Welcome,
+            context: TemplateContext::new()
+                .insert("subscriber.name", name)
+                .insert("subscriber.email", email)
+                .insert("unsubscribe_url", unsubscribe_url),
+        },
+    )
+    .await?;
     Ok(())
 }
@@ -0,0 +1,79 @@
+use crate::error::BizErrorEnum;
+use crate::request::UnsubscribeData;
+use crate::routes::delete_subscription_tokens;
+use crate::startup::HmacSecret;
+use crate::webhook_delivery_worker;
+use crate::webhooks::{WebhookEvent, WebhookSubscriber};
+use actix_web::{web, HttpResponse};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[utoipa::path(
+    get,
+    path = "/subscriptions/unsubscribe",
+    tag = "public",
+    summary = "Unsubscribe",
+    description = "The link at the bottom of every email.",
+    params(UnsubscribeData),
+    responses(
+        (status = 200, description = "The subscriber is unsubscribed"),
+        (status = 400, description = "The link was not issued by us"),
+    )
+)]
+#[tracing::instrument(
+    name = "/subscriptions/unsubscribe: Unsubscribe a subscriber",
+    skip(query, pool, hmac_secret)
+)]
+pub async fn unsubscribe(
+    query: web::Query<UnsubscribeData>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, BizErrorEnum> {
+    let subscriber_id = query.into_inner().verify(&hmac_secret).inspect_err(|_| {
+        tracing::warn!("Rejected an unsubscribe link which was not issued by us")
+    })?;
+
+    let mut transaction = pool.begin().await.map_err(BizErrorEnum::PgPoolError)?;
+    // A confirmation link still in the inbox must not subscribe them again
+    delete_subscription_tokens(&mut transaction, subscriber_id).await?;
+    // Following the link twice must not report it twice
+    if let Some((email, name)) = unsubscribe_subscriber(&mut transaction, subscriber_id).await? {
+        let event = WebhookEvent::SubscriberUnsubscribed(WebhookSubscriber {
+            id: subscriber_id,
+            email: &email,
+            name: &name,
+        });
+        webhook_delivery_worker::enqueue_event(&mut transaction, event).await?;
+    }
+    transaction
+        .commit()
+        .await
+        .map_err(BizErrorEnum::TransactionCommitError)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Returns the email and the name of the subscriber, unless they were unsubscribed already.
+#[tracing::instrument(
+    name = "Update status of subscriptions to unsubscribed",
+    skip(transaction)
+)]
+async fn unsubscribe_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+) -> Result<Option<(String, String)>, BizErrorEnum> {
+    let record = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed'
+        WHERE id = $1 AND status <> 'unsubscribed'
+        RETURNING email, name
+    "#,
+        id
+    )
+    .fetch_optional(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to update subscriptions: {:?}", e);
+        BizErrorEnum::UpdateSubscriptionsError(e)
+    })?;
+    Ok(record.map(|r| (r.email, r.name)))
+}
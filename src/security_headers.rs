@@ -0,0 +1,98 @@
+use crate::configuration::SecurityHeadersSettings;
+use crate::error::BizErrorEnum;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY,
+    STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use actix_web::{web, HttpMessage};
+use actix_web_lab::middleware::Next;
+use base64::Engine;
+use rand::RngCore;
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
+
+/// Placeholder of the nonce in the configured Content-Security-Policy.
+const NONCE_PLACEHOLDER: &str = "{{nonce}}";
+
+/// The Content-Security-Policy nonce of the current request.
+///
+/// Inline `<script>`/`<style>` elements of a page must carry it as their `nonce`
+/// attribute, handlers get it with `web::ReqData<CspNonce>`.
+#[derive(Clone, Debug)]
+pub struct CspNonce(String);
+
+impl Display for CspNonce {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl Deref for CspNonce {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl CspNonce {
+    fn generate() -> Self {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Self(base64::engine::general_purpose::STANDARD.encode(bytes))
+    }
+}
+
+/// Add the configured security headers to every response.
+///
+/// Headers already set by a handler are left untouched.
+pub async fn add_security_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let settings = req
+        .app_data::<web::Data<SecurityHeadersSettings>>()
+        .ok_or(BizErrorEnum::AppDataNotFound("SecurityHeadersSettings"))?
+        .clone();
+    let nonce = CspNonce::generate();
+    req.extensions_mut().insert(nonce.clone());
+
+    // Middlewares must turn rejections into responses (`ServiceRequest::error_response`),
+    // an `Err` goes straight to the client without passing through here.
+    let mut response = next.call(req).await?;
+
+    let headers = response.headers_mut();
+    let csp = settings
+        .content_security_policy
+        .replace(NONCE_PLACEHOLDER, &nonce);
+    insert_if_absent(headers, CONTENT_SECURITY_POLICY, &csp)?;
+    insert_if_absent(headers, X_FRAME_OPTIONS, &settings.frame_options)?;
+    insert_if_absent(headers, REFERRER_POLICY, &settings.referrer_policy)?;
+    insert_if_absent(headers, X_CONTENT_TYPE_OPTIONS, "nosniff")?;
+    if settings.hsts_max_age_seconds > 0 {
+        let hsts = format!(
+            "max-age={}; includeSubDomains",
+            settings.hsts_max_age_seconds
+        );
+        insert_if_absent(headers, STRICT_TRANSPORT_SECURITY, &hsts)?;
+    }
+
+    Ok(response)
+}
+
+fn insert_if_absent(
+    headers: &mut HeaderMap,
+    name: HeaderName,
+    value: &str,
+) -> Result<(), BizErrorEnum> {
+    if !headers.contains_key(&name) {
+        let value = HeaderValue::from_str(value).map_err(|e| {
+            tracing::error!("Invalid {} header value: {:?}", name, e);
+            BizErrorEnum::InvalidHeaderValue(name.to_string(), e)
+        })?;
+        headers.insert(name, value);
+    }
+    Ok(())
+}
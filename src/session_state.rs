@@ -1,8 +1,9 @@
-use crate::constant::SESSION_USER_ID;
+use crate::constant::{SESSION_CSRF_TOKEN, SESSION_ID, SESSION_LOGGED_IN_AT, SESSION_USER_ID};
 use crate::error::BizErrorEnum;
 use actix_session::{Session, SessionExt};
 use actix_web::dev::Payload;
 use actix_web::{FromRequest, HttpRequest};
+use chrono::{DateTime, TimeZone, Utc};
 use std::future::Ready;
 use uuid::Uuid;
 
@@ -17,13 +18,54 @@ impl TypedSession {
     pub fn insert_user_id(&self, user_id: Uuid) -> Result<(), BizErrorEnum> {
         self.0
             .insert(SESSION_USER_ID, user_id)
-            .map_err(|e| BizErrorEnum::ActixSessionInsertError(e))
+            .map_err(BizErrorEnum::ActixSessionInsertError)
     }
 
     pub fn get_user_id(&self) -> Result<Option<Uuid>, BizErrorEnum> {
         self.0
             .get(SESSION_USER_ID)
-            .map_err(|e| BizErrorEnum::ActixSessionGetError(e))
+            .map_err(BizErrorEnum::ActixSessionGetError)
+    }
+
+    /// The id of the `user_sessions` record tracking this session.
+    pub fn insert_session_id(&self, session_id: Uuid) -> Result<(), BizErrorEnum> {
+        self.0
+            .insert(SESSION_ID, session_id)
+            .map_err(BizErrorEnum::ActixSessionInsertError)
+    }
+
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, BizErrorEnum> {
+        self.0
+            .get(SESSION_ID)
+            .map_err(BizErrorEnum::ActixSessionGetError)
+    }
+
+    /// Stored as a unix timestamp, used to enforce the absolute session timeout.
+    pub fn insert_logged_in_at(&self, logged_in_at: DateTime<Utc>) -> Result<(), BizErrorEnum> {
+        self.0
+            .insert(SESSION_LOGGED_IN_AT, logged_in_at.timestamp())
+            .map_err(BizErrorEnum::ActixSessionInsertError)
+    }
+
+    pub fn get_logged_in_at(&self) -> Result<Option<DateTime<Utc>>, BizErrorEnum> {
+        let timestamp: Option<i64> = self
+            .0
+            .get(SESSION_LOGGED_IN_AT)
+            .map_err(BizErrorEnum::ActixSessionGetError)?;
+        Ok(timestamp.and_then(|t| Utc.timestamp_opt(t, 0).single()))
+    }
+
+    /// The synchronizer token every form POST must send back, see `auth::CsrfToken`.
+    pub fn insert_csrf_token(&self, csrf_token: &str) -> Result<(), BizErrorEnum> {
+        self.0
+            .insert(SESSION_CSRF_TOKEN, csrf_token)
+            .map_err(BizErrorEnum::ActixSessionInsertError)
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, BizErrorEnum> {
+        self.0
+            .get(SESSION_CSRF_TOKEN)
+            .map_err(BizErrorEnum::ActixSessionGetError)
     }
 
     pub fn log_out(&self) {
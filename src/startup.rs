@@ -1,18 +1,25 @@
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::configuration::{
+    AttachmentSettings, DatabaseSettings, IdempotencySettings, OpenApiSettings,
+    PasswordPolicySettings, SecurityHeadersSettings, SessionSettings, Settings,
+    SubscriptionSettings, TrackingSettings, WebhookSettings,
+};
 use crate::email_client::EmailClient;
 use crate::error::BizErrorEnum;
-use crate::{auth, routes};
+use crate::idempotency::IdempotencyPolicy;
+use crate::{auth, idempotency, routes, security_headers};
 use actix_session::storage::RedisSessionStore;
-use actix_session::SessionMiddleware;
 use actix_web::cookie::Key;
 use actix_web::dev::Server;
-use actix_web::{web, App, HttpServer};
+use actix_web::http::Method;
+use actix_web::{web, App, HttpServer, Route};
 use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_web_flash_messages::FlashMessagesFramework;
+use argon2::Params;
+use hmac::{Hmac, Mac};
 use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
-use std::net::TcpListener;
+use std::net::{IpAddr, TcpListener};
 use std::time::Duration;
 use tracing_actix_web::TracingLogger;
 
@@ -24,11 +31,12 @@ pub struct Application {
 
 impl Application {
     // We have converted the `build` function into a constructor for `Application`.
-    pub async fn build(config: Settings) -> Result<Self, BizErrorEnum> {
+    /// `email_client` is shared with the workers, so that `/health_check` sees their circuit breaker.
+    pub async fn build(config: Settings, email_client: EmailClient) -> Result<Self, BizErrorEnum> {
         let pg_pool = get_connection_pool(&config.database);
 
-        // Build an `EmailClient` using `configuration`
-        let email_client = config.email_client.client();
+        // Fail fast on invalid password hashing parameters
+        let argon2_params = config.argon2.params()?;
 
         // We have removed the hard-coded `8000` - it's now coming from our settings!
         // 0.0.0.0 as host to instruct our application to accept connections from any network interface,
@@ -46,7 +54,18 @@ impl Application {
             email_client,
             config.application.base_url,
             config.application.hmac_secret,
+            config.application.trusted_proxies,
             config.redis_uri,
+            argon2_params,
+            config.password_policy,
+            config.session,
+            config.security_headers,
+            config.subscriptions,
+            config.tracking,
+            config.attachments,
+            config.idempotency,
+            config.webhooks,
+            config.openapi,
         )
         .await?;
 
@@ -75,13 +94,37 @@ impl Application {
 #[derive(Debug)]
 pub struct ApplicationBaseUrl(pub String);
 
+/// The reverse proxies in front of the application, see `utils::client_ip`.
+#[derive(Debug)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+impl std::ops::Deref for TrustedProxies {
+    type Target = [IpAddr];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn run(
     listener: TcpListener,
     pg_pool: PgPool,
     email_client: EmailClient,
     app_base_url: String,
     hmac_secret: Secret<String>,
+    trusted_proxies: Vec<IpAddr>,
     redis_uri: Secret<String>,
+    argon2_params: Params,
+    password_policy: PasswordPolicySettings,
+    session_settings: SessionSettings,
+    security_headers: SecurityHeadersSettings,
+    subscription_settings: SubscriptionSettings,
+    tracking_settings: TrackingSettings,
+    attachment_settings: AttachmentSettings,
+    idempotency_settings: IdempotencySettings,
+    webhook_settings: WebhookSettings,
+    openapi_settings: OpenApiSettings,
 ) -> Result<Server, BizErrorEnum> {
     // Wrap the connection in a smart pointer
     let connect_pool = web::Data::new(pg_pool);
@@ -89,9 +132,43 @@ async fn run(
     // Re-use the same HTTP client across multiple requests
     let email_client = web::Data::new(email_client);
 
-    // Use at sending confirmation email
+    // Use at sending confirmation and welcome emails
     let app_base_url = web::Data::new(ApplicationBaseUrl(app_base_url));
 
+    // Use at finding out the IP address of the client
+    let trusted_proxies = web::Data::new(TrustedProxies(trusted_proxies));
+
+    // Use at hashing and verifying passwords
+    let argon2_params = web::Data::new(argon2_params);
+
+    // Use at changing passwords
+    let password_policy = web::Data::new(password_policy);
+
+    // Use at enforcing the absolute session timeout
+    let session_settings = web::Data::new(session_settings);
+
+    // Use at adding security headers to every response
+    let security_headers = web::Data::new(security_headers);
+
+    // Use at protecting the subscription form against abuse
+    let subscription_settings = web::Data::new(subscription_settings);
+
+    // Use at recording opens and clicks, and at showing them on the issue pages
+    let tracking_settings = web::Data::new(tracking_settings);
+
+    // Use at uploading the attachments of newsletter issues
+    let max_request_size = attachment_settings.max_request_size();
+    let attachment_settings = web::Data::new(attachment_settings);
+
+    // Use at replaying the saved responses of the admin forms
+    let idempotency_settings = web::Data::new(idempotency_settings);
+
+    // Use at validating the URLs of the webhook endpoints
+    let webhook_settings = web::Data::new(webhook_settings);
+
+    // Use at documenting the routes
+    let openapi_settings = web::Data::new(openapi_settings);
+
     // Flash message, CookieMessageStore enforces that the cookie used as storage is signed
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
@@ -111,37 +188,65 @@ async fn run(
             // Middlewares are added using the `wrap` method on `App`
             .wrap(message_framework.clone())
             .wrap(TracingLogger::default())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                secret_key.clone(),
+            .wrap(session_settings.middleware(redis_store.clone(), secret_key.clone()))
+            // Registered last, so that even the responses of other middlewares get the headers
+            .wrap(actix_web_lab::middleware::from_fn(
+                security_headers::add_security_headers,
             ))
             // Register the connection as part of the application state
             // Get a pointer copy and attach it to the application state
             .app_data(connect_pool.clone())
             .app_data(email_client.clone())
             .app_data(app_base_url.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(argon2_params.clone())
+            .app_data(password_policy.clone())
+            .app_data(session_settings.clone())
+            .app_data(security_headers.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(tracking_settings.clone())
+            .app_data(attachment_settings.clone())
+            .app_data(idempotency_settings.clone())
+            .app_data(webhook_settings.clone())
+            .app_data(openapi_settings.clone())
             .app_data(web::Data::new(HmacSecret(hmac_secret.clone())))
-            .route("/", web::get().to(routes::home))
             .service(
-                web::scope("/admin")
-                    .wrap(actix_web_lab::middleware::from_fn(
-                        auth::reject_anonymous_users,
-                    ))
-                    .route("/dashboard", web::get().to(routes::admin_dashboard))
-                    .route("/password", web::get().to(routes::change_password_form))
-                    .route("/password", web::post().to(routes::change_password))
-                    .route(
-                        "/newsletter",
-                        web::get().to(routes::publish_newsletter_form),
+                ADMIN_ENDPOINTS.iter().fold(
+                    web::scope(ADMIN_SCOPE)
+                        // Forms with attachments are larger than the default limit
+                        .app_data(web::PayloadConfig::new(max_request_size))
+                        // Registered first, so it runs after `reject_anonymous_users`
+                        .wrap(actix_web_lab::middleware::from_fn(
+                            auth::reject_forged_requests,
+                        ))
+                        .wrap(actix_web_lab::middleware::from_fn(
+                            auth::reject_anonymous_users,
+                        )),
+                    |scope, endpoint| scope.route(endpoint.path, endpoint.route()),
+                ),
+            )
+            // JSON only, authenticated by API keys rather than by the session
+            .service(
+                API_ENDPOINTS
+                    .iter()
+                    .fold(
+                        web::scope(API_SCOPE)
+                            // Registered first, so that `render_json_errors` sees its rejections
+                            .wrap(actix_web_lab::middleware::from_fn(
+                                auth::reject_invalid_api_keys,
+                            ))
+                            .wrap(actix_web_lab::middleware::from_fn(
+                                routes::render_json_errors,
+                            )),
+                        |scope, endpoint| scope.route(endpoint.path, endpoint.route()),
                     )
-                    .route("/newsletter", web::post().to(routes::publish_newsletter))
-                    .route("/logout", web::post().to(routes::log_out)),
+                    .default_service(web::to(routes::api_endpoint_not_found)),
             )
-            .route("/login", web::get().to(routes::login_form))
-            .route("/login", web::post().to(routes::login))
-            .route("/health_check", web::get().to(routes::health_check))
-            .route("/subscriptions", web::post().to(routes::subscribe))
-            .route("/subscriptions/confirm", web::get().to(routes::confirm))
+            .configure(|config| {
+                for endpoint in PUBLIC_ENDPOINTS {
+                    config.route(endpoint.path, endpoint.route());
+                }
+            })
     })
     .listen(listener)
     .map_err(|e| {
@@ -154,6 +259,193 @@ async fn run(
     Ok(server)
 }
 
+/// A route of the application: `run` registers every endpoint of the tables below,
+/// the OpenAPI document is checked against them.
+pub struct Endpoint {
+    pub method: Method,
+    /// Relative to the scope of the table
+    pub path: &'static str,
+    /// Everything but the method, e.g. the handler and the middlewares of the route only
+    handler: fn(Route) -> Route,
+}
+
+impl Endpoint {
+    const fn new(method: Method, path: &'static str, handler: fn(Route) -> Route) -> Self {
+        Self {
+            method,
+            path,
+            handler,
+        }
+    }
+
+    fn route(&self) -> Route {
+        (self.handler)(web::route().method(self.method.clone()))
+    }
+}
+
+pub const ADMIN_SCOPE: &str = "/admin";
+pub const API_SCOPE: &str = "/api/v1";
+
+/// Open to anyone.
+pub const PUBLIC_ENDPOINTS: &[Endpoint] = &[
+    Endpoint::new(Method::GET, "/", |route| route.to(routes::home)),
+    Endpoint::new(Method::GET, "/login", |route| {
+        route
+            .to(routes::login_form)
+            .wrap(actix_web_lab::middleware::from_fn(
+                auth::reject_forged_requests,
+            ))
+    }),
+    Endpoint::new(Method::POST, "/login", |route| {
+        route
+            .to(routes::login)
+            .wrap(actix_web_lab::middleware::from_fn(
+                auth::reject_forged_requests,
+            ))
+    }),
+    Endpoint::new(Method::GET, "/health_check", |route| {
+        route.to(routes::health_check)
+    }),
+    Endpoint::new(Method::POST, "/subscriptions", |route| {
+        route
+            .to(routes::subscribe)
+            .wrap(actix_web_lab::middleware::from_fn(|req, next| {
+                idempotency::idempotent(req, next, IdempotencyPolicy::optional())
+            }))
+    }),
+    Endpoint::new(Method::GET, "/subscriptions/confirm", |route| {
+        route.to(routes::confirm)
+    }),
+    Endpoint::new(Method::GET, "/subscriptions/unsubscribe", |route| {
+        route.to(routes::unsubscribe)
+    }),
+    Endpoint::new(Method::GET, "/issues/open", |route| {
+        route.to(routes::track_open)
+    }),
+    Endpoint::new(Method::GET, "/issues/click", |route| {
+        route.to(routes::track_click)
+    }),
+    Endpoint::new(Method::GET, "/openapi.json", |route| {
+        route.to(routes::openapi_json)
+    }),
+    Endpoint::new(Method::GET, "/openapi", |route| {
+        route.to(routes::openapi_viewer)
+    }),
+];
+
+/// Under `ADMIN_SCOPE`, for logged in users only.
+pub const ADMIN_ENDPOINTS: &[Endpoint] = &[
+    Endpoint::new(Method::GET, "/dashboard", |route| {
+        route.to(routes::admin_dashboard)
+    }),
+    Endpoint::new(Method::GET, "/password", |route| {
+        route.to(routes::change_password_form)
+    }),
+    Endpoint::new(Method::POST, "/password", |route| {
+        route.to(routes::change_password)
+    }),
+    Endpoint::new(Method::GET, "/newsletter", |route| {
+        route.to(routes::publish_newsletter_form)
+    }),
+    Endpoint::new(Method::POST, "/newsletter", |route| {
+        route
+            .to(routes::publish_newsletter)
+            .wrap(actix_web_lab::middleware::from_fn(|req, next| {
+                idempotency::idempotent(
+                    req,
+                    next,
+                    IdempotencyPolicy::required().on_replay(routes::flash_newsletter_accepted),
+                )
+            }))
+    }),
+    Endpoint::new(Method::GET, "/email_templates", |route| {
+        route.to(routes::email_templates_form)
+    }),
+    Endpoint::new(Method::POST, "/email_templates", |route| {
+        route.to(routes::update_email_template)
+    }),
+    Endpoint::new(Method::GET, "/issues", |route| {
+        route.to(routes::newsletter_issues)
+    }),
+    Endpoint::new(Method::GET, "/issues/{issue_id}", |route| {
+        route.to(routes::newsletter_issue)
+    }),
+    Endpoint::new(Method::GET, "/sessions", |route| {
+        route.to(routes::active_sessions)
+    }),
+    Endpoint::new(Method::POST, "/sessions/revoke", |route| {
+        route.to(routes::revoke_session)
+    }),
+    Endpoint::new(Method::POST, "/sessions/revoke_others", |route| {
+        route.to(routes::revoke_other_sessions)
+    }),
+    Endpoint::new(Method::GET, "/api_keys", |route| route.to(routes::api_keys)),
+    Endpoint::new(Method::POST, "/api_keys", |route| {
+        route.to(routes::create_api_key)
+    }),
+    Endpoint::new(Method::POST, "/api_keys/revoke", |route| {
+        route.to(routes::revoke_api_key)
+    }),
+    Endpoint::new(Method::GET, "/webhooks", |route| {
+        route.to(routes::webhook_endpoints)
+    }),
+    Endpoint::new(Method::POST, "/webhooks", |route| {
+        route.to(routes::create_webhook)
+    }),
+    Endpoint::new(Method::POST, "/webhooks/remove", |route| {
+        route.to(routes::remove_webhook)
+    }),
+    Endpoint::new(Method::GET, "/webhooks/deliveries", |route| {
+        route.to(routes::webhook_deliveries)
+    }),
+    Endpoint::new(Method::POST, "/logout", |route| route.to(routes::log_out)),
+];
+
+/// Under `API_SCOPE`, for API keys only.
+pub const API_ENDPOINTS: &[Endpoint] = &[
+    Endpoint::new(Method::GET, "/subscribers", |route| {
+        route.to(routes::api_list_subscribers)
+    }),
+    Endpoint::new(Method::POST, "/subscribers", |route| {
+        route
+            .to(routes::api_create_subscriber)
+            .wrap(actix_web_lab::middleware::from_fn(|req, next| {
+                idempotency::idempotent(req, next, IdempotencyPolicy::optional())
+            }))
+    }),
+    Endpoint::new(Method::GET, "/subscribers/{subscriber_id}", |route| {
+        route.to(routes::api_get_subscriber)
+    }),
+    Endpoint::new(Method::PATCH, "/subscribers/{subscriber_id}", |route| {
+        route.to(routes::api_update_subscriber)
+    }),
+    Endpoint::new(Method::DELETE, "/subscribers/{subscriber_id}", |route| {
+        route.to(routes::api_delete_subscriber)
+    }),
+    Endpoint::new(Method::POST, "/issues", |route| {
+        route
+            .to(routes::api_publish_issue)
+            .wrap(actix_web_lab::middleware::from_fn(|req, next| {
+                idempotency::idempotent(req, next, IdempotencyPolicy::optional())
+            }))
+    }),
+    Endpoint::new(Method::GET, "/issues/{issue_id}", |route| {
+        route.to(routes::api_get_issue)
+    }),
+];
+
+/// The method and the full path of every endpoint.
+pub fn endpoints() -> impl Iterator<Item = (&'static Method, String)> {
+    let scoped = |scope: &'static str, endpoints: &'static [Endpoint]| {
+        endpoints
+            .iter()
+            .map(move |endpoint| (&endpoint.method, format!("{}{}", scope, endpoint.path)))
+    };
+    scoped("", PUBLIC_ENDPOINTS)
+        .chain(scoped(ADMIN_SCOPE, ADMIN_ENDPOINTS))
+        .chain(scoped(API_SCOPE, API_ENDPOINTS))
+}
+
 pub fn get_connection_pool(config: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new()
         .acquire_timeout(Duration::from_secs(5))
@@ -162,3 +454,15 @@ pub fn get_connection_pool(config: &DatabaseSettings) -> PgPool {
 
 #[derive(Debug, Clone)]
 pub struct HmacSecret(pub Secret<String>);
+
+impl HmacSecret {
+    /// Hex-encoded HMAC-SHA256 of `kind=value`, to store a personal value without the value.
+    pub fn keyed_hash(&self, kind: &str, value: &str) -> Result<String, BizErrorEnum> {
+        let mut hmac = Hmac::<sha2::Sha256>::new_from_slice(self.0.expose_secret().as_bytes())
+            .map_err(BizErrorEnum::HmacGenerateError)?;
+        hmac.update(kind.as_bytes());
+        hmac.update(b"=");
+        hmac.update(value.as_bytes());
+        Ok(hex::encode(hmac.finalize().into_bytes()))
+    }
+}
@@ -0,0 +1,47 @@
+use crate::configuration::{Settings, SubscriptionSettings};
+use crate::error::BizErrorEnum;
+use crate::startup;
+use chrono::Utc;
+use sqlx::PgPool;
+
+#[tracing::instrument(name = "Run subscription attempts cleanup", skip_all)]
+pub async fn run_cleanup_until_stopped(config: Settings) -> Result<(), BizErrorEnum> {
+    let connection_pool = startup::get_connection_pool(&config.database);
+
+    cleanup_loop(connection_pool, config.subscriptions).await
+}
+
+#[tracing::instrument(name = "Subscription attempts cleanup loop", skip_all)]
+async fn cleanup_loop(pool: PgPool, settings: SubscriptionSettings) -> Result<(), BizErrorEnum> {
+    loop {
+        // A failed run is retried at the next tick, the rate limits only count recent attempts
+        let _ = delete_expired_attempts(&pool, &settings).await;
+        tokio::time::sleep(settings.cleanup_interval()).await;
+    }
+}
+
+/// Delete the attempts older than the rate limit window, they are never counted again.
+///
+/// Returns how many attempts were deleted.
+#[tracing::instrument(name = "Delete expired subscription attempts", skip_all)]
+pub async fn delete_expired_attempts(
+    pool: &PgPool,
+    settings: &SubscriptionSettings,
+) -> Result<u64, BizErrorEnum> {
+    let window_start =
+        Utc::now() - chrono::Duration::seconds(settings.rate_limit_window_seconds as i64);
+    let n_deleted = sqlx::query!(
+        "DELETE FROM subscription_attempts WHERE attempted_at < $1",
+        window_start
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to delete expired subscription_attempts: {:?}", e);
+        BizErrorEnum::DeleteSubscriptionAttemptsError(e)
+    })?
+    .rows_affected();
+
+    tracing::info!(n_deleted, "Deleted expired subscription attempts.");
+    Ok(n_deleted)
+}
@@ -0,0 +1,263 @@
+use crate::error::BizErrorEnum;
+use crate::request::{ClickTrackingData, OpenTrackingData};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A transparent 1x1 GIF.
+pub const PIXEL_GIF: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff\
+!\xf9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EngagementEvent {
+    Open,
+    /// The link which was followed
+    Click(String),
+}
+
+impl EngagementEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EngagementEvent::Open => "open",
+            EngagementEvent::Click(_) => "click",
+        }
+    }
+}
+
+/// The image which reports that the issue was opened, to be appended to its HTML content.
+pub fn open_pixel(
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    app_base_url: &ApplicationBaseUrl,
+    secret: &HmacSecret,
+) -> Result<String, BizErrorEnum> {
+    let src = OpenTrackingData::link(issue_id, subscriber_id, app_base_url, secret)?;
+    Ok(format!(
+        r#"<img src="{}" width="1" height="1" alt="">"#,
+        htmlescape::encode_minimal(&src)
+    ))
+}
+
+/// Sends every http(s) link of `html` through the click tracking endpoint.
+///
+/// Only quoted `href` attributes are rewritten, anything else is left as it is.
+pub fn track_clicks(
+    html: &str,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    app_base_url: &ApplicationBaseUrl,
+    secret: &HmacSecret,
+) -> Result<String, BizErrorEnum> {
+    rewrite_links(html, |url| {
+        ClickTrackingData::link(issue_id, subscriber_id, url, app_base_url, secret)
+    })
+}
+
+fn rewrite_links(
+    html: &str,
+    mut rewrite: impl FnMut(&str) -> Result<String, BizErrorEnum>,
+) -> Result<String, BizErrorEnum> {
+    // ASCII lowercasing keeps the byte offsets of `html`
+    let lowercase = html.to_ascii_lowercase();
+    let mut rewritten = String::with_capacity(html.len());
+    let mut position = 0;
+    while let Some(found) = lowercase[position..].find("href=") {
+        let start = position + found;
+        let value_start = start + "href=".len();
+        let preceded_by_space = html[..start].ends_with(|c: char| c.is_ascii_whitespace());
+        let quote = html[value_start..].chars().next();
+        let value_end = match quote {
+            Some(quote @ ('"' | '\'')) if preceded_by_space => html[value_start + 1..]
+                .find(quote)
+                .map(|end| value_start + 1 + end),
+            _ => None,
+        };
+        let value_end = match value_end {
+            Some(end) => end,
+            None => {
+                rewritten.push_str(&html[position..value_start]);
+                position = value_start;
+                continue;
+            }
+        };
+        let value = &html[value_start + 1..value_end];
+        // Attribute values are escaped, e.g. `&amp;` between query parameters
+        let url = htmlescape::decode_html(value).unwrap_or_else(|_| value.to_string());
+        rewritten.push_str(&html[position..value_start + 1]);
+        if is_trackable(&url) {
+            rewritten.push_str(&htmlescape::encode_minimal(&rewrite(&url)?));
+        } else {
+            rewritten.push_str(value);
+        }
+        position = value_end;
+    }
+    rewritten.push_str(&html[position..]);
+    Ok(rewritten)
+}
+
+/// `mailto:`, anchors and the like do not go through a browser.
+fn is_trackable(url: &str) -> bool {
+    let url = url.trim_start().to_ascii_lowercase();
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+#[tracing::instrument(name = "Save engagement event", skip(pool))]
+pub async fn record_event(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    event: EngagementEvent,
+) -> Result<(), BizErrorEnum> {
+    let url = match &event {
+        EngagementEvent::Open => None,
+        EngagementEvent::Click(url) => Some(url.as_str()),
+    };
+    sqlx::query!(
+        r#"
+            INSERT INTO issue_engagement_events (
+                event_id,
+                newsletter_issue_id,
+                subscriber_id,
+                event_type,
+                url
+            )
+            VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        issue_id,
+        subscriber_id,
+        event.as_str(),
+        url
+    )
+    .execute(pool)
+    .await
+    .map_err(BizErrorEnum::InsertIssueEngagementEventsError)?;
+    Ok(())
+}
+
+/// How the recipients of an issue engaged with it.
+pub struct IssueEngagement {
+    pub title: String,
+    pub published_at: DateTime<Utc>,
+    pub recipients_count: i32,
+    pub track_opens: bool,
+    pub track_clicks: bool,
+    /// Subscribers who opened the issue, following a link counts as opening it
+    pub unique_opens: i64,
+    /// Subscribers who followed at least one link
+    pub unique_clicks: i64,
+    /// Every followed link with its number of clicks, the most popular first
+    pub links: Vec<(String, i64)>,
+}
+
+impl IssueEngagement {
+    pub fn open_rate(&self) -> f64 {
+        rate(self.unique_opens, self.recipients_count)
+    }
+
+    pub fn click_rate(&self) -> f64 {
+        rate(self.unique_clicks, self.recipients_count)
+    }
+}
+
+/// In percent.
+fn rate(count: i64, recipients_count: i32) -> f64 {
+    if recipients_count <= 0 {
+        return 0.0;
+    }
+    (count as f64 * 100.0 / recipients_count as f64).min(100.0)
+}
+
+/// `None` if there is no such issue.
+#[tracing::instrument(name = "Query issue engagement", skip(pool))]
+pub async fn get_issue_engagement(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<IssueEngagement>, BizErrorEnum> {
+    let issue = sqlx::query!(
+        r#"
+            SELECT title, published_at, recipients_count, track_opens, track_clicks
+            FROM newsletter_issues
+            WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(BizErrorEnum::QueryNewsletterIssuesError)?;
+    let issue = match issue {
+        Some(issue) => issue,
+        None => return Ok(None),
+    };
+
+    let counts = sqlx::query!(
+        r#"
+            SELECT
+                COUNT(DISTINCT subscriber_id) AS "unique_opens!",
+                COUNT(DISTINCT subscriber_id) FILTER (WHERE event_type = 'click') AS "unique_clicks!"
+            FROM issue_engagement_events
+            WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(BizErrorEnum::QueryIssueEngagementEventsError)?;
+
+    let links = sqlx::query!(
+        r#"
+            SELECT url AS "url!", COUNT(*) AS "clicks!"
+            FROM issue_engagement_events
+            WHERE newsletter_issue_id = $1 AND event_type = 'click' AND url IS NOT NULL
+            GROUP BY url
+            ORDER BY 2 DESC, 1
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(BizErrorEnum::QueryIssueEngagementEventsError)?
+    .into_iter()
+    .map(|r| (r.url, r.clicks))
+    .collect();
+
+    Ok(Some(IssueEngagement {
+        title: issue.title,
+        published_at: issue.published_at,
+        recipients_count: issue.recipients_count,
+        track_opens: issue.track_opens,
+        track_clicks: issue.track_clicks,
+        unique_opens: counts.unique_opens,
+        unique_clicks: counts.unique_clicks,
+        links,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rewrite_links;
+
+    fn rewrite(html: &str) -> String {
+        rewrite_links(html, |url| Ok(format!("https://t.example/?url={}", url))).unwrap()
+    }
+
+    #[test]
+    fn web_links_are_rewritten() {
+        assert_eq!(
+            rewrite(r#"<p><a href="https://example.com/?a=1&amp;b=2">Read</a></p>"#),
+            r#"<p><a href="https://t.example/?url=https://example.com/?a=1&amp;b=2">Read</a></p>"#
+        );
+        assert_eq!(
+            rewrite("<A HREF='http://example.com'>Read</A>"),
+            "<A HREF='https://t.example/?url=http://example.com'>Read</A>"
+        );
+    }
+
+    #[test]
+    fn other_links_are_kept() {
+        let html = r##"<a href="mailto:a@example.com">Mail</a><a href="#top">Top</a>
+            <a data-href="https://example.com">x</a><a href=https://example.com>y</a>"##;
+        assert_eq!(rewrite(html), html);
+    }
+}
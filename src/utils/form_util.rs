@@ -0,0 +1,45 @@
+use actix_multipart::Multipart;
+use actix_web::dev::Payload;
+use actix_web::http::header::{HeaderMap, CONTENT_TYPE};
+use actix_web::web;
+use futures_util::TryStreamExt;
+
+/// Forms with files are sent as `multipart/form-data` rather than url-encoded.
+pub fn is_multipart(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .trim_start()
+                .to_ascii_lowercase()
+                .starts_with("multipart/form-data")
+        })
+}
+
+/// A request body read by a middleware, to be handed back to the handler.
+pub fn payload_of(body: web::Bytes) -> Payload {
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body);
+    payload.into()
+}
+
+/// The value of the text part `name` of a multipart body.
+pub async fn multipart_text_field(
+    headers: &HeaderMap,
+    body: web::Bytes,
+    name: &str,
+) -> Option<String> {
+    let mut multipart = Multipart::new(headers, payload_of(body));
+    while let Ok(Some(mut field)) = multipart.try_next().await {
+        if field.name() != Some(name) {
+            continue;
+        }
+        let mut value = vec![];
+        while let Ok(Some(chunk)) = field.try_next().await {
+            value.extend_from_slice(&chunk);
+        }
+        return String::from_utf8(value).ok();
+    }
+    None
+}
@@ -0,0 +1,83 @@
+use crate::startup::TrustedProxies;
+use actix_web::{web, HttpRequest};
+use std::net::IpAddr;
+
+/// The IP address of the client, `None` only if the peer address is unknown.
+///
+/// `X-Forwarded-For` can be set by anyone, it is only believed when the peer is one of the
+/// trusted proxies. It is then read from the right, every proxy appending the address it
+/// got the request from, and the first address that is not a trusted proxy wins.
+pub fn client_ip(request: &HttpRequest) -> Option<IpAddr> {
+    let peer_ip = request.peer_addr()?.ip();
+    let trusted_proxies = match request.app_data::<web::Data<TrustedProxies>>() {
+        Some(trusted_proxies) if trusted_proxies.contains(&peer_ip) => trusted_proxies,
+        _ => return Some(peer_ip),
+    };
+
+    let mut client_ip = peer_ip;
+    let forwarded_for = request
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>();
+    for forwarded_ip in forwarded_for.into_iter().rev() {
+        match forwarded_ip.trim().parse::<IpAddr>() {
+            Ok(ip) => client_ip = ip,
+            // Whatever comes before a malformed entry is up to the client
+            Err(_) => break,
+        }
+        if !trusted_proxies.contains(&client_ip) {
+            break;
+        }
+    }
+    Some(client_ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::client_ip;
+    use crate::startup::TrustedProxies;
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+    use std::net::{IpAddr, SocketAddr};
+
+    const PROXY: &str = "10.0.0.2";
+
+    fn request(peer_ip: &str, forwarded_for: Option<&str>) -> actix_web::HttpRequest {
+        let mut request = TestRequest::default()
+            .peer_addr(SocketAddr::new(peer_ip.parse().unwrap(), 40000))
+            .app_data(web::Data::new(TrustedProxies(vec![PROXY.parse().unwrap()])));
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.insert_header(("X-Forwarded-For", forwarded_for));
+        }
+        request.to_http_request()
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn the_header_of_an_untrusted_peer_is_ignored() {
+        let request = request("203.0.113.7", Some("198.51.100.23"));
+        assert_eq!(client_ip(&request), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn the_header_of_a_trusted_proxy_is_read_from_the_right() {
+        let request = request(PROXY, Some("192.0.2.1, 198.51.100.23, 10.0.0.2"));
+        assert_eq!(client_ip(&request), ip("198.51.100.23"));
+    }
+
+    #[test]
+    fn a_trusted_proxy_without_header_is_the_client() {
+        assert_eq!(client_ip(&request(PROXY, None)), ip(PROXY));
+    }
+
+    #[test]
+    fn a_malformed_entry_stops_the_walk() {
+        let request = request(PROXY, Some("198.51.100.23, unknown"));
+        assert_eq!(client_ip(&request), ip(PROXY));
+    }
+}
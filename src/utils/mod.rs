@@ -1,9 +1,13 @@
 mod error_util;
+mod form_util;
+mod ip_util;
 mod response_util;
 mod session_util;
 mod string_util;
 
 pub use error_util::*;
+pub use form_util::*;
+pub use ip_util::*;
 pub use response_util::*;
 pub use session_util::*;
 pub use string_util::*;
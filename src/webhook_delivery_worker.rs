@@ -0,0 +1,407 @@
+use crate::configuration::{Settings, WebhookSettings};
+use crate::error::BizErrorEnum;
+use crate::issue_delivery_worker::ExecutionOutcome;
+use crate::webhooks::WebhookEvent;
+use crate::{startup, telemetry, webhooks};
+use chrono::Utc;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+/// Store `event` with a delivery to every endpoint listening to its type,
+/// in the same transaction as the change it reports.
+///
+/// Nothing is stored if no endpoint listens.
+#[tracing::instrument(name = "Store webhook event", skip_all, fields(event_type = event.event_type().as_str()))]
+pub async fn enqueue_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: WebhookEvent<'_>,
+) -> Result<(), BizErrorEnum> {
+    let event_type = event.event_type().as_str();
+    let webhook_ids: Vec<Uuid> = sqlx::query!(
+        r#"
+            SELECT webhook_id FROM webhook_endpoints
+            WHERE removed_at IS NULL AND $1 = ANY(event_types)
+        "#,
+        event_type
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .map_err(BizErrorEnum::QueryWebhookEndpointsError)?
+    .into_iter()
+    .map(|r| r.webhook_id)
+    .collect();
+    if webhook_ids.is_empty() {
+        return Ok(());
+    }
+
+    let event_id = Uuid::new_v4();
+    let created_at = Utc::now();
+    sqlx::query!(
+        r#"
+            INSERT INTO webhook_events (event_id, event_type, payload, created_at)
+            VALUES ($1, $2, $3, $4)
+        "#,
+        event_id,
+        event_type,
+        event.payload(event_id, created_at)?,
+        created_at
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(BizErrorEnum::InsertWebhookEventsError)?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO webhook_delivery_queue (event_id, webhook_id)
+            SELECT $1, webhook_id FROM UNNEST($2::uuid[]) AS webhook_id
+        "#,
+        event_id,
+        &webhook_ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(BizErrorEnum::InsertWebhookDeliveryQueueError)?;
+
+    Ok(())
+}
+
+/// The client posting the events, shared by all the endpoints.
+///
+/// Redirects are not followed: the endpoint must answer by itself.
+/// Unless `allow_private_networks`, host names only resolve to public addresses.
+pub fn http_client(settings: &WebhookSettings) -> Client {
+    let mut builder = Client::builder()
+        .timeout(settings.timeout())
+        .redirect(Policy::none());
+    if !settings.allow_private_networks {
+        builder = builder.dns_resolver(Arc::new(PublicResolver));
+    }
+    builder.build().unwrap()
+}
+
+/// Drops the private addresses of a host name at every connection, so that a name
+/// valid when the endpoint was added cannot be pointed at the private network later.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| webhooks::is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address.", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[tracing::instrument(name = "Run webhook delivery worker", skip_all)]
+pub async fn run_worker_until_stopped(config: Settings) -> Result<(), BizErrorEnum> {
+    let connection_pool = startup::get_connection_pool(&config.database);
+    let http_client = http_client(&config.webhooks);
+
+    worker_loop(connection_pool, http_client, config.webhooks).await
+}
+
+#[tracing::instrument(name = "Webhook delivery worker loop", skip_all)]
+async fn worker_loop(
+    pool: PgPool,
+    http_client: Client,
+    settings: WebhookSettings,
+) -> Result<(), BizErrorEnum> {
+    loop {
+        match try_execute_task(&pool, &http_client, &settings).await {
+            Ok(ExecutionOutcome::EmptyQueue) | Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(_) => {}
+        }
+    }
+}
+
+struct DeliveryTask {
+    event_id: Uuid,
+    webhook_id: Uuid,
+    n_retries: i16,
+    event_type: String,
+    payload: String,
+    url: String,
+    secret: String,
+}
+
+/// What `webhook_deliveries.outcome` stores.
+#[derive(Clone, Copy, Debug)]
+enum DeliveryOutcome {
+    Delivered,
+    Retrying,
+    /// The last attempt failed too
+    Failed,
+}
+
+impl DeliveryOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryOutcome::Delivered => "delivered",
+            DeliveryOutcome::Retrying => "retrying",
+            DeliveryOutcome::Failed => "failed",
+        }
+    }
+}
+
+/// What came of posting an event to an endpoint.
+struct Attempt {
+    /// `None` if the endpoint did not answer, e.g. a timeout
+    status_code: Option<u16>,
+    /// `None` if the endpoint answered with a 2xx status
+    error: Option<String>,
+    duration: Duration,
+}
+
+impl Attempt {
+    /// The event was not posted, the endpoint cannot be reached
+    fn refused(error: BizErrorEnum) -> Self {
+        Attempt {
+            status_code: None,
+            error: Some(error.to_string()),
+            duration: Duration::ZERO,
+        }
+    }
+}
+
+/// Post the oldest due event to its endpoint, every attempt is logged in `webhook_deliveries`.
+///
+/// A failed delivery is retried later with an exponential backoff,
+/// until `max_retries` is reached.
+#[tracing::instrument(
+    name = "Execute task in webhook delivery queue",
+    skip_all,
+    fields(event_id = tracing::field::Empty, webhook_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    http_client: &Client,
+    settings: &WebhookSettings,
+) -> Result<ExecutionOutcome, BizErrorEnum> {
+    let (mut transaction, task) = match dequeue_task(pool).await? {
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+        Some(task) => task,
+    };
+    telemetry::record_field("event_id", task.event_id);
+    telemetry::record_field("webhook_id", task.webhook_id);
+
+    let attempt = post_event(http_client, &task, settings).await?;
+    let outcome = match &attempt.error {
+        None => DeliveryOutcome::Delivered,
+        Some(error) if task.n_retries + 1 >= settings.max_retries => {
+            tracing::error!(
+                error.message = %error,
+                n_retries = task.n_retries,
+                "Failed to deliver a webhook event. Giving up."
+            );
+            DeliveryOutcome::Failed
+        }
+        Some(error) => {
+            tracing::warn!(
+                error.message = %error,
+                n_retries = task.n_retries,
+                "Failed to deliver a webhook event. Retrying later."
+            );
+            DeliveryOutcome::Retrying
+        }
+    };
+    record_delivery(&mut transaction, &task, &attempt, outcome).await?;
+    match outcome {
+        DeliveryOutcome::Retrying => {
+            let backoff = settings.retry_backoff(task.n_retries);
+            postpone_task(transaction, &task, backoff).await?
+        }
+        DeliveryOutcome::Delivered | DeliveryOutcome::Failed => {
+            delete_task(transaction, &task).await?
+        }
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Any answer but a 2xx status is a failure, the body of the answer is ignored.
+async fn post_event(
+    http_client: &Client,
+    task: &DeliveryTask,
+    settings: &WebhookSettings,
+) -> Result<Attempt, BizErrorEnum> {
+    // An IP address is connected to without going through the resolver
+    let url = match Url::parse(&task.url) {
+        Ok(url) if settings.allow_private_networks || webhooks::has_public_host(&url) => url,
+        Ok(_) => return Ok(Attempt::refused(BizErrorEnum::WebhookUrlIsPrivate)),
+        Err(_) => return Ok(Attempt::refused(BizErrorEnum::WebhookUrlIsInvalid)),
+    };
+    let signature =
+        webhooks::signature_header(&task.secret, Utc::now().timestamp(), &task.payload)?;
+    let started_at = Instant::now();
+    let response = http_client
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .header("X-Webhook-Id", task.event_id.to_string())
+        .header("X-Webhook-Event", &task.event_type)
+        .header("X-Webhook-Signature", signature)
+        .body(task.payload.clone())
+        .send()
+        .await;
+    let duration = started_at.elapsed();
+
+    Ok(match response {
+        Ok(response) if response.status().is_success() => Attempt {
+            status_code: Some(response.status().as_u16()),
+            error: None,
+            duration,
+        },
+        Ok(response) => Attempt {
+            status_code: Some(response.status().as_u16()),
+            error: Some(format!("The endpoint answered {}.", response.status())),
+            duration,
+        },
+        Err(e) => Attempt {
+            status_code: None,
+            error: Some(e.to_string()),
+            duration,
+        },
+    })
+}
+
+#[tracing::instrument(name = "Record webhook delivery", skip(transaction, task, attempt))]
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    attempt: &Attempt,
+    outcome: DeliveryOutcome,
+) -> Result<(), BizErrorEnum> {
+    sqlx::query!(
+        r#"
+            INSERT INTO webhook_deliveries (
+                delivery_id,
+                event_id,
+                webhook_id,
+                attempt,
+                status_code,
+                error,
+                outcome,
+                duration_milliseconds
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        Uuid::new_v4(),
+        task.event_id,
+        task.webhook_id,
+        task.n_retries + 1,
+        attempt.status_code.map(|status_code| status_code as i16),
+        attempt.error,
+        outcome.as_str(),
+        i32::try_from(attempt.duration.as_millis()).unwrap_or(i32::MAX)
+    )
+    .execute(transaction)
+    .await
+    .map_err(BizErrorEnum::InsertWebhookDeliveriesError)?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Dequeue webhook delivery task", skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, DeliveryTask)>, BizErrorEnum> {
+    let mut transaction = pool.begin().await.map_err(BizErrorEnum::PgPoolError)?;
+
+    let task = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+            SELECT q.event_id, q.webhook_id, q.n_retries, e.event_type, e.payload, w.url, w.secret
+            FROM webhook_delivery_queue q
+            JOIN webhook_events e ON e.event_id = q.event_id
+            JOIN webhook_endpoints w ON w.webhook_id = q.webhook_id
+            WHERE q.execute_after <= now()
+            ORDER BY q.execute_after
+            FOR UPDATE OF q
+            SKIP LOCKED
+            LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .map_err(BizErrorEnum::QueryWebhookDeliveryQueueError)?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(name = "Postpone webhook delivery task", skip(transaction, task))]
+async fn postpone_task(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+    backoff: Duration,
+) -> Result<(), BizErrorEnum> {
+    let execute_after =
+        Utc::now() + chrono::Duration::from_std(backoff).unwrap_or(chrono::Duration::max_value());
+    sqlx::query!(
+        r#"
+            UPDATE webhook_delivery_queue
+            SET n_retries = n_retries + 1, execute_after = $3
+            WHERE event_id = $1 AND webhook_id = $2
+        "#,
+        task.event_id,
+        task.webhook_id,
+        execute_after
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(BizErrorEnum::UpdateWebhookDeliveryQueueError)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(BizErrorEnum::TransactionCommitError)
+}
+
+#[tracing::instrument(name = "Delete webhook delivery task", skip_all)]
+async fn delete_task(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), BizErrorEnum> {
+    sqlx::query!(
+        "DELETE FROM webhook_delivery_queue WHERE event_id = $1 AND webhook_id = $2",
+        task.event_id,
+        task.webhook_id
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(BizErrorEnum::DeleteWebhookDeliveryQueueError)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(BizErrorEnum::TransactionCommitError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PublicResolver;
+    use reqwest::dns::Resolve;
+
+    #[tokio::test]
+    async fn names_of_private_addresses_do_not_resolve() {
+        let resolving = PublicResolver.resolve("localhost".parse().unwrap());
+        assert!(resolving.await.is_err());
+    }
+}
@@ -0,0 +1,492 @@
+use crate::error::BizErrorEnum;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use reqwest::Url;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use uuid::Uuid;
+
+/// Tells a webhook secret apart from other secrets, e.g. in a leaked configuration file.
+const WEBHOOK_SECRET_PREFIX: &str = "whsec_";
+const WEBHOOK_SECRET_RANDOM_LENGTH: usize = 32;
+/// The last attempts are enough to see what goes wrong with an endpoint.
+const DELIVERY_LOG_LENGTH: i64 = 100;
+
+/// What the webhook endpoints can listen to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WebhookEventType {
+    SubscriberSubscribed,
+    SubscriberConfirmed,
+    SubscriberUnsubscribed,
+    /// Deleted through the API, nothing of the subscriber is kept
+    SubscriberDeleted,
+    /// Every email of a newsletter issue left the delivery queue
+    IssueDelivered,
+}
+
+impl WebhookEventType {
+    pub const ALL: [WebhookEventType; 5] = [
+        WebhookEventType::SubscriberSubscribed,
+        WebhookEventType::SubscriberConfirmed,
+        WebhookEventType::SubscriberUnsubscribed,
+        WebhookEventType::SubscriberDeleted,
+        WebhookEventType::IssueDelivered,
+    ];
+
+    /// The `type` of the payload, also what `webhook_endpoints.event_types` stores.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventType::SubscriberSubscribed => "subscriber.subscribed",
+            WebhookEventType::SubscriberConfirmed => "subscriber.confirmed",
+            WebhookEventType::SubscriberUnsubscribed => "subscriber.unsubscribed",
+            WebhookEventType::SubscriberDeleted => "subscriber.deleted",
+            WebhookEventType::IssueDelivered => "issue.delivered",
+        }
+    }
+}
+
+/// A subscriber as the payloads show it.
+#[derive(Debug, Serialize)]
+pub struct WebhookSubscriber<'a> {
+    pub id: Uuid,
+    pub email: &'a str,
+    pub name: &'a str,
+}
+
+/// A newsletter issue as the payloads show it.
+#[derive(Debug, Serialize)]
+pub struct WebhookIssue {
+    pub id: Uuid,
+    pub title: String,
+    /// Confirmed subscribers when the issue was published
+    pub recipients: i32,
+    /// Accepted by an email provider
+    pub delivered: i64,
+}
+
+/// Something which happened, to be posted to the endpoints listening to its type.
+#[derive(Debug)]
+pub enum WebhookEvent<'a> {
+    /// They filled in the subscription form, or were created through the API
+    SubscriberSubscribed(WebhookSubscriber<'a>),
+    SubscriberConfirmed(WebhookSubscriber<'a>),
+    SubscriberUnsubscribed(WebhookSubscriber<'a>),
+    SubscriberDeleted(WebhookSubscriber<'a>),
+    IssueDelivered(WebhookIssue),
+}
+
+impl WebhookEvent<'_> {
+    pub fn event_type(&self) -> WebhookEventType {
+        match self {
+            WebhookEvent::SubscriberSubscribed(_) => WebhookEventType::SubscriberSubscribed,
+            WebhookEvent::SubscriberConfirmed(_) => WebhookEventType::SubscriberConfirmed,
+            WebhookEvent::SubscriberUnsubscribed(_) => WebhookEventType::SubscriberUnsubscribed,
+            WebhookEvent::SubscriberDeleted(_) => WebhookEventType::SubscriberDeleted,
+            WebhookEvent::IssueDelivered(_) => WebhookEventType::IssueDelivered,
+        }
+    }
+
+    /// The JSON body posted to the endpoints, the same for all of them and for every retry.
+    pub fn payload(
+        &self,
+        event_id: Uuid,
+        created_at: DateTime<Utc>,
+    ) -> Result<String, BizErrorEnum> {
+        let data = match self {
+            WebhookEvent::SubscriberSubscribed(subscriber)
+            | WebhookEvent::SubscriberConfirmed(subscriber)
+            | WebhookEvent::SubscriberUnsubscribed(subscriber)
+            | WebhookEvent::SubscriberDeleted(subscriber) => {
+                serde_json::json!({ "subscriber": subscriber })
+            }
+            WebhookEvent::IssueDelivered(issue) => serde_json::json!({ "issue": issue }),
+        };
+        serde_json::to_string(&serde_json::json!({
+            "id": event_id,
+            "type": self.event_type().as_str(),
+            "created_at": created_at.to_rfc3339(),
+            "data": data,
+        }))
+        .map_err(BizErrorEnum::SerializeWebhookPayloadError)
+    }
+}
+
+/// An endpoint as listed on the admin pages, the secret is only shown once.
+#[derive(Debug)]
+pub struct WebhookEndpoint {
+    pub webhook_id: Uuid,
+    pub url: String,
+    pub event_types: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    /// Deliveries waiting for their first attempt or for a retry
+    pub pending: i64,
+}
+
+/// One attempt to post an event, as the delivery log shows it.
+#[derive(Debug)]
+pub struct WebhookDelivery {
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub url: String,
+    pub attempt: i16,
+    pub status_code: Option<i16>,
+    pub error: Option<String>,
+    pub outcome: String,
+    pub duration_milliseconds: i32,
+    pub attempted_at: DateTime<Utc>,
+}
+
+/// Add an endpoint listening to `event_types`, returns the secret of its signatures.
+///
+/// Unless `allow_private_networks`, the URL must not name a loopback or private address.
+#[tracing::instrument(name = "Create webhook endpoint", skip(pool))]
+pub async fn create_webhook(
+    user_id: Uuid,
+    url: &str,
+    event_types: &[WebhookEventType],
+    allow_private_networks: bool,
+    pool: &PgPool,
+) -> Result<String, BizErrorEnum> {
+    let url = parse_webhook_url(url, allow_private_networks)?;
+    if event_types.is_empty() {
+        return Err(BizErrorEnum::WebhookEventTypesAreEmpty);
+    }
+    let event_types: Vec<String> = event_types
+        .iter()
+        .map(|event_type| event_type.as_str().to_string())
+        .collect();
+    let secret = generate_webhook_secret();
+    sqlx::query!(
+        r#"
+            INSERT INTO webhook_endpoints (
+                webhook_id,
+                url,
+                secret,
+                event_types,
+                created_by,
+                created_at
+            )
+            VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        Uuid::new_v4(),
+        url.as_str(),
+        secret,
+        &event_types,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .map_err(BizErrorEnum::InsertWebhookEndpointsError)?;
+
+    Ok(secret)
+}
+
+#[tracing::instrument(name = "Query webhook endpoints", skip(pool))]
+pub async fn get_webhooks(pool: &PgPool) -> Result<Vec<WebhookEndpoint>, BizErrorEnum> {
+    sqlx::query_as!(
+        WebhookEndpoint,
+        r#"
+            SELECT
+                w.webhook_id,
+                w.url,
+                w.event_types,
+                w.created_at,
+                (
+                    SELECT COUNT(*) FROM webhook_delivery_queue q
+                    WHERE q.webhook_id = w.webhook_id
+                ) AS "pending!"
+            FROM webhook_endpoints w
+            WHERE w.removed_at IS NULL
+            ORDER BY w.created_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(BizErrorEnum::QueryWebhookEndpointsError)
+}
+
+/// Stop posting to an endpoint, its pending deliveries are dropped.
+///
+/// The endpoint itself is kept for the delivery log.
+#[tracing::instrument(name = "Remove webhook endpoint", skip(pool))]
+pub async fn remove_webhook(webhook_id: Uuid, pool: &PgPool) -> Result<(), BizErrorEnum> {
+    let mut transaction = pool.begin().await.map_err(BizErrorEnum::PgPoolError)?;
+    sqlx::query!(
+        r#"
+            UPDATE webhook_endpoints
+            SET removed_at = now()
+            WHERE webhook_id = $1 AND removed_at IS NULL
+        "#,
+        webhook_id
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(BizErrorEnum::UpdateWebhookEndpointsError)?;
+    sqlx::query!(
+        "DELETE FROM webhook_delivery_queue WHERE webhook_id = $1",
+        webhook_id
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(BizErrorEnum::DeleteWebhookDeliveryQueueError)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(BizErrorEnum::TransactionCommitError)
+}
+
+/// The latest attempts, of every endpoint, the most recent first.
+#[tracing::instrument(name = "Query webhook deliveries", skip(pool))]
+pub async fn get_webhook_deliveries(pool: &PgPool) -> Result<Vec<WebhookDelivery>, BizErrorEnum> {
+    sqlx::query_as!(
+        WebhookDelivery,
+        r#"
+            SELECT
+                d.event_id,
+                e.event_type,
+                w.url,
+                d.attempt,
+                d.status_code,
+                d.error,
+                d.outcome,
+                d.duration_milliseconds,
+                d.attempted_at
+            FROM webhook_deliveries d
+            JOIN webhook_events e ON e.event_id = d.event_id
+            JOIN webhook_endpoints w ON w.webhook_id = d.webhook_id
+            ORDER BY d.attempted_at DESC, d.attempt DESC
+            LIMIT $1
+        "#,
+        DELIVERY_LOG_LENGTH
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(BizErrorEnum::QueryWebhookDeliveriesError)
+}
+
+/// The value of the `X-Webhook-Signature` header: `t=<unix timestamp>,v1=<hex>`.
+///
+/// `v1` is the HMAC-SHA256 of `<timestamp>.<payload>` keyed with the secret of the endpoint,
+/// the timestamp lets the receiver reject replayed requests.
+pub fn signature_header(
+    secret: &str,
+    timestamp: i64,
+    payload: &str,
+) -> Result<String, BizErrorEnum> {
+    let mut hmac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(BizErrorEnum::HmacGenerateError)?;
+    hmac.update(timestamp.to_string().as_bytes());
+    hmac.update(b".");
+    hmac.update(payload.as_bytes());
+    Ok(format!(
+        "t={},v1={}",
+        timestamp,
+        hex::encode(hmac.finalize().into_bytes())
+    ))
+}
+
+/// Whether `ip` can be reached from the internet.
+///
+/// The endpoints are posted to from inside the deployment: a private address would let
+/// whoever adds an endpoint reach the database, the cache or the metadata service of the host.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ipv4(ip),
+            None => is_public_ipv6(ip),
+        },
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "This network", 0.0.0.0 reaches the host itself
+        || first == 0
+        // Shared address space of carrier-grade NAT
+        || (first == 100 && (second & 0b1100_0000) == 64))
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let first_segment = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local, fc00::/7
+        || (first_segment & 0xfe00) == 0xfc00
+        // Link-local, fe80::/10
+        || (first_segment & 0xffc0) == 0xfe80)
+}
+
+/// Whether the host of `url` is not a private address or `localhost`.
+///
+/// Host names are only known to be public once resolved, see `webhook_delivery_worker`.
+pub fn has_public_host(url: &Url) -> bool {
+    // `localhost.` is `localhost` too
+    let host = url.host_str().unwrap_or_default().trim_end_matches('.');
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => is_public_ip(ip),
+        Err(_) => host != "localhost" && !host.ends_with(".localhost"),
+    }
+}
+
+fn parse_webhook_url(url: &str, allow_private_networks: bool) -> Result<Url, BizErrorEnum> {
+    let url = Url::parse(url.trim()).map_err(|_| BizErrorEnum::WebhookUrlIsInvalid)?;
+    if !matches!(url.scheme(), "http" | "https") || !url.has_host() {
+        return Err(BizErrorEnum::WebhookUrlIsInvalid);
+    }
+    if !allow_private_networks && !has_public_host(&url) {
+        return Err(BizErrorEnum::WebhookUrlIsPrivate);
+    }
+    Ok(url)
+}
+
+fn generate_webhook_secret() -> String {
+    let mut rng = rand::thread_rng();
+    let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(WEBHOOK_SECRET_RANDOM_LENGTH)
+        .collect();
+    format!("{}{}", WEBHOOK_SECRET_PREFIX, random)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        generate_webhook_secret, is_public_ip, parse_webhook_url, signature_header, WebhookEvent,
+        WebhookSubscriber, WEBHOOK_SECRET_PREFIX,
+    };
+    use crate::error::BizErrorEnum;
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_err, assert_ok};
+    use uuid::Uuid;
+
+    #[test]
+    fn the_signature_is_the_hmac_of_the_timestamp_and_the_payload() {
+        // Computed with Python's `hmac` module
+        assert_eq!(
+            signature_header("whsec_test", 1760000000, r#"{"id":1}"#).unwrap(),
+            "t=1760000000,v1=2219cf46908f1b47101360db549457ea39bd64104042305e012ef4cefca1ee7c"
+        );
+    }
+
+    #[test]
+    fn the_signature_depends_on_the_timestamp() {
+        let v1 = |timestamp| {
+            let header = signature_header("whsec_test", timestamp, "{}").unwrap();
+            header.split_once(",v1=").unwrap().1.to_string()
+        };
+        assert_ne!(v1(1760000000), v1(1760000001));
+    }
+
+    #[test]
+    fn generated_secrets_are_prefixed_and_unique() {
+        let (a, b) = (generate_webhook_secret(), generate_webhook_secret());
+        assert!(a.starts_with(WEBHOOK_SECRET_PREFIX));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn only_absolute_http_urls_are_accepted() {
+        assert_ok!(parse_webhook_url(
+            "https://crm.example.com/hooks/newsletter",
+            false
+        ));
+        assert_ok!(parse_webhook_url("http://127.0.0.1:8080", true));
+        assert_err!(parse_webhook_url("crm.example.com/hooks", true));
+        assert_err!(parse_webhook_url("ftp://crm.example.com", true));
+        assert_err!(parse_webhook_url("mailto:crm@example.com", true));
+    }
+
+    #[test]
+    fn urls_of_private_networks_are_rejected_unless_allowed() {
+        for url in [
+            "http://127.0.0.1:8080",
+            "http://localhost:8080",
+            "http://api.localhost",
+            "http://localhost.:8080",
+            "http://10.0.0.5",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]",
+            "http://[fd00::1]",
+            "http://[::ffff:192.168.1.1]",
+            "http://0.0.0.0:5432",
+        ] {
+            assert!(matches!(
+                parse_webhook_url(url, false),
+                Err(BizErrorEnum::WebhookUrlIsPrivate)
+            ));
+            assert_ok!(parse_webhook_url(url, true));
+        }
+    }
+
+    #[test]
+    fn only_public_ips_are_public() {
+        for ip in [
+            "93.184.216.34",
+            "2606:2800:220:1::1",
+            "::ffff:93.184.216.34",
+        ] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.0.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "224.0.0.1",
+            "::",
+            "::1",
+            "fc00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn the_payload_has_the_type_and_the_data_of_the_event() {
+        let event_id = Uuid::new_v4();
+        let subscriber_id = Uuid::new_v4();
+        let event = WebhookEvent::SubscriberConfirmed(WebhookSubscriber {
+            id: subscriber_id,
+            email: "ursula_le_guin@gmail.com",
+            name: "le guin",
+        });
+
+        let payload = event
+            .payload(
+                event_id,
+                Utc.with_ymd_and_hms(2026, 10, 18, 0, 0, 0).unwrap(),
+            )
+            .unwrap();
+
+        let payload: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(payload["id"], event_id.to_string());
+        assert_eq!(payload["type"], "subscriber.confirmed");
+        assert_eq!(payload["created_at"], "2026-10-18T00:00:00+00:00");
+        assert_eq!(
+            payload["data"]["subscriber"]["id"],
+            subscriber_id.to_string()
+        );
+        assert_eq!(
+            payload["data"]["subscriber"]["email"],
+            "ursula_le_guin@gmail.com"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::helpers;
+use crate::helpers::TestApp;
+use reqwest::Method;
+use uuid::Uuid;
+
+async fn active_api_key_ids(app: &TestApp) -> Vec<Uuid> {
+    sqlx::query!(
+        "SELECT api_key_id FROM api_keys WHERE created_by = $1 AND revoked_at IS NULL",
+        app.test_user.user_id
+    )
+    .fetch_all(&app.connect_pool)
+    .await
+    .expect("Failed to fetch active api keys.")
+    .into_iter()
+    .map(|r| r.api_key_id)
+    .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_api_keys() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app.get_admin_api_keys().await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_new_api_key_is_shown_once_and_only_its_hash_is_stored() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let api_key = app.create_api_key().await;
+
+    // Assert
+    let stored = sqlx::query!("SELECT name, key_prefix, key_hash FROM api_keys")
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.name, "CMS");
+    assert!(api_key.starts_with(&stored.key_prefix));
+    assert_ne!(stored.key_hash, api_key);
+    assert!(!stored.key_hash.contains(&api_key));
+
+    let html_page = app.get_admin_api_keys_html().await;
+    assert!(html_page.contains("CMS"));
+    assert!(html_page.contains(&stored.key_prefix));
+    assert!(!html_page.contains(&api_key));
+}
+
+#[tokio::test]
+async fn an_api_key_without_a_name_is_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_create_api_key(&serde_json::json!({ "name": "  " }))
+        .await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/admin/api_keys");
+    let html_page = app.get_admin_api_keys_html().await;
+    assert!(html_page.contains("The name of the API key is empty."));
+    assert!(active_api_key_ids(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn a_revoked_api_key_is_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let api_key = app.create_api_key().await;
+    let response = app
+        .api_v1(Method::GET, "/subscribers", &api_key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let api_key_id = active_api_key_ids(&app).await[0];
+    let response = app
+        .post_revoke_api_key(&serde_json::json!({ "api_key_id": api_key_id }))
+        .await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/admin/api_keys");
+    assert!(app
+        .get_admin_api_keys_html()
+        .await
+        .contains("The API key has been revoked."));
+    let response = app
+        .api_v1(Method::GET, "/subscribers", &api_key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_last_use_of_an_api_key_is_recorded() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let api_key = app.create_api_key().await;
+
+    // Act
+    app.api_v1(Method::GET, "/subscribers", &api_key)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let html_page = app.get_admin_api_keys_html().await;
+    assert!(!html_page.contains("Never"));
+}
@@ -0,0 +1,282 @@
+use crate::helpers;
+use crate::helpers::TestApp;
+use uuid::Uuid;
+
+async fn active_session_ids(app: &TestApp) -> Vec<Uuid> {
+    sqlx::query!(
+        "SELECT session_id FROM user_sessions WHERE user_id = $1 AND revoked_at IS NULL",
+        app.test_user.user_id
+    )
+    .fetch_all(&app.connect_pool)
+    .await
+    .expect("Failed to fetch active sessions.")
+    .into_iter()
+    .map(|r| r.session_id)
+    .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_active_sessions() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app.get_admin_sessions().await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn active_sessions_lists_every_logged_in_device() {
+    // Arrange
+    let mut app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let first_device = app.api_client.clone();
+    app.api_client = helpers::build_api_client();
+    app.test_user.login(&app).await;
+    app.api_client = first_device;
+
+    // Act
+    let html_page = app.get_admin_sessions_html().await;
+
+    // Assert
+    let session_ids = active_session_ids(&app).await;
+    assert_eq!(session_ids.len(), 2);
+    assert!(html_page.contains("This session"));
+    assert_eq!(html_page.matches("/admin/sessions/revoke\"").count(), 1);
+}
+
+#[tokio::test]
+async fn a_revoked_session_is_logged_out() {
+    // Arrange
+    let mut app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let first_session_id = active_session_ids(&app).await[0];
+    let first_device = app.api_client.clone();
+    app.api_client = helpers::build_api_client();
+    app.test_user.login(&app).await;
+    let second_device = app.api_client.clone();
+    let second_session_id = active_session_ids(&app)
+        .await
+        .into_iter()
+        .find(|id| *id != first_session_id)
+        .unwrap();
+
+    // Act - Part 1 - Revoke the second session from the first device
+    app.api_client = first_device;
+    let response = app
+        .post_revoke_session(&serde_json::json!({ "session_id": second_session_id }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get_admin_sessions_html().await;
+    assert!(html_page.contains("<p><i>The session has been logged out.</i></p>"));
+
+    // Act - Part 2 - The second device is kicked out
+    app.api_client = second_device;
+    let response = app.get_admin_dashboard().await;
+    helpers::assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your session has been revoked, please log in again.</i></p>"));
+
+    // Assert
+    assert_eq!(active_session_ids(&app).await, vec![first_session_id]);
+}
+
+#[tokio::test]
+async fn revoking_other_sessions_keeps_the_current_one() {
+    // Arrange
+    let mut app = TestApp::spawn_app().await;
+    let other_device = helpers::build_api_client();
+    let current_device = app.api_client.clone();
+    app.api_client = other_device.clone();
+    app.test_user.login(&app).await;
+    app.api_client = current_device;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.post_revoke_other_sessions().await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/admin/sessions");
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.api_client = other_device;
+    let response = app.get_admin_dashboard().await;
+    helpers::assert_is_redirect_to(&response, "/login");
+    assert_eq!(active_session_ids(&app).await.len(), 1);
+}
+
+#[tokio::test]
+async fn changing_password_logs_out_other_sessions() {
+    // Arrange
+    let mut app = TestApp::spawn_app().await;
+    let other_device = helpers::build_api_client();
+    let current_device = app.api_client.clone();
+    app.api_client = other_device.clone();
+    app.test_user.login(&app).await;
+    app.api_client = current_device;
+    app.test_user.login(&app).await;
+
+    // Act
+    let new_password = Uuid::new_v4().to_string();
+    let body = serde_json::json!({
+        "current_password": &app.test_user.password,
+        "new_password": &new_password,
+        "new_password_check": &new_password
+    });
+    let response = app.post_change_password(&body).await;
+    helpers::assert_is_redirect_to(&response, "/admin/password");
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.api_client = other_device;
+    let response = app.get_admin_dashboard().await;
+    helpers::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_session_expires_after_the_idle_timeout() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| config.session.idle_timeout_seconds = 1).await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Stay idle for too long
+    tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_session_expires_after_the_absolute_timeout_despite_activity() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.session.absolute_timeout_seconds = 2;
+        config.session.idle_timeout_seconds = 60;
+    })
+    .await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Keep the session busy
+    // The login time is stored with a one second precision
+    for _ in 0..3 {
+        let response = app.get_admin_dashboard().await;
+        assert_eq!(response.status().as_u16(), 200);
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(2000)).await;
+
+    // Act - Part 2 - Past the absolute timeout
+    let response = app.get_admin_dashboard().await;
+    helpers::assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 3 - Follow the redirect
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Your session has expired, please log in again."));
+    assert!(active_session_ids(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn the_session_cookie_follows_the_configured_policy() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.session.cookie_name = "admin_session".into();
+        config.session.persistent_cookie = true;
+        config.session.idle_timeout_seconds = 600;
+    })
+    .await;
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+
+    // Assert
+    let session_cookie = response
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find(|value| value.starts_with("admin_session="))
+        .expect("No session cookie was set.");
+    assert!(session_cookie.contains("HttpOnly"));
+    assert!(session_cookie.contains("SameSite=Lax"));
+    assert!(session_cookie.contains("Max-Age=600"));
+    assert!(!session_cookie.contains("Secure"));
+}
+
+#[tokio::test]
+async fn a_browser_session_cookie_has_no_max_age() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+
+    // Assert
+    let session_cookie = response
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find(|value| value.starts_with("id="))
+        .expect("No session cookie was set.");
+    assert!(!session_cookie.contains("Max-Age"));
+}
+
+/// Log in through a client claiming to forward for `203.0.113.7`.
+async fn login_forwarded_for(app: &mut TestApp) -> Option<String> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("X-Forwarded-For", "203.0.113.7".parse().unwrap());
+    app.api_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .default_headers(headers)
+        .build()
+        .unwrap();
+    app.test_user.login(app).await;
+
+    sqlx::query!("SELECT ip_address FROM user_sessions")
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap()
+        .ip_address
+}
+
+#[tokio::test]
+async fn a_forwarded_ip_address_is_ignored_from_an_untrusted_peer() {
+    // Arrange
+    let mut app = TestApp::spawn_app().await;
+
+    // Act
+    let ip_address = login_forwarded_for(&mut app).await;
+
+    // Assert
+    assert_eq!(ip_address.as_deref(), Some("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn a_forwarded_ip_address_is_recorded_behind_a_trusted_proxy() {
+    // Arrange
+    let mut app = TestApp::spawn_app_with(|config| {
+        config.application.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    })
+    .await;
+
+    // Act
+    let ip_address = login_forwarded_for(&mut app).await;
+
+    // Assert
+    assert_eq!(ip_address.as_deref(), Some("203.0.113.7"));
+}
@@ -0,0 +1,407 @@
+use crate::helpers::TestApp;
+use reqwest::{Method, Response};
+use serde_json::Value;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn json_body(response: Response) -> Value {
+    response.json().await.expect("The body is not JSON.")
+}
+
+fn assert_json_error(body: &Value, status: u16, code: &str) {
+    assert_eq!(body["error"]["status"], status);
+    assert_eq!(body["error"]["code"], code);
+    assert!(body["error"]["message"].is_string());
+}
+
+async fn create_subscriber(app: &TestApp, api_key: &str, email: &str) -> Response {
+    app.api_v1(Method::POST, "/subscribers", api_key)
+        .json(&serde_json::json!({ "email": email, "name": "Le Guin" }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn newsletter_issues_count(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn requests_without_a_valid_api_key_are_rejected_with_a_json_error() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let url = format!("{}/api/v1/subscribers", &app.address);
+
+    for request in [
+        app.api_client.get(&url),
+        app.api_client.get(&url).bearer_auth("z2p_not-a-key"),
+        app.api_client.get(&url).basic_auth("admin", Some("123456")),
+    ] {
+        // Act
+        let response = request.send().await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+        assert_eq!(response.headers()["Content-Type"], "application/json");
+        assert_json_error(&json_body(response).await, 401, "unauthorized");
+    }
+}
+
+#[tokio::test]
+async fn a_logged_in_session_does_not_give_access_to_the_api() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(&format!("{}/api/v1/subscribers", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_created_read_updated_and_deleted() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let api_key = app.create_api_key().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Create, the confirmation email goes out as for the form
+    let response = create_subscriber(&app, &api_key, "ursula_le_guin@gmail.com").await;
+    assert_eq!(response.status().as_u16(), 201);
+    let location = response.headers()["Location"].to_str().unwrap().to_owned();
+    let created = json_body(response).await;
+    assert_eq!(created["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(created["status"], "pending_confirmation");
+    assert_eq!(
+        location,
+        format!("/api/v1/subscribers/{}", created["id"].as_str().unwrap())
+    );
+    app.dispatch_outbox_emails().await;
+
+    // Act - Part 2 - Read
+    let path = location.trim_start_matches("/api/v1");
+    let response = app
+        .api_v1(Method::GET, path, &api_key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(json_body(response).await, created);
+    let response = app
+        .api_v1(Method::GET, "/subscribers", &api_key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(json_body(response).await["subscribers"][0], created);
+
+    // Act - Part 3 - Update
+    let response = app
+        .api_v1(Method::PATCH, path, &api_key)
+        .json(&serde_json::json!({ "name": "Ursula K. Le Guin" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let updated = json_body(response).await;
+    assert_eq!(updated["name"], "Ursula K. Le Guin");
+    assert_eq!(updated["email"], created["email"]);
+
+    // Act - Part 4 - Delete
+    let response = app
+        .api_v1(Method::DELETE, path, &api_key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+
+    // Assert
+    let response = app
+        .api_v1(Method::GET, path, &api_key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+    assert_json_error(&json_body(response).await, 404, "not_found");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_listed_by_status() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let api_key = app.create_api_key().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    create_subscriber(&app, &api_key, "pending@example.com").await;
+
+    // Act
+    let response = app
+        .api_v1(Method::GET, "/subscribers?status=confirmed", &api_key)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let subscribers = json_body(response).await["subscribers"].clone();
+    assert_eq!(subscribers.as_array().unwrap().len(), 1);
+    assert_eq!(subscribers[0]["email"], "ursula_le_guin@gmail.com");
+    let response = app
+        .api_v1(Method::GET, "/subscribers?status=banned", &api_key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+    assert_json_error(&json_body(response).await, 400, "bad_request");
+}
+
+#[tokio::test]
+async fn creating_an_existing_subscriber_is_a_conflict() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let api_key = app.create_api_key().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    create_subscriber(&app, &api_key, "ursula_le_guin@gmail.com").await;
+
+    // Act
+    let response = create_subscriber(&app, &api_key, "ursula_le_guin@gmail.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let body = json_body(response).await;
+    assert_json_error(&body, 409, "conflict");
+    assert_eq!(
+        body["error"]["message"],
+        "A subscriber with this email address already exists."
+    );
+}
+
+#[tokio::test]
+async fn invalid_requests_get_a_400_explaining_what_is_wrong() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let api_key = app.create_api_key().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "email": "not-an-email", "name": "Le Guin" }).to_string(),
+            "Subscriber's email",
+        ),
+        (
+            serde_json::json!({ "email": "ursula_le_guin@gmail.com", "name": "" }).to_string(),
+            "Subscriber's name is empty.",
+        ),
+        (
+            serde_json::json!({ "name": "Le Guin" }).to_string(),
+            "missing field `email`",
+        ),
+        ("{ not json".to_string(), "Json deserialize error"),
+    ];
+
+    for (body, expected_message) in test_cases {
+        // Act
+        let response = app
+            .api_v1(Method::POST, "/subscribers", &api_key)
+            .header("Content-Type", "application/json")
+            .body(body.clone())
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "Body: {}", body);
+        let response_body = json_body(response).await;
+        assert_json_error(&response_body, 400, "bad_request");
+        let message = response_body["error"]["message"].as_str().unwrap();
+        assert!(
+            message.contains(expected_message),
+            "{} does not mention {}",
+            message,
+            expected_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_published_issue_reports_its_delivery_status() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let api_key = app.create_api_key().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Publish
+    let response = app
+        .api_v1(Method::POST, "/issues", &api_key)
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content_format": "markdown",
+            "markdown_content": "Hello **{{subscriber.name}}**",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    let published = json_body(response).await;
+    assert_eq!(published["title"], "Newsletter title");
+    assert_eq!(published["delivery"]["recipients"], 1);
+    assert_eq!(published["delivery"]["pending"], 1);
+    assert_eq!(published["delivery"]["delivered"], 0);
+
+    // Act - Part 2 - Deliver
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let issue_path = format!("/issues/{}", published["id"].as_str().unwrap());
+    let response = app
+        .api_v1(Method::GET, &issue_path, &api_key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let issue = json_body(response).await;
+    assert_eq!(issue["delivery"]["pending"], 0);
+    assert_eq!(issue["delivery"]["delivered"], 1);
+    assert_eq!(issue["delivery"]["not_delivered"], 0);
+}
+
+#[tokio::test]
+async fn publishing_is_idempotent_per_api_key() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let api_key = app.create_api_key().await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+    });
+
+    // Act
+    let mut responses = vec![];
+    for _ in 0..2 {
+        let response = app
+            .api_v1(Method::POST, "/issues", &api_key)
+            .header("Idempotency-Key", &idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 201);
+        responses.push(response.text().await.unwrap());
+    }
+
+    // Assert
+    assert_eq!(responses[0], responses[1]);
+    assert_eq!(newsletter_issues_count(&app).await, 1);
+    let scope = sqlx::query!("SELECT scope FROM idempotency")
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap()
+        .scope;
+    assert!(scope.starts_with("api_key:"));
+}
+
+#[tokio::test]
+async fn an_invalid_issue_is_rejected_before_anything_is_saved() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let api_key = app.create_api_key().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "title": "", "text_content": "text", "html_content": "<p>html</p>" }),
+            400,
+        ),
+        (
+            serde_json::json!({ "title": "Hi {{subscriber.nmae}}", "text_content": "text", "html_content": "<p>html</p>" }),
+            400,
+        ),
+        (
+            serde_json::json!({ "title": "Title", "text_content": "text", "html_content": "<p>html</p>", "trusted_raw_html": true }),
+            403,
+        ),
+    ];
+
+    for (body, status) in test_cases {
+        // Act
+        let response = app
+            .api_v1(Method::POST, "/issues", &api_key)
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), status, "Body: {}", body);
+        assert_eq!(json_body(response).await["error"]["status"], status);
+    }
+    assert_eq!(newsletter_issues_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn unknown_endpoints_and_resources_get_a_json_404() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let api_key = app.create_api_key().await;
+
+    for (method, path) in [
+        (Method::GET, "/unknown".to_string()),
+        // Only `POST /issues` exists
+        (Method::PUT, "/issues".to_string()),
+        (Method::GET, format!("/issues/{}", Uuid::new_v4())),
+    ] {
+        // Act
+        let response = app.api_v1(method, &path, &api_key).send().await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 404, "Path: {}", path);
+        assert_json_error(&json_body(response).await, 404, "not_found");
+    }
+}
+
+#[tokio::test]
+async fn clients_which_do_not_accept_json_get_a_406() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let api_key = app.create_api_key().await;
+
+    // Act
+    let response = app
+        .api_v1(Method::GET, "/subscribers", &api_key)
+        .header("Accept", "text/html")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 406);
+    assert_json_error(&json_body(response).await, 406, "not_acceptable");
+}
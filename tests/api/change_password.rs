@@ -166,3 +166,50 @@ async fn changing_password_works() {
     let response = app.post_login(&login_body).await;
     helpers::assert_is_redirect_to(&response, "/admin/dashboard");
 }
+
+#[tokio::test]
+async fn new_password_must_satisfy_the_password_policy() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let test_cases = vec![
+        (
+            app.test_user.password.clone(),
+            "The new password must be different from the current password.",
+        ),
+        (
+            format!("{}-{}", &app.test_user.username, Uuid::new_v4()),
+            "The new password must not contain your username.",
+        ),
+        (
+            "password123".to_string(),
+            "The new password has appeared in a data breach, please choose another one.",
+        ),
+        (
+            "abcdefgh".to_string(),
+            "The new password is too easy to guess, please choose a stronger one.",
+        ),
+    ];
+    for (new_password, error_message) in test_cases {
+        // Act - Part 1 - Try to change password
+        let body = serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password
+        });
+        let response = app.post_change_password(&body).await;
+
+        // Assert
+        helpers::assert_is_redirect_to(&response, "/admin/password");
+
+        // Act - Part 2 - Follow the redirect
+        let html_page = app.get_change_password_html().await;
+        assert!(
+            html_page.contains(&format!("<p><i>{}</i></p>", error_message)),
+            "The password `{}` was not rejected with `{}`",
+            new_password,
+            error_message
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use crate::helpers;
+use crate::helpers::TestApp;
+
+async fn post_form_with_csrf_token(app: &TestApp, path: &str, csrf_token: Option<&str>) -> u16 {
+    let mut body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "current_password": &app.test_user.password,
+        "new_password": "a brand new passphrase",
+        "new_password_check": "a brand new passphrase",
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    });
+    if let Some(csrf_token) = csrf_token {
+        body["csrf_token"] = csrf_token.into();
+    }
+    app.api_client
+        .post(format!("{}{}", &app.address, path))
+        .form(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .status()
+        .as_u16()
+}
+
+#[tokio::test]
+async fn login_without_a_csrf_token_is_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.get_login_html().await;
+
+    // Act
+    let status = post_form_with_csrf_token(&app, "/login", None).await;
+
+    // Assert
+    assert_eq!(status, 403);
+    helpers::assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn login_with_a_csrf_token_of_another_session_is_rejected() {
+    // Arrange
+    let mut app = TestApp::spawn_app().await;
+    let attacker_csrf_token = app.get_csrf_token().await;
+    app.api_client = helpers::build_api_client();
+    app.get_login_html().await;
+
+    // Act
+    let status = post_form_with_csrf_token(&app, "/login", Some(&attacker_csrf_token)).await;
+
+    // Assert
+    assert_eq!(status, 403);
+}
+
+#[tokio::test]
+async fn admin_forms_without_a_valid_csrf_token_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    for path in [
+        "/admin/password",
+        "/admin/newsletter",
+        "/admin/sessions/revoke_others",
+        "/admin/logout",
+    ] {
+        // Act
+        let missing = post_form_with_csrf_token(&app, path, None).await;
+        let forged = post_form_with_csrf_token(&app, path, Some("forged-token")).await;
+
+        // Assert
+        assert_eq!(missing, 403, "{} accepted a form without CSRF token", path);
+        assert_eq!(forged, 403, "{} accepted a forged CSRF token", path);
+    }
+    // Still logged in, nothing has been changed
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn admin_forms_embed_the_csrf_token_of_the_session() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let csrf_token = app.get_csrf_token().await;
+
+    // Act
+    let pages = [
+        app.get_admin_dashboard_html().await,
+        app.get_change_password_html().await,
+        app.get_newsletter_html().await,
+        app.get_admin_sessions_html().await,
+    ];
+
+    // Assert
+    for html_page in pages {
+        assert_eq!(helpers::extract_csrf_token(&html_page), csrf_token);
+    }
+}
+
+#[tokio::test]
+async fn the_csrf_token_is_rotated_on_login() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let anonymous_csrf_token = app.get_csrf_token().await;
+
+    // Act
+    app.test_user.login(&app).await;
+
+    // Assert
+    assert_ne!(app.get_csrf_token().await, anonymous_csrf_token);
+    let status =
+        post_form_with_csrf_token(&app, "/admin/logout", Some(&anonymous_csrf_token)).await;
+    assert_eq!(status, 403);
+}
+
+#[tokio::test]
+async fn a_valid_csrf_token_is_accepted() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let csrf_token = app.get_csrf_token().await;
+
+    // Act
+    let status = post_form_with_csrf_token(&app, "/admin/logout", Some(&csrf_token)).await;
+
+    // Assert
+    assert_eq!(status, 303);
+    helpers::assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
@@ -0,0 +1,192 @@
+use crate::helpers;
+use crate::helpers::TestApp;
+use secrecy::Secret;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockBuilder, MockServer, ResponseTemplate};
+use zero_2_prod::configuration::EmailProviderSettings;
+
+// Short-hand for a common mocking setup
+fn when_sending_an_email() -> MockBuilder {
+    Mock::given(path("/email")).and(method("POST"))
+}
+
+/// An application with a second mock server standing in for the secondary email provider.
+async fn spawn_app_with_secondary_provider() -> (TestApp, MockServer) {
+    let secondary_server = MockServer::start().await;
+    let secondary = EmailProviderSettings {
+        name: "backup".into(),
+        base_url: secondary_server.uri(),
+        authorization_token: Secret::new("backup-token".into()),
+    };
+    let app =
+        TestApp::spawn_app_with(|config| config.email_client.secondary = Some(secondary)).await;
+    (app, secondary_server)
+}
+
+async fn delivery_providers(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT provider FROM email_deliveries ORDER BY sent_at")
+        .fetch_all(&app.connect_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.provider)
+        .collect()
+}
+
+#[tokio::test]
+async fn confirmation_emails_fail_over_to_the_secondary_provider() {
+    // Arrange
+    let (app, secondary_server) = spawn_app_with_secondary_provider().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&secondary_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_outbox_emails().await;
+
+    // Assert
+    let email_request = &secondary_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    assert!(confirmation_links
+        .html
+        .as_str()
+        .contains("/subscriptions/confirm"));
+    let delivery = sqlx::query!("SELECT provider, outbox_message_id FROM email_deliveries")
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.provider, "backup");
+    assert!(delivery.outbox_message_id.is_some());
+}
+
+#[tokio::test]
+async fn newsletter_issues_fail_over_to_the_secondary_provider() {
+    // Arrange
+    let (app, secondary_server) = spawn_app_with_secondary_provider().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&secondary_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let delivery =
+        sqlx::query!("SELECT provider FROM email_deliveries WHERE newsletter_issue_id IS NOT NULL")
+            .fetch_one(&app.connect_pool)
+            .await
+            .unwrap();
+    assert_eq!(delivery.provider, "backup");
+}
+
+#[tokio::test]
+async fn emails_go_back_to_the_primary_provider_once_it_recovers() {
+    // Arrange
+    let secondary_server = MockServer::start().await;
+    let secondary = EmailProviderSettings {
+        name: "backup".into(),
+        base_url: secondary_server.uri(),
+        authorization_token: Secret::new("backup-token".into()),
+    };
+    let app = TestApp::spawn_app_with(|config| {
+        config.email_client.secondary = Some(secondary);
+        config.email_client.circuit_breaker.failure_threshold = 1;
+        config.email_client.circuit_breaker.open_milliseconds = 200;
+    })
+    .await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&secondary_server)
+        .await;
+    let outage = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    // Act - Part 1 - The primary is down
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_outbox_emails().await;
+    drop(outage);
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 2 - The primary is back
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    app.post_subscriptions("name=ursula&email=ursula%40gmail.com".into())
+        .await;
+    app.dispatch_outbox_emails().await;
+
+    // Assert
+    assert_eq!(delivery_providers(&app).await, vec!["backup", "postmark"]);
+}
+
+#[tokio::test]
+async fn an_email_is_not_sent_twice_when_its_delivery_cannot_be_recorded() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    sqlx::query("ALTER TABLE email_deliveries ADD CONSTRAINT broken CHECK (false) NOT VALID")
+        .execute(&app.connect_pool)
+        .await
+        .unwrap();
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    app.dispatch_all_pending_emails().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let pending = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(pending.count, 0);
+    // The newsletter email was sent once, checked on drop
+}
@@ -0,0 +1,244 @@
+use crate::helpers;
+use crate::helpers::TestApp;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn email_body(email_request: &wiremock::Request) -> serde_json::Value {
+    serde_json::from_slice(&email_request.body).unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_email_templates() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app.get_email_templates().await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_update_an_email_template() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app
+        .post_email_template(&serde_json::json!({
+            "template_name": "confirmation",
+            "subject": "Hello",
+            "html_body": "{{confirmation_link}}",
+            "text_body": "{{confirmation_link}}",
+        }))
+        .await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn every_email_template_can_be_edited() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = app.get_email_templates_html().await;
+
+    // Assert
+    for template_name in ["confirmation", "welcome", "password_reset", "newsletter"] {
+        assert!(html_page.contains(&format!(
+            r#"name="template_name" value="{}""#,
+            template_name
+        )));
+    }
+    // The stored HTML is shown as text, not rendered
+    assert!(html_page.contains("&lt;a href=&quot;{{confirmation_link}}&quot;&gt;"));
+}
+
+#[tokio::test]
+async fn new_subscribers_get_the_edited_confirmation_email() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Edit the template
+    let response = app
+        .post_email_template(&serde_json::json!({
+            "template_name": "confirmation",
+            "subject": "Please confirm, {{subscriber.name}}",
+            "html_body": r#"<p>Dear {{ subscriber.name }}, <a href="{{confirmation_link}}">confirm</a></p>"#,
+            "text_body": "Dear {{subscriber.name}}, visit {{confirmation_link}}",
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/email_templates");
+    let html_page = app.get_email_templates_html().await;
+    assert!(html_page.contains("The confirmation email template has been saved."));
+
+    // Act - Part 2 - Subscribe
+    app.post_subscriptions("name=ursula%20%26%20co&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_outbox_emails().await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body = email_body(email_request);
+    assert_eq!(body["Subject"], "Please confirm, ursula & co");
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(html_body.starts_with("<p>Dear ursula &amp; co, <a href=\"http://127.0.0.1"));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Dear ursula & co, visit http://127.0.0.1"));
+    app.get_confirmation_links(email_request);
+}
+
+#[tokio::test]
+async fn queued_emails_are_rendered_with_the_template_as_it_is_when_sent() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Act - The template is edited while the email waits in the outbox
+    let response = app
+        .post_email_template(&serde_json::json!({
+            "template_name": "confirmation",
+            "subject": "Last step, {{subscriber.name}}",
+            "html_body": r#"<a href="{{confirmation_link}}">Confirm</a>"#,
+            "text_body": "Confirm: {{confirmation_link}}",
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/email_templates");
+    app.dispatch_outbox_emails().await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    assert_eq!(email_body(email_request)["Subject"], "Last step, le guin");
+    app.get_confirmation_links(email_request);
+}
+
+#[tokio::test]
+async fn email_templates_with_unknown_placeholders_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_email_template(&serde_json::json!({
+            "template_name": "confirmation",
+            "subject": "Welcome!",
+            "html_body": "<a href=\"{{unsubscribe_url}}\">{{confirmation_link}}</a>",
+            "text_body": "{{confirmation_link}}",
+        }))
+        .await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/admin/email_templates");
+    let html_page = app.get_email_templates_html().await;
+    assert!(
+        html_page.contains("The email template uses an unknown placeholder: {{unsubscribe_url}}.")
+    );
+    let template =
+        sqlx::query!("SELECT html_body FROM email_templates WHERE template_name = 'confirmation'")
+            .fetch_one(&app.connect_pool)
+            .await
+            .unwrap();
+    assert!(!template.html_body.contains("unsubscribe_url"));
+}
+
+#[tokio::test]
+async fn unknown_email_templates_are_rejected_with_a_400() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_email_template(&serde_json::json!({
+            "template_name": "farewell",
+            "subject": "Bye",
+            "html_body": "Bye",
+            "text_body": "Bye",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn newsletter_issues_are_wrapped_in_the_newsletter_template_for_each_recipient() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_outbox_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.test_user.login(&app).await;
+    app.post_email_template(&serde_json::json!({
+        "template_name": "newsletter",
+        "subject": "[Weekly] {{newsletter.title}}",
+        "html_body": "<p>Hi {{subscriber.name}}</p>{{newsletter.content}}<a href=\"{{unsubscribe_url}}\">Unsubscribe</a>",
+        "text_body": "Hi {{subscriber.name}}\n{{newsletter.content}}\nUnsubscribe: {{unsubscribe_url}}",
+    }))
+    .await;
+
+    // Act
+    app.post_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body = email_body(&email_request);
+    assert_eq!(body["Subject"], "[Weekly] Newsletter title");
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Hi le guin</p><p>Newsletter body as HTML</p><a href=\"http://127.0.0.1"));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Hi le guin\nNewsletter body as plain text\nUnsubscribe: "));
+    app.get_unsubscribe_link(&email_request);
+}
@@ -1,4 +1,7 @@
 use crate::helpers::TestApp;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+use zero_2_prod::domain::SubscriberEmail;
 
 /// `tokio::test` is the testing equivalent of `tokio::api`.
 /// It also spares you from having to specify the `#[test]` attribute.
@@ -17,5 +20,42 @@ async fn health_check_works() {
 
     // Assert
     assert!(response.status().is_success());
-    assert_eq!(Some(0), response.content_length());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["email_providers"][0]["circuit_breaker"], "closed");
+}
+
+#[tokio::test]
+async fn health_check_reports_an_open_email_circuit_breaker() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.email_client.circuit_breaker.failure_threshold = 2;
+        config.email_client.circuit_breaker.open_milliseconds = 60_000;
+    })
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(503))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let recipient = SubscriberEmail::parse("ursula_le_guin@gmail.com".into()).unwrap();
+    for _ in 0..2 {
+        let _ = app
+            .email_client
+            .send_email(&recipient, "Subject", "<p>Body</p>", "Body")
+            .await;
+    }
+
+    // Act
+    let response = app.get_health_check().await;
+
+    // Assert
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["email_providers"][0]["circuit_breaker"], "open");
+    assert!(
+        body["email_providers"][0]["retry_after_seconds"]
+            .as_u64()
+            .unwrap()
+            > 0
+    );
 }
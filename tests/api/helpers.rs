@@ -2,15 +2,24 @@ use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHasher};
 use linkify::{LinkFinder, LinkKind};
 use once_cell::sync::Lazy;
-use reqwest::{Response, Url};
+use reqwest::{RequestBuilder, Response, Url};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero_2_prod::configuration::DatabaseSettings;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero_2_prod::configuration::{
+    DatabaseSettings, EmailOutboxSettings, IdempotencySettings, Settings, SubscriptionSettings,
+    TrackingSettings, WebhookSettings,
+};
 use zero_2_prod::email_client::EmailClient;
-use zero_2_prod::issue_delivery_worker::ExecutionOutcome;
+use zero_2_prod::idempotency;
+use zero_2_prod::issue_delivery_worker::{AttachmentCache, ExecutionOutcome};
+use zero_2_prod::startup::{ApplicationBaseUrl, HmacSecret};
 use zero_2_prod::telemetry;
-use zero_2_prod::{configuration, issue_delivery_worker};
+use zero_2_prod::{
+    configuration, email_outbox_worker, issue_delivery_worker, subscription_attempts_cleanup,
+    webhook_delivery_worker,
+};
 use zero_2_prod::{startup, startup::Application};
 
 // Ensure that the `tracing` stack is only initialised once using `once_cell`
@@ -31,6 +40,14 @@ pub struct TestApp {
     pub connect_pool: PgPool,
     pub email_server: MockServer,
     pub email_client: EmailClient,
+    pub email_outbox: EmailOutboxSettings,
+    pub app_base_url: ApplicationBaseUrl,
+    pub hmac_secret: HmacSecret,
+    pub tracking: TrackingSettings,
+    pub idempotency: IdempotencySettings,
+    pub subscriptions: SubscriptionSettings,
+    pub webhooks: WebhookSettings,
+    pub webhook_client: reqwest::Client,
     pub port: u16,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
@@ -45,6 +62,11 @@ impl TestApp {
     /// if we fail to perform the required setup we can just panic and crash
     /// all the things.
     pub async fn spawn_app() -> Self {
+        Self::spawn_app_with(|_| {}).await
+    }
+
+    /// Same as `spawn_app`, `configure` can override the settings before the application is built.
+    pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> Self {
         Lazy::force(&TRACING);
 
         // Launch a mock server to stand in for Postmark's API
@@ -59,7 +81,10 @@ impl TestApp {
             // Use a random OS port
             config.application.port = 0;
             // Use the mock server as email API
-            config.email_client.base_url = email_server.uri();
+            config.email_client.primary.base_url = email_server.uri();
+            // Fill in the subscription form as fast as we like
+            config.subscriptions.min_form_fill_seconds = 0;
+            configure(&mut config);
             config
         };
 
@@ -67,7 +92,9 @@ impl TestApp {
         configure_database(&configuration.database).await;
 
         // Notice the .clone!
-        let application = Application::build(configuration.clone())
+        // Shared with the application, as in `main`
+        let email_client = configuration.email_client.clone().client();
+        let application = Application::build(configuration.clone(), email_client.clone())
             .await
             .expect("Failed to build application.");
 
@@ -79,18 +106,21 @@ impl TestApp {
         // but we have no use for it here, hence the non-binding let
         let _ = tokio::spawn(application.run_until_stopped());
 
-        let client = reqwest::Client::builder()
-            // By default, a Client will automatically handle HTTP redirects
-            .redirect(reqwest::redirect::Policy::none())
-            .cookie_store(true)
-            .build()
-            .expect("Failed to build client instance");
+        let client = build_api_client();
         // We return the application address to the caller!
         let test_app = TestApp {
             address: format!("http://127.0.0.1:{}", app_port),
             connect_pool: startup::get_connection_pool(&configuration.database),
             email_server,
-            email_client: configuration.email_client.client(),
+            email_client,
+            email_outbox: configuration.email_outbox.clone(),
+            app_base_url: ApplicationBaseUrl(configuration.application.base_url.clone()),
+            hmac_secret: HmacSecret(configuration.application.hmac_secret.clone()),
+            tracking: configuration.tracking.clone(),
+            idempotency: configuration.idempotency.clone(),
+            subscriptions: configuration.subscriptions.clone(),
+            webhooks: configuration.webhooks.clone(),
+            webhook_client: webhook_delivery_worker::http_client(&configuration.webhooks),
             port: app_port,
             test_user: TestUser::new(),
             api_client: client,
@@ -99,19 +129,112 @@ impl TestApp {
         test_app
     }
 
+    /// Send the emails waiting in the outbox, e.g. subscription confirmations.
+    pub async fn dispatch_outbox_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue | ExecutionOutcome::EmailProviderUnavailable =
+                email_outbox_worker::try_execute_task(
+                    &self.connect_pool,
+                    &self.email_client,
+                    &self.email_outbox,
+                )
+                .await
+                .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Deliver the newsletter issues waiting in the queue.
     pub async fn dispatch_all_pending_emails(&self) {
+        let mut attachment_cache = AttachmentCache::default();
+        loop {
+            if let ExecutionOutcome::EmptyQueue | ExecutionOutcome::EmailProviderUnavailable =
+                issue_delivery_worker::try_execute_task(
+                    &self.connect_pool,
+                    &self.email_client,
+                    &self.app_base_url,
+                    &self.hmac_secret,
+                    &self.tracking,
+                    &mut attachment_cache,
+                )
+                .await
+                .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Post the webhook events which are due, retries included if they are due already.
+    pub async fn dispatch_webhook_events(&self) {
         loop {
-            if let ExecutionOutcome::EmptyQueue =
-                issue_delivery_worker::try_execute_task(&self.connect_pool, &self.email_client)
-                    .await
-                    .unwrap()
+            if let ExecutionOutcome::EmptyQueue = webhook_delivery_worker::try_execute_task(
+                &self.connect_pool,
+                &self.webhook_client,
+                &self.webhooks,
+            )
+            .await
+            .unwrap()
             {
                 break;
             }
         }
     }
 
+    /// One run of the idempotency cleanup task, returns how many records were deleted.
+    pub async fn delete_expired_idempotency_records(&self) -> u64 {
+        idempotency::delete_expired_records(&self.connect_pool, &self.idempotency)
+            .await
+            .unwrap()
+    }
+
+    /// One run of the subscription attempts cleanup task, returns how many attempts were deleted.
+    pub async fn delete_expired_subscription_attempts(&self) -> u64 {
+        subscription_attempts_cleanup::delete_expired_attempts(
+            &self.connect_pool,
+            &self.subscriptions,
+        )
+        .await
+        .unwrap()
+    }
+
+    /// Subscribe ursula_le_guin@gmail.com and confirm, with the emails this takes.
+    pub async fn create_confirmed_subscriber(&self) {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Confirm subscriber")
+            .expect(2)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+            .await;
+        self.dispatch_outbox_emails().await;
+        let email_request = &self.email_server.received_requests().await.unwrap()[0];
+        let confirmation_links = self.get_confirmation_links(email_request);
+        reqwest::get(confirmation_links.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        self.dispatch_outbox_emails().await;
+    }
+
+    /// Submit the subscription form with a genuine form token, like a browser would.
     pub async fn post_subscriptions(&self, body: String) -> Response {
+        let form_token = extract_hidden_field(&self.get_home_html().await, "form_token");
+        let body = if body.is_empty() {
+            format!("form_token={}", form_token)
+        } else {
+            format!("{}&form_token={}", body, form_token)
+        };
+        self.post_subscriptions_raw(body).await
+    }
+
+    /// Submit the subscription form body as is.
+    pub async fn post_subscriptions_raw(&self, body: String) -> Response {
         self.api_client
             .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
@@ -121,6 +244,17 @@ impl TestApp {
             .expect("Failed to post subscriptions.")
     }
 
+    pub async fn get_home_html(&self) -> String {
+        self.api_client
+            .get(&self.address)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .expect("Failed to get home html.")
+    }
+
     pub async fn get_health_check(&self) -> Response {
         self.api_client
             .get(format!("{}/health_check", &self.address))
@@ -135,7 +269,37 @@ impl TestApp {
     {
         self.api_client
             .post(&format!("{}/admin/newsletter", &self.address))
-            .form(&body)
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to post newsletter.")
+    }
+
+    /// Submit the publish form as `multipart/form-data`, `files` are (file name, media type, bytes).
+    pub async fn post_newsletter_with_attachments(
+        &self,
+        body: &serde_json::Value,
+        files: Vec<(&str, &str, Vec<u8>)>,
+    ) -> Response {
+        let body = self.with_csrf_token(body).await;
+        let mut form = reqwest::multipart::Form::new();
+        for (name, value) in body.as_object().unwrap() {
+            let value = match value {
+                serde_json::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            form = form.text(name.clone(), value);
+        }
+        for (file_name, content_type, content) in files {
+            let part = reqwest::multipart::Part::bytes(content)
+                .file_name(file_name.to_string())
+                .mime_str(content_type)
+                .unwrap();
+            form = form.part("attachments", part);
+        }
+        self.api_client
+            .post(&format!("{}/admin/newsletter", &self.address))
+            .multipart(form)
             .send()
             .await
             .expect("Failed to post newsletter.")
@@ -157,13 +321,37 @@ impl TestApp {
             .expect("Failed to get newsletter html.")
     }
 
+    pub async fn get_issues(&self) -> Response {
+        self.api_client
+            .get(&format!("{}/admin/issues", &self.address))
+            .send()
+            .await
+            .expect("Failed to get issues.")
+    }
+
+    pub async fn get_issue(&self, issue_id: Uuid) -> Response {
+        self.api_client
+            .get(&format!("{}/admin/issues/{}", &self.address, issue_id))
+            .send()
+            .await
+            .expect("Failed to get issue.")
+    }
+
+    pub async fn get_issue_html(&self, issue_id: Uuid) -> String {
+        self.get_issue(issue_id)
+            .await
+            .text()
+            .await
+            .expect("Failed to get issue html.")
+    }
+
     pub async fn post_login<Body>(&self, body: &Body) -> Response
     where
         Body: serde::Serialize,
     {
         self.api_client
             .post(format!("{}/login", &self.address))
-            .form(body)
+            .form(&self.with_csrf_token(body).await)
             .send()
             .await
             .expect("Failed to post login.")
@@ -219,7 +407,7 @@ impl TestApp {
     {
         self.api_client
             .post(&format!("{}/admin/password", &self.address))
-            .form(body)
+            .form(&self.with_csrf_token(body).await)
             .send()
             .await
             .expect("Failed to post change_password.")
@@ -228,11 +416,243 @@ impl TestApp {
     pub async fn post_logout(&self) -> Response {
         self.api_client
             .post(&format!("{}/admin/logout", &self.address))
+            .form(&self.with_csrf_token(&serde_json::json!({})).await)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_admin_sessions(&self) -> Response {
+        self.api_client
+            .get(&format!("{}/admin/sessions", &self.address))
+            .send()
+            .await
+            .expect("Failed to get admin sessions.")
+    }
+
+    pub async fn get_admin_sessions_html(&self) -> String {
+        self.get_admin_sessions()
+            .await
+            .text()
+            .await
+            .expect("Failed to get admin sessions html.")
+    }
+
+    pub async fn post_revoke_session<Body>(&self, body: &Body) -> Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/sessions/revoke", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to post revoke session.")
+    }
+
+    pub async fn post_revoke_other_sessions(&self) -> Response {
+        self.api_client
+            .post(&format!("{}/admin/sessions/revoke_others", &self.address))
+            .form(&self.with_csrf_token(&serde_json::json!({})).await)
+            .send()
+            .await
+            .expect("Failed to post revoke other sessions.")
+    }
+
+    pub async fn get_email_templates(&self) -> Response {
+        self.api_client
+            .get(&format!("{}/admin/email_templates", &self.address))
+            .send()
+            .await
+            .expect("Failed to get email templates.")
+    }
+
+    pub async fn get_email_templates_html(&self) -> String {
+        self.get_email_templates()
+            .await
+            .text()
+            .await
+            .expect("Failed to get email templates html.")
+    }
+
+    pub async fn post_email_template<Body>(&self, body: &Body) -> Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/email_templates", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to post email template.")
+    }
+
+    pub async fn get_admin_api_keys(&self) -> Response {
+        self.api_client
+            .get(&format!("{}/admin/api_keys", &self.address))
+            .send()
+            .await
+            .expect("Failed to get admin api keys.")
+    }
+
+    pub async fn get_admin_api_keys_html(&self) -> String {
+        self.get_admin_api_keys()
+            .await
+            .text()
+            .await
+            .expect("Failed to get admin api keys html.")
+    }
+
+    pub async fn post_create_api_key<Body>(&self, body: &Body) -> Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/api_keys", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to post create api key.")
+    }
+
+    pub async fn post_revoke_api_key<Body>(&self, body: &Body) -> Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/api_keys/revoke", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to post revoke api key.")
+    }
+
+    pub async fn get_admin_webhooks(&self) -> Response {
+        self.api_client
+            .get(&format!("{}/admin/webhooks", &self.address))
+            .send()
+            .await
+            .expect("Failed to get admin webhooks.")
+    }
+
+    pub async fn get_admin_webhooks_html(&self) -> String {
+        self.get_admin_webhooks()
+            .await
+            .text()
+            .await
+            .expect("Failed to get admin webhooks html.")
+    }
+
+    pub async fn post_create_webhook<Body>(&self, body: &Body) -> Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/webhooks", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to post create webhook.")
+    }
+
+    pub async fn post_remove_webhook<Body>(&self, body: &Body) -> Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/webhooks/remove", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to post remove webhook.")
+    }
+
+    pub async fn get_webhook_deliveries_html(&self) -> String {
+        self.api_client
+            .get(&format!("{}/admin/webhooks/deliveries", &self.address))
+            .send()
+            .await
+            .expect("Failed to get webhook deliveries.")
+            .text()
+            .await
+            .expect("Failed to get webhook deliveries html.")
+    }
+
+    /// Log in and add an endpoint listening to `event_types`, e.g. `["issue_delivered"]`,
+    /// returns the secret of its signatures.
+    pub async fn create_webhook(&self, url: &str, event_types: &[&str]) -> String {
+        self.test_user.login(self).await;
+        let mut body = serde_json::json!({ "url": url });
+        for event_type in event_types {
+            body[*event_type] = "true".into();
+        }
+        let html_page = self.post_create_webhook(&body).await.text().await.unwrap();
+        let start = html_page
+            .find("<code>whsec_")
+            .expect("No webhook secret in the page.")
+            + "<code>".len();
+        let end = start + html_page[start..].find('<').unwrap();
+        html_page[start..end].to_owned()
+    }
+
+    pub async fn get_openapi_json(&self) -> serde_json::Value {
+        self.api_client
+            .get(&format!("{}/openapi.json", &self.address))
+            .send()
+            .await
+            .expect("Failed to get openapi json.")
+            .json()
+            .await
+            .expect("The OpenAPI document is not JSON.")
+    }
+
+    pub async fn get_openapi_viewer(&self) -> Response {
+        self.api_client
+            .get(&format!("{}/openapi", &self.address))
+            .send()
+            .await
+            .expect("Failed to get openapi viewer.")
+    }
+
+    /// Log in and create an API key from the admin page, returns the key.
+    pub async fn create_api_key(&self) -> String {
+        self.test_user.login(self).await;
+        let html_page = self
+            .post_create_api_key(&serde_json::json!({ "name": "CMS" }))
+            .await
+            .text()
+            .await
+            .unwrap();
+        let start = html_page
+            .find("<code>z2p_")
+            .expect("No API key in the page.")
+            + "<code>".len();
+        let end = start + html_page[start..].find('<').unwrap();
+        html_page[start..end].to_owned()
+    }
+
+    /// A request to `/api/v1{path}`, authenticated by `api_key`.
+    pub fn api_v1(&self, method: reqwest::Method, path: &str, api_key: &str) -> RequestBuilder {
+        self.api_client
+            .request(method, format!("{}/api/v1{}", &self.address, path))
+            .bearer_auth(api_key)
+    }
+
+    /// The CSRF token of the current session, as embedded in the forms we serve.
+    pub async fn get_csrf_token(&self) -> String {
+        extract_csrf_token(&self.get_login_html().await)
+    }
+
+    /// Add the CSRF token of the current session to a form body, like a browser would.
+    pub async fn with_csrf_token<Body>(&self, body: &Body) -> serde_json::Value
+    where
+        Body: serde::Serialize,
+    {
+        let mut body = serde_json::to_value(body).expect("Failed to serialize the form body.");
+        body["csrf_token"] = self.get_csrf_token().await.into();
+        body
+    }
+
     /// Extract the confirmation links embedded in the request to the email API.
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         // Parse the body as JSON, starting from raw bytes
@@ -266,6 +686,79 @@ impl TestApp {
             plain_text: text_link,
         }
     }
+
+    /// Extract the unsubscribe link embedded in the plain text body of an email.
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let raw_link = LinkFinder::new()
+            .links(body["TextBody"].as_str().unwrap())
+            .filter(|l| *l.kind() == LinkKind::Url)
+            .map(|l| l.as_str().to_owned())
+            .find(|l| l.contains("/subscriptions/unsubscribe"))
+            .expect("No unsubscribe link in the email.");
+        let mut unsubscribe_url = Url::parse(&raw_link).expect("Failed to parse unsubscribe link");
+        assert_eq!(unsubscribe_url.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_url.set_port(Some(self.port)).unwrap();
+        unsubscribe_url
+    }
+
+    /// The open pixel and the click tracking links of the HTML body, pointing at our port.
+    pub fn get_tracking_links(&self, email_request: &wiremock::Request) -> TrackingLinks {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let links: Vec<Url> = body["HtmlBody"]
+            .as_str()
+            .unwrap()
+            .split('"')
+            .filter(|value| value.contains("/issues/open") || value.contains("/issues/click"))
+            .map(|value| {
+                let raw_link = htmlescape::decode_html(value).unwrap();
+                let mut link = Url::parse(&raw_link).expect("Failed to parse tracking link");
+                assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+                link.set_port(Some(self.port)).unwrap();
+                link
+            })
+            .collect();
+        let (pixels, clicks): (Vec<Url>, Vec<Url>) = links
+            .into_iter()
+            .partition(|link| link.path() == "/issues/open");
+        TrackingLinks {
+            pixel: pixels.into_iter().next(),
+            clicks,
+        }
+    }
+}
+
+/// Tracking links embedded in an email sent to a subscriber.
+#[derive(Debug)]
+pub struct TrackingLinks {
+    pub pixel: Option<Url>,
+    pub clicks: Vec<Url>,
+}
+
+/// A client with its own cookie store, i.e. a browser on its own device.
+pub fn build_api_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        // By default, a Client will automatically handle HTTP redirects
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .expect("Failed to build client instance")
+}
+
+/// Find the value of the hidden `csrf_token` field of a page.
+pub fn extract_csrf_token(html_page: &str) -> String {
+    extract_hidden_field(html_page, "csrf_token")
+}
+
+/// Find the value of a hidden field of a page.
+pub fn extract_hidden_field(html_page: &str, name: &str) -> String {
+    let marker = format!(r#"name="{}" value=""#, name);
+    let start = html_page
+        .find(&marker)
+        .unwrap_or_else(|| panic!("No {} field in the page.", name))
+        + marker.len();
+    let end = start + html_page[start..].find('"').unwrap();
+    html_page[start..end].to_owned()
 }
 
 pub fn assert_is_redirect_to(response: &Response, location: &str) {
@@ -0,0 +1,208 @@
+use crate::helpers;
+use crate::helpers::TestApp;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Publish an issue to the confirmed subscribers and return what they received.
+async fn publish_and_deliver(app: &TestApp, tracking: serde_json::Value) -> wiremock::Request {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Deliver newsletter issue")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let mut body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Read https://example.com/post",
+        "html_content": r#"<p>Read <a href="https://example.com/post?a=1&amp;b=2">the post</a> or <a href="mailto:editor@example.com">reply</a></p>"#,
+        "idempotency_key": Uuid::new_v4().to_string()
+    });
+    body.as_object_mut()
+        .unwrap()
+        .extend(tracking.as_object().unwrap().clone());
+    let response = app.post_newsletter(&body).await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    app.dispatch_all_pending_emails().await;
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
+async fn issue_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_issues() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let list_response = app.get_issues().await;
+    let issue_response = app.get_issue(Uuid::new_v4()).await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&list_response, "/login");
+    helpers::assert_is_redirect_to(&issue_response, "/login");
+}
+
+#[tokio::test]
+async fn opens_and_clicks_are_recorded_and_shown_on_the_issue_page() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    let email_request = publish_and_deliver(
+        &app,
+        serde_json::json!({"track_opens": true, "track_clicks": true}),
+    )
+    .await;
+    let tracking_links = app.get_tracking_links(&email_request);
+    let html_body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    // Neither the other links nor the unsubscribe link of the template are rewritten
+    assert!(html_body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(r#"href="mailto:editor@example.com""#));
+    assert_eq!(tracking_links.clicks.len(), 1);
+
+    // Act - Part 1 - Open the email
+    let pixel_response = app
+        .api_client
+        .get(tracking_links.pixel.unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(pixel_response.status().as_u16(), 200);
+    assert_eq!(
+        pixel_response.headers().get("Content-Type").unwrap(),
+        "image/gif"
+    );
+
+    // Act - Part 2 - Follow the link, twice
+    for _ in 0..2 {
+        let click_response = app
+            .api_client
+            .get(tracking_links.clicks[0].clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(click_response.status().as_u16(), 302);
+        assert_eq!(
+            click_response.headers().get("Location").unwrap(),
+            "https://example.com/post?a=1&b=2"
+        );
+    }
+
+    // Act - Part 3 - Look at the issue page
+    let issue_id = issue_id(&app).await;
+    assert!(app
+        .get_issues()
+        .await
+        .text()
+        .await
+        .unwrap()
+        .contains(&format!(
+            r#"<a href="/admin/issues/{}">Newsletter title</a>"#,
+            issue_id
+        )));
+    let html_page = app.get_issue_html(issue_id).await;
+
+    // Assert
+    assert!(html_page.contains("<tr><td>Recipients</td><td>1</td><td></td></tr>"));
+    assert!(html_page.contains("<tr><td>Opens</td><td>1</td><td>100.0%</td></tr>"));
+    assert!(html_page.contains("<tr><td>Clicks</td><td>1</td><td>100.0%</td></tr>"));
+    assert!(html_page.contains("<tr><td>https://example.com/post?a=1&amp;b=2</td><td>2</td></tr>"));
+}
+
+#[tokio::test]
+async fn issues_are_not_tracked_unless_the_author_asks_for_it() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let email_request = publish_and_deliver(&app, serde_json::json!({})).await;
+
+    // Assert
+    let tracking_links = app.get_tracking_links(&email_request);
+    assert!(tracking_links.pixel.is_none());
+    assert!(tracking_links.clicks.is_empty());
+    let html_page = app.get_issue_html(issue_id(&app).await).await;
+    assert!(html_page.contains("<tr><td>Opens</td><td>0</td><td>Not tracked</td></tr>"));
+}
+
+#[tokio::test]
+async fn the_privacy_switch_of_the_settings_disables_tracking() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| config.tracking.enabled = false).await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let email_request = publish_and_deliver(
+        &app,
+        serde_json::json!({"track_opens": true, "track_clicks": true}),
+    )
+    .await;
+
+    // Assert
+    let tracking_links = app.get_tracking_links(&email_request);
+    assert!(tracking_links.pixel.is_none());
+    assert!(tracking_links.clicks.is_empty());
+    let html_page = app.get_issue_html(issue_id(&app).await).await;
+    assert!(html_page.contains("Tracking is disabled in the settings"));
+}
+
+#[tokio::test]
+async fn forged_tracking_links_are_rejected_with_a_400() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let query = format!(
+        "issue_id={}&subscriber_id={}&url=https%3A%2F%2Fevil.example.com&tag=00",
+        Uuid::new_v4(),
+        Uuid::new_v4()
+    );
+
+    // Act
+    let click_response = app
+        .api_client
+        .get(format!("{}/issues/click?{}", app.address, query))
+        .send()
+        .await
+        .unwrap();
+    let pixel_response = app
+        .api_client
+        .get(format!("{}/issues/open?{}", app.address, query))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(click_response.status().as_u16(), 400);
+    assert!(click_response.headers().get("Location").is_none());
+    assert_eq!(pixel_response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn unknown_issues_return_a_404() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_issue(Uuid::new_v4()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
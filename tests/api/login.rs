@@ -1,5 +1,8 @@
 use crate::helpers;
 use crate::helpers::TestApp;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use uuid::Uuid;
 // use zero_2_prod::constant::LOGIN_ERROR_MSG;
 
 #[tokio::test]
@@ -19,18 +22,18 @@ async fn an_error_flash_message_is_set_on_failure() {
     //    .cookies()
     //    .find(|c| c.name() == LOGIN_ERROR_MSG)
     //    .expect("Failed to find login_error_msg cookie");
-    //assert_eq!(login_cookie.value(), "Invalid username.");
+    //assert_eq!(login_cookie.value(), "Invalid username or password.");
     helpers::assert_is_redirect_to(&response, "/login");
 
     // Act - Part 2 - Follow the redirect
     // Request Headers:
-    // Cookie: login_error_msg=Invalid username.
+    // Cookie: login_error_msg=Invalid username or password.
     let html_page = app.get_login_html().await;
-    assert!(html_page.contains(r#"<p><i>Invalid username.</i></p>"#));
+    assert!(html_page.contains(r#"<p><i>Invalid username or password.</i></p>"#));
 
     // Act - Part 3 - Reload the login page
     let html_page = app.get_login_html().await;
-    assert!(!html_page.contains(r#"<p><i>Invalid username.</i></p>"#));
+    assert!(!html_page.contains(r#"<p><i>Invalid username or password.</i></p>"#));
 }
 
 #[tokio::test]
@@ -50,3 +53,89 @@ async fn redirect_to_admin_dashboard_after_login_success() {
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
 }
+
+#[tokio::test]
+async fn unknown_username_and_wrong_password_are_indistinguishable() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act - Part 1 - Login with a username that does not exist
+    let unknown_username_body = serde_json::json!({
+        "username": Uuid::new_v4().to_string(),
+        "password": Uuid::new_v4().to_string()
+    });
+    let unknown_username_response = app.post_login(&unknown_username_body).await;
+    let unknown_username_html = app.get_login_html().await;
+
+    // Act - Part 2 - Login with an existing username but a wrong password
+    let wrong_password_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": Uuid::new_v4().to_string()
+    });
+    let wrong_password_response = app.post_login(&wrong_password_body).await;
+    let wrong_password_html = app.get_login_html().await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&unknown_username_response, "/login");
+    helpers::assert_is_redirect_to(&wrong_password_response, "/login");
+    let header_names = |response: &reqwest::Response| {
+        let mut names = response
+            .headers()
+            .keys()
+            .map(|name| name.as_str().to_owned())
+            .filter(|name| name != "date")
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+    assert_eq!(
+        header_names(&unknown_username_response),
+        header_names(&wrong_password_response)
+    );
+    assert_eq!(unknown_username_html, wrong_password_html);
+    assert!(unknown_username_html.contains(r#"<p><i>Invalid username or password.</i></p>"#));
+}
+
+#[tokio::test]
+async fn login_upgrades_a_password_hash_with_outdated_parameters() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let weak_params = Params::new(4096, 1, 1, None).unwrap();
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let weak_password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, weak_params)
+        .hash_password(app.test_user.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        weak_password_hash,
+        app.test_user.user_id
+    )
+    .execute(&app.connect_pool)
+    .await
+    .unwrap();
+
+    // Act - Part 1 - Login
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    helpers::assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Assert
+    let saved = sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.connect_pool)
+    .await
+    .unwrap();
+    assert_ne!(saved.password_hash, weak_password_hash);
+    assert!(saved.password_hash.contains("m=19456,t=2,p=1"));
+
+    // Act - Part 2 - Logout and login again against the upgraded hash
+    app.post_logout().await;
+    let response = app.post_login(&login_body).await;
+    helpers::assert_is_redirect_to(&response, "/admin/dashboard");
+}
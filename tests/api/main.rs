@@ -1,8 +1,20 @@
+mod admin_api_keys;
 mod admin_dashboard;
+mod admin_sessions;
+mod api_v1;
 mod change_password;
+mod csrf;
+mod email_failover;
+mod email_templates;
 mod health_check;
 mod helpers;
+mod issues_tracking;
 mod login;
 mod newsletter;
+mod newsletter_attachments;
+mod openapi;
+mod security_headers;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod webhooks;
@@ -3,6 +3,7 @@ use crate::helpers::{ConfirmationLinks, TestApp};
 use fake::faker::internet::en::SafeEmail;
 use fake::faker::name::en::Name;
 use fake::Fake;
+use sqlx::{Postgres, Transaction};
 use std::time::Duration;
 use uuid::Uuid;
 use wiremock::matchers::{any, method, path};
@@ -158,6 +159,7 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .await
         .error_for_status()
         .expect("Failed to post subscription");
+    app.dispatch_outbox_emails().await;
 
     // We now inspect the requests received by the mock Postmark server
     // to retrieve the confirmation link and return it
@@ -176,11 +178,19 @@ async fn create_confirmed_subscriber(app: &TestApp) {
     // We can then reuse the same helper and just add
     // an extra step to actually call the confirmation link!
     let confirmation_links = create_unconfirmed_subscriber(app).await;
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Welcome confirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
     reqwest::get(confirmation_links.html)
         .await
         .unwrap()
         .error_for_status()
         .unwrap();
+    app.dispatch_outbox_emails().await;
 }
 
 #[deprecated(since = "1.0", note = "old style")]
@@ -357,6 +367,276 @@ async fn concurrent_form_submission_is_handled_gracefully() {
     app.dispatch_all_pending_emails().await;
 }
 
+async fn newsletters_count(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn reusing_an_idempotency_key_for_another_newsletter_is_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let key = Uuid::new_v4().to_string();
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": key
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+
+    // Act
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Another title",
+            "text_content": "Another body as plain text",
+            "html_content": "<p>Another body as HTML</p>",
+            "idempotency_key": key
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 422);
+    assert_eq!(newsletters_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn the_idempotency_key_can_be_sent_as_a_header() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let body = app
+        .with_csrf_token(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>"
+        }))
+        .await;
+    let key = Uuid::new_v4().to_string();
+
+    // Act
+    for _ in 0..2 {
+        let response = app
+            .api_client
+            .post(&format!("{}/admin/newsletter", &app.address))
+            .header("Idempotency-Key", &key)
+            .form(&body)
+            .send()
+            .await
+            .unwrap();
+        helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    }
+
+    // Assert
+    assert_eq!(newsletters_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn newsletters_without_an_idempotency_key_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(newsletters_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_rejected_form_does_not_use_up_its_idempotency_key() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let key = Uuid::new_v4().to_string();
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Dear {{subscriber.nmae}}",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": key
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+
+    // Act - The author fixes the typo and submits the same form again
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Dear {{subscriber.name}}",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": key
+        }))
+        .await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    assert_eq!(newsletters_count(&app).await, 1);
+}
+
+/// Hold the idempotency key the way a request in flight does, until the transaction ends.
+async fn hold_idempotency_key(app: &TestApp, key: &str) -> Transaction<'static, Postgres> {
+    let mut transaction = app.connect_pool.begin().await.unwrap();
+    sqlx::query!(
+        r#"
+            INSERT INTO idempotency (scope, user_id, idempotency_key, created_at, expires_at)
+            VALUES ($1, $2, $3, now(), now() + interval '1 day')
+        "#,
+        format!("user:{}", app.test_user.user_id),
+        app.test_user.user_id,
+        key
+    )
+    .execute(&mut transaction)
+    .await
+    .unwrap();
+    transaction
+}
+
+#[tokio::test]
+async fn a_duplicate_request_waits_for_the_request_in_flight() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let key = Uuid::new_v4().to_string();
+    let in_flight = hold_idempotency_key(&app, &key).await;
+
+    // Act - The request in flight fails while the duplicate is waiting
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": key
+    });
+    let (response, _) = tokio::join!(app.post_newsletter(&body), async {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        in_flight.rollback().await.unwrap();
+    });
+
+    // Assert - The duplicate took over the key
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_duplicate_request_gets_a_409_when_the_request_in_flight_takes_too_long() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.idempotency.in_flight_timeout_milliseconds = 200;
+    })
+    .await;
+    app.test_user.login(&app).await;
+    let key = Uuid::new_v4().to_string();
+    let in_flight = hold_idempotency_key(&app, &key).await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": key
+    });
+
+    // Act - Part 1 - Submit while the key is held
+    let response = app.post_newsletter(&body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    assert_eq!(response.headers()["Retry-After"], "1");
+
+    // Act - Part 2 - Retry once the request in flight is gone
+    in_flight.rollback().await.unwrap();
+    let response = app.post_newsletter(&body).await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+}
+
+#[tokio::test]
+async fn an_expired_idempotency_key_is_processed_again() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletter(&body).await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    app.dispatch_all_pending_emails().await;
+
+    // Act - The same key, once the retention window is over
+    sqlx::query!("UPDATE idempotency SET expires_at = now() - interval '1 second'")
+        .execute(&app.connect_pool)
+        .await
+        .unwrap();
+    let response = app.post_newsletter(&body).await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(newsletters_count(&app).await, 2);
+}
+
+#[tokio::test]
+async fn expired_idempotency_records_are_deleted_in_batches() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| config.idempotency.cleanup_batch_size = 2).await;
+    app.test_user.login(&app).await;
+    let publish = || async {
+        let response = app
+            .post_newsletter(&serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>",
+                "idempotency_key": Uuid::new_v4().to_string()
+            }))
+            .await;
+        helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    };
+    for _ in 0..3 {
+        publish().await;
+    }
+    sqlx::query!("UPDATE idempotency SET expires_at = now() - interval '1 second'")
+        .execute(&app.connect_pool)
+        .await
+        .unwrap();
+    publish().await;
+
+    // Act
+    let n_deleted = app.delete_expired_idempotency_records().await;
+
+    // Assert
+    assert_eq!(n_deleted, 3);
+    let n_left = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM idempotency"#)
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_left, 1);
+}
+
 #[deprecated]
 async fn transient_errors_do_not_cause_duplicate_deliveries_on_retries() {
     // Arrange
@@ -401,3 +681,416 @@ async fn transient_errors_do_not_cause_duplicate_deliveries_on_retries() {
     let response = app.post_newsletter(&body).await;
     assert_eq!(response.status().as_u16(), 303);
 }
+
+#[tokio::test]
+async fn merge_fields_are_filled_in_for_each_recipient() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    // A blank name gets the fallback
+    sqlx::query!(
+        "UPDATE subscriptions SET name = '' WHERE email = (SELECT MIN(email) FROM subscriptions)"
+    )
+    .execute(&app.connect_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "News for {{subscriber.name | \"you\"}}",
+            "text_content": "Dear {{subscriber.name | \"reader\"}}, since {{subscriber.subscribed_at}}",
+            "html_content": "<p>Dear {{ subscriber.name | \"reader\" }} ({{subscriber.email}})</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let subscribers = sqlx::query!("SELECT email, name, subscribed_at FROM subscriptions")
+        .fetch_all(&app.connect_pool)
+        .await
+        .unwrap();
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    for subscriber in subscribers {
+        let name = if subscriber.name.is_empty() {
+            "reader"
+        } else {
+            subscriber.name.as_str()
+        };
+        let body = email_requests
+            .iter()
+            .map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap())
+            .filter(|body| body["Subject"].as_str().unwrap().starts_with("News for"))
+            .find(|body| body["To"] == subscriber.email.as_str())
+            .expect("The subscriber did not get the issue.");
+        let subject_name = if subscriber.name.is_empty() {
+            "you"
+        } else {
+            name
+        };
+        assert_eq!(body["Subject"], format!("News for {}", subject_name));
+        assert!(body["HtmlBody"].as_str().unwrap().starts_with(&format!(
+            "<p>Dear {} ({})</p>",
+            htmlescape::encode_minimal(name),
+            subscriber.email
+        )));
+        assert!(body["TextBody"].as_str().unwrap().starts_with(&format!(
+            "Dear {}, since {}",
+            name,
+            subscriber.subscribed_at.format("%B %-d, %Y")
+        )));
+    }
+}
+
+#[tokio::test]
+async fn newsletters_with_unknown_merge_fields_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Dear {{subscriber.nmae}}",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    let html_page = app.get_newsletter_html().await;
+    assert!(html_page.contains(
+        "The newsletter issue uses an unknown merge field: {{subscriber.nmae}}. \
+        Available merge fields: {{subscriber.name}}, {{subscriber.email}}, {{subscriber.subscribed_at}}."
+    ));
+    let issues = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_all(&app.connect_pool)
+        .await
+        .unwrap();
+    assert!(issues.is_empty());
+}
+
+#[tokio::test]
+async fn newsletters_written_in_markdown_are_sent_as_html_and_plain_text() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let markdown = "Hi **{{subscriber.name | \"reader\"}}**,\n\n\
+                    - read [the post](https://example.com/post)\n\n<script>alert(1)</script>";
+
+    // Act
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "content_format": "markdown",
+            "markdown_content": markdown,
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let issue =
+        sqlx::query!("SELECT html_content, text_content, markdown_content FROM newsletter_issues")
+            .fetch_one(&app.connect_pool)
+            .await
+            .unwrap();
+    assert_eq!(issue.markdown_content.as_deref(), Some(markdown));
+    assert!(issue
+        .html_content
+        .contains(r#"<a href="https://example.com/post">the post</a>"#));
+    assert!(!issue.html_content.contains("<script>"));
+    assert!(issue
+        .text_content
+        .contains("- read the post (https://example.com/post)"));
+
+    let subscriber = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["HtmlBody"].as_str().unwrap().starts_with(&format!(
+        "<p>Hi <strong>{}</strong>,</p>",
+        htmlescape::encode_minimal(&subscriber.name)
+    )));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with(&format!("Hi {},\n\n- read the post", subscriber.name)));
+}
+
+#[tokio::test]
+async fn newsletters_without_content_in_the_chosen_format_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "content_format": "markdown",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "text_content": "Newsletter body as plain text",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn html_content_is_sanitized_and_the_author_is_warned() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Publish
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": r#"<p onmouseover="track()">Newsletter body</p><script>alert(1)</script>"#,
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_newsletter_html().await;
+    assert!(html_page.contains("The HTML content was sanitized: Removed 1 &lt;script&gt; element."));
+    assert!(html_page.contains(
+        "The HTML content was sanitized: Removed the onmouseover attribute from 1 &lt;p&gt; element."
+    ));
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let issue = sqlx::query!("SELECT html_content FROM newsletter_issues")
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.html_content, "<p>Newsletter body</p>");
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(!body["HtmlBody"].as_str().unwrap().contains("<script>"));
+}
+
+#[tokio::test]
+async fn trusted_raw_html_is_rejected_without_the_permission() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Publish
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<script>alert(1)</script><p>Newsletter body</p>",
+            "trusted_raw_html": true,
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_newsletter_html().await;
+    assert!(html_page.contains("You are not allowed to publish raw HTML"));
+
+    // Assert
+    let issues = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_all(&app.connect_pool)
+        .await
+        .unwrap();
+    assert!(issues.is_empty());
+}
+
+#[tokio::test]
+async fn trusted_raw_html_is_kept_for_users_with_the_permission() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    sqlx::query!(
+        "UPDATE users SET can_publish_raw_html = true WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.connect_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+    let html_content =
+        "<!--[if mso]><table><![endif]--><p style=\"color: red\">Newsletter body</p>";
+
+    // Act
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": html_content,
+            "trusted_raw_html": true,
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    let issue = sqlx::query!("SELECT html_content FROM newsletter_issues")
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.html_content, html_content);
+    let html_page = app.get_newsletter_html().await;
+    assert!(!html_page.contains("sanitized"));
+}
+
+#[tokio::test]
+async fn issues_are_sent_with_their_sender_name_and_reply_to_on_the_broadcast_stream() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "from_name": "Ursula, from the Newsletter",
+            "reply_to": "editor@example.com",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let from = body["From"].as_str().unwrap();
+    assert!(from.starts_with(r#""Ursula, from the Newsletter" <"#));
+    assert_eq!(body["ReplyTo"], "editor@example.com");
+    assert_eq!(body["MessageStream"], "broadcast");
+    assert_eq!(body["Tag"], "newsletter");
+    let header = &body["Headers"][0];
+    assert_eq!(header["Name"], "List-Unsubscribe");
+    assert!(header["Value"]
+        .as_str()
+        .unwrap()
+        .contains("/subscriptions/unsubscribe?"));
+}
+
+#[tokio::test]
+async fn newsletters_with_an_invalid_reply_to_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "reply_to": "editor-at-example.com",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    let html_page = app.get_newsletter_html().await;
+    assert!(html_page
+        .contains("The reply-to address editor-at-example.com is not a valid email address."));
+    let issues = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_all(&app.connect_pool)
+        .await
+        .unwrap();
+    assert!(issues.is_empty());
+}
+
+#[tokio::test]
+async fn deliveries_are_paused_while_the_email_provider_circuit_is_open() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.email_client.circuit_breaker.failure_threshold = 1;
+        config.email_client.circuit_breaker.open_milliseconds = 60_000;
+    })
+    .await;
+    app.create_confirmed_subscriber().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        // The circuit opens after the first failure
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let pending = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(pending.len(), 1);
+}
@@ -0,0 +1,282 @@
+use crate::helpers;
+use crate::helpers::TestApp;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const PDF: &[u8] = b"%PDF-1.7\n%fake report";
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+fn newsletter_form(html_content: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": html_content,
+        "idempotency_key": Uuid::new_v4().to_string()
+    })
+}
+
+async fn attachments_count(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issue_attachments"#)
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn attachments_and_inline_images_are_sent_with_the_issue() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter_with_attachments(
+            &newsletter_form(r#"<p>Our logo: <img src="cid:logo.png" alt="Logo"></p>"#),
+            vec![
+                ("report.pdf", "application/pdf", PDF.to_vec()),
+                ("logo.png", "image/png", PNG.to_vec()),
+            ],
+        )
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(r#"<img src="cid:logo.png" alt="Logo">"#));
+    assert_eq!(
+        body["Attachments"],
+        serde_json::json!([
+            {
+                "Name": "logo.png",
+                "Content": "iVBORw0KGgoAAAANSUhEUg==",
+                "ContentType": "image/png",
+                "ContentID": "cid:logo.png"
+            },
+            {
+                "Name": "report.pdf",
+                "Content": "JVBERi0xLjcKJWZha2UgcmVwb3J0",
+                "ContentType": "application/pdf"
+            }
+        ])
+    );
+}
+
+#[tokio::test]
+async fn every_recipient_gets_the_attachments() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    sqlx::query!(
+        r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, 'octavia_butler@gmail.com', 'octavia', now(), 'confirmed')
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&app.connect_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletter_with_attachments(
+        &newsletter_form("<p>Newsletter body as HTML</p>"),
+        vec![("report.pdf", "application/pdf", PDF.to_vec())],
+    )
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let issue_emails = &email_requests[email_requests.len() - 2..];
+    for email_request in issue_emails {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        assert_eq!(
+            body["Attachments"][0]["Content"],
+            "JVBERi0xLjcKJWZha2UgcmVwb3J0"
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_publish_form_can_be_sent_as_multipart_without_files() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletter_with_attachments(
+            &newsletter_form("<p>Newsletter body as HTML</p>"),
+            vec![("", "application/octet-stream", vec![])],
+        )
+        .await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    let html_page = app.get_newsletter_html().await;
+    assert!(html_page.contains("The newsletter issue has been accepted"));
+    assert_eq!(attachments_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn attachments_of_other_types_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletter_with_attachments(
+            &newsletter_form("<p>Newsletter body as HTML</p>"),
+            vec![("setup.exe", "application/x-msdownload", b"MZ".to_vec())],
+        )
+        .await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    let html_page = app.get_newsletter_html().await;
+    assert!(html_page.contains("setup.exe has the type application/x-msdownload, allowed types:"));
+    let issues = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_all(&app.connect_pool)
+        .await
+        .unwrap();
+    assert!(issues.is_empty());
+}
+
+#[tokio::test]
+async fn attachments_which_are_not_what_they_claim_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletter_with_attachments(
+            &newsletter_form("<p>Newsletter body as HTML</p>"),
+            vec![("report.pdf", "application/pdf", b"MZ\x90\x00".to_vec())],
+        )
+        .await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    let html_page = app.get_newsletter_html().await;
+    assert!(html_page.contains("report.pdf is not a valid application/pdf file."));
+    assert_eq!(attachments_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn attachments_larger_than_the_limits_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.attachments.max_file_size_bytes = 2048;
+        config.attachments.max_total_size_bytes = 3072;
+    })
+    .await;
+    app.test_user.login(&app).await;
+    let large_pdf = [PDF, &[b' '; 2048]].concat();
+    let small_pdf = [PDF, &[b' '; 1600]].concat();
+
+    // Act - Part 1 - One file is too large
+    let response = app
+        .post_newsletter_with_attachments(
+            &newsletter_form("<p>Newsletter body as HTML</p>"),
+            vec![("large.pdf", "application/pdf", large_pdf)],
+        )
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    let html_page = app.get_newsletter_html().await;
+    assert!(html_page.contains("large.pdf is too large, attachments must not exceed 2 KiB."));
+
+    // Act - Part 2 - All the files together are too large
+    let response = app
+        .post_newsletter_with_attachments(
+            &newsletter_form("<p>Newsletter body as HTML</p>"),
+            vec![
+                ("first.pdf", "application/pdf", small_pdf.clone()),
+                ("second.pdf", "application/pdf", small_pdf),
+            ],
+        )
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    let html_page = app.get_newsletter_html().await;
+    assert!(
+        html_page.contains("The attachments are too large, together they must not exceed 3 KiB.")
+    );
+
+    // Assert
+    assert_eq!(attachments_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn multipart_forms_without_a_valid_csrf_token_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let form = reqwest::multipart::Form::new()
+        .text("title", "Newsletter title")
+        .text("text_content", "Newsletter body as plain text")
+        .text("html_content", "<p>Newsletter body as HTML</p>")
+        .text("idempotency_key", Uuid::new_v4().to_string())
+        .text("csrf_token", "forged");
+
+    // Act
+    let response = app
+        .api_client
+        .post(&format!("{}/admin/newsletter", &app.address))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn retrying_a_multipart_form_replays_the_saved_response() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let form = newsletter_form("<p>Newsletter body as HTML</p>");
+
+    // Act - Every submission gets a multipart boundary of its own
+    for _ in 0..2 {
+        let response = app
+            .post_newsletter_with_attachments(
+                &form,
+                vec![("report.pdf", "application/pdf", PDF.to_vec())],
+            )
+            .await;
+        helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+    }
+
+    // Assert
+    assert_eq!(attachments_count(&app).await, 1);
+}
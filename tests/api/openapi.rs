@@ -0,0 +1,213 @@
+use crate::helpers::TestApp;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use uuid::Uuid;
+use zero_2_prod::startup;
+
+const METHODS: [&str; 5] = ["get", "post", "put", "patch", "delete"];
+
+/// The `(method, path)` of every route registered in `startup::run`.
+fn registered_routes() -> BTreeSet<(String, String)> {
+    startup::endpoints()
+        .map(|(method, path)| (method.as_str().to_lowercase(), path))
+        .collect()
+}
+
+fn documented_routes(doc: &Value) -> BTreeSet<(String, String)> {
+    let mut routes = BTreeSet::new();
+    for (path, item) in doc["paths"].as_object().unwrap() {
+        for method in METHODS {
+            if item[method].is_object() {
+                routes.insert((method.to_string(), path.clone()));
+            }
+        }
+    }
+    routes
+}
+
+#[tokio::test]
+async fn every_endpoint_is_served() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    for (method, path) in startup::endpoints() {
+        // Act
+        let path = path
+            .replace("{issue_id}", &Uuid::new_v4().to_string())
+            .replace("{subscriber_id}", &Uuid::new_v4().to_string());
+        let response = app
+            .api_client
+            .request(method.clone(), format!("{}{}", &app.address, path))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert - Rejected maybe, but not for lack of a route
+        assert!(
+            ![404, 405].contains(&response.status().as_u16()),
+            "{} {} is not served",
+            method,
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn every_registered_route_is_documented() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let documented = documented_routes(&app.get_openapi_json().await);
+
+    // Assert
+    let undocumented: Vec<_> = registered_routes()
+        .difference(&documented)
+        .cloned()
+        .collect();
+    assert!(
+        undocumented.is_empty(),
+        "Add these routes to `ApiDoc`: {:?}",
+        undocumented
+    );
+}
+
+#[tokio::test]
+async fn every_documented_route_is_registered() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let documented = documented_routes(&app.get_openapi_json().await);
+
+    // Assert
+    let unknown: Vec<_> = documented
+        .difference(&registered_routes())
+        .cloned()
+        .collect();
+    assert!(unknown.is_empty(), "No such routes: {:?}", unknown);
+}
+
+#[tokio::test]
+async fn the_request_types_are_documented() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let doc = app.get_openapi_json().await;
+
+    // Assert
+    assert_eq!(doc["openapi"], "3.1.0");
+    let subscribe = &doc["paths"]["/subscriptions"]["post"]["requestBody"]["content"]
+        ["application/x-www-form-urlencoded"]["schema"];
+    assert_eq!(subscribe["$ref"], "#/components/schemas/SubscribeData");
+    let schema = &doc["components"]["schemas"]["SubscribeData"];
+    assert_eq!(schema["required"], serde_json::json!(["email", "name"]));
+
+    let newsletter = &doc["paths"]["/admin/newsletter"]["post"]["requestBody"]["content"];
+    assert!(newsletter["multipart/form-data"].is_object());
+    let schema = &doc["components"]["schemas"]["NewsletterData"];
+    assert_eq!(
+        schema["properties"]["content_format"]["$ref"],
+        "#/components/schemas/ContentFormat"
+    );
+
+    // Query parameters
+    let confirm = &doc["paths"]["/subscriptions/confirm"]["get"]["parameters"][0];
+    assert_eq!(confirm["name"], "subscription_token");
+    assert_eq!(confirm["in"], "query");
+    assert_eq!(confirm["required"], true);
+
+    // Secrets are documented as passwords
+    let login = &doc["components"]["schemas"]["LoginData"]["properties"]["password"];
+    assert_eq!(login["format"], "password");
+}
+
+#[tokio::test]
+async fn the_error_responses_of_the_api_are_documented() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let doc = app.get_openapi_json().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/api/v1/subscribers", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+
+    // Assert
+    for (path, item) in doc["paths"].as_object().unwrap() {
+        if !path.starts_with("/api/v1/") {
+            continue;
+        }
+        for method in METHODS.iter().filter(|method| item[**method].is_object()) {
+            let operation = &item[*method];
+            assert_eq!(operation["security"][0]["api_key"], serde_json::json!([]));
+            assert_eq!(
+                operation["responses"]["401"]["content"]["application/json"]["schema"]["$ref"],
+                "#/components/schemas/ApiErrorBody",
+                "{} {}",
+                method,
+                path
+            );
+        }
+    }
+    // The schema describes what the API actually answers
+    let detail = &doc["components"]["schemas"]["ApiErrorDetail"]["properties"];
+    let documented: BTreeSet<_> = detail.as_object().unwrap().keys().collect();
+    let answered: BTreeSet<_> = body["error"].as_object().unwrap().keys().collect();
+    assert_eq!(documented, answered);
+}
+
+#[tokio::test]
+async fn the_admin_routes_require_a_session() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let doc = app.get_openapi_json().await;
+
+    // Assert
+    assert_eq!(
+        doc["components"]["securitySchemes"]["session"]["in"],
+        "cookie"
+    );
+    let operation = &doc["paths"]["/admin/password"]["post"];
+    assert_eq!(operation["security"][0]["session"], serde_json::json!([]));
+    assert!(operation["responses"]["403"].is_object());
+    assert!(doc["paths"]["/subscriptions"]["post"]["security"].is_null());
+}
+
+#[tokio::test]
+async fn the_viewer_shows_the_operations_and_the_schemas() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app.get_openapi_viewer().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<code>POST /subscriptions</code>"));
+    assert!(html_page.contains("<code>DELETE /api/v1/subscribers/{subscriber_id}</code>"));
+    assert!(html_page.contains(r##"<a href="#schema-SubscribeData">SubscribeData</a>"##));
+    assert!(html_page.contains(r#"<h3 id="schema-SubscribeData">SubscribeData</h3>"#));
+}
+
+#[tokio::test]
+async fn the_viewer_can_be_disabled() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| config.openapi.viewer_enabled = false).await;
+
+    // Act
+    let response = app.get_openapi_viewer().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    // The document itself is still served
+    assert!(app.get_openapi_json().await["paths"].is_object());
+}
@@ -0,0 +1,96 @@
+use crate::helpers::TestApp;
+use reqwest::Response;
+
+fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+    response
+        .headers()
+        .get(name)
+        .map(|value| value.to_str().expect("Header value is not valid UTF-8."))
+}
+
+fn assert_has_security_headers(response: &Response) {
+    let csp = header(response, "Content-Security-Policy").expect("No CSP header.");
+    assert!(csp.contains("default-src 'self'"));
+    assert!(csp.contains("frame-ancestors 'none'"));
+    assert_eq!(header(response, "X-Frame-Options"), Some("DENY"));
+    assert_eq!(header(response, "Referrer-Policy"), Some("same-origin"));
+    assert_eq!(header(response, "X-Content-Type-Options"), Some("nosniff"));
+}
+
+fn csp_nonce(response: &Response) -> String {
+    let csp = header(response, "Content-Security-Policy").expect("No CSP header.");
+    let start = csp.find("'nonce-").expect("No nonce in the CSP.") + "'nonce-".len();
+    let end = start + csp[start..].find('\'').unwrap();
+    csp[start..end].to_owned()
+}
+
+#[tokio::test]
+async fn public_pages_have_security_headers() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let login = app
+        .api_client
+        .get(format!("{}/login", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let health_check = app.get_health_check().await;
+
+    // Assert
+    assert_has_security_headers(&login);
+    assert_has_security_headers(&health_check);
+}
+
+#[tokio::test]
+async fn admin_pages_have_security_headers() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act - Part 1 - Rejected by the authentication middleware
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 303);
+    assert_has_security_headers(&response);
+
+    // Act - Part 2 - Logged in
+    app.test_user.login(&app).await;
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_has_security_headers(&response);
+}
+
+#[tokio::test]
+async fn every_response_gets_a_fresh_csp_nonce() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let first = app.get_health_check().await;
+    let second = app.get_health_check().await;
+
+    // Assert
+    assert!(!csp_nonce(&first).is_empty());
+    assert_ne!(csp_nonce(&first), csp_nonce(&second));
+}
+
+#[tokio::test]
+async fn hsts_is_only_sent_when_configured() {
+    // Arrange
+    let local = TestApp::spawn_app().await;
+    let production = TestApp::spawn_app_with(|config| {
+        config.security_headers.hsts_max_age_seconds = 31536000;
+    })
+    .await;
+
+    // Act
+    let local_response = local.get_health_check().await;
+    let production_response = production.get_health_check().await;
+
+    // Assert
+    assert_eq!(header(&local_response, "Strict-Transport-Security"), None);
+    assert_eq!(
+        header(&production_response, "Strict-Transport-Security"),
+        Some("max-age=31536000; includeSubDomains")
+    );
+}
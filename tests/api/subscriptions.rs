@@ -1,4 +1,6 @@
+use crate::helpers;
 use crate::helpers::TestApp;
+use uuid::Uuid;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -113,6 +115,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
 
     // Act
     let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_outbox_emails().await;
 
     // Assert
     assert_eq!(200, response.status().as_u16());
@@ -142,6 +145,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
 
     // Act
     app.post_subscriptions(body.into()).await;
+    app.dispatch_outbox_emails().await;
 
     // Assert
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
@@ -166,3 +170,414 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+async fn subscriptions_count(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.connect_pool)
+        .await
+        .expect("Failed to count subscriptions.")
+        .count
+}
+
+#[tokio::test]
+async fn subscribe_silently_ignores_bots() {
+    // Arrange
+    let app =
+        TestApp::spawn_app_with(|config| config.subscriptions.min_form_fill_seconds = 60).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let form_token = helpers::extract_hidden_field(&app.get_home_html().await, "form_token");
+    let test_cases = vec![
+        (
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".to_string(),
+            "without a form token",
+        ),
+        (
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token=1700000000.abcdef"
+                .to_string(),
+            "with a forged form token",
+        ),
+        (
+            format!(
+                "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+                form_token
+            ),
+            "faster than a human",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_subscriptions_raw(body).await;
+
+        // Assert
+        assert_eq!(200, response.status().as_u16(), "{}", description);
+    }
+    app.dispatch_outbox_emails().await;
+    assert_eq!(subscriptions_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn subscribe_silently_ignores_a_filled_in_honeypot() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&website=http%3A%2F%2Fspam.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_outbox_emails().await;
+    assert_eq!(subscriptions_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn subscribe_silently_ignores_disposable_email_addresses() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body = "name=le%20guin&email=ursula%40mailinator.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_outbox_emails().await;
+    assert_eq!(subscriptions_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn subscribe_is_rate_limited_per_ip_address() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| config.subscriptions.max_attempts_per_ip = 2).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    for i in 0..3 {
+        // Act
+        let body = format!("name=le%20guin&email=ursula_{}%40gmail.com", i);
+        let response = app.post_subscriptions(body).await;
+
+        // Assert
+        assert_eq!(200, response.status().as_u16());
+    }
+    app.dispatch_outbox_emails().await;
+    assert_eq!(subscriptions_count(&app).await, 2);
+}
+
+#[tokio::test]
+async fn the_per_ip_rate_limit_cannot_be_bypassed_with_a_forwarded_header() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| config.subscriptions.max_attempts_per_ip = 2).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    for i in 0..3 {
+        // Act
+        let body = format!("name=le%20guin&email=ursula_{}%40gmail.com", i);
+        let response = app
+            .api_client
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", format!("203.0.113.{}", i))
+            .body(subscription_form(&app, &body).await)
+            .send()
+            .await
+            .expect("Failed to post subscriptions.");
+
+        // Assert
+        assert_eq!(200, response.status().as_u16());
+    }
+    app.dispatch_outbox_emails().await;
+    assert_eq!(subscriptions_count(&app).await, 2);
+}
+
+#[tokio::test]
+async fn the_cleanup_task_only_deletes_attempts_past_the_window() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    sqlx::query!(
+        r#"
+            INSERT INTO subscription_attempts (ip_hash, email_hash, attempted_at)
+            VALUES ('ip', 'email', now() - make_interval(secs => $1))
+        "#,
+        app.subscriptions.rate_limit_window_seconds as f64 + 1.0
+    )
+    .execute(&app.connect_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let n_deleted = app.delete_expired_subscription_attempts().await;
+
+    // Assert
+    assert_eq!(n_deleted, 1);
+    let remaining = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscription_attempts"#)
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, 1);
+}
+
+#[tokio::test]
+async fn subscribe_is_rate_limited_per_email_address() {
+    // Arrange
+    let app =
+        TestApp::spawn_app_with(|config| config.subscriptions.max_attempts_per_email = 2).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // The second attempt sends a new confirmation email, the third one is dropped
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    for email in [
+        "ursula%40gmail.com",
+        "URSULA%40gmail.com",
+        "ursula%40gmail.com",
+    ] {
+        // Act
+        let body = format!("name=le%20guin&email={}", email);
+        let response = app.post_subscriptions(body).await;
+
+        // Assert
+        assert_eq!(200, response.status().as_u16());
+    }
+    app.dispatch_outbox_emails().await;
+}
+
+#[tokio::test]
+async fn subscribe_does_not_reveal_existing_subscribers() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let new_subscriber = app.post_subscriptions(body.into()).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&app.connect_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let existing_subscriber = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(new_subscriber.status(), existing_subscriber.status());
+    assert_eq!(
+        new_subscriber.text().await.unwrap(),
+        existing_subscriber.text().await.unwrap()
+    );
+    assert_eq!(subscriptions_count(&app).await, 1);
+    app.dispatch_outbox_emails().await;
+}
+
+#[tokio::test]
+async fn subscribe_succeeds_even_if_the_email_provider_is_down() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let failure = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    // Act - Part 1 - The provider fails
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_outbox_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let outbox = sqlx::query!("SELECT n_retries, execute_after FROM email_outbox")
+        .fetch_one(&app.connect_pool)
+        .await
+        .expect("The confirmation email is not in the outbox anymore.");
+    assert_eq!(outbox.n_retries, 1);
+    assert!(outbox.execute_after > chrono::Utc::now());
+    drop(failure);
+
+    // Act - Part 2 - The provider is back when the retry is due
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    sqlx::query!("UPDATE email_outbox SET execute_after = now()")
+        .execute(&app.connect_pool)
+        .await
+        .unwrap();
+    app.dispatch_outbox_emails().await;
+
+    // Assert
+    let outbox = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_outbox"#)
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.count, 0);
+}
+
+#[tokio::test]
+async fn an_outbox_email_is_dropped_after_the_maximum_number_of_retries() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.email_outbox.max_retries = 3;
+        config.email_outbox.retry_backoff_seconds = 0;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_outbox_emails().await;
+
+    // Assert
+    let outbox = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_outbox"#)
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.count, 0);
+}
+
+/// A subscription form with a genuine form token, sent as is on every retry.
+async fn subscription_form(app: &TestApp, body: &str) -> String {
+    let form_token = helpers::extract_hidden_field(&app.get_home_html().await, "form_token");
+    format!("{}&form_token={}", body, form_token)
+}
+
+async fn post_subscriptions_with_key(
+    app: &TestApp,
+    body: &str,
+    idempotency_key: &str,
+    ip_address: &str,
+) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Idempotency-Key", idempotency_key)
+        .header("X-Forwarded-For", ip_address)
+        .body(body.to_string())
+        .send()
+        .await
+        .expect("Failed to post subscriptions.")
+}
+
+#[tokio::test]
+async fn retrying_a_subscription_with_the_same_idempotency_key_stores_it_once() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.idempotency.anonymous_retention_seconds = 120;
+    })
+    .await;
+    let body = subscription_form(&app, "name=le%20guin&email=ursula_le_guin%40gmail.com").await;
+    let key = Uuid::new_v4().to_string();
+
+    // Act
+    for _ in 0..2 {
+        let response = post_subscriptions_with_key(&app, &body, &key, "203.0.113.7").await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Assert
+    let outbox = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_outbox"#)
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.count, 1);
+    let record = sqlx::query!(
+        r#"
+            SELECT user_id, expires_at - created_at = interval '120 seconds' AS "anonymous_retention!"
+            FROM idempotency
+        "#
+    )
+    .fetch_one(&app.connect_pool)
+    .await
+    .unwrap();
+    assert!(record.user_id.is_none());
+    assert!(record.anonymous_retention);
+}
+
+#[tokio::test]
+async fn idempotency_keys_of_anonymous_callers_are_scoped_by_ip_address() {
+    // Arrange - The test client is the proxy telling the IP address of each caller
+    let app = TestApp::spawn_app_with(|config| {
+        config.application.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    })
+    .await;
+    let key = Uuid::new_v4().to_string();
+    let ursula = subscription_form(&app, "name=le%20guin&email=ursula_le_guin%40gmail.com").await;
+    let octavia = subscription_form(&app, "name=octavia&email=octavia_butler%40gmail.com").await;
+    let response = post_subscriptions_with_key(&app, &ursula, &key, "203.0.113.7").await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 1 - The same key from another caller
+    let response = post_subscriptions_with_key(&app, &octavia, &key, "198.51.100.23").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriptions_count(&app).await, 2);
+
+    // Act - Part 2 - The same key for another form from the first caller
+    let response = post_subscriptions_with_key(&app, &octavia, &key, "203.0.113.7").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 422);
+}
+
+#[tokio::test]
+async fn a_forwarded_ip_address_does_not_change_the_scope_of_an_untrusted_caller() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let key = Uuid::new_v4().to_string();
+    let ursula = subscription_form(&app, "name=le%20guin&email=ursula_le_guin%40gmail.com").await;
+    let octavia = subscription_form(&app, "name=octavia&email=octavia_butler%40gmail.com").await;
+    let response = post_subscriptions_with_key(&app, &ursula, &key, "203.0.113.7").await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let response = post_subscriptions_with_key(&app, &octavia, &key, "198.51.100.23").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 422);
+    assert_eq!(subscriptions_count(&app).await, 1);
+}
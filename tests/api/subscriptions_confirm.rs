@@ -28,6 +28,7 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_outbox_emails().await;
     let email_request = &app
         .email_server
         .received_requests()
@@ -55,6 +56,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
 
     // Subscribe
     app.post_subscriptions(body.into()).await;
+    app.dispatch_outbox_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(&email_request);
 
@@ -75,3 +77,37 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn confirmed_subscribers_get_a_single_welcome_email() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_outbox_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(&email_request);
+
+    // Act - Follow the link twice, the token is used up the first time
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert!(!response.status().is_success());
+    app.dispatch_outbox_emails().await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Your subscription is confirmed");
+    assert!(body["HtmlBody"].as_str().unwrap().contains("Hi le guin"));
+    app.get_unsubscribe_link(email_request);
+}
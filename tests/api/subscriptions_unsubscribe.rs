@@ -0,0 +1,163 @@
+use crate::helpers::TestApp;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Subscribe and confirm, then return the unsubscribe link of the welcome email.
+async fn unsubscribe_link_of_a_confirmed_subscriber(app: &TestApp) -> reqwest::Url {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Confirmation and welcome emails")
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_outbox_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_outbox_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    app.get_unsubscribe_link(email_request)
+}
+
+#[tokio::test]
+async fn clicking_on_the_unsubscribe_link_unsubscribes_a_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let unsubscribe_link = unsubscribe_link_of_a_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.connect_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn an_old_confirmation_link_does_not_subscribe_again() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let unsubscribe_link = unsubscribe_link_of_a_confirmed_subscriber(&app).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(unsubscribe_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert!(!response.status().is_success());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.connect_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn a_subscriber_coming_back_confirms_with_the_new_link() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let unsubscribe_link = unsubscribe_link_of_a_confirmed_subscriber(&app).await;
+    reqwest::get(unsubscribe_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.email_server.reset().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_outbox_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.connect_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn forged_unsubscribe_links_are_rejected_with_a_400() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let mut unsubscribe_link = unsubscribe_link_of_a_confirmed_subscriber(&app).await;
+    let tag = unsubscribe_link
+        .query_pairs()
+        .find(|(key, _)| key == "tag")
+        .unwrap()
+        .1
+        .into_owned();
+    unsubscribe_link.set_query(Some(&format!(
+        "subscriber_id={}&tag={}",
+        Uuid::new_v4(),
+        tag
+    )));
+
+    // Act
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.connect_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_get_newsletter_issues() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let unsubscribe_link = unsubscribe_link_of_a_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    app.post_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+
+    // Act - Unsubscribe before the issue goes out
+    reqwest::get(unsubscribe_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - No email on top of the confirmation and the welcome ones, checked on drop
+}
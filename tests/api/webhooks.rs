@@ -0,0 +1,646 @@
+use crate::helpers;
+use crate::helpers::TestApp;
+use hmac::{Hmac, Mac};
+use reqwest::Method;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// A receiver answering `status` to every event.
+async fn webhook_receiver(status: u16) -> MockServer {
+    let receiver = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(status))
+        .mount(&receiver)
+        .await;
+    receiver
+}
+
+async fn received_events(receiver: &MockServer) -> Vec<serde_json::Value> {
+    receiver
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect()
+}
+
+/// wiremock splits the values at commas, put them back together.
+fn header(request: &wiremock::Request, name: &'static str) -> String {
+    request.headers[&name.into()]
+        .iter()
+        .map(|value| value.as_str())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Check the request the way a receiver would, with the secret of the endpoint.
+fn assert_is_signed_with(request: &wiremock::Request, secret: &str) {
+    let header = header(request, "x-webhook-signature");
+    let (timestamp, signature) = header
+        .strip_prefix("t=")
+        .and_then(|header| header.split_once(",v1="))
+        .expect("The signature header is malformed.");
+    let mut hmac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    hmac.update(format!("{}.", timestamp).as_bytes());
+    hmac.update(&request.body);
+    hmac.verify_slice(&hex::decode(signature).unwrap())
+        .expect("The signature does not match the body.");
+    let age = chrono::Utc::now().timestamp() - timestamp.parse::<i64>().unwrap();
+    assert!((0..60).contains(&age));
+}
+
+async fn webhook_ids(app: &TestApp) -> Vec<Uuid> {
+    sqlx::query!("SELECT webhook_id FROM webhook_endpoints WHERE removed_at IS NULL")
+        .fetch_all(&app.connect_pool)
+        .await
+        .expect("Failed to fetch webhook endpoints.")
+        .into_iter()
+        .map(|r| r.webhook_id)
+        .collect()
+}
+
+async fn pending_deliveries(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM webhook_delivery_queue"#)
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_webhooks() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app.get_admin_webhooks().await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_new_endpoint_shows_its_secret_once() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let secret = app
+        .create_webhook(
+            "https://crm.example.com/hooks?source=<newsletter>",
+            &["subscriber_confirmed", "issue_delivered"],
+        )
+        .await;
+
+    // Assert
+    let stored = sqlx::query!("SELECT url, secret, event_types FROM webhook_endpoints")
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.secret, secret);
+    assert_eq!(
+        stored.event_types,
+        vec!["subscriber.confirmed", "issue.delivered"]
+    );
+
+    let html_page = app.get_admin_webhooks_html().await;
+    assert!(html_page.contains("https://crm.example.com/hooks?source=%3Cnewsletter%3E"));
+    assert!(html_page.contains("subscriber.confirmed, issue.delivered"));
+    assert!(!html_page.contains(&secret));
+}
+
+#[tokio::test]
+async fn an_invalid_endpoint_is_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "url": "crm.example.com/hooks", "issue_delivered": "true" }),
+            "The URL of the webhook must be an absolute http or https URL.",
+        ),
+        (
+            serde_json::json!({ "url": "https://crm.example.com/hooks" }),
+            "Choose at least one event for the webhook.",
+        ),
+    ];
+
+    for (body, error_message) in test_cases {
+        // Act
+        let response = app.post_create_webhook(&body).await;
+
+        // Assert
+        helpers::assert_is_redirect_to(&response, "/admin/webhooks");
+        assert!(app.get_admin_webhooks_html().await.contains(error_message));
+    }
+    assert!(webhook_ids(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn an_endpoint_on_a_private_network_is_rejected() {
+    // Arrange
+    let app =
+        TestApp::spawn_app_with(|config| config.webhooks.allow_private_networks = false).await;
+    app.test_user.login(&app).await;
+
+    for url in [
+        "http://169.254.169.254/latest/meta-data",
+        "http://localhost:6379",
+        "http://[::1]/hooks",
+    ] {
+        // Act
+        let body = serde_json::json!({ "url": url, "issue_delivered": "true" });
+        let response = app.post_create_webhook(&body).await;
+
+        // Assert
+        helpers::assert_is_redirect_to(&response, "/admin/webhooks");
+        assert!(app
+            .get_admin_webhooks_html()
+            .await
+            .contains("The URL of the webhook must not point to a private network."));
+    }
+    assert!(webhook_ids(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn nothing_is_posted_to_an_endpoint_on_a_private_network() {
+    // Arrange
+    let app =
+        TestApp::spawn_app_with(|config| config.webhooks.allow_private_networks = false).await;
+    let receiver = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&receiver)
+        .await;
+    // Added while private networks were allowed
+    sqlx::query!(
+        r#"
+            INSERT INTO webhook_endpoints (webhook_id, url, secret, event_types, created_by, created_at)
+            VALUES ($1, $2, 'whsec_test', ARRAY['subscriber.subscribed'], $3, now())
+        "#,
+        Uuid::new_v4(),
+        format!("{}/hooks", receiver.uri()),
+        app.test_user.user_id
+    )
+    .execute(&app.connect_pool)
+    .await
+    .unwrap();
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_webhook_events().await;
+
+    // Assert
+    let delivery = sqlx::query!("SELECT status_code, error, outcome FROM webhook_deliveries")
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status_code, None);
+    assert_eq!(
+        delivery.error.as_deref(),
+        Some("The URL of the webhook must not point to a private network.")
+    );
+    assert_eq!(delivery.outcome, "retrying");
+}
+
+#[tokio::test]
+async fn a_subscription_is_posted_signed_to_the_endpoint() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let receiver = webhook_receiver(200).await;
+    let secret = app
+        .create_webhook(
+            &format!("{}/hooks", receiver.uri()),
+            &["subscriber_subscribed"],
+        )
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_webhook_events().await;
+
+    // Assert
+    let requests = receiver.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    let request = &requests[0];
+    assert_is_signed_with(request, &secret);
+    assert_eq!(header(request, "content-type"), "application/json");
+    assert_eq!(header(request, "x-webhook-event"), "subscriber.subscribed");
+
+    let event: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(
+        header(request, "x-webhook-id"),
+        event["id"].as_str().unwrap()
+    );
+    assert_eq!(event["type"], "subscriber.subscribed");
+    let subscriber = &event["data"]["subscriber"];
+    assert_eq!(subscriber["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(subscriber["name"], "le guin");
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.connect_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber["id"], saved.id.to_string());
+}
+
+#[tokio::test]
+async fn an_endpoint_only_gets_the_events_it_listens_to() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let receiver = webhook_receiver(200).await;
+    app.create_webhook(
+        &format!("{}/hooks", receiver.uri()),
+        &["subscriber_confirmed"],
+    )
+    .await;
+
+    // Act
+    app.create_confirmed_subscriber().await;
+    app.dispatch_webhook_events().await;
+
+    // Assert
+    let events = received_events(&receiver).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["type"], "subscriber.confirmed");
+    assert_eq!(
+        events[0]["data"]["subscriber"]["email"],
+        "ursula_le_guin@gmail.com"
+    );
+}
+
+#[tokio::test]
+async fn unsubscribing_twice_is_posted_once() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let receiver = webhook_receiver(200).await;
+    app.create_webhook(
+        &format!("{}/hooks", receiver.uri()),
+        &["subscriber_unsubscribed"],
+    )
+    .await;
+    app.create_confirmed_subscriber().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let unsubscribe_link = app.get_unsubscribe_link(email_request);
+
+    // Act
+    for _ in 0..2 {
+        reqwest::get(unsubscribe_link.clone())
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+    app.dispatch_webhook_events().await;
+
+    // Assert
+    let events = received_events(&receiver).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["type"], "subscriber.unsubscribed");
+}
+
+#[tokio::test]
+async fn subscribers_created_through_the_api_are_posted() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let receiver = webhook_receiver(200).await;
+    app.create_webhook(
+        &format!("{}/hooks", receiver.uri()),
+        &["subscriber_subscribed"],
+    )
+    .await;
+    let api_key = app.create_api_key().await;
+
+    // Act
+    let response = app
+        .api_v1(Method::POST, "/subscribers", &api_key)
+        .json(&serde_json::json!({ "name": "le guin", "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    app.dispatch_webhook_events().await;
+
+    // Assert
+    let events = received_events(&receiver).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["type"], "subscriber.subscribed");
+}
+
+#[tokio::test]
+async fn subscribers_deleted_through_the_api_are_posted() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let receiver = webhook_receiver(200).await;
+    app.create_webhook(
+        &format!("{}/hooks", receiver.uri()),
+        &["subscriber_deleted"],
+    )
+    .await;
+    let api_key = app.create_api_key().await;
+    let subscriber: serde_json::Value = app
+        .api_v1(Method::POST, "/subscribers", &api_key)
+        .json(&serde_json::json!({ "name": "le guin", "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let subscriber_id = subscriber["id"].as_str().unwrap();
+
+    // Act
+    let response = app
+        .api_v1(
+            Method::DELETE,
+            &format!("/subscribers/{}", subscriber_id),
+            &api_key,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+    app.dispatch_webhook_events().await;
+
+    // Assert
+    let events = received_events(&receiver).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["type"], "subscriber.deleted");
+    let deleted = &events[0]["data"]["subscriber"];
+    assert_eq!(deleted["id"], subscriber_id);
+    assert_eq!(deleted["email"], "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn an_issue_is_posted_once_its_last_email_is_sent() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let receiver = webhook_receiver(200).await;
+    app.create_webhook(&format!("{}/hooks", receiver.uri()), &["issue_delivered"])
+        .await;
+    app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+
+    // Act - Part 1 - The emails are still in the queue
+    app.dispatch_webhook_events().await;
+    assert!(received_events(&receiver).await.is_empty());
+
+    // Act - Part 2 - The emails are sent
+    app.dispatch_all_pending_emails().await;
+    app.dispatch_webhook_events().await;
+
+    // Assert
+    let events = received_events(&receiver).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["type"], "issue.delivered");
+    let issue = &events[0]["data"]["issue"];
+    assert_eq!(issue["title"], "Newsletter title");
+    assert_eq!(issue["recipients"], 1);
+    assert_eq!(issue["delivered"], 1);
+}
+
+#[tokio::test]
+async fn an_issue_is_not_posted_while_one_of_its_emails_is_in_flight() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let receiver = webhook_receiver(200).await;
+    app.create_webhook(&format!("{}/hooks", receiver.uri()), &["issue_delivered"])
+        .await;
+    app.create_confirmed_subscriber().await;
+    sqlx::query!(
+        r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, 'octavia_butler@gmail.com', 'octavia', now(), 'confirmed')
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&app.connect_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    helpers::assert_is_redirect_to(&response, "/admin/newsletter");
+
+    // Act - Part 1 - Another worker holds one of the two emails
+    let mut in_flight = app.connect_pool.begin().await.unwrap();
+    sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue LIMIT 1 FOR UPDATE")
+        .fetch_one(&mut in_flight)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    app.dispatch_webhook_events().await;
+    assert!(received_events(&receiver).await.is_empty());
+
+    // Act - Part 2 - The other worker gave up, its email is sent again
+    in_flight.rollback().await.unwrap();
+    app.dispatch_all_pending_emails().await;
+    app.dispatch_webhook_events().await;
+
+    // Assert
+    let events = received_events(&receiver).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["data"]["issue"]["delivered"], 2);
+}
+
+#[tokio::test]
+async fn an_issue_without_recipients_is_posted_right_away() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let receiver = webhook_receiver(200).await;
+    app.create_webhook(&format!("{}/hooks", receiver.uri()), &["issue_delivered"])
+        .await;
+
+    // Act
+    app.post_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+    app.dispatch_webhook_events().await;
+
+    // Assert
+    let events = received_events(&receiver).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["data"]["issue"]["recipients"], 0);
+}
+
+#[tokio::test]
+async fn a_failed_delivery_is_retried_and_logged() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| config.webhooks.retry_backoff_seconds = 0).await;
+    let receiver = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&receiver)
+        .await;
+    Mock::given(path("/hooks"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&receiver)
+        .await;
+    app.create_webhook(
+        &format!("{}/hooks", receiver.uri()),
+        &["subscriber_subscribed"],
+    )
+    .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_webhook_events().await;
+
+    // Assert
+    let events = received_events(&receiver).await;
+    assert_eq!(events[0], events[1], "A retry posts the very same event.");
+    assert_eq!(pending_deliveries(&app).await, 0);
+    let deliveries = sqlx::query!(
+        "SELECT attempt, status_code, outcome FROM webhook_deliveries ORDER BY attempt"
+    )
+    .fetch_all(&app.connect_pool)
+    .await
+    .unwrap();
+    let deliveries: Vec<_> = deliveries
+        .into_iter()
+        .map(|d| (d.attempt, d.status_code, d.outcome))
+        .collect();
+    assert_eq!(
+        deliveries,
+        vec![
+            (1, Some(503), "retrying".to_string()),
+            (2, Some(204), "delivered".to_string())
+        ]
+    );
+
+    let html_page = app.get_webhook_deliveries_html().await;
+    assert!(html_page.contains("The endpoint answered 503 Service Unavailable."));
+    assert!(html_page.contains("delivered"));
+}
+
+#[tokio::test]
+async fn a_delivery_is_given_up_after_max_retries() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.webhooks.retry_backoff_seconds = 0;
+        config.webhooks.max_retries = 3;
+    })
+    .await;
+    let receiver = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(3)
+        .mount(&receiver)
+        .await;
+    app.create_webhook(
+        &format!("{}/hooks", receiver.uri()),
+        &["subscriber_subscribed"],
+    )
+    .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_webhook_events().await;
+
+    // Assert
+    assert_eq!(pending_deliveries(&app).await, 0);
+    let outcomes: Vec<String> =
+        sqlx::query!("SELECT outcome FROM webhook_deliveries ORDER BY attempt")
+            .fetch_all(&app.connect_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|d| d.outcome)
+            .collect();
+    assert_eq!(outcomes, vec!["retrying", "retrying", "failed"]);
+}
+
+#[tokio::test]
+async fn a_failed_delivery_waits_for_its_backoff() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let receiver = webhook_receiver(500).await;
+    app.create_webhook(
+        &format!("{}/hooks", receiver.uri()),
+        &["subscriber_subscribed"],
+    )
+    .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_webhook_events().await;
+
+    // Assert
+    assert_eq!(received_events(&receiver).await.len(), 1);
+    assert_eq!(pending_deliveries(&app).await, 1);
+    assert!(app
+        .get_admin_webhooks_html()
+        .await
+        .contains("<td>1</td><td>"));
+}
+
+#[tokio::test]
+async fn a_removed_endpoint_gets_nothing_more() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let receiver = webhook_receiver(200).await;
+    app.create_webhook(
+        &format!("{}/hooks", receiver.uri()),
+        &["subscriber_subscribed"],
+    )
+    .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Act
+    let webhook_id = webhook_ids(&app).await[0];
+    let response = app
+        .post_remove_webhook(&serde_json::json!({ "webhook_id": webhook_id }))
+        .await;
+
+    // Assert
+    helpers::assert_is_redirect_to(&response, "/admin/webhooks");
+    assert!(app
+        .get_admin_webhooks_html()
+        .await
+        .contains("The endpoint has been removed."));
+    assert_eq!(pending_deliveries(&app).await, 0);
+    app.post_subscriptions("name=tolkien&email=tolkien%40gmail.com".into())
+        .await;
+    app.dispatch_webhook_events().await;
+    assert!(received_events(&receiver).await.is_empty());
+}